@@ -0,0 +1,183 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+extern crate bdk;
+
+use bdk::bitcoin::hashes::Hash;
+use bdk::bitcoin::{OutPoint, ScriptBuf, TxOut, Txid, WPubkeyHash};
+use bdk::chain::ConfirmationTime;
+#[cfg(feature = "waste-metric")]
+use bdk::wallet::coin_selection::WasteMetricCoinSelection;
+use bdk::wallet::coin_selection::{
+    BranchAndBoundCoinSelection, CoinSelectionAlgorithm, Excess, KnapsackCoinSelection,
+    LargestFirstCoinSelection, OldestFirstCoinSelection, PrivacyCoinSelection,
+    SingleRandomDrawCoinSelection, SmallestFirstCoinSelection,
+};
+use bdk::{FeeRate, KeychainKind, LocalOutput, Utxo, WeightedUtxo};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// script sig len (4WU) + n. of items on witness (1WU) + signature len (1WU) +
+// signature and sighash (72WU) + pubkey len (1WU) + pubkey (33WU)
+const P2WPKH_SATISFACTION_WEIGHT: usize = 4 + 1 + 1 + 72 + 1 + 33;
+
+const PAYMENTS: usize = 100;
+
+struct Scenario {
+    name: &'static str,
+    utxos: usize,
+    min_value: u64,
+    max_value: u64,
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "many small utxos",
+        utxos: 200,
+        min_value: 1_000,
+        max_value: 100_000,
+    },
+    Scenario {
+        name: "few large utxos",
+        utxos: 10,
+        min_value: 1_000_000,
+        max_value: 10_000_000,
+    },
+    Scenario {
+        name: "mixed utxos",
+        utxos: 100,
+        min_value: 1_000,
+        max_value: 5_000_000,
+    },
+];
+
+const FEE_RATES: &[f32] = &[2.0, 20.0, 100.0];
+
+#[derive(Default)]
+struct Report {
+    fees: u64,
+    changes: usize,
+    inputs: usize,
+    successes: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn print(&self, name: &str) {
+        let average = |total: u64| total as f64 / self.successes.max(1) as f64;
+        println!(
+            "    {:<16} fee {:>10.1} sats  change {:>5.1}%  inputs {:>5.1}  failures {:>3}",
+            name,
+            average(self.fees),
+            100.0 * self.changes as f64 / self.successes.max(1) as f64,
+            average(self.inputs as u64),
+            self.failures,
+        );
+    }
+}
+
+fn generate_utxos(rng: &mut StdRng, scenario: &Scenario) -> Vec<WeightedUtxo> {
+    (0..scenario.utxos)
+        .map(|i| WeightedUtxo {
+            satisfaction_weight: P2WPKH_SATISFACTION_WEIGHT,
+            utxo: Utxo::Local(LocalOutput {
+                outpoint: OutPoint::new(Txid::all_zeros(), i as u32),
+                txout: TxOut {
+                    value: rng.gen_range(scenario.min_value..=scenario.max_value),
+                    script_pubkey: ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+                },
+                keychain: KeychainKind::External,
+                is_spent: false,
+                derivation_index: i as u32,
+                confirmation_time: ConfirmationTime::Confirmed {
+                    height: rng.gen_range(1..800_000),
+                    time: 0,
+                },
+            }),
+        })
+        .collect()
+}
+
+/// This example benchmarks the coin selection algorithms of [`bdk::wallet::coin_selection`].
+///
+/// Every algorithm selects the coins for the same random payments, at different fee rates, from
+/// synthetic UTXO sets of P2WPKH outputs. For each of them it reports the average fee spent on
+/// the selection (inputs, plus the change output or the excess left to the miners), how often a
+/// change output is created, the average number of inputs and how many payments failed.
+///
+/// Run it with `cargo run --release --example coin_selection`, and add `--features waste-metric`
+/// to include `WasteMetricCoinSelection`.
+fn main() {
+    let drain_script = ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::all_zeros());
+    #[allow(unused_mut)]
+    let mut algorithms: Vec<(&str, Box<dyn CoinSelectionAlgorithm>)> = vec![
+        ("largest-first", Box::new(LargestFirstCoinSelection)),
+        ("smallest-first", Box::new(SmallestFirstCoinSelection)),
+        ("oldest-first", Box::new(OldestFirstCoinSelection)),
+        ("bnb", Box::<BranchAndBoundCoinSelection>::default()),
+        ("privacy", Box::new(PrivacyCoinSelection::new())),
+        (
+            "srd",
+            Box::new(SingleRandomDrawCoinSelection::default().seed(1)),
+        ),
+        (
+            "knapsack",
+            Box::new(KnapsackCoinSelection::default().seed(1)),
+        ),
+    ];
+    #[cfg(feature = "waste-metric")]
+    algorithms.insert(
+        4,
+        ("waste-metric", Box::<WasteMetricCoinSelection>::default()),
+    );
+
+    for scenario in SCENARIOS {
+        for fee_rate in FEE_RATES {
+            println!("{} at {} sat/vB", scenario.name, fee_rate);
+            let fee_rate = FeeRate::from_sat_per_vb(*fee_rate);
+
+            for (name, algorithm) in &algorithms {
+                // Every algorithm gets the same UTXOs and payments
+                let mut rng = StdRng::seed_from_u64(42);
+                let utxos = generate_utxos(&mut rng, scenario);
+                let total = utxos.iter().map(|u| u.utxo.txout().value).sum::<u64>();
+
+                let mut report = Report::default();
+                for _ in 0..PAYMENTS {
+                    let target_amount = rng.gen_range(10_000..=(total / 2).max(10_000));
+                    match algorithm.coin_select(
+                        vec![],
+                        utxos.clone(),
+                        fee_rate,
+                        target_amount,
+                        &drain_script,
+                    ) {
+                        Ok(result) => {
+                            report.successes += 1;
+                            report.inputs += result.selected.len();
+                            report.fees += result.fee_amount;
+                            match result.excess {
+                                Excess::Change { fee, .. } => {
+                                    report.changes += 1;
+                                    report.fees += fee;
+                                }
+                                Excess::NoChange {
+                                    remaining_amount, ..
+                                } => report.fees += remaining_amount,
+                            }
+                        }
+                        Err(_) => report.failures += 1,
+                    }
+                }
+                report.print(name);
+            }
+        }
+    }
+}
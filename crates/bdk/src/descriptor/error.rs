@@ -42,6 +42,8 @@ pub enum Error {
     Miniscript(miniscript::Error),
     /// Hex decoding error
     Hex(bitcoin::hashes::hex::Error),
+    /// Invalid `musig()` key expression
+    Musig(crate::wallet::musig::Error),
 }
 
 impl From<crate::keys::KeyError> for Error {
@@ -79,6 +81,7 @@ impl fmt::Display for Error {
             Self::Pk(err) => write!(f, "Key-related error: {}", err),
             Self::Miniscript(err) => write!(f, "Miniscript error: {}", err),
             Self::Hex(err) => write!(f, "Hex decoding error: {}", err),
+            Self::Musig(err) => write!(f, "MuSig2 error: {}", err),
         }
     }
 }
@@ -121,3 +124,9 @@ impl From<crate::descriptor::policy::PolicyError> for Error {
         Error::Policy(err)
     }
 }
+
+impl From<crate::wallet::musig::Error> for Error {
+    fn from(err: crate::wallet::musig::Error) -> Self {
+        Error::Musig(err)
+    }
+}
@@ -96,8 +96,9 @@ impl IntoWalletDescriptor for &str {
             }
             None => self,
         };
+        let descriptor = crate::wallet::musig::expand_descriptor(descriptor, network)?;
 
-        ExtendedDescriptor::parse_descriptor(secp, descriptor)?
+        ExtendedDescriptor::parse_descriptor(secp, &descriptor)?
             .into_wallet_descriptor(secp, network)
     }
 }
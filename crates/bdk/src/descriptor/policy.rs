@@ -48,7 +48,7 @@ use serde::{Serialize, Serializer};
 
 use bitcoin::bip32::Fingerprint;
 use bitcoin::hashes::{hash160, ripemd160, sha256};
-use bitcoin::{absolute, key::XOnlyPublicKey, PublicKey, Sequence};
+use bitcoin::{absolute, key::XOnlyPublicKey, secp256k1, PublicKey, Sequence};
 
 use miniscript::descriptor::{
     DescriptorPublicKey, ShInner, SinglePub, SinglePubKey, SortedMultiVec, WshInner,
@@ -60,6 +60,7 @@ use miniscript::{
 
 use crate::descriptor::ExtractPolicy;
 use crate::keys::ExtScriptContext;
+use crate::wallet::musig::{self, KeyAggContext};
 use crate::wallet::signer::{SignerId, SignersContainer};
 use crate::wallet::utils::{After, Older, SecpCtx};
 
@@ -146,6 +147,14 @@ pub enum SatisfiableItem {
         /// The required threshold count
         threshold: usize,
     },
+    /// MuSig2 signature for an aggregate key, which needs the partial signatures of all the
+    /// participants
+    Musig {
+        /// The aggregate key
+        key: PkOrF,
+        /// The participant public keys
+        keys: Vec<PkOrF>,
+    },
 
     // Complex item
     /// Threshold items with threshold count
@@ -644,6 +653,67 @@ impl Policy {
         Ok(Some(policy))
     }
 
+    fn make_musig(
+        key: &DescriptorPublicKey,
+        key_agg: &KeyAggContext,
+        signer_key: &secp256k1::PublicKey,
+        build_sat: BuildSatisfaction,
+        secp: &SecpCtx,
+    ) -> Result<Policy, PolicyError> {
+        let participants = key_agg.keys();
+        let mut contribution = Satisfaction::Partial {
+            n: participants.len(),
+            m: participants.len(),
+            items: vec![],
+            conditions: Default::default(),
+            sorted: None,
+        };
+        let mut satisfaction = contribution.clone();
+
+        for (index, participant) in participants.iter().enumerate() {
+            if participant == signer_key {
+                contribution.add(
+                    &Satisfaction::Complete {
+                        condition: Default::default(),
+                    },
+                    index,
+                )?;
+            }
+
+            if let Some(psbt) = build_sat.psbt() {
+                // Once aggregated, the partial signatures aren't needed anymore
+                if miniscript::Tap::find_signature(psbt, key, secp)
+                    || psbt
+                        .inputs
+                        .iter()
+                        .all(|input| musig::has_partial_signature(input, key_agg, participant))
+                {
+                    satisfaction.add(
+                        &Satisfaction::Complete {
+                            condition: Default::default(),
+                        },
+                        index,
+                    )?;
+                }
+            }
+        }
+        satisfaction.finalize();
+        contribution.finalize();
+
+        let mut policy: Policy = SatisfiableItem::Musig {
+            key: PkOrF::from_key(key, secp),
+            keys: participants
+                .iter()
+                .map(|pk| PkOrF::Pubkey(PublicKey::new(*pk)))
+                .collect(),
+        }
+        .into();
+        policy.contribution = contribution;
+        policy.satisfaction = satisfaction;
+
+        Ok(policy)
+    }
+
     /// Return whether or not a specific path in the policy tree is required to unambiguously
     /// create a transaction
     ///
@@ -665,7 +735,9 @@ impl Policy {
             SatisfiableItem::Thresh { items, threshold } if items.len() == *threshold => {
                 (0..*threshold).collect()
             }
-            SatisfiableItem::Multisig { keys, .. } => (0..keys.len()).collect(),
+            SatisfiableItem::Multisig { keys, .. } | SatisfiableItem::Musig { keys, .. } => {
+                (0..keys.len()).collect()
+            }
             _ => HashSet::new(),
         };
         let selected: HashSet<_> = match path.get(&self.id) {
@@ -720,6 +792,16 @@ impl Policy {
 
                 Ok(Condition::default())
             }
+            SatisfiableItem::Musig { keys, .. } => {
+                if selected.len() < keys.len() {
+                    return Err(PolicyError::NotEnoughItemsSelected(self.id.clone()));
+                }
+                if let Some(item) = selected.into_iter().find(|&i| i >= keys.len()) {
+                    return Err(PolicyError::IndexOutOfRange(item));
+                }
+
+                Ok(Condition::default())
+            }
             SatisfiableItem::AbsoluteTimelock { value } => Ok(Condition {
                 csv: None,
                 timelock: Some(*value),
@@ -1134,8 +1216,22 @@ impl ExtractPolicy for Descriptor<DescriptorPublicKey> {
             Descriptor::Tr(tr) => {
                 // If there's no tap tree, treat this as a single sig, otherwise build a `Thresh`
                 // node with threshold = 1 and the key spend signature plus all the tree leaves
-                let key_spend_sig =
-                    miniscript::Tap::make_signature(tr.internal_key(), signers, build_sat, secp);
+                // The internal key may be a MuSig2 aggregate key we co-sign for
+                let key_spend_sig = match signers
+                    .find(signer_id(tr.internal_key(), secp))
+                    .and_then(|signer| signer.musig_key())
+                {
+                    Some((key_agg, signer_key)) => Policy::make_musig(
+                        tr.internal_key(),
+                        key_agg,
+                        &signer_key,
+                        build_sat,
+                        secp,
+                    )?,
+                    None => {
+                        miniscript::Tap::make_signature(tr.internal_key(), signers, build_sat, secp)
+                    }
+                };
 
                 if tr.taptree().is_none() {
                     Ok(Some(key_spend_sig))
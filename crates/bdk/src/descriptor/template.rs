@@ -511,6 +511,7 @@ macro_rules! expand_make_bipxx {
                     KeychainKind::Internal => {
                         derivation_path.push(bip32::ChildNumber::from_normal_idx(1)?)
                     }
+                    KeychainKind::Custom(_) => return Err(custom_keychain_error()),
                 };
 
                 let derivation_path: bip32::DerivationPath = derivation_path.into();
@@ -527,6 +528,7 @@ macro_rules! expand_make_bipxx {
                 let derivation_path: bip32::DerivationPath = match keychain {
                     KeychainKind::External => vec![bip32::ChildNumber::from_normal_idx(0)?].into(),
                     KeychainKind::Internal => vec![bip32::ChildNumber::from_normal_idx(1)?].into(),
+                    KeychainKind::Custom(_) => return Err(custom_keychain_error()),
                 };
 
                 let source_path = bip32::DerivationPath::from(vec![
@@ -544,6 +546,13 @@ macro_rules! expand_make_bipxx {
     };
 }
 
+/// BIP44/49/84/86 only define the external and internal chains of an account.
+fn custom_keychain_error() -> DescriptorError {
+    DescriptorError::Key(crate::keys::KeyError::Message(
+        "BIP templates only support the external and internal keychains".into(),
+    ))
+}
+
 expand_make_bipxx!(legacy, Legacy);
 expand_make_bipxx!(segwit_v0, Segwitv0);
 expand_make_bipxx!(segwit_v1, Tap);
@@ -29,6 +29,7 @@ extern crate bip39;
 pub mod descriptor;
 pub mod keys;
 pub mod psbt;
+pub mod silent_payments;
 pub(crate) mod types;
 pub mod wallet;
 
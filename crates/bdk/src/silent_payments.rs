@@ -0,0 +1,1046 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Silent payments
+//!
+//! Support for [BIP352] silent payments: a [`SilentPaymentAddress`] is a static address made of a
+//! scan and a spend key, from which the sender derives a fresh taproot output for every payment
+//! using the private keys of the transaction's inputs.
+//!
+//! To send, add the address as a recipient with [`TxBuilder::add_silent_payment_recipient`]. The
+//! wallet computes the output once the inputs are selected, so all the inputs must be the
+//! wallet's own and their private keys must be available to its signers.
+//!
+//! To receive, a [`SilentPaymentIndex`] scans blocks, e.g. the ones emitted by
+//! `bdk_bitcoind_rpc::Emitter`, with [`SilentPaymentIndex::scan_block`]. Finding an output
+//! requires the public keys of the transaction's inputs, so the scan needs the previous outputs of
+//! the transactions paying taproot outputs. They are looked up with a function, e.g. in a
+//! [`TxGraph`] with [`TxGraph::get_txout`] or from the chain source (bitcoind's
+//! `getrawtransaction` with `-txindex`, or `getblock` with verbosity 3). A scan fails with
+//! [`MissingPrevout`] if a previous output is unknown, rather than missing a payment.
+//!
+//! The index is also an [`Indexer`], so that it can be used in an [`IndexedTxGraph`] which keeps
+//! the transactions creating or spending the outputs found, with
+//! [`IndexedTxGraph::apply_block_relevant`].
+//!
+//! ```
+//! # use bdk::bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+//! # use bdk::bitcoin::{Block, Network, OutPoint, TxOut};
+//! # use bdk::chain::{ConfirmationHeightAnchor, IndexedTxGraph};
+//! use bdk::silent_payments::SilentPaymentIndex;
+//!
+//! # let secp = Secp256k1::new();
+//! # let scan_secret = SecretKey::from_slice(&[1; 32]).unwrap();
+//! # let spend_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap());
+//! # let get_prevout = |_: OutPoint| -> Option<TxOut> { None };
+//! let index = SilentPaymentIndex::new(scan_secret, spend_key, Network::Testnet);
+//! println!("Pay me at {}", index.address());
+//!
+//! let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, _>::new(index);
+//! # let blocks: Vec<(Block, u32)> = vec![];
+//! for (block, height) in blocks {
+//!     // e.g. `getrawtransaction` of the chain source
+//!     let _changeset = graph.index.scan_block(&block, get_prevout)?;
+//!     let _ = graph.apply_block_relevant(block, height);
+//! }
+//! for (outpoint, output) in graph.index.outputs() {
+//!     println!("Received {} (tweak {:?})", outpoint, output.tweak);
+//! }
+//! # Ok::<_, bdk::silent_payments::MissingPrevout>(())
+//! ```
+//!
+//! [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+//! [`TxBuilder::add_silent_payment_recipient`]: crate::wallet::tx_builder::TxBuilder::add_silent_payment_recipient
+//! [`TxGraph`]: bdk_chain::TxGraph
+//! [`TxGraph::get_txout`]: bdk_chain::TxGraph::get_txout
+//! [`IndexedTxGraph`]: bdk_chain::IndexedTxGraph
+//! [`Indexer`]: bdk_chain::indexed_tx_graph::Indexer
+//! [`IndexedTxGraph::apply_block_relevant`]: bdk_chain::IndexedTxGraph::apply_block_relevant
+
+use crate::collections::{BTreeMap, HashMap};
+use alloc::string::String;
+use alloc::vec::Vec;
+use bdk_chain::indexed_tx_graph::Indexer;
+use bdk_chain::Append;
+use bitcoin::bech32::{self, FromBase32, ToBase32, Variant};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{hash160, sha256, Hash, HashEngine};
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{
+    self, All, Parity, PublicKey, Scalar, Secp256k1, SecretKey, Signing, Verification,
+    XOnlyPublicKey,
+};
+use bitcoin::{Block, Network, OutPoint, Script, ScriptBuf, Transaction, TxIn, TxOut, Txid};
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// The x coordinate of the BIP341 NUMS point `H`, the internal key of script path only outputs.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// A silent payment address, i.e. a scan and a spend public key.
+///
+/// It is displayed and parsed as a bech32m string starting with `sp1` on mainnet, `tsp1` on
+/// testnet and signet and `sprt1` on regtest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SilentPaymentAddress {
+    /// The network the address is meant for. Addresses for testnet and signet are the same, and
+    /// parsed as testnet ones.
+    pub network: Network,
+    /// The key the receiver scans the transactions with.
+    pub scan_key: PublicKey,
+    /// The key the receiver spends the outputs with, tweaked by the sender for each output.
+    pub spend_key: PublicKey,
+}
+
+impl SilentPaymentAddress {
+    /// Create the address of the given scan and spend keys.
+    pub fn new(scan_key: PublicKey, spend_key: PublicKey, network: Network) -> Self {
+        Self {
+            network,
+            scan_key,
+            spend_key,
+        }
+    }
+
+    /// Create the address of the given keys with the label `m`.
+    ///
+    /// Labels let the receiver tell the payments to different addresses apart while scanning for
+    /// all of them at once. The label `0` is reserved for change.
+    pub fn new_labeled<C: Signing + Verification>(
+        secp: &Secp256k1<C>,
+        scan_secret: &SecretKey,
+        spend_key: PublicKey,
+        m: u32,
+        network: Network,
+    ) -> Self {
+        let spend_key = spend_key
+            .add_exp_tweak(secp, &Scalar::from(label_tweak(scan_secret, m)))
+            .expect("the label tweak is a hash");
+        Self::new(scan_secret.public_key(secp), spend_key, network)
+    }
+
+    /// Whether the address can be used on `network`.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        hrp(self.network) == hrp(network)
+    }
+
+    /// The script pubkey the wallet uses in place of the output until the inputs are selected.
+    pub(crate) fn placeholder_script(&self) -> ScriptBuf {
+        taproot_script(self.spend_key.x_only_public_key().0)
+    }
+}
+
+fn hrp(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "sp",
+        Network::Regtest => "sprt",
+        _ => "tsp",
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = vec![bech32::u5::try_from_u8(0).expect("0 is a valid u5")];
+        let mut keys = self.scan_key.serialize().to_vec();
+        keys.extend_from_slice(&self.spend_key.serialize());
+        data.extend(keys.to_base32());
+        let address =
+            bech32::encode(hrp(self.network), data, Variant::Bech32m).map_err(|_| fmt::Error)?;
+        f.write_str(&address)
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = ParseAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, data, variant) = bech32::decode(s).map_err(ParseAddressError::Bech32)?;
+        let network = match hrp.as_str() {
+            "sp" => Network::Bitcoin,
+            "tsp" => Network::Testnet,
+            "sprt" => Network::Regtest,
+            _ => return Err(ParseAddressError::UnknownHrp(hrp)),
+        };
+        if variant != Variant::Bech32m {
+            return Err(ParseAddressError::NotBech32m);
+        }
+        let (version, data) = data
+            .split_first()
+            .ok_or(ParseAddressError::InvalidLength(0))?;
+        let version = version.to_u8();
+        let data = Vec::<u8>::from_base32(data).map_err(ParseAddressError::Bech32)?;
+        // later versions may append data, which is ignored
+        match version {
+            0 if data.len() != 66 => return Err(ParseAddressError::InvalidLength(data.len())),
+            1..=30 if data.len() < 66 => return Err(ParseAddressError::InvalidLength(data.len())),
+            31 => return Err(ParseAddressError::UnknownVersion(version)),
+            _ => {}
+        }
+        let scan_key = PublicKey::from_slice(&data[..33]).map_err(ParseAddressError::InvalidKey)?;
+        let spend_key =
+            PublicKey::from_slice(&data[33..66]).map_err(ParseAddressError::InvalidKey)?;
+        Ok(Self::new(scan_key, spend_key, network))
+    }
+}
+
+/// Error parsing a [`SilentPaymentAddress`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseAddressError {
+    /// The string is not valid bech32
+    Bech32(bech32::Error),
+    /// The human readable part is not the one of a silent payment address
+    UnknownHrp(String),
+    /// The address is encoded with bech32 instead of bech32m
+    NotBech32m,
+    /// The address version is not supported
+    UnknownVersion(u8),
+    /// The address doesn't contain two keys, the length of its data is given
+    InvalidLength(usize),
+    /// One of the keys is invalid
+    InvalidKey(secp256k1::Error),
+}
+
+impl fmt::Display for ParseAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bech32(e) => write!(f, "Invalid bech32: {}", e),
+            Self::UnknownHrp(hrp) => write!(f, "Unknown silent payment address prefix `{}`", hrp),
+            Self::NotBech32m => write!(f, "Silent payment addresses must be encoded with bech32m"),
+            Self::UnknownVersion(version) => {
+                write!(f, "Unknown silent payment address version {}", version)
+            }
+            Self::InvalidLength(len) => write!(f, "Invalid silent payment address length {}", len),
+            Self::InvalidKey(e) => write!(f, "Invalid silent payment address key: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseAddressError {}
+
+/// Error computing the outputs of silent payments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// The address is meant for another network, the one given
+    InvalidNetwork(Network),
+    /// The private key of the input isn't known, the wallet must own all the inputs
+    ForeignInput(OutPoint),
+    /// None of the wallet's signers has the private key of the input
+    MissingPrivateKey(OutPoint),
+    /// None of the inputs is of a type whose key can be used for silent payments, i.e. P2TR, P2WPKH,
+    /// P2SH-P2WPKH or P2PKH with a compressed key
+    NoEligibleInputs,
+    /// The keys of the inputs cancel out, the transaction must spend other inputs
+    InvalidInputKeys,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidNetwork(network) => {
+                write!(f, "Silent payment address is for network {}", network)
+            }
+            Self::ForeignInput(outpoint) => write!(
+                f,
+                "Cannot send silent payments spending the foreign input {}",
+                outpoint
+            ),
+            Self::MissingPrivateKey(outpoint) => {
+                write!(f, "Missing the private key of input {}", outpoint)
+            }
+            Self::NoEligibleInputs => write!(f, "No input can be used to send silent payments"),
+            Self::InvalidInputKeys => write!(f, "The keys of the inputs sum to zero"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SendError {}
+
+/// Error returned when scanning a transaction whose previous output is unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingPrevout(pub OutPoint);
+
+impl fmt::Display for MissingPrevout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Previous output {} of a scanned transaction is unknown",
+            self.0
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MissingPrevout {}
+
+/// Compute the taproot output keys paying the silent payment `recipients`.
+///
+/// `outpoints` are the outpoints of all the inputs of the transaction and `input_keys` the private
+/// keys of its eligible inputs, i.e. the P2TR, P2WPKH, P2SH-P2WPKH and compressed P2PKH ones. The
+/// keys of P2TR inputs are the tweaked output keys, negated if their public key has an odd y
+/// coordinate. The output keys are returned in the order of `recipients`.
+///
+/// The transaction must not spend any other input, or the receivers won't find their outputs.
+pub fn sender_output_keys<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    outpoints: &[OutPoint],
+    input_keys: &[SecretKey],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<XOnlyPublicKey>, SendError> {
+    let (first, others) = input_keys
+        .split_first()
+        .ok_or(SendError::NoEligibleInputs)?;
+    let input_key = others
+        .iter()
+        .try_fold(*first, |sum, key| sum.add_tweak(&Scalar::from(*key)))
+        .map_err(|_| SendError::InvalidInputKeys)?;
+    let input_hash =
+        input_hash(outpoints, &input_key.public_key(secp)).ok_or(SendError::InvalidInputKeys)?;
+    let input_key = input_key
+        .mul_tweak(&input_hash)
+        .map_err(|_| SendError::InvalidInputKeys)?;
+
+    let mut counts = BTreeMap::<PublicKey, u32>::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let k = counts.entry(recipient.scan_key).or_default();
+            let shared_secret = recipient
+                .scan_key
+                .mul_tweak(secp, &Scalar::from(input_key))
+                .map_err(|_| SendError::InvalidInputKeys)?;
+            let tweak = shared_secret_tweak(&shared_secret, *k);
+            *k += 1;
+            let output_key = recipient
+                .spend_key
+                .add_exp_tweak(secp, &Scalar::from(tweak))
+                .map_err(|_| SendError::InvalidInputKeys)?;
+            Ok(output_key.x_only_public_key().0)
+        })
+        .collect()
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// The hash committing to the inputs, or `None` if there are no inputs.
+fn input_hash(outpoints: &[OutPoint], input_key: &PublicKey) -> Option<Scalar> {
+    let smallest_outpoint = outpoints.iter().map(serialize).min()?;
+    let hash = tagged_hash(
+        "BIP0352/Inputs",
+        &[&smallest_outpoint, &input_key.serialize()],
+    );
+    Scalar::from_be_bytes(hash).ok()
+}
+
+/// The tweak of the `k`-th output for the same scan key, as a secret key so it can be summed.
+fn shared_secret_tweak(shared_secret: &PublicKey, k: u32) -> SecretKey {
+    let hash = tagged_hash(
+        "BIP0352/SharedSecret",
+        &[&shared_secret.serialize(), &k.to_be_bytes()],
+    );
+    SecretKey::from_slice(&hash).expect("the hash is a valid secret key")
+}
+
+fn label_tweak(scan_secret: &SecretKey, m: u32) -> SecretKey {
+    let hash = tagged_hash(
+        "BIP0352/Label",
+        &[&scan_secret.secret_bytes(), &m.to_be_bytes()],
+    );
+    SecretKey::from_slice(&hash).expect("the hash is a valid secret key")
+}
+
+fn taproot_script(key: XOnlyPublicKey) -> ScriptBuf {
+    ScriptBuf::new_v1_p2tr_tweaked(bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(
+        key,
+    ))
+}
+
+fn compressed_key(bytes: &[u8]) -> Option<PublicKey> {
+    if bytes.len() == 33 {
+        PublicKey::from_slice(bytes).ok()
+    } else {
+        None
+    }
+}
+
+/// The public key of an eligible input spending `prevout`, `None` if the input is not eligible.
+fn input_public_key(txin: &TxIn, prevout: &Script) -> Option<PublicKey> {
+    if prevout.is_v1_p2tr() {
+        let mut witness = txin.witness.to_vec();
+        if witness.len() > 1 && witness.last().and_then(|e| e.first()) == Some(&0x50) {
+            // drop the annex
+            witness.pop();
+        }
+        if witness.len() > 1 {
+            // script path spends of script only outputs have no key
+            let control_block = witness.last()?;
+            if control_block.get(1..33) == Some(&NUMS_H[..]) {
+                return None;
+            }
+        }
+        let key = XOnlyPublicKey::from_slice(&prevout.as_bytes()[2..34]).ok()?;
+        Some(PublicKey::from_x_only_public_key(key, Parity::Even))
+    } else if prevout.is_v0_p2wpkh() {
+        compressed_key(txin.witness.last()?)
+    } else if prevout.is_p2sh() {
+        let redeem_script = txin.script_sig.instructions().last()?.ok()?;
+        if Script::from_bytes(redeem_script.push_bytes()?.as_bytes()).is_v0_p2wpkh() {
+            compressed_key(txin.witness.last()?)
+        } else {
+            None
+        }
+    } else if prevout.is_p2pkh() {
+        let pubkey_hash = &prevout.as_bytes()[3..23];
+        txin.script_sig
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes()),
+                _ => None,
+            })
+            .filter(|bytes| hash160::Hash::hash(bytes)[..] == *pubkey_hash)
+            .find_map(compressed_key)
+    } else {
+        None
+    }
+}
+
+/// An output received with silent payments, found by a [`SilentPaymentIndex`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SilentPaymentOutput {
+    /// The tweak added to the spend key, including the one of the label.
+    pub tweak: SecretKey,
+    /// The label of the address the output pays.
+    pub label: Option<u32>,
+}
+
+impl SilentPaymentOutput {
+    /// The private key of the output, given the private spend key.
+    pub fn spending_key(&self, spend_secret: &SecretKey) -> SecretKey {
+        spend_secret
+            .add_tweak(&Scalar::from(self.tweak))
+            .expect("the output key is not the point at infinity")
+    }
+}
+
+/// The changes made to a [`SilentPaymentIndex`], i.e. the outputs it found.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeSet(pub BTreeMap<OutPoint, SilentPaymentOutput>);
+
+impl Append for ChangeSet {
+    fn append(&mut self, other: Self) {
+        self.0.extend(other.0)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// An [`Indexer`] finding the outputs paying a silent payment address, and its labeled addresses.
+///
+/// See the [module documentation](self) for how it is meant to be used.
+#[derive(Debug)]
+pub struct SilentPaymentIndex {
+    secp: Secp256k1<All>,
+    network: Network,
+    scan_secret: SecretKey,
+    spend_key: PublicKey,
+    labels: HashMap<PublicKey, (u32, SecretKey)>,
+    outputs: BTreeMap<OutPoint, SilentPaymentOutput>,
+}
+
+impl SilentPaymentIndex {
+    /// Create an index finding the payments to the address of `scan_secret` and `spend_key`.
+    pub fn new(scan_secret: SecretKey, spend_key: PublicKey, network: Network) -> Self {
+        Self {
+            secp: Secp256k1::new(),
+            network,
+            scan_secret,
+            spend_key,
+            labels: HashMap::new(),
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// The unlabeled address of the index.
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress::new(
+            self.scan_secret.public_key(&self.secp),
+            self.spend_key,
+            self.network,
+        )
+    }
+
+    /// Also find the payments to the address with label `m`, returning the address.
+    ///
+    /// The outputs found before the label was added are not found again.
+    pub fn add_label(&mut self, m: u32) -> SilentPaymentAddress {
+        let tweak = label_tweak(&self.scan_secret, m);
+        self.labels.insert(tweak.public_key(&self.secp), (m, tweak));
+        SilentPaymentAddress::new_labeled(
+            &self.secp,
+            &self.scan_secret,
+            self.spend_key,
+            m,
+            self.network,
+        )
+    }
+
+    /// The outputs found by the index, spent or not.
+    pub fn outputs(&self) -> &BTreeMap<OutPoint, SilentPaymentOutput> {
+        &self.outputs
+    }
+
+    /// Find the outputs of `tx` paying the index's addresses, and add them to the index.
+    ///
+    /// `prevout` returns the previous outputs spent by `tx`. It is only called for transactions
+    /// which may pay silent payments, i.e. which have taproot outputs. Returns the outputs found, or
+    /// [`MissingPrevout`] if `prevout` doesn't know one of the previous outputs.
+    pub fn scan_tx<F>(
+        &mut self,
+        tx: &Transaction,
+        mut prevout: F,
+    ) -> Result<ChangeSet, MissingPrevout>
+    where
+        F: FnMut(OutPoint) -> Option<TxOut>,
+    {
+        let changeset = self.find_outputs(tx, &mut prevout)?;
+        self.apply_changeset(changeset.clone());
+        Ok(changeset)
+    }
+
+    /// Find the outputs of the transactions of `block` paying the index's addresses, and add them
+    /// to the index.
+    ///
+    /// Previous outputs created in `block` are found in the block, the others are returned by
+    /// `prevout`, see [`scan_tx`](Self::scan_tx). Nothing is added to the index if a previous output
+    /// is unknown.
+    pub fn scan_block<F>(
+        &mut self,
+        block: &Block,
+        mut prevout: F,
+    ) -> Result<ChangeSet, MissingPrevout>
+    where
+        F: FnMut(OutPoint) -> Option<TxOut>,
+    {
+        let mut block_outputs = HashMap::<OutPoint, TxOut>::new();
+        let mut changeset = ChangeSet::default();
+        for tx in &block.txdata {
+            changeset.append(self.find_outputs(tx, &mut |outpoint| {
+                block_outputs
+                    .get(&outpoint)
+                    .cloned()
+                    .or_else(|| prevout(outpoint))
+            })?);
+            let txid = tx.txid();
+            block_outputs.extend(
+                (0u32..)
+                    .zip(&tx.output)
+                    .map(|(vout, txout)| (OutPoint::new(txid, vout), txout.clone())),
+            );
+        }
+        self.apply_changeset(changeset.clone());
+        Ok(changeset)
+    }
+
+    fn find_outputs(
+        &self,
+        tx: &Transaction,
+        prevout: &mut dyn FnMut(OutPoint) -> Option<TxOut>,
+    ) -> Result<ChangeSet, MissingPrevout> {
+        let mut changeset = ChangeSet::default();
+        if tx.is_coin_base() || !tx.output.iter().any(|o| o.script_pubkey.is_v1_p2tr()) {
+            return Ok(changeset);
+        }
+
+        let mut prevouts = Vec::with_capacity(tx.input.len());
+        for txin in &tx.input {
+            let txout =
+                prevout(txin.previous_output).ok_or(MissingPrevout(txin.previous_output))?;
+            prevouts.push(txout.script_pubkey);
+        }
+        let mut input_keys = Vec::new();
+        for (txin, prevout) in tx.input.iter().zip(&prevouts) {
+            if prevout.witness_version().map_or(false, |v| v.to_num() > 1) {
+                return Ok(changeset);
+            }
+            input_keys.extend(input_public_key(txin, prevout));
+        }
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let shared_secret = match self.shared_secret(&outpoints, &input_keys) {
+            Some(shared_secret) => shared_secret,
+            None => return Ok(changeset),
+        };
+
+        let txid = tx.txid();
+        let mut outputs = tx
+            .output
+            .iter()
+            .zip(0u32..)
+            .filter(|(txout, _)| txout.script_pubkey.is_v1_p2tr())
+            .filter_map(|(txout, vout)| {
+                let key = XOnlyPublicKey::from_slice(&txout.script_pubkey.as_bytes()[2..34]);
+                Some((vout, key.ok()?))
+            })
+            .collect::<Vec<_>>();
+        for k in 0.. {
+            let tweak = shared_secret_tweak(&shared_secret, k);
+            let output_key = self
+                .spend_key
+                .add_exp_tweak(&self.secp, &Scalar::from(tweak))
+                .expect("the tweak is a hash");
+            let found = outputs.iter().enumerate().find_map(|(i, &(vout, key))| {
+                let output = self.match_output(key, output_key, tweak)?;
+                Some((i, vout, output))
+            });
+            match found {
+                Some((i, vout, output)) => {
+                    outputs.remove(i);
+                    changeset.0.insert(OutPoint::new(txid, vout), output);
+                }
+                None => break,
+            }
+        }
+        Ok(changeset)
+    }
+
+    /// The ECDH shared secret of the inputs with the scan key.
+    fn shared_secret(&self, outpoints: &[OutPoint], input_keys: &[PublicKey]) -> Option<PublicKey> {
+        let input_key = PublicKey::combine_keys(&input_keys.iter().collect::<Vec<_>>()).ok()?;
+        let input_hash = input_hash(outpoints, &input_key)?;
+        input_key
+            .mul_tweak(&self.secp, &input_hash)
+            .and_then(|key| key.mul_tweak(&self.secp, &Scalar::from(self.scan_secret)))
+            .ok()
+    }
+
+    /// Whether the output `key` pays `output_key`, or its tweak by one of the labels.
+    fn match_output(
+        &self,
+        key: XOnlyPublicKey,
+        output_key: PublicKey,
+        tweak: SecretKey,
+    ) -> Option<SilentPaymentOutput> {
+        if key == output_key.x_only_public_key().0 {
+            return Some(SilentPaymentOutput { tweak, label: None });
+        }
+        if self.labels.is_empty() {
+            return None;
+        }
+        let key = PublicKey::from_x_only_public_key(key, Parity::Even);
+        let output_key = output_key.negate(&self.secp);
+        [key, key.negate(&self.secp)]
+            .iter()
+            .filter_map(|key| key.combine(&output_key).ok())
+            .find_map(|label_key| self.labels.get(&label_key))
+            .map(|&(m, label_tweak)| SilentPaymentOutput {
+                tweak: tweak
+                    .add_tweak(&Scalar::from(label_tweak))
+                    .expect("the tweaks are hashes"),
+                label: Some(m),
+            })
+    }
+
+    /// Whether some of the outputs found were created by `txid`.
+    fn has_outputs_of(&self, txid: Txid) -> bool {
+        self.outputs
+            .range(OutPoint::new(txid, 0)..=OutPoint::new(txid, u32::MAX))
+            .next()
+            .is_some()
+    }
+}
+
+impl Indexer for SilentPaymentIndex {
+    type ChangeSet = ChangeSet;
+
+    fn index_txout(&mut self, _outpoint: OutPoint, _txout: &TxOut) -> Self::ChangeSet {
+        ChangeSet::default()
+    }
+
+    /// Transactions are not scanned when indexed, see [`SilentPaymentIndex::scan_tx`].
+    fn index_tx(&mut self, _tx: &Transaction) -> Self::ChangeSet {
+        ChangeSet::default()
+    }
+
+    fn apply_changeset(&mut self, changeset: Self::ChangeSet) {
+        self.outputs.extend(changeset.0)
+    }
+
+    fn initial_changeset(&self) -> Self::ChangeSet {
+        ChangeSet(self.outputs.clone())
+    }
+
+    fn is_tx_relevant(&self, tx: &Transaction) -> bool {
+        self.has_outputs_of(tx.txid())
+            || tx
+                .input
+                .iter()
+                .any(|txin| self.outputs.contains_key(&txin.previous_output))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+    use bitcoin::hash_types::TxMerkleNode;
+    use bitcoin::{absolute, block, BlockHash, CompactTarget, Sequence, Witness};
+
+    fn secret_key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn spend(outpoint: OutPoint, witness: Witness) -> TxIn {
+        TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness,
+        }
+    }
+
+    fn tx(input: Vec<TxIn>, output: Vec<ScriptBuf>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::ZERO,
+            input,
+            output: output
+                .into_iter()
+                .map(|script_pubkey| TxOut {
+                    value: 10_000,
+                    script_pubkey,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn address_round_trip() {
+        let secp = Secp256k1::new();
+        let address = SilentPaymentAddress::new(
+            secret_key(1).public_key(&secp),
+            secret_key(2).public_key(&secp),
+            Network::Bitcoin,
+        );
+        let encoded = address.to_string();
+        assert!(encoded.starts_with("sp1q"));
+        assert_eq!(encoded.len(), 116);
+        assert_eq!(encoded.parse::<SilentPaymentAddress>().unwrap(), address);
+
+        let regtest = SilentPaymentAddress {
+            network: Network::Regtest,
+            ..address
+        };
+        assert!(regtest.to_string().starts_with("sprt1q"));
+        let signet = SilentPaymentAddress {
+            network: Network::Signet,
+            ..address
+        };
+        let parsed = signet.to_string().parse::<SilentPaymentAddress>().unwrap();
+        assert_eq!(parsed.network, Network::Testnet);
+        assert!(parsed.is_valid_for_network(Network::Signet));
+        assert!(!parsed.is_valid_for_network(Network::Bitcoin));
+    }
+
+    #[test]
+    fn address_parse_errors() {
+        let secp = Secp256k1::new();
+        let mut keys = secret_key(1).public_key(&secp).serialize().to_vec();
+        keys.extend_from_slice(&secret_key(2).public_key(&secp).serialize());
+        let encode = |hrp: &str, version: u8, data: &[u8], variant: Variant| {
+            let mut u5s = vec![bech32::u5::try_from_u8(version).unwrap()];
+            u5s.extend(data.to_base32());
+            bech32::encode(hrp, u5s, variant).unwrap()
+        };
+
+        assert_eq!(
+            encode("bc", 0, &keys, Variant::Bech32m).parse::<SilentPaymentAddress>(),
+            Err(ParseAddressError::UnknownHrp("bc".into()))
+        );
+        assert_eq!(
+            encode("sp", 0, &keys, Variant::Bech32).parse::<SilentPaymentAddress>(),
+            Err(ParseAddressError::NotBech32m)
+        );
+        assert_eq!(
+            encode("sp", 31, &keys, Variant::Bech32m).parse::<SilentPaymentAddress>(),
+            Err(ParseAddressError::UnknownVersion(31))
+        );
+        assert_eq!(
+            encode("sp", 0, &keys[..65], Variant::Bech32m).parse::<SilentPaymentAddress>(),
+            Err(ParseAddressError::InvalidLength(65))
+        );
+        // later versions can add data
+        let mut longer = keys.clone();
+        longer.push(0);
+        assert_eq!(
+            encode("sp", 0, &longer, Variant::Bech32m).parse::<SilentPaymentAddress>(),
+            Err(ParseAddressError::InvalidLength(67))
+        );
+        assert!(encode("sp", 1, &longer, Variant::Bech32m)
+            .parse::<SilentPaymentAddress>()
+            .is_ok());
+    }
+
+    #[test]
+    fn send_and_receive() {
+        let secp = Secp256k1::new();
+        let (scan_secret, spend_secret) = (secret_key(1), secret_key(2));
+        let mut index = SilentPaymentIndex::new(
+            scan_secret,
+            spend_secret.public_key(&secp),
+            Network::Regtest,
+        );
+        let address = index.address();
+        let labeled_address = index.add_label(1);
+        assert_ne!(address, labeled_address);
+
+        // the previous outputs: a P2WPKH one and a P2TR one
+        let (wpkh_key, tr_key) = (secret_key(3), secret_key(4));
+        let wpkh_pubkey = bitcoin::PublicKey::new(wpkh_key.public_key(&secp));
+        let (tr_pubkey, parity) = tr_key.x_only_public_key(&secp);
+        let prev_tx = tx(
+            vec![spend(OutPoint::null(), Witness::new())],
+            vec![
+                ScriptBuf::new_v0_p2wpkh(&wpkh_pubkey.wpubkey_hash().unwrap()),
+                taproot_script(tr_pubkey),
+            ],
+        );
+        let prevout = |outpoint: OutPoint| {
+            assert_eq!(outpoint.txid, prev_tx.txid());
+            prev_tx.output.get(outpoint.vout as usize).cloned()
+        };
+        assert!(index.scan_tx(&prev_tx, |_| None).unwrap().is_empty());
+
+        let tr_key = match parity {
+            Parity::Even => tr_key,
+            Parity::Odd => tr_key.negate(),
+        };
+        let outpoints = [
+            OutPoint::new(prev_tx.txid(), 0),
+            OutPoint::new(prev_tx.txid(), 1),
+        ];
+        let output_keys = sender_output_keys(
+            &secp,
+            &outpoints,
+            &[wpkh_key, tr_key],
+            &[address, address, labeled_address],
+        )
+        .unwrap();
+        assert_eq!(output_keys.len(), 3);
+        assert_ne!(output_keys[0], output_keys[1]);
+
+        let tx = tx(
+            vec![
+                spend(
+                    outpoints[0],
+                    Witness::from_slice(&[vec![0; 71], wpkh_pubkey.to_bytes()]),
+                ),
+                spend(outpoints[1], Witness::from_slice(&[vec![0; 64]])),
+            ],
+            vec![
+                taproot_script(output_keys[2]),
+                ScriptBuf::new_v0_p2wpkh(&wpkh_pubkey.wpubkey_hash().unwrap()),
+                taproot_script(output_keys[1]),
+                taproot_script(output_keys[0]),
+            ],
+        );
+        let changeset = index.scan_tx(&tx, prevout).unwrap();
+        assert_eq!(changeset.0.len(), 3);
+        assert!(index.index_tx(&tx).is_empty());
+        assert!(index.is_tx_relevant(&tx));
+        assert!(!index.is_tx_relevant(&prev_tx));
+
+        for (vout, label) in [(0, Some(1)), (2, None), (3, None)] {
+            let output = index.outputs()[&OutPoint::new(tx.txid(), vout)];
+            assert_eq!(output.label, label);
+            let spending_key = output.spending_key(&spend_secret);
+            assert_eq!(
+                taproot_script(spending_key.x_only_public_key(&secp).0),
+                tx.output[vout as usize].script_pubkey
+            );
+        }
+
+        // transactions spending the outputs are relevant
+        let spending_tx = self::tx(
+            vec![spend(OutPoint::new(tx.txid(), 2), Witness::new())],
+            vec![ScriptBuf::new()],
+        );
+        assert!(index.is_tx_relevant(&spending_tx));
+    }
+
+    #[test]
+    fn unknown_prevouts_are_errors() {
+        let secp = Secp256k1::new();
+        let (scan_secret, spend_secret) = (secret_key(1), secret_key(2));
+        let mut index = SilentPaymentIndex::new(
+            scan_secret,
+            spend_secret.public_key(&secp),
+            Network::Regtest,
+        );
+        let input_key = secret_key(3);
+        let (input_pubkey, parity) = input_key.x_only_public_key(&secp);
+        let input_key = match parity {
+            Parity::Even => input_key,
+            Parity::Odd => input_key.negate(),
+        };
+        let prev_tx = tx(
+            vec![spend(OutPoint::null(), Witness::new())],
+            vec![taproot_script(input_pubkey)],
+        );
+        let outpoint = OutPoint::new(prev_tx.txid(), 0);
+        let output_keys =
+            sender_output_keys(&secp, &[outpoint], &[input_key], &[index.address()]).unwrap();
+        let tx = tx(
+            vec![spend(outpoint, Witness::from_slice(&[vec![0; 64]]))],
+            vec![taproot_script(output_keys[0])],
+        );
+
+        assert_eq!(index.scan_tx(&tx, |_| None), Err(MissingPrevout(outpoint)));
+        let mut block = Block {
+            header: block::Header {
+                version: block::Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0),
+                nonce: 0,
+            },
+            txdata: vec![tx.clone()],
+        };
+        assert_eq!(
+            index.scan_block(&block, |_| None),
+            Err(MissingPrevout(outpoint))
+        );
+        assert!(index.outputs().is_empty());
+
+        // previous outputs created in the same block are known
+        block.txdata.insert(0, prev_tx);
+        let changeset = index.scan_block(&block, |_| None).unwrap();
+        assert_eq!(changeset.0.len(), 1);
+        assert!(index.is_tx_relevant(&tx));
+    }
+
+    fn from_hex<T: bitcoin::consensus::Decodable>(hex: &str) -> T {
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+        bitcoin::consensus::deserialize(&bytes).unwrap()
+    }
+
+    fn secret_key_hex(hex: &str) -> SecretKey {
+        SecretKey::from_str(hex).unwrap()
+    }
+
+    /// Vectors of <https://github.com/bitcoin/bips/blob/master/bip-0352/send_and_receive_test_vectors.json>.
+    #[test]
+    fn bip352_test_vectors() {
+        let secp = Secp256k1::new();
+        let outpoints: [OutPoint; 2] = [
+            OutPoint::new(
+                from_hex("169e1e83e930853391bc6f35f605c6754cfead57cf8387639d3b4096c54f18f4"),
+                0,
+            ),
+            OutPoint::new(
+                from_hex("8dd4f5fbd5e980fc02f35c6ce145935b11e284605bf599a13c6d415db55d07a1"),
+                0,
+            ),
+        ];
+        let scan_secret =
+            secret_key_hex("0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c");
+        let spend_secret =
+            secret_key_hex("9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3");
+        let address: SilentPaymentAddress = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+            .parse()
+            .unwrap();
+        let mut index = SilentPaymentIndex::new(
+            scan_secret,
+            spend_secret.public_key(&secp),
+            Network::Bitcoin,
+        );
+        assert_eq!(index.address(), address);
+
+        // "Simple send: two inputs", with P2WPKH inputs, in both orders
+        let input_keys = [
+            secret_key_hex("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1"),
+            secret_key_hex("93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16"),
+        ];
+        let expected = XOnlyPublicKey::from_str(
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+        )
+        .unwrap();
+        let reversed = [input_keys[1], input_keys[0]];
+        for input_keys in [&input_keys, &reversed] {
+            let output_keys =
+                sender_output_keys(&secp, &outpoints, input_keys, &[address]).unwrap();
+            assert_eq!(output_keys, [expected]);
+        }
+        let pubkeys = input_keys.map(|key| bitcoin::PublicKey::new(key.public_key(&secp)));
+        let tx = tx(
+            outpoints
+                .iter()
+                .zip(&pubkeys)
+                .map(|(outpoint, pubkey)| {
+                    spend(
+                        *outpoint,
+                        Witness::from_slice(&[vec![0; 71], pubkey.to_bytes()]),
+                    )
+                })
+                .collect(),
+            vec![taproot_script(expected)],
+        );
+        let changeset = index
+            .scan_tx(&tx, |outpoint| {
+                let pubkey = pubkeys[outpoints.iter().position(|o| *o == outpoint)?];
+                Some(TxOut {
+                    value: 100_000,
+                    script_pubkey: ScriptBuf::new_v0_p2wpkh(&pubkey.wpubkey_hash().unwrap()),
+                })
+            })
+            .unwrap();
+        let output = changeset.0[&OutPoint::new(tx.txid(), 0)];
+        assert_eq!(
+            output.tweak,
+            secret_key_hex("f438b40179a3c4262de12986c0e6cce0634007cdc79c1dcd3e20b9ebc2e7eef6")
+        );
+
+        // "Taproot only inputs with even y-values"
+        let input_keys = [
+            secret_key_hex("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1"),
+            secret_key_hex("fc8716a97a48ba9a05a98ae47b5cd201a25a7fd5d8b73c203c5f7b6b6b3b6ad7"),
+        ];
+        let output_keys = sender_output_keys(&secp, &outpoints, &input_keys, &[address]).unwrap();
+        assert_eq!(
+            output_keys,
+            [XOnlyPublicKey::from_str(
+                "de88bea8e7ffc9ce1af30d1132f910323c505185aec8eae361670421e749a1fb"
+            )
+            .unwrap()]
+        );
+    }
+}
@@ -22,31 +22,96 @@ use serde::{Deserialize, Serialize};
 /// Types of keychains
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub enum KeychainKind {
+    // `External` and `Internal` used to have the explicit discriminants `0` and `1`, which can't be
+    // combined with the data of `Custom` on our MSRV. They keep their serialized variant indices.
     /// External keychain, used for deriving recipient addresses.
-    External = 0,
+    External,
     /// Internal keychain, used for deriving change addresses.
-    Internal = 1,
+    Internal,
+    /// Additional keychain identified by a number, e.g. a per-customer deposit descriptor or an
+    /// imported legacy descriptor. See [`Wallet::add_keychain`].
+    ///
+    /// [`Wallet::add_keychain`]: crate::Wallet::add_keychain
+    Custom(CustomKeychainId),
 }
 
 impl KeychainKind {
+    /// Create a [`KeychainKind::Custom`] keychain with the given `id`.
+    pub fn custom(id: u32) -> Self {
+        KeychainKind::Custom(CustomKeychainId::new(id))
+    }
+
     /// Return [`KeychainKind`] as a byte
+    ///
+    /// All [`KeychainKind::Custom`] keychains are represented by the same byte.
+    #[deprecated(
+        since = "1.0.0-alpha.3",
+        note = "a single byte can't tell custom keychains apart, use `as_ref` instead"
+    )]
     pub fn as_byte(&self) -> u8 {
-        match self {
-            KeychainKind::External => b'e',
-            KeychainKind::Internal => b'i',
-        }
+        self.as_ref()[0]
     }
 }
 
+/// The bytes are `b"e"` for [`KeychainKind::External`], `b"i"` for [`KeychainKind::Internal`] and
+/// `b"c"` followed by the big-endian id for [`KeychainKind::Custom`].
 impl AsRef<[u8]> for KeychainKind {
     fn as_ref(&self) -> &[u8] {
         match self {
             KeychainKind::External => b"e",
             KeychainKind::Internal => b"i",
+            KeychainKind::Custom(id) => &id.0,
         }
     }
 }
 
+/// The identifier of a [`KeychainKind::Custom`] keychain.
+///
+/// It is stored as the bytes [`KeychainKind::as_ref`] returns, and serialized as a `u32`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[serde(from = "u32", into = "u32")]
+pub struct CustomKeychainId([u8; 5]);
+
+impl CustomKeychainId {
+    /// Create a [`CustomKeychainId`] from its number.
+    pub fn new(id: u32) -> Self {
+        let mut bytes = [b'c'; 5];
+        bytes[1..].copy_from_slice(&id.to_be_bytes());
+        Self(bytes)
+    }
+
+    /// Get the number of the [`CustomKeychainId`].
+    pub fn get(&self) -> u32 {
+        let mut id = [0_u8; 4];
+        id.copy_from_slice(&self.0[1..]);
+        u32::from_be_bytes(id)
+    }
+}
+
+impl From<u32> for CustomKeychainId {
+    fn from(id: u32) -> Self {
+        Self::new(id)
+    }
+}
+
+impl From<CustomKeychainId> for u32 {
+    fn from(id: CustomKeychainId) -> Self {
+        id.get()
+    }
+}
+
+impl core::fmt::Debug for CustomKeychainId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+impl core::fmt::Display for CustomKeychainId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
 /// Fee rate
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
 // Internally stored as satoshi/vbyte
@@ -136,6 +201,12 @@ impl Default for FeeRate {
     }
 }
 
+impl From<bitcoin::FeeRate> for FeeRate {
+    fn from(fee_rate: bitcoin::FeeRate) -> Self {
+        FeeRate::from_sat_per_kwu(fee_rate.to_sat_per_kwu() as f32)
+    }
+}
+
 impl Sub for FeeRate {
     type Output = Self;
 
@@ -302,4 +373,28 @@ mod tests {
         assert!((fee.as_sat_per_vb() - 1.0).abs() < f32::EPSILON);
         assert_eq!(fee.sat_per_kwu(), 250.0);
     }
+
+    #[test]
+    fn test_keychain_kind_bytes() {
+        assert_eq!(KeychainKind::External.as_ref(), b"e");
+        assert_eq!(KeychainKind::Internal.as_ref(), b"i");
+        assert_eq!(KeychainKind::custom(1).as_ref(), b"c\x00\x00\x00\x01");
+        assert_ne!(
+            KeychainKind::custom(1).as_ref(),
+            KeychainKind::custom(2).as_ref()
+        );
+        assert!(KeychainKind::custom(2) < KeychainKind::custom(256));
+    }
+
+    #[test]
+    fn test_keychain_kind_serde() {
+        let keychain = KeychainKind::custom(7);
+        let json = serde_json::to_string(&keychain).unwrap();
+        assert_eq!(json, r#"{"Custom":7}"#);
+        assert_eq!(
+            serde_json::from_str::<KeychainKind>(&json).unwrap(),
+            keychain
+        );
+        assert_eq!(format!("{:?}", keychain), "Custom(7)");
+    }
 }
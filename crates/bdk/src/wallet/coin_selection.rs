@@ -78,6 +78,7 @@
 //!             selected: all_utxos_selected,
 //!             fee_amount: additional_fees,
 //!             excess,
+//!             waste: None,
 //!         })
 //!     }
 //! }
@@ -100,18 +101,25 @@
 //! # Ok::<(), anyhow::Error>(())
 //! ```
 
+use crate::collections::{BTreeMap, BTreeSet};
 use crate::types::FeeRate;
-use crate::wallet::utils::IsDust;
+use crate::wallet::utils::{script_type, IsDust};
+use crate::KeychainKind;
 use crate::Utxo;
 use crate::WeightedUtxo;
 
+use alloc::string::String;
 use alloc::vec::Vec;
+#[cfg(feature = "waste-metric")]
+use bdk_coin_select::{coin_select_bnb, CoinSelector, CoinSelectorOpt, WeightedValue};
 use bitcoin::consensus::encode::serialize;
-use bitcoin::{Script, Weight};
+use bitcoin::{AddressType, OutPoint, Script, ScriptBuf, Weight};
 
 use core::convert::TryInto;
 use core::fmt::{self, Formatter};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
 /// Default coin selection algorithm used by [`TxBuilder`](super::tx_builder::TxBuilder) if not
 /// overridden
@@ -188,6 +196,10 @@ pub struct CoinSelectionResult {
     pub fee_amount: u64,
     /// Remaining amount after deducing fees and outgoing outputs
     pub excess: Excess,
+    /// The waste metric of the selection in satoshis, if computed by the algorithm
+    ///
+    /// Only the `WasteMetricCoinSelection` algorithm of the `waste-metric` feature computes it.
+    pub waste: Option<i64>,
 }
 
 impl CoinSelectionResult {
@@ -303,6 +315,37 @@ impl CoinSelectionAlgorithm for OldestFirstCoinSelection {
     }
 }
 
+/// SmallestFirstCoinSelection always picks the utxo with the smallest value to add to the selected coins next
+///
+/// This coin selection algorithm sorts the available UTXOs by value and then picks them starting
+/// from the smallest ones until the required amount is reached. Spending many small UTXOs makes
+/// the transaction more expensive, but consolidates the wallet's UTXO set.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SmallestFirstCoinSelection;
+
+impl CoinSelectionAlgorithm for SmallestFirstCoinSelection {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        mut optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        // We put the "required UTXOs" first and make sure the optional UTXOs are sorted from
+        // smallest to largest
+        let utxos = {
+            optional_utxos.sort_unstable_by_key(|wu| wu.utxo.txout().value);
+            required_utxos
+                .into_iter()
+                .map(|utxo| (true, utxo))
+                .chain(optional_utxos.into_iter().map(|utxo| (false, utxo)))
+        };
+
+        select_sorted_utxos(utxos, fee_rate, target_amount, drain_script)
+    }
+}
+
 /// Decide if change can be created
 ///
 /// - `remaining_amount`: the amount in which the selected coins exceed the target amount
@@ -370,6 +413,7 @@ fn select_sorted_utxos(
         selected,
         fee_amount,
         excess,
+        waste: None,
     })
 }
 
@@ -707,207 +751,1145 @@ impl BranchAndBoundCoinSelection {
             selected,
             fee_amount,
             excess,
+            waste: None,
         }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use assert_matches::assert_matches;
-    use core::str::FromStr;
-
-    use bdk_chain::ConfirmationTime;
-    use bitcoin::{OutPoint, ScriptBuf, TxOut};
-
-    use super::*;
-    use crate::types::*;
-    use crate::wallet::Vbytes;
-
-    use rand::rngs::StdRng;
-    use rand::seq::SliceRandom;
-    use rand::{Rng, RngCore, SeedableRng};
-
-    // n. of items on witness (1WU) + signature len (1WU) + signature and sighash (72WU)
-    // + pubkey len (1WU) + pubkey (33WU) + script sig len (1 byte, 4WU)
-    const P2WPKH_SATISFACTION_SIZE: usize = 1 + 1 + 72 + 1 + 33 + 4;
+/// Branch and bound coin selection minimizing the waste metric
+///
+/// The waste of a selection is what its inputs cost now compared to spending them at the long
+/// term fee rate, plus the cost of the change output (creating it now and spending it later), or
+/// the excess going to fees if there's no change:
+///
+/// `waste = weight(inputs) * (fee_rate - long_term_fee_rate) + (change_cost or excess)`
+///
+/// When the fee rate is lower than the long term fee rate, selections with more inputs have less
+/// waste, so the wallet consolidates its UTXOs while fees are cheap. If branch and bound can't
+/// find a solution, the optional UTXOs are selected from the largest one.
+///
+/// The search is done by [`bdk_coin_select`]'s [`coin_select_bnb`](bdk_coin_select::coin_select_bnb),
+/// and the waste of the selection is reported in [`CoinSelectionResult::waste`]. Whether to create
+/// the change output is decided as with the other algorithms, see [`decide_change`].
+///
+/// This algorithm is only available with the `waste-metric` feature.
+#[cfg(feature = "waste-metric")]
+#[derive(Debug, Clone, Copy)]
+pub struct WasteMetricCoinSelection {
+    long_term_fee_rate: FeeRate,
+    change_satisfaction_weight: usize,
+    bnb_rounds: usize,
+}
 
-    const FEE_AMOUNT: u64 = 50;
+#[cfg(feature = "waste-metric")]
+impl Default for WasteMetricCoinSelection {
+    fn default() -> Self {
+        Self::new(FeeRate::from_sat_per_vb(10.0))
+    }
+}
 
-    fn utxo(value: u64, index: u32, confirmation_time: ConfirmationTime) -> WeightedUtxo {
-        assert!(index < 10);
-        let outpoint = OutPoint::from_str(&format!(
-            "000000000000000000000000000000000000000000000000000000000000000{}:0",
-            index
-        ))
-        .unwrap();
-        WeightedUtxo {
-            satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
-            utxo: Utxo::Local(LocalOutput {
-                outpoint,
-                txout: TxOut {
-                    value,
-                    script_pubkey: ScriptBuf::new(),
-                },
-                keychain: KeychainKind::External,
-                is_spent: false,
-                derivation_index: 42,
-                confirmation_time,
-            }),
+#[cfg(feature = "waste-metric")]
+impl WasteMetricCoinSelection {
+    /// Create a new instance with the fee rate expected to spend UTXOs in the long term
+    ///
+    /// The cost of spending the change is computed for a P2WPKH output, use
+    /// [`change_satisfaction_weight`](Self::change_satisfaction_weight) for other output types.
+    pub fn new(long_term_fee_rate: FeeRate) -> Self {
+        Self {
+            long_term_fee_rate,
+            // script sig len (4WU) + n. of items on witness (1WU) + signature len (1WU) +
+            // signature and sighash (72WU) + pubkey len (1WU) + pubkey (33WU)
+            change_satisfaction_weight: 4 + 1 + 1 + 72 + 1 + 33,
+            bnb_rounds: BNB_TOTAL_TRIES,
         }
     }
 
-    fn get_test_utxos() -> Vec<WeightedUtxo> {
-        vec![
-            utxo(100_000, 0, ConfirmationTime::Unconfirmed { last_seen: 0 }),
-            utxo(
-                FEE_AMOUNT - 40,
-                1,
-                ConfirmationTime::Unconfirmed { last_seen: 0 },
-            ),
-            utxo(200_000, 2, ConfirmationTime::Unconfirmed { last_seen: 0 }),
-        ]
+    /// Set the satisfaction weight of the change output, used to compute the cost of spending it
+    pub fn change_satisfaction_weight(mut self, satisfaction_weight: usize) -> Self {
+        self.change_satisfaction_weight = satisfaction_weight;
+        self
     }
 
-    fn get_oldest_first_test_utxos() -> Vec<WeightedUtxo> {
-        // ensure utxos are from different tx
-        let utxo1 = utxo(
-            120_000,
-            1,
-            ConfirmationTime::Confirmed {
-                height: 1,
-                time: 1231006505,
-            },
-        );
-        let utxo2 = utxo(
-            80_000,
-            2,
-            ConfirmationTime::Confirmed {
-                height: 2,
-                time: 1231006505,
-            },
-        );
-        let utxo3 = utxo(
-            300_000,
-            3,
-            ConfirmationTime::Confirmed {
-                height: 3,
-                time: 1231006505,
-            },
-        );
-        vec![utxo1, utxo2, utxo3]
+    /// Set the maximum number of rounds of branch and bound
+    pub fn bnb_rounds(mut self, rounds: usize) -> Self {
+        self.bnb_rounds = rounds;
+        self
     }
+}
 
-    fn generate_random_utxos(rng: &mut StdRng, utxos_number: usize) -> Vec<WeightedUtxo> {
-        let mut res = Vec::new();
-        for _ in 0..utxos_number {
-            res.push(WeightedUtxo {
-                satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
-                utxo: Utxo::Local(LocalOutput {
-                    outpoint: OutPoint::from_str(
-                        "ebd9813ecebc57ff8f30797de7c205e3c7498ca950ea4341ee51a685ff2fa30a:0",
-                    )
-                    .unwrap(),
-                    txout: TxOut {
-                        value: rng.gen_range(0..200000000),
-                        script_pubkey: ScriptBuf::new(),
-                    },
-                    keychain: KeychainKind::External,
-                    is_spent: false,
-                    derivation_index: rng.next_u32(),
-                    confirmation_time: if rng.gen_bool(0.5) {
-                        ConfirmationTime::Confirmed {
-                            height: rng.next_u32(),
-                            time: rng.next_u64(),
-                        }
-                    } else {
-                        ConfirmationTime::Unconfirmed { last_seen: 0 }
-                    },
-                }),
-            });
-        }
-        res
-    }
+#[cfg(feature = "waste-metric")]
+impl CoinSelectionAlgorithm for WasteMetricCoinSelection {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        mut optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        // If branch and bound fails, the optional UTXOs are selected in this order
+        optional_utxos.sort_unstable_by_key(|u| core::cmp::Reverse(u.utxo.txout().value));
+        let required_count = required_utxos.len();
+        let utxos = required_utxos
+            .into_iter()
+            .chain(optional_utxos)
+            .collect::<Vec<_>>();
 
-    fn generate_same_value_utxos(utxos_value: u64, utxos_number: usize) -> Vec<WeightedUtxo> {
-        let utxo = WeightedUtxo {
-            satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
-            utxo: Utxo::Local(LocalOutput {
-                outpoint: OutPoint::from_str(
-                    "ebd9813ecebc57ff8f30797de7c205e3c7498ca950ea4341ee51a685ff2fa30a:0",
+        let input_fee = |u: &WeightedUtxo, fee_rate: FeeRate| {
+            fee_rate.fee_wu(input_weight(u.satisfaction_weight))
+        };
+        let drain_weight = Weight::from_vb((serialize(drain_script).len() + 8) as u64)
+            .expect("the drain output weight fits into a u64");
+        let change_cost = fee_rate.fee_wu(drain_weight)
+            + self
+                .long_term_fee_rate
+                .fee_wu(input_weight(self.change_satisfaction_weight));
+
+        let candidates = utxos
+            .iter()
+            .map(|u| {
+                let txout = u.utxo.txout();
+                WeightedValue::new(
+                    txout.value,
+                    u.satisfaction_weight as u32,
+                    txout.script_pubkey.is_witness_program(),
                 )
-                .unwrap(),
-                txout: TxOut {
-                    value: utxos_value,
-                    script_pubkey: ScriptBuf::new(),
-                },
-                keychain: KeychainKind::External,
-                is_spent: false,
-                derivation_index: 42,
-                confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
-            }),
+            })
+            .collect::<Vec<_>>();
+        // The fees of the transaction header and outputs are already part of `target_amount`
+        let opts = CoinSelectorOpt {
+            target_value: Some(target_amount),
+            max_extra_target: 0,
+            target_feerate: fee_rate.as_sat_per_vb() / 4.0,
+            long_term_feerate: Some(self.long_term_fee_rate.as_sat_per_vb() / 4.0),
+            min_absolute_fee: 0,
+            base_weight: 0,
+            drain_weight: drain_weight.to_wu() as u32,
+            spend_drain_weight: input_weight(self.change_satisfaction_weight).to_wu() as u32,
+            min_drain_value: drain_script.dust_value().to_sat(),
         };
-        vec![utxo; utxos_number]
-    }
+        let mut selector = CoinSelector::new(&candidates, &opts);
+        for index in 0..required_count {
+            selector.select(index);
+        }
+        let mut selector = coin_select_bnb(self.bnb_rounds, selector.clone()).unwrap_or(selector);
+
+        // Our fees are rounded up for every input, so we may still need a bit more
+        let enough = |selector: &CoinSelector| {
+            let (value, fee) = selector.selected_indexes().fold((0, 0), |(value, fee), i| {
+                (
+                    value + utxos[i].utxo.txout().value,
+                    fee + input_fee(&utxos[i], fee_rate),
+                )
+            });
+            value >= target_amount + fee
+        };
+        let mut unselected = selector
+            .unselected_indexes()
+            .collect::<Vec<_>>()
+            .into_iter();
+        while !enough(&selector) {
+            match unselected.next() {
+                Some(index) => selector.select(index),
+                None => {
+                    let (needed, available) =
+                        utxos
+                            .iter()
+                            .fold((target_amount, 0), |(needed, available), u| {
+                                (
+                                    needed + input_fee(u, fee_rate),
+                                    available + u.utxo.txout().value,
+                                )
+                            });
+                    return Err(Error::InsufficientFunds { needed, available });
+                }
+            };
+        }
 
-    fn sum_random_utxos(mut rng: &mut StdRng, utxos: &mut Vec<WeightedUtxo>) -> u64 {
-        let utxos_picked_len = rng.gen_range(2..utxos.len() / 2);
-        utxos.shuffle(&mut rng);
-        utxos[..utxos_picked_len]
+        let selected = selector
+            .selected_indexes()
+            .map(|i| &utxos[i])
+            .collect::<Vec<_>>();
+        let fee_amount = selected.iter().map(|u| input_fee(u, fee_rate)).sum::<u64>();
+        let selected_amount = selected.iter().map(|u| u.utxo.txout().value).sum::<u64>();
+        let inputs_waste = selected
             .iter()
-            .map(|u| u.utxo.txout().value)
-            .sum()
+            .map(|u| input_fee(u, fee_rate) as i64 - input_fee(u, self.long_term_fee_rate) as i64)
+            .sum::<i64>();
+
+        let remaining_amount = selected_amount - target_amount - fee_amount;
+        let excess = decide_change(remaining_amount, fee_rate, drain_script);
+        let waste = inputs_waste
+            + match excess {
+                Excess::Change { .. } => change_cost as i64,
+                Excess::NoChange {
+                    remaining_amount, ..
+                } => remaining_amount as i64,
+            };
+
+        Ok(CoinSelectionResult {
+            selected: selected.into_iter().map(|u| u.utxo.clone()).collect(),
+            fee_amount,
+            excess,
+            waste: Some(waste),
+        })
     }
+}
 
-    #[test]
-    fn test_largest_first_coin_selection_success() {
-        let utxos = get_test_utxos();
-        let drain_script = ScriptBuf::default();
-        let target_amount = 250_000 + FEE_AMOUNT;
+/// The change that [`SingleRandomDrawCoinSelection`] and [`KnapsackCoinSelection`] aim for by
+/// default, in satoshis. It's the same as Bitcoin Core's `CHANGE_LOWER`.
+pub const MIN_CHANGE: u64 = 50_000;
 
-        let result = LargestFirstCoinSelection
-            .coin_select(
-                utxos,
-                vec![],
-                FeeRate::from_sat_per_vb(1.0),
-                target_amount,
-                &drain_script,
-            )
-            .unwrap();
+/// Number of random subsets tried by [`KnapsackCoinSelection`] for each target
+const KNAPSACK_ITERATIONS: usize = 1_000;
 
-        assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 300_010);
-        assert_eq!(result.fee_amount, 204)
-    }
+/// Single Random Draw coin selection
+///
+/// This coin selection algorithm picks UTXOs at random until they cover the target amount, the
+/// fee of a change output and a change of at least [`min_change`](Self::min_change) satoshis. If
+/// the UTXOs run out before that, the transaction is created without change if the target amount
+/// is covered. UTXOs with a negative effective value are never selected, unless required.
+///
+/// Picking UTXOs at random doesn't reveal anything about the wallet and keeps a mix of UTXO
+/// values, at the cost of larger transactions than other algorithms on average.
+#[derive(Debug, Clone, Copy)]
+pub struct SingleRandomDrawCoinSelection {
+    min_change: u64,
+    seed: Option<u64>,
+}
 
-    #[test]
-    fn test_largest_first_coin_selection_use_all() {
-        let utxos = get_test_utxos();
-        let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+impl Default for SingleRandomDrawCoinSelection {
+    fn default() -> Self {
+        Self {
+            min_change: MIN_CHANGE,
+            seed: None,
+        }
+    }
+}
 
-        let result = LargestFirstCoinSelection
-            .coin_select(
-                utxos,
-                vec![],
-                FeeRate::from_sat_per_vb(1.0),
-                target_amount,
-                &drain_script,
-            )
-            .unwrap();
+impl SingleRandomDrawCoinSelection {
+    /// Set the minimum change, in satoshis, to aim for
+    pub fn min_change(mut self, min_change: u64) -> Self {
+        self.min_change = min_change;
+        self
+    }
 
-        assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 300_010);
-        assert_eq!(result.fee_amount, 204);
+    /// Draw the UTXOs with a random number generator seeded with `seed`
+    ///
+    /// **This makes the selection predictable**, it's only meant for tests and benchmarks.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
     }
+}
 
-    #[test]
-    fn test_largest_first_coin_selection_use_only_necessary() {
-        let utxos = get_test_utxos();
-        let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+impl CoinSelectionAlgorithm for SingleRandomDrawCoinSelection {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        let (required_utxos, mut optional_utxos) =
+            output_groups(required_utxos, optional_utxos, fee_rate);
+        optional_utxos.shuffle(&mut rng(self.seed));
 
-        let result = LargestFirstCoinSelection
-            .coin_select(
+        let target = target_amount as i64;
+        let change_fee = fee_rate.fee_vb(serialize(drain_script).len() + 8) as i64;
+        let goal = target + change_fee + self.min_change as i64;
+
+        let mut curr_value = required_utxos
+            .iter()
+            .map(|u| u.effective_value)
+            .sum::<i64>();
+        let mut selected_utxos = vec![];
+        for utxo in optional_utxos.iter() {
+            if curr_value >= goal {
+                break;
+            }
+            curr_value += utxo.effective_value;
+            selected_utxos.push(utxo.clone());
+        }
+
+        if curr_value < target {
+            return Err(insufficient_funds(
+                target_amount,
+                required_utxos.iter().chain(optional_utxos.iter()),
+            ));
+        }
+
+        let excess = decide_change((curr_value - target) as u64, fee_rate, drain_script);
+        Ok(BranchAndBoundCoinSelection::calculate_cs_result(
+            selected_utxos,
+            required_utxos,
+            excess,
+        ))
+    }
+}
+
+/// Knapsack coin selection
+///
+/// The algorithm used by Bitcoin Core before branch and bound. It looks for a UTXO matching the
+/// target amount exactly, and otherwise for the random subset of the UTXOs smaller than the
+/// target amount plus [`min_change`](Self::min_change) whose value comes closest to the target
+/// amount, or to the target amount plus `min_change` so that the change isn't too small. If the
+/// smallest UTXO larger than that is a better match it's used alone instead.
+///
+/// Values are compared after subtracting the fee for spending each UTXO, and UTXOs with a
+/// negative effective value are never selected, unless required.
+#[derive(Debug, Clone, Copy)]
+pub struct KnapsackCoinSelection {
+    min_change: u64,
+    seed: Option<u64>,
+}
+
+impl Default for KnapsackCoinSelection {
+    fn default() -> Self {
+        Self {
+            min_change: MIN_CHANGE,
+            seed: None,
+        }
+    }
+}
+
+impl KnapsackCoinSelection {
+    /// Set the minimum change, in satoshis, to aim for
+    pub fn min_change(mut self, min_change: u64) -> Self {
+        self.min_change = min_change;
+        self
+    }
+
+    /// Draw the random subsets with a random number generator seeded with `seed`
+    ///
+    /// **This makes the selection predictable**, it's only meant for tests and benchmarks.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Looks for the subset of `utxos`, sorted by decreasing effective value and summing up to
+    // `total_value`, that exceeds `target` by the least amount. Returns the subset and its value.
+    fn approximate_best_subset(
+        rng: &mut StdRng,
+        utxos: &[OutputGroup],
+        total_value: i64,
+        target: i64,
+    ) -> (Vec<bool>, i64) {
+        let mut best_selection = vec![true; utxos.len()];
+        let mut best_value = total_value;
+
+        for _ in 0..KNAPSACK_ITERATIONS {
+            if best_value == target {
+                break;
+            }
+            let mut included = vec![false; utxos.len()];
+            let mut curr_value = 0;
+            let mut reached_target = false;
+            // The first pass includes UTXOs at random, the second one the rest of them in order
+            for pass in 0..2 {
+                if reached_target {
+                    break;
+                }
+                for (i, utxo) in utxos.iter().enumerate() {
+                    let include = if pass == 0 {
+                        rng.gen_bool(0.5)
+                    } else {
+                        !included[i]
+                    };
+                    if !include {
+                        continue;
+                    }
+                    curr_value += utxo.effective_value;
+                    included[i] = true;
+                    if curr_value >= target {
+                        reached_target = true;
+                        if curr_value < best_value {
+                            best_value = curr_value;
+                            best_selection = included.clone();
+                        }
+                        // Keep looking for a closer match without this UTXO
+                        curr_value -= utxo.effective_value;
+                        included[i] = false;
+                    }
+                }
+            }
+        }
+
+        (best_selection, best_value)
+    }
+}
+
+impl CoinSelectionAlgorithm for KnapsackCoinSelection {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        let (required_utxos, mut optional_utxos) =
+            output_groups(required_utxos, optional_utxos, fee_rate);
+        let mut rng = rng(self.seed);
+        optional_utxos.shuffle(&mut rng);
+
+        let required_value = required_utxos
+            .iter()
+            .map(|u| u.effective_value)
+            .sum::<i64>();
+        // What's left to select, once the required UTXOs are spent
+        let target = target_amount as i64 - required_value;
+        let min_change = self.min_change as i64;
+
+        let result = |selected_utxos: Vec<OutputGroup>, required_utxos: Vec<OutputGroup>| {
+            let selected_value = selected_utxos
+                .iter()
+                .map(|u| u.effective_value)
+                .sum::<i64>();
+            let excess = decide_change((selected_value - target) as u64, fee_rate, drain_script);
+            Ok(BranchAndBoundCoinSelection::calculate_cs_result(
+                selected_utxos,
+                required_utxos,
+                excess,
+            ))
+        };
+
+        if target <= 0 {
+            return result(vec![], required_utxos);
+        }
+
+        let mut lowest_larger: Option<OutputGroup> = None;
+        let mut applicable_utxos = vec![];
+        let mut total_lower = 0;
+        for utxo in optional_utxos.iter() {
+            if utxo.effective_value == target {
+                return result(vec![utxo.clone()], required_utxos);
+            } else if utxo.effective_value < target + min_change {
+                total_lower += utxo.effective_value;
+                applicable_utxos.push(utxo.clone());
+            } else if lowest_larger
+                .as_ref()
+                .map_or(true, |larger| utxo.effective_value < larger.effective_value)
+            {
+                lowest_larger = Some(utxo.clone());
+            }
+        }
+
+        if total_lower == target {
+            return result(applicable_utxos, required_utxos);
+        }
+        if total_lower < target {
+            return match lowest_larger {
+                Some(larger) => result(vec![larger], required_utxos),
+                None => Err(insufficient_funds(
+                    target_amount,
+                    required_utxos.iter().chain(optional_utxos.iter()),
+                )),
+            };
+        }
+
+        applicable_utxos.sort_unstable_by_key(|u| core::cmp::Reverse(u.effective_value));
+        let (mut best_selection, mut best_value) =
+            Self::approximate_best_subset(&mut rng, &applicable_utxos, total_lower, target);
+        if best_value != target && total_lower >= target + min_change {
+            let (selection, value) = Self::approximate_best_subset(
+                &mut rng,
+                &applicable_utxos,
+                total_lower,
+                target + min_change,
+            );
+            best_selection = selection;
+            best_value = value;
+        }
+
+        // The smallest larger UTXO is better if the subset didn't reach the target exactly and
+        // would leave too little change, or if it's closer to the target
+        if let Some(larger) = lowest_larger {
+            if (best_value != target && best_value < target + min_change)
+                || larger.effective_value <= best_value
+            {
+                return result(vec![larger], required_utxos);
+            }
+        }
+
+        let selected_utxos = applicable_utxos
+            .into_iter()
+            .zip(best_selection)
+            .filter_map(|(utxo, is_selected)| if is_selected { Some(utxo) } else { None })
+            .collect();
+        result(selected_utxos, required_utxos)
+    }
+}
+
+/// Coin selection that avoids linking the wallet's coins together
+///
+/// Every input of a transaction is assumed to belong to the same owner, so spending coins
+/// together links them. To reveal as little as possible, this algorithm:
+///
+/// 1. Always spends all the UTXOs sitting on the same script pubkey together, since reusing the
+///    address already linked them. The ones of the required UTXOs are spent too.
+/// 2. Spends UTXOs of a single script type, and doesn't mix [`KeychainKind::External`] UTXOs
+///    received from different sources, as given with [`with_sources`]. If that's not enough, it
+///    falls back to mixing the sources, and then to mixing script types.
+/// 3. Prefers the UTXOs of the same script type as the change output, see
+///    [`TxBuilder::match_change_script_type`] to also match the change to the recipient.
+///
+/// Within these constraints the UTXOs are picked from the largest group of UTXOs on the same
+/// script pubkey, and the candidates with the lowest fee are preferred.
+///
+/// [`KeychainKind::External`]: crate::KeychainKind::External
+/// [`with_sources`]: Self::with_sources
+/// [`TxBuilder::match_change_script_type`]: super::tx_builder::TxBuilder::match_change_script_type
+#[derive(Debug, Default, Clone)]
+pub struct PrivacyCoinSelection {
+    sources: BTreeMap<OutPoint, String>,
+}
+
+// UTXOs sitting on the same script pubkey, which must be spent together
+#[derive(Debug, Clone)]
+struct ScriptGroup {
+    utxos: Vec<OutputGroup>,
+    required: bool,
+    script_type: Option<AddressType>,
+    // The source of the first external UTXO on the script pubkey
+    source: Option<String>,
+}
+
+impl ScriptGroup {
+    fn effective_value(&self) -> i64 {
+        self.utxos.iter().map(|u| u.effective_value).sum()
+    }
+
+    fn fee(&self) -> u64 {
+        self.utxos.iter().map(|u| u.fee).sum()
+    }
+}
+
+impl PrivacyCoinSelection {
+    /// Create a [`PrivacyCoinSelection`] which doesn't know where the UTXOs come from.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a [`PrivacyCoinSelection`] with the source of the UTXOs, e.g. who paid them, as
+    /// `(outpoint, source)` pairs.
+    ///
+    /// [`KeychainKind::External`](crate::KeychainKind::External) UTXOs with the same source are
+    /// spent together before mixing sources. The UTXOs without a source are treated as if they all
+    /// came from the same unknown source.
+    pub fn with_sources(sources: impl IntoIterator<Item = (OutPoint, String)>) -> Self {
+        Self {
+            sources: sources.into_iter().collect(),
+        }
+    }
+
+    // Spends the required groups and the optional ones in `pool` from the largest, until the
+    // target amount is reached
+    fn select_groups<'a>(
+        required: &[&'a ScriptGroup],
+        pool: impl Iterator<Item = &'a ScriptGroup>,
+        target_amount: i64,
+    ) -> Option<Vec<&'a ScriptGroup>> {
+        let mut pool = pool.collect::<Vec<_>>();
+        pool.sort_by_key(|g| core::cmp::Reverse(g.effective_value()));
+
+        let mut selected = required.to_vec();
+        let mut curr_value = selected.iter().map(|g| g.effective_value()).sum::<i64>();
+        for group in pool {
+            if curr_value >= target_amount {
+                break;
+            }
+            curr_value += group.effective_value();
+            selected.push(group);
+        }
+        if curr_value >= target_amount {
+            Some(selected)
+        } else {
+            None
+        }
+    }
+}
+
+impl CoinSelectionAlgorithm for PrivacyCoinSelection {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        let mut groups: BTreeMap<ScriptBuf, ScriptGroup> = BTreeMap::new();
+        let utxos = required_utxos
+            .into_iter()
+            .map(|u| (true, u))
+            .chain(optional_utxos.into_iter().map(|u| (false, u)));
+        for (required, weighted_utxo) in utxos {
+            let script_pubkey = weighted_utxo.utxo.txout().script_pubkey.clone();
+            let source = match &weighted_utxo.utxo {
+                Utxo::Local(local) if local.keychain == KeychainKind::External => {
+                    self.sources.get(&local.outpoint).cloned()
+                }
+                _ => None,
+            };
+            let group = groups
+                .entry(script_pubkey)
+                .or_insert_with_key(|script_pubkey| ScriptGroup {
+                    utxos: vec![],
+                    required: false,
+                    script_type: script_type(script_pubkey),
+                    source: None,
+                });
+            group.utxos.push(OutputGroup::new(weighted_utxo, fee_rate));
+            group.required |= required;
+            if group.source.is_none() {
+                group.source = source;
+            }
+        }
+
+        let (required, optional): (Vec<_>, Vec<_>) = groups.values().partition(|g| g.required);
+        // Like with the other algorithms, UTXOs that cost more than they are worth are skipped
+        let optional = optional
+            .into_iter()
+            .filter(|g| g.effective_value().is_positive())
+            .collect::<Vec<_>>();
+        let target = target_amount as i64;
+        let drain_type = script_type(drain_script);
+
+        // From the most to the least private: a single script type and source, a single script
+        // type, anything
+        let buckets = optional
+            .iter()
+            .map(|g| (g.script_type, g.source.clone()))
+            .collect::<BTreeSet<_>>();
+        let script_types = optional
+            .iter()
+            .map(|g| g.script_type)
+            .collect::<BTreeSet<_>>();
+        let tiers: Vec<Vec<Vec<&ScriptGroup>>> = vec![
+            buckets
+                .iter()
+                .filter(|(script_type, source)| {
+                    required
+                        .iter()
+                        .all(|g| &g.script_type == script_type && &g.source == source)
+                })
+                .map(|(script_type, source)| {
+                    optional
+                        .iter()
+                        .copied()
+                        .filter(|g| &g.script_type == script_type && &g.source == source)
+                        .collect()
+                })
+                .collect(),
+            script_types
+                .iter()
+                .filter(|script_type| required.iter().all(|g| &g.script_type == *script_type))
+                .map(|script_type| {
+                    optional
+                        .iter()
+                        .copied()
+                        .filter(|g| &g.script_type == script_type)
+                        .collect()
+                })
+                .collect(),
+            vec![optional.clone()],
+        ];
+
+        let selected = tiers
+            .into_iter()
+            .find_map(|pools| {
+                pools
+                    .into_iter()
+                    .filter_map(|pool| Self::select_groups(&required, pool.into_iter(), target))
+                    .min_by_key(|selected| {
+                        let matches_change = selected.iter().all(|g| g.script_type == drain_type);
+                        let fee = selected.iter().map(|g| g.fee()).sum::<u64>();
+                        (!matches_change, fee)
+                    })
+            })
+            .ok_or_else(|| {
+                insufficient_funds(
+                    target_amount,
+                    required
+                        .iter()
+                        .chain(optional.iter())
+                        .flat_map(|g| g.utxos.iter()),
+                )
+            })?;
+
+        let (required, optional): (Vec<_>, Vec<_>) = selected.into_iter().partition(|g| g.required);
+        let required_utxos = required
+            .into_iter()
+            .flat_map(|g| g.utxos.clone())
+            .collect::<Vec<_>>();
+        let selected_utxos = optional
+            .into_iter()
+            .flat_map(|g| g.utxos.clone())
+            .collect::<Vec<_>>();
+        let selected_value = required_utxos
+            .iter()
+            .chain(selected_utxos.iter())
+            .map(|u| u.effective_value)
+            .sum::<i64>();
+
+        let excess = decide_change((selected_value - target) as u64, fee_rate, drain_script);
+        Ok(BranchAndBoundCoinSelection::calculate_cs_result(
+            selected_utxos,
+            required_utxos,
+            excess,
+        ))
+    }
+}
+
+// Maps the UTXOs to output groups, filtering out the optional ones with a negative effective
+// value.
+fn output_groups(
+    required_utxos: Vec<WeightedUtxo>,
+    optional_utxos: Vec<WeightedUtxo>,
+    fee_rate: FeeRate,
+) -> (Vec<OutputGroup>, Vec<OutputGroup>) {
+    let required_utxos = required_utxos
+        .into_iter()
+        .map(|u| OutputGroup::new(u, fee_rate))
+        .collect();
+    let optional_utxos = optional_utxos
+        .into_iter()
+        .map(|u| OutputGroup::new(u, fee_rate))
+        .filter(|u| u.effective_value.is_positive())
+        .collect();
+    (required_utxos, optional_utxos)
+}
+
+// The error returned when spending all the `utxos` isn't enough.
+fn insufficient_funds<'a>(
+    target_amount: u64,
+    utxos: impl Iterator<Item = &'a OutputGroup>,
+) -> Error {
+    let (fees, available) = utxos.fold((0, 0), |(fees, value), u| {
+        (fees + u.fee, value + u.weighted_utxo.utxo.txout().value)
+    });
+    Error::InsufficientFunds {
+        needed: target_amount + fees,
+        available,
+    }
+}
+
+fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+#[cfg(feature = "waste-metric")]
+fn input_weight(satisfaction_weight: usize) -> Weight {
+    Weight::from_wu((TXIN_BASE_WEIGHT + satisfaction_weight) as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use core::str::FromStr;
+
+    use bdk_chain::ConfirmationTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, ScriptBuf, TxOut};
+
+    use super::*;
+    use crate::types::*;
+    use crate::wallet::Vbytes;
+
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, RngCore, SeedableRng};
+
+    // n. of items on witness (1WU) + signature len (1WU) + signature and sighash (72WU)
+    // + pubkey len (1WU) + pubkey (33WU) + script sig len (1 byte, 4WU)
+    const P2WPKH_SATISFACTION_SIZE: usize = 1 + 1 + 72 + 1 + 33 + 4;
+
+    const FEE_AMOUNT: u64 = 50;
+
+    fn utxo(value: u64, index: u32, confirmation_time: ConfirmationTime) -> WeightedUtxo {
+        assert!(index < 10);
+        let outpoint = OutPoint::from_str(&format!(
+            "000000000000000000000000000000000000000000000000000000000000000{}:0",
+            index
+        ))
+        .unwrap();
+        WeightedUtxo {
+            satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
+            utxo: Utxo::Local(LocalOutput {
+                outpoint,
+                txout: TxOut {
+                    value,
+                    script_pubkey: ScriptBuf::new(),
+                },
+                keychain: KeychainKind::External,
+                is_spent: false,
+                derivation_index: 42,
+                confirmation_time,
+            }),
+        }
+    }
+
+    fn get_test_utxos() -> Vec<WeightedUtxo> {
+        vec![
+            utxo(100_000, 0, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+            utxo(
+                FEE_AMOUNT - 40,
+                1,
+                ConfirmationTime::Unconfirmed { last_seen: 0 },
+            ),
+            utxo(200_000, 2, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+        ]
+    }
+
+    fn get_oldest_first_test_utxos() -> Vec<WeightedUtxo> {
+        // ensure utxos are from different tx
+        let utxo1 = utxo(
+            120_000,
+            1,
+            ConfirmationTime::Confirmed {
+                height: 1,
+                time: 1231006505,
+            },
+        );
+        let utxo2 = utxo(
+            80_000,
+            2,
+            ConfirmationTime::Confirmed {
+                height: 2,
+                time: 1231006505,
+            },
+        );
+        let utxo3 = utxo(
+            300_000,
+            3,
+            ConfirmationTime::Confirmed {
+                height: 3,
+                time: 1231006505,
+            },
+        );
+        vec![utxo1, utxo2, utxo3]
+    }
+
+    fn generate_random_utxos(rng: &mut StdRng, utxos_number: usize) -> Vec<WeightedUtxo> {
+        let mut res = Vec::new();
+        for _ in 0..utxos_number {
+            res.push(WeightedUtxo {
+                satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
+                utxo: Utxo::Local(LocalOutput {
+                    outpoint: OutPoint::from_str(
+                        "ebd9813ecebc57ff8f30797de7c205e3c7498ca950ea4341ee51a685ff2fa30a:0",
+                    )
+                    .unwrap(),
+                    txout: TxOut {
+                        value: rng.gen_range(0..200000000),
+                        script_pubkey: ScriptBuf::new(),
+                    },
+                    keychain: KeychainKind::External,
+                    is_spent: false,
+                    derivation_index: rng.next_u32(),
+                    confirmation_time: if rng.gen_bool(0.5) {
+                        ConfirmationTime::Confirmed {
+                            height: rng.next_u32(),
+                            time: rng.next_u64(),
+                        }
+                    } else {
+                        ConfirmationTime::Unconfirmed { last_seen: 0 }
+                    },
+                }),
+            });
+        }
+        res
+    }
+
+    fn generate_same_value_utxos(utxos_value: u64, utxos_number: usize) -> Vec<WeightedUtxo> {
+        let utxo = WeightedUtxo {
+            satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
+            utxo: Utxo::Local(LocalOutput {
+                outpoint: OutPoint::from_str(
+                    "ebd9813ecebc57ff8f30797de7c205e3c7498ca950ea4341ee51a685ff2fa30a:0",
+                )
+                .unwrap(),
+                txout: TxOut {
+                    value: utxos_value,
+                    script_pubkey: ScriptBuf::new(),
+                },
+                keychain: KeychainKind::External,
+                is_spent: false,
+                derivation_index: 42,
+                confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
+            }),
+        };
+        vec![utxo; utxos_number]
+    }
+
+    fn sum_random_utxos(mut rng: &mut StdRng, utxos: &mut Vec<WeightedUtxo>) -> u64 {
+        let utxos_picked_len = rng.gen_range(2..utxos.len() / 2);
+        utxos.shuffle(&mut rng);
+        utxos[..utxos_picked_len]
+            .iter()
+            .map(|u| u.utxo.txout().value)
+            .sum()
+    }
+
+    #[test]
+    fn test_largest_first_coin_selection_success() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 250_000 + FEE_AMOUNT;
+
+        let result = LargestFirstCoinSelection
+            .coin_select(
+                utxos,
+                vec![],
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 3);
+        assert_eq!(result.selected_amount(), 300_010);
+        assert_eq!(result.fee_amount, 204)
+    }
+
+    #[test]
+    fn test_largest_first_coin_selection_use_all() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 20_000 + FEE_AMOUNT;
+
+        let result = LargestFirstCoinSelection
+            .coin_select(
+                utxos,
+                vec![],
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 3);
+        assert_eq!(result.selected_amount(), 300_010);
+        assert_eq!(result.fee_amount, 204);
+    }
+
+    #[test]
+    fn test_largest_first_coin_selection_use_only_necessary() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 20_000 + FEE_AMOUNT;
+
+        let result = LargestFirstCoinSelection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 200_000);
+        assert_eq!(result.fee_amount, 68);
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_largest_first_coin_selection_insufficient_funds() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 500_000 + FEE_AMOUNT;
+
+        LargestFirstCoinSelection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_largest_first_coin_selection_insufficient_funds_high_fees() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 250_000 + FEE_AMOUNT;
+
+        LargestFirstCoinSelection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1000.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_oldest_first_coin_selection_success() {
+        let utxos = get_oldest_first_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 180_000 + FEE_AMOUNT;
+
+        let result = OldestFirstCoinSelection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 200_000);
+        assert_eq!(result.fee_amount, 136)
+    }
+
+    #[test]
+    fn test_oldest_first_coin_selection_use_all() {
+        let utxos = get_oldest_first_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 20_000 + FEE_AMOUNT;
+
+        let result = OldestFirstCoinSelection
+            .coin_select(
+                utxos,
+                vec![],
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 3);
+        assert_eq!(result.selected_amount(), 500_000);
+        assert_eq!(result.fee_amount, 204);
+    }
+
+    #[test]
+    fn test_oldest_first_coin_selection_use_only_necessary() {
+        let utxos = get_oldest_first_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 20_000 + FEE_AMOUNT;
+
+        let result = OldestFirstCoinSelection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 120_000);
+        assert_eq!(result.fee_amount, 68);
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_oldest_first_coin_selection_insufficient_funds() {
+        let utxos = get_oldest_first_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 600_000 + FEE_AMOUNT;
+
+        OldestFirstCoinSelection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_oldest_first_coin_selection_insufficient_funds_high_fees() {
+        let utxos = get_oldest_first_test_utxos();
+
+        let target_amount: u64 = utxos.iter().map(|wu| wu.utxo.txout().value).sum::<u64>() - 50;
+        let drain_script = ScriptBuf::default();
+
+        OldestFirstCoinSelection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1000.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_bnb_coin_selection_success() {
+        // In this case bnb won't find a suitable match and single random draw will
+        // select three outputs
+        let utxos = generate_same_value_utxos(100_000, 20);
+
+        let drain_script = ScriptBuf::default();
+
+        let target_amount = 250_000 + FEE_AMOUNT;
+
+        let result = BranchAndBoundCoinSelection::default()
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 3);
+        assert_eq!(result.selected_amount(), 300_000);
+        assert_eq!(result.fee_amount, 204);
+    }
+
+    #[test]
+    fn test_bnb_coin_selection_required_are_enough() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 20_000 + FEE_AMOUNT;
+
+        let result = BranchAndBoundCoinSelection::default()
+            .coin_select(
+                utxos.clone(),
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 3);
+        assert_eq!(result.selected_amount(), 300_010);
+        assert_eq!(result.fee_amount, 204);
+    }
+
+    #[test]
+    fn test_bnb_coin_selection_optional_are_enough() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 299756 + FEE_AMOUNT;
+
+        let result = BranchAndBoundCoinSelection::default()
+            .coin_select(
                 vec![],
                 utxos,
                 FeeRate::from_sat_per_vb(1.0),
@@ -916,19 +1898,56 @@ mod test {
             )
             .unwrap();
 
-        assert_eq!(result.selected.len(), 1);
-        assert_eq!(result.selected_amount(), 200_000);
-        assert_eq!(result.fee_amount, 68);
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 300000);
+        assert_eq!(result.fee_amount, 136);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_bnb_coin_selection_required_not_enough() {
+        let utxos = get_test_utxos();
+
+        let required = vec![utxos[0].clone()];
+        let mut optional = utxos[1..].to_vec();
+        optional.push(utxo(
+            500_000,
+            3,
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        ));
+
+        // Defensive assertions, for sanity and in case someone changes the test utxos vector.
+        let amount: u64 = required.iter().map(|u| u.utxo.txout().value).sum();
+        assert_eq!(amount, 100_000);
+        let amount: u64 = optional.iter().map(|u| u.utxo.txout().value).sum();
+        assert!(amount > 150_000);
+        let drain_script = ScriptBuf::default();
+
+        let target_amount = 150_000 + FEE_AMOUNT;
+
+        let result = BranchAndBoundCoinSelection::default()
+            .coin_select(
+                required,
+                optional,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 300_000);
+        assert_eq!(result.fee_amount, 136);
     }
 
     #[test]
     #[should_panic(expected = "InsufficientFunds")]
-    fn test_largest_first_coin_selection_insufficient_funds() {
+    fn test_bnb_coin_selection_insufficient_funds() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
         let target_amount = 500_000 + FEE_AMOUNT;
 
-        LargestFirstCoinSelection
+        BranchAndBoundCoinSelection::default()
             .coin_select(
                 vec![],
                 utxos,
@@ -941,12 +1960,12 @@ mod test {
 
     #[test]
     #[should_panic(expected = "InsufficientFunds")]
-    fn test_largest_first_coin_selection_insufficient_funds_high_fees() {
+    fn test_bnb_coin_selection_insufficient_funds_high_fees() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
         let target_amount = 250_000 + FEE_AMOUNT;
 
-        LargestFirstCoinSelection
+        BranchAndBoundCoinSelection::default()
             .coin_select(
                 vec![],
                 utxos,
@@ -958,12 +1977,12 @@ mod test {
     }
 
     #[test]
-    fn test_oldest_first_coin_selection_success() {
-        let utxos = get_oldest_first_test_utxos();
+    fn test_bnb_coin_selection_check_fee_rate() {
+        let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 180_000 + FEE_AMOUNT;
+        let target_amount = 99932; // first utxo's effective value
 
-        let result = OldestFirstCoinSelection
+        let result = BranchAndBoundCoinSelection::new(0)
             .coin_select(
                 vec![],
                 utxos,
@@ -973,124 +1992,281 @@ mod test {
             )
             .unwrap();
 
-        assert_eq!(result.selected.len(), 2);
-        assert_eq!(result.selected_amount(), 200_000);
-        assert_eq!(result.fee_amount, 136)
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 100_000);
+        let input_size = (TXIN_BASE_WEIGHT + P2WPKH_SATISFACTION_SIZE).vbytes();
+        // the final fee rate should be exactly the same as the fee rate given
+        assert!((1.0 - (result.fee_amount as f32 / input_size as f32)).abs() < f32::EPSILON);
     }
 
     #[test]
-    fn test_oldest_first_coin_selection_use_all() {
-        let utxos = get_oldest_first_test_utxos();
+    fn test_bnb_coin_selection_exact_match() {
+        let seed = [0; 32];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+        for _i in 0..200 {
+            let mut optional_utxos = generate_random_utxos(&mut rng, 16);
+            let target_amount = sum_random_utxos(&mut rng, &mut optional_utxos);
+            let drain_script = ScriptBuf::default();
+            let result = BranchAndBoundCoinSelection::new(0)
+                .coin_select(
+                    vec![],
+                    optional_utxos,
+                    FeeRate::from_sat_per_vb(0.0),
+                    target_amount,
+                    &drain_script,
+                )
+                .unwrap();
+            assert_eq!(result.selected_amount(), target_amount);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "BnBNoExactMatch")]
+    fn test_bnb_function_no_exact_match() {
+        let fee_rate = FeeRate::from_sat_per_vb(10.0);
+        let utxos: Vec<OutputGroup> = get_test_utxos()
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect();
+
+        let curr_available_value = utxos.iter().fold(0, |acc, x| acc + x.effective_value);
+
+        let size_of_change = 31;
+        let cost_of_change = size_of_change as f32 * fee_rate.as_sat_per_vb();
+
         let drain_script = ScriptBuf::default();
         let target_amount = 20_000 + FEE_AMOUNT;
-
-        let result = OldestFirstCoinSelection
-            .coin_select(
-                utxos,
+        BranchAndBoundCoinSelection::new(size_of_change)
+            .bnb(
                 vec![],
-                FeeRate::from_sat_per_vb(1.0),
-                target_amount,
+                utxos,
+                0,
+                curr_available_value,
+                target_amount as i64,
+                cost_of_change,
                 &drain_script,
+                fee_rate,
             )
             .unwrap();
+    }
 
-        assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 500_000);
-        assert_eq!(result.fee_amount, 204);
+    #[test]
+    #[should_panic(expected = "BnBTotalTriesExceeded")]
+    fn test_bnb_function_tries_exceeded() {
+        let fee_rate = FeeRate::from_sat_per_vb(10.0);
+        let utxos: Vec<OutputGroup> = generate_same_value_utxos(100_000, 100_000)
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect();
+
+        let curr_available_value = utxos.iter().fold(0, |acc, x| acc + x.effective_value);
+
+        let size_of_change = 31;
+        let cost_of_change = size_of_change as f32 * fee_rate.as_sat_per_vb();
+        let target_amount = 20_000 + FEE_AMOUNT;
+
+        let drain_script = ScriptBuf::default();
+
+        BranchAndBoundCoinSelection::new(size_of_change)
+            .bnb(
+                vec![],
+                utxos,
+                0,
+                curr_available_value,
+                target_amount as i64,
+                cost_of_change,
+                &drain_script,
+                fee_rate,
+            )
+            .unwrap();
     }
 
+    // The match won't be exact but still in the range
     #[test]
-    fn test_oldest_first_coin_selection_use_only_necessary() {
-        let utxos = get_oldest_first_test_utxos();
+    fn test_bnb_function_almost_exact_match_with_fees() {
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        let size_of_change = 31;
+        let cost_of_change = size_of_change as f32 * fee_rate.as_sat_per_vb();
+
+        let utxos: Vec<_> = generate_same_value_utxos(50_000, 10)
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect();
+
+        let curr_value = 0;
+
+        let curr_available_value = utxos.iter().fold(0, |acc, x| acc + x.effective_value);
+
+        // 2*(value of 1 utxo)  - 2*(1 utxo fees with 1.0sat/vbyte fee rate) -
+        // cost_of_change + 5.
+        let target_amount = 2 * 50_000 - 2 * 67 - cost_of_change.ceil() as i64 + 5;
+
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
 
-        let result = OldestFirstCoinSelection
-            .coin_select(
+        let result = BranchAndBoundCoinSelection::new(size_of_change)
+            .bnb(
                 vec![],
                 utxos,
-                FeeRate::from_sat_per_vb(1.0),
+                curr_value,
+                curr_available_value,
                 target_amount,
+                cost_of_change,
                 &drain_script,
+                fee_rate,
             )
             .unwrap();
+        assert_eq!(result.selected_amount(), 100_000);
+        assert_eq!(result.fee_amount, 136);
+    }
 
-        assert_eq!(result.selected.len(), 1);
-        assert_eq!(result.selected_amount(), 120_000);
-        assert_eq!(result.fee_amount, 68);
+    // TODO: bnb() function should be optimized, and this test should be done with more utxos
+    #[test]
+    fn test_bnb_function_exact_match_more_utxos() {
+        let seed = [0; 32];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let fee_rate = FeeRate::from_sat_per_vb(0.0);
+
+        for _ in 0..200 {
+            let optional_utxos: Vec<_> = generate_random_utxos(&mut rng, 40)
+                .into_iter()
+                .map(|u| OutputGroup::new(u, fee_rate))
+                .collect();
+
+            let curr_value = 0;
+
+            let curr_available_value = optional_utxos
+                .iter()
+                .fold(0, |acc, x| acc + x.effective_value);
+
+            let target_amount =
+                optional_utxos[3].effective_value + optional_utxos[23].effective_value;
+
+            let drain_script = ScriptBuf::default();
+
+            let result = BranchAndBoundCoinSelection::new(0)
+                .bnb(
+                    vec![],
+                    optional_utxos,
+                    curr_value,
+                    curr_available_value,
+                    target_amount,
+                    0.0,
+                    &drain_script,
+                    fee_rate,
+                )
+                .unwrap();
+            assert_eq!(result.selected_amount(), target_amount as u64);
+        }
+    }
+
+    #[test]
+    fn test_single_random_draw_function_success() {
+        let seed = [0; 32];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let mut utxos = generate_random_utxos(&mut rng, 300);
+        let target_amount = sum_random_utxos(&mut rng, &mut utxos) + FEE_AMOUNT;
+
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        let utxos: Vec<OutputGroup> = utxos
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect();
+
+        let drain_script = ScriptBuf::default();
+
+        let result = BranchAndBoundCoinSelection::default().single_random_draw(
+            vec![],
+            utxos,
+            0,
+            target_amount as i64,
+            &drain_script,
+            fee_rate,
+        );
+
+        assert!(result.selected_amount() > target_amount);
+        assert_eq!(result.fee_amount, (result.selected.len() * 68) as u64);
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_oldest_first_coin_selection_insufficient_funds() {
-        let utxos = get_oldest_first_test_utxos();
+    fn test_bnb_exclude_negative_effective_value() {
+        let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 600_000 + FEE_AMOUNT;
 
-        OldestFirstCoinSelection
-            .coin_select(
-                vec![],
-                utxos,
-                FeeRate::from_sat_per_vb(1.0),
-                target_amount,
-                &drain_script,
-            )
-            .unwrap();
+        let selection = BranchAndBoundCoinSelection::default().coin_select(
+            vec![],
+            utxos,
+            FeeRate::from_sat_per_vb(10.0),
+            500_000,
+            &drain_script,
+        );
+
+        assert_matches!(
+            selection,
+            Err(Error::InsufficientFunds {
+                available: 300_000,
+                ..
+            })
+        );
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_oldest_first_coin_selection_insufficient_funds_high_fees() {
-        let utxos = get_oldest_first_test_utxos();
-
-        let target_amount: u64 = utxos.iter().map(|wu| wu.utxo.txout().value).sum::<u64>() - 50;
+    fn test_bnb_include_negative_effective_value_when_required() {
+        let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
 
-        OldestFirstCoinSelection
-            .coin_select(
-                vec![],
-                utxos,
-                FeeRate::from_sat_per_vb(1000.0),
-                target_amount,
-                &drain_script,
-            )
-            .unwrap();
+        let (required, optional) = utxos
+            .into_iter()
+            .partition(|u| matches!(u, WeightedUtxo { utxo, .. } if utxo.txout().value < 1000));
+
+        let selection = BranchAndBoundCoinSelection::default().coin_select(
+            required,
+            optional,
+            FeeRate::from_sat_per_vb(10.0),
+            500_000,
+            &drain_script,
+        );
+
+        assert_matches!(
+            selection,
+            Err(Error::InsufficientFunds {
+                available: 300_010,
+                ..
+            })
+        );
     }
 
     #[test]
-    fn test_bnb_coin_selection_success() {
-        // In this case bnb won't find a suitable match and single random draw will
-        // select three outputs
-        let utxos = generate_same_value_utxos(100_000, 20);
-
+    fn test_bnb_sum_of_effective_value_negative() {
+        let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
 
-        let target_amount = 250_000 + FEE_AMOUNT;
-
-        let result = BranchAndBoundCoinSelection::default()
-            .coin_select(
-                vec![],
-                utxos,
-                FeeRate::from_sat_per_vb(1.0),
-                target_amount,
-                &drain_script,
-            )
-            .unwrap();
+        let selection = BranchAndBoundCoinSelection::default().coin_select(
+            utxos,
+            vec![],
+            FeeRate::from_sat_per_vb(10_000.0),
+            500_000,
+            &drain_script,
+        );
 
-        assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 300_000);
-        assert_eq!(result.fee_amount, 204);
+        assert_matches!(
+            selection,
+            Err(Error::InsufficientFunds {
+                available: 300_010,
+                ..
+            })
+        );
     }
 
     #[test]
-    fn test_bnb_coin_selection_required_are_enough() {
+    fn test_smallest_first_coin_selection_success() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+        let target_amount = 250_000 + FEE_AMOUNT;
 
-        let result = BranchAndBoundCoinSelection::default()
+        let result = SmallestFirstCoinSelection
             .coin_select(
-                utxos.clone(),
+                vec![],
                 utxos,
                 FeeRate::from_sat_per_vb(1.0),
                 target_amount,
@@ -1100,16 +2276,16 @@ mod test {
 
         assert_eq!(result.selected.len(), 3);
         assert_eq!(result.selected_amount(), 300_010);
-        assert_eq!(result.fee_amount, 204);
+        assert_eq!(result.fee_amount, 204)
     }
 
     #[test]
-    fn test_bnb_coin_selection_optional_are_enough() {
+    fn test_smallest_first_coin_selection_use_only_necessary() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 299756 + FEE_AMOUNT;
+        let target_amount = 20_000 + FEE_AMOUNT;
 
-        let result = BranchAndBoundCoinSelection::default()
+        let result = SmallestFirstCoinSelection
             .coin_select(
                 vec![],
                 utxos,
@@ -1120,93 +2296,93 @@ mod test {
             .unwrap();
 
         assert_eq!(result.selected.len(), 2);
-        assert_eq!(result.selected_amount(), 300000);
-        assert_eq!(result.fee_amount, 136);
+        assert_eq!(result.selected_amount(), 100_010);
+        assert_eq!(result.fee_amount, 136)
     }
 
     #[test]
-    #[ignore]
-    fn test_bnb_coin_selection_required_not_enough() {
+    fn test_smallest_first_coin_selection_insufficient_funds() {
         let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 500_000 + FEE_AMOUNT;
 
-        let required = vec![utxos[0].clone()];
-        let mut optional = utxos[1..].to_vec();
-        optional.push(utxo(
-            500_000,
-            3,
-            ConfirmationTime::Unconfirmed { last_seen: 0 },
-        ));
+        let result = SmallestFirstCoinSelection.coin_select(
+            vec![],
+            utxos,
+            FeeRate::from_sat_per_vb(1.0),
+            target_amount,
+            &drain_script,
+        );
+        assert_matches!(result, Err(Error::InsufficientFunds { .. }));
+    }
 
-        // Defensive assertions, for sanity and in case someone changes the test utxos vector.
-        let amount: u64 = required.iter().map(|u| u.utxo.txout().value).sum();
-        assert_eq!(amount, 100_000);
-        let amount: u64 = optional.iter().map(|u| u.utxo.txout().value).sum();
-        assert!(amount > 150_000);
+    #[test]
+    fn test_single_random_draw_coin_selection_success() {
+        let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
+        let target_amount = 250_000 + FEE_AMOUNT;
 
-        let target_amount = 150_000 + FEE_AMOUNT;
-
-        let result = BranchAndBoundCoinSelection::default()
+        let result = SingleRandomDrawCoinSelection::default()
+            .seed(42)
             .coin_select(
-                required,
-                optional,
+                vec![],
+                utxos,
                 FeeRate::from_sat_per_vb(1.0),
                 target_amount,
                 &drain_script,
             )
             .unwrap();
 
+        // the utxo worth less than its fee is never picked
         assert_eq!(result.selected.len(), 2);
         assert_eq!(result.selected_amount(), 300_000);
         assert_eq!(result.fee_amount, 136);
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_bnb_coin_selection_insufficient_funds() {
-        let utxos = get_test_utxos();
+    fn test_single_random_draw_coin_selection_min_change() {
         let drain_script = ScriptBuf::default();
-        let target_amount = 500_000 + FEE_AMOUNT;
+        let target_amount = 150_000 + FEE_AMOUNT;
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
 
-        BranchAndBoundCoinSelection::default()
+        let result = SingleRandomDrawCoinSelection::default()
+            .seed(42)
             .coin_select(
                 vec![],
-                utxos,
-                FeeRate::from_sat_per_vb(1.0),
+                generate_same_value_utxos(100_000, 10),
+                fee_rate,
                 target_amount,
                 &drain_script,
             )
             .unwrap();
-    }
-
-    #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_bnb_coin_selection_insufficient_funds_high_fees() {
-        let utxos = get_test_utxos();
-        let drain_script = ScriptBuf::default();
-        let target_amount = 250_000 + FEE_AMOUNT;
+        // two utxos would leave less than `MIN_CHANGE` as change
+        assert_eq!(result.selected.len(), 3);
+        assert_matches!(result.excess, Excess::Change { amount, .. } if amount > MIN_CHANGE);
 
-        BranchAndBoundCoinSelection::default()
+        let result = SingleRandomDrawCoinSelection::default()
+            .min_change(0)
+            .seed(42)
             .coin_select(
                 vec![],
-                utxos,
-                FeeRate::from_sat_per_vb(1000.0),
+                generate_same_value_utxos(100_000, 10),
+                fee_rate,
                 target_amount,
                 &drain_script,
             )
             .unwrap();
+        assert_eq!(result.selected.len(), 2);
     }
 
     #[test]
-    fn test_bnb_coin_selection_check_fee_rate() {
+    fn test_single_random_draw_coin_selection_required_are_enough() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 99932; // first utxo's effective value
+        let target_amount = 20_000 + FEE_AMOUNT;
 
-        let result = BranchAndBoundCoinSelection::new(0)
+        let result = SingleRandomDrawCoinSelection::default()
             .coin_select(
-                vec![],
-                utxos,
+                vec![utxos[2].clone()],
+                utxos[..2].to_vec(),
                 FeeRate::from_sat_per_vb(1.0),
                 target_amount,
                 &drain_script,
@@ -1214,267 +2390,421 @@ mod test {
             .unwrap();
 
         assert_eq!(result.selected.len(), 1);
-        assert_eq!(result.selected_amount(), 100_000);
-        let input_size = (TXIN_BASE_WEIGHT + P2WPKH_SATISFACTION_SIZE).vbytes();
-        // the final fee rate should be exactly the same as the fee rate given
-        assert!((1.0 - (result.fee_amount as f32 / input_size as f32)).abs() < f32::EPSILON);
+        assert_eq!(result.selected_amount(), 200_000);
     }
 
     #[test]
-    fn test_bnb_coin_selection_exact_match() {
-        let seed = [0; 32];
-        let mut rng: StdRng = SeedableRng::from_seed(seed);
+    fn test_single_random_draw_coin_selection_insufficient_funds() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 500_000 + FEE_AMOUNT;
 
-        for _i in 0..200 {
-            let mut optional_utxos = generate_random_utxos(&mut rng, 16);
-            let target_amount = sum_random_utxos(&mut rng, &mut optional_utxos);
-            let drain_script = ScriptBuf::default();
-            let result = BranchAndBoundCoinSelection::new(0)
-                .coin_select(
-                    vec![],
-                    optional_utxos,
-                    FeeRate::from_sat_per_vb(0.0),
-                    target_amount,
-                    &drain_script,
-                )
-                .unwrap();
-            assert_eq!(result.selected_amount(), target_amount);
-        }
+        let result = SingleRandomDrawCoinSelection::default().coin_select(
+            vec![],
+            utxos,
+            FeeRate::from_sat_per_vb(1.0),
+            target_amount,
+            &drain_script,
+        );
+        assert_matches!(result, Err(Error::InsufficientFunds { .. }));
     }
 
     #[test]
-    #[should_panic(expected = "BnBNoExactMatch")]
-    fn test_bnb_function_no_exact_match() {
-        let fee_rate = FeeRate::from_sat_per_vb(10.0);
-        let utxos: Vec<OutputGroup> = get_test_utxos()
-            .into_iter()
-            .map(|u| OutputGroup::new(u, fee_rate))
-            .collect();
-
-        let curr_available_value = utxos.iter().fold(0, |acc, x| acc + x.effective_value);
-
-        let size_of_change = 31;
-        let cost_of_change = size_of_change as f32 * fee_rate.as_sat_per_vb();
-
+    fn test_knapsack_coin_selection_exact_match() {
+        let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
-        BranchAndBoundCoinSelection::new(size_of_change)
-            .bnb(
+        // the effective value of the 100_000 sats utxo
+        let target_amount = 100_000 - 68;
+
+        let result = KnapsackCoinSelection::default()
+            .seed(42)
+            .coin_select(
                 vec![],
                 utxos,
-                0,
-                curr_available_value,
-                target_amount as i64,
-                cost_of_change,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
                 &drain_script,
-                fee_rate,
             )
             .unwrap();
-    }
-
-    #[test]
-    #[should_panic(expected = "BnBTotalTriesExceeded")]
-    fn test_bnb_function_tries_exceeded() {
-        let fee_rate = FeeRate::from_sat_per_vb(10.0);
-        let utxos: Vec<OutputGroup> = generate_same_value_utxos(100_000, 100_000)
-            .into_iter()
-            .map(|u| OutputGroup::new(u, fee_rate))
-            .collect();
-
-        let curr_available_value = utxos.iter().fold(0, |acc, x| acc + x.effective_value);
 
-        let size_of_change = 31;
-        let cost_of_change = size_of_change as f32 * fee_rate.as_sat_per_vb();
-        let target_amount = 20_000 + FEE_AMOUNT;
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 100_000);
+        assert_matches!(
+            result.excess,
+            Excess::NoChange {
+                remaining_amount: 0,
+                ..
+            }
+        );
+    }
 
+    #[test]
+    fn test_knapsack_coin_selection_lowest_larger() {
         let drain_script = ScriptBuf::default();
+        let target_amount = 500_000 + FEE_AMOUNT;
+        let utxos = vec![
+            utxo(100_000, 0, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+            utxo(200_000, 1, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+            utxo(1_000_000, 2, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+            utxo(2_000_000, 3, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+        ];
 
-        BranchAndBoundCoinSelection::new(size_of_change)
-            .bnb(
+        let result = KnapsackCoinSelection::default()
+            .seed(42)
+            .coin_select(
                 vec![],
                 utxos,
-                0,
-                curr_available_value,
-                target_amount as i64,
-                cost_of_change,
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
                 &drain_script,
-                fee_rate,
             )
             .unwrap();
+
+        // the smaller utxos aren't enough
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 1_000_000);
     }
 
-    // The match won't be exact but still in the range
     #[test]
-    fn test_bnb_function_almost_exact_match_with_fees() {
-        let fee_rate = FeeRate::from_sat_per_vb(1.0);
-        let size_of_change = 31;
-        let cost_of_change = size_of_change as f32 * fee_rate.as_sat_per_vb();
-
-        let utxos: Vec<_> = generate_same_value_utxos(50_000, 10)
-            .into_iter()
-            .map(|u| OutputGroup::new(u, fee_rate))
-            .collect();
-
-        let curr_value = 0;
-
-        let curr_available_value = utxos.iter().fold(0, |acc, x| acc + x.effective_value);
-
-        // 2*(value of 1 utxo)  - 2*(1 utxo fees with 1.0sat/vbyte fee rate) -
-        // cost_of_change + 5.
-        let target_amount = 2 * 50_000 - 2 * 67 - cost_of_change.ceil() as i64 + 5;
-
+    fn test_knapsack_coin_selection_subset_with_min_change() {
         let drain_script = ScriptBuf::default();
+        let target_amount = 250_000 + FEE_AMOUNT;
 
-        let result = BranchAndBoundCoinSelection::new(size_of_change)
-            .bnb(
+        let result = KnapsackCoinSelection::default()
+            .seed(42)
+            .coin_select(
                 vec![],
-                utxos,
-                curr_value,
-                curr_available_value,
+                generate_same_value_utxos(100_000, 10),
+                FeeRate::from_sat_per_vb(1.0),
                 target_amount,
-                cost_of_change,
                 &drain_script,
-                fee_rate,
             )
             .unwrap();
-        assert_eq!(result.selected_amount(), 100_000);
-        assert_eq!(result.fee_amount, 136);
+
+        // three utxos would leave less than `MIN_CHANGE` as change
+        assert_eq!(result.selected.len(), 4);
+        assert_matches!(result.excess, Excess::Change { amount, .. } if amount > MIN_CHANGE);
     }
 
-    // TODO: bnb() function should be optimized, and this test should be done with more utxos
     #[test]
-    fn test_bnb_function_exact_match_more_utxos() {
-        let seed = [0; 32];
-        let mut rng: StdRng = SeedableRng::from_seed(seed);
-        let fee_rate = FeeRate::from_sat_per_vb(0.0);
-
-        for _ in 0..200 {
-            let optional_utxos: Vec<_> = generate_random_utxos(&mut rng, 40)
-                .into_iter()
-                .map(|u| OutputGroup::new(u, fee_rate))
-                .collect();
-
-            let curr_value = 0;
-
-            let curr_available_value = optional_utxos
-                .iter()
-                .fold(0, |acc, x| acc + x.effective_value);
-
-            let target_amount =
-                optional_utxos[3].effective_value + optional_utxos[23].effective_value;
-
-            let drain_script = ScriptBuf::default();
-
-            let result = BranchAndBoundCoinSelection::new(0)
-                .bnb(
+    fn test_knapsack_coin_selection_random_utxos() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let drain_script = ScriptBuf::default();
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        for _ in 0..20 {
+            let mut optional_utxos = generate_random_utxos(&mut rng, 16);
+            let target_amount = sum_random_utxos(&mut rng, &mut optional_utxos);
+            let result = KnapsackCoinSelection::default()
+                .seed(rng.next_u64())
+                .coin_select(
                     vec![],
                     optional_utxos,
-                    curr_value,
-                    curr_available_value,
+                    fee_rate,
                     target_amount,
-                    0.0,
                     &drain_script,
-                    fee_rate,
                 )
                 .unwrap();
-            assert_eq!(result.selected_amount(), target_amount as u64);
+            assert!(result.selected_amount() >= target_amount + result.fee_amount);
         }
     }
 
     #[test]
-    fn test_single_random_draw_function_success() {
-        let seed = [0; 32];
-        let mut rng: StdRng = SeedableRng::from_seed(seed);
-        let mut utxos = generate_random_utxos(&mut rng, 300);
-        let target_amount = sum_random_utxos(&mut rng, &mut utxos) + FEE_AMOUNT;
+    fn test_knapsack_coin_selection_required_are_enough() {
+        let utxos = get_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let target_amount = 20_000 + FEE_AMOUNT;
 
-        let fee_rate = FeeRate::from_sat_per_vb(1.0);
-        let utxos: Vec<OutputGroup> = utxos
-            .into_iter()
-            .map(|u| OutputGroup::new(u, fee_rate))
-            .collect();
+        let result = KnapsackCoinSelection::default()
+            .coin_select(
+                vec![utxos[2].clone()],
+                utxos[..2].to_vec(),
+                FeeRate::from_sat_per_vb(1.0),
+                target_amount,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 200_000);
+    }
 
+    #[test]
+    fn test_knapsack_coin_selection_insufficient_funds() {
+        let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
+        let target_amount = 500_000 + FEE_AMOUNT;
 
-        let result = BranchAndBoundCoinSelection::default().single_random_draw(
+        let result = KnapsackCoinSelection::default().coin_select(
             vec![],
             utxos,
-            0,
-            target_amount as i64,
+            FeeRate::from_sat_per_vb(1.0),
+            target_amount,
             &drain_script,
-            fee_rate,
         );
+        assert_matches!(result, Err(Error::InsufficientFunds { .. }));
+    }
 
-        assert!(result.selected_amount() > target_amount);
-        assert_eq!(result.fee_amount, (result.selected.len() * 68) as u64);
+    fn p2wpkh(n: u8) -> ScriptBuf {
+        ScriptBuf::new_v0_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([n; 20]))
+    }
+
+    fn p2tr(n: u8) -> ScriptBuf {
+        // OP_1 OP_PUSHBYTES_32 <output key>
+        let mut script = vec![0x51, 0x20];
+        script.extend([n; 32]);
+        ScriptBuf::from_bytes(script)
+    }
+
+    fn privacy_utxo(value: u64, index: u32, script_pubkey: ScriptBuf) -> WeightedUtxo {
+        let mut weighted_utxo = utxo(value, index, ConfirmationTime::Unconfirmed { last_seen: 0 });
+        if let Utxo::Local(local) = &mut weighted_utxo.utxo {
+            local.txout.script_pubkey = script_pubkey;
+        }
+        weighted_utxo
+    }
+
+    fn script_pubkeys(result: &CoinSelectionResult) -> Vec<ScriptBuf> {
+        let mut script_pubkeys = result
+            .selected
+            .iter()
+            .map(|u| u.txout().script_pubkey.clone())
+            .collect::<Vec<_>>();
+        script_pubkeys.sort();
+        script_pubkeys
     }
 
     #[test]
-    fn test_bnb_exclude_negative_effective_value() {
-        let utxos = get_test_utxos();
-        let drain_script = ScriptBuf::default();
+    fn test_privacy_coin_selection_spends_script_pubkey_together() {
+        let utxos = vec![
+            privacy_utxo(50_000, 0, p2wpkh(1)),
+            privacy_utxo(50_000, 1, p2wpkh(1)),
+            privacy_utxo(80_000, 2, p2wpkh(2)),
+        ];
+
+        let result = PrivacyCoinSelection::new()
+            .coin_select(
+                vec![],
+                utxos.clone(),
+                FeeRate::from_sat_per_vb(1.0),
+                30_000,
+                &p2wpkh(3),
+            )
+            .unwrap();
+        assert_eq!(script_pubkeys(&result), vec![p2wpkh(1), p2wpkh(1)]);
 
-        let selection = BranchAndBoundCoinSelection::default().coin_select(
+        // a required utxo brings the others on its script pubkey along
+        let result = PrivacyCoinSelection::new()
+            .coin_select(
+                vec![utxos[2].clone(), utxos[0].clone()],
+                vec![utxos[1].clone()],
+                FeeRate::from_sat_per_vb(1.0),
+                30_000,
+                &p2wpkh(3),
+            )
+            .unwrap();
+        assert_eq!(
+            script_pubkeys(&result),
+            vec![p2wpkh(1), p2wpkh(1), p2wpkh(2)]
+        );
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_single_script_type() {
+        let utxos = vec![
+            privacy_utxo(100_000, 0, p2wpkh(1)),
+            privacy_utxo(100_000, 1, p2tr(1)),
+            privacy_utxo(100_000, 2, p2tr(2)),
+        ];
+
+        let result = PrivacyCoinSelection::new()
+            .coin_select(
+                vec![],
+                utxos.clone(),
+                FeeRate::from_sat_per_vb(1.0),
+                150_000,
+                &p2wpkh(3),
+            )
+            .unwrap();
+        assert_eq!(script_pubkeys(&result), vec![p2tr(1), p2tr(2)]);
+
+        // the inputs of the same type as the change are preferred
+        let result = PrivacyCoinSelection::new()
+            .coin_select(
+                vec![],
+                utxos.clone(),
+                FeeRate::from_sat_per_vb(1.0),
+                50_000,
+                &p2wpkh(3),
+            )
+            .unwrap();
+        assert_eq!(script_pubkeys(&result), vec![p2wpkh(1)]);
+
+        // mixing types is the last resort
+        let result = PrivacyCoinSelection::new()
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                250_000,
+                &p2wpkh(3),
+            )
+            .unwrap();
+        assert_eq!(result.selected.len(), 3);
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_avoids_mixing_sources() {
+        let utxos = vec![
+            privacy_utxo(100_000, 0, p2wpkh(1)),
+            privacy_utxo(120_000, 1, p2wpkh(2)),
+            privacy_utxo(100_000, 2, p2wpkh(3)),
+        ];
+        let coin_selection = PrivacyCoinSelection::with_sources(
+            utxos
+                .iter()
+                .zip(["alice", "bob", "alice"])
+                .map(|(u, source)| (u.utxo.outpoint(), String::from(source))),
+        );
+
+        let result = coin_selection
+            .coin_select(
+                vec![],
+                utxos.clone(),
+                FeeRate::from_sat_per_vb(1.0),
+                150_000,
+                &p2wpkh(4),
+            )
+            .unwrap();
+        assert_eq!(script_pubkeys(&result), vec![p2wpkh(1), p2wpkh(3)]);
+
+        let result = coin_selection
+            .coin_select(
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                250_000,
+                &p2wpkh(4),
+            )
+            .unwrap();
+        assert_eq!(result.selected.len(), 3);
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_insufficient_funds() {
+        let utxos = vec![
+            privacy_utxo(100_000, 0, p2wpkh(1)),
+            privacy_utxo(100_000, 1, p2tr(1)),
+        ];
+
+        let result = PrivacyCoinSelection::new().coin_select(
             vec![],
             utxos,
-            FeeRate::from_sat_per_vb(10.0),
+            FeeRate::from_sat_per_vb(1.0),
             500_000,
-            &drain_script,
+            &p2wpkh(3),
         );
+        assert_matches!(result, Err(Error::InsufficientFunds { .. }));
+    }
 
+    #[cfg(feature = "waste-metric")]
+    fn get_consolidation_test_utxos() -> Vec<WeightedUtxo> {
+        vec![
+            utxo(50_000, 0, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+            utxo(30_000, 1, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+            utxo(20_000, 2, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+        ]
+    }
+
+    #[test]
+    #[cfg(feature = "waste-metric")]
+    fn test_waste_metric_coin_selection_consolidates_when_cheap() {
+        let drain_script = ScriptBuf::default();
+
+        let result = WasteMetricCoinSelection::new(FeeRate::from_sat_per_vb(10.0))
+            .coin_select(
+                vec![],
+                get_consolidation_test_utxos(),
+                FeeRate::from_sat_per_vb(1.0),
+                49_500,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 50_000);
+        assert_eq!(result.fee_amount, 136);
         assert_matches!(
-            selection,
-            Err(Error::InsufficientFunds {
-                available: 300_000,
+            result.excess,
+            Excess::NoChange {
+                remaining_amount: 364,
                 ..
-            })
+            }
         );
+        // 2 inputs of 68 vbytes, spent at 1 sat/vbyte instead of 10, plus the excess
+        assert_eq!(result.waste, Some(2 * 68 * (1 - 10) + 364));
     }
 
     #[test]
-    fn test_bnb_include_negative_effective_value_when_required() {
-        let utxos = get_test_utxos();
+    #[cfg(feature = "waste-metric")]
+    fn test_waste_metric_coin_selection_avoids_inputs_when_expensive() {
         let drain_script = ScriptBuf::default();
 
-        let (required, optional) = utxos
-            .into_iter()
-            .partition(|u| matches!(u, WeightedUtxo { utxo, .. } if utxo.txout().value < 1000));
+        let result = WasteMetricCoinSelection::new(FeeRate::from_sat_per_vb(10.0))
+            .coin_select(
+                vec![],
+                get_consolidation_test_utxos(),
+                FeeRate::from_sat_per_vb(20.0),
+                46_000,
+                &drain_script,
+            )
+            .unwrap();
 
-        let selection = BranchAndBoundCoinSelection::default().coin_select(
-            required,
-            optional,
-            FeeRate::from_sat_per_vb(10.0),
-            500_000,
-            &drain_script,
-        );
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 50_000);
+        assert_eq!(result.fee_amount, 1360);
+        assert_matches!(result.excess, Excess::Change { .. });
+        // the change output costs 9 vbytes at 20 sat/vbyte, and 68 vbytes at 10 sat/vbyte to spend
+        assert_eq!(result.waste, Some(68 * (20 - 10) + 9 * 20 + 68 * 10));
+    }
 
-        assert_matches!(
-            selection,
-            Err(Error::InsufficientFunds {
-                available: 300_010,
-                ..
-            })
-        );
+    #[test]
+    #[cfg(feature = "waste-metric")]
+    fn test_waste_metric_coin_selection_required_utxos() {
+        let utxos = get_consolidation_test_utxos();
+        let drain_script = ScriptBuf::default();
+
+        let result = WasteMetricCoinSelection::default()
+            .coin_select(
+                vec![utxos[2].clone()],
+                utxos[..2].to_vec(),
+                FeeRate::from_sat_per_vb(1.0),
+                40_000,
+                &drain_script,
+            )
+            .unwrap();
+
+        assert!(result.selected.contains(&utxos[2].utxo));
+        assert!(result.selected_amount() >= 40_000 + result.fee_amount);
     }
 
     #[test]
-    fn test_bnb_sum_of_effective_value_negative() {
-        let utxos = get_test_utxos();
+    #[cfg(feature = "waste-metric")]
+    fn test_waste_metric_coin_selection_insufficient_funds() {
         let drain_script = ScriptBuf::default();
 
-        let selection = BranchAndBoundCoinSelection::default().coin_select(
-            utxos,
+        let selection = WasteMetricCoinSelection::default().coin_select(
             vec![],
-            FeeRate::from_sat_per_vb(10_000.0),
-            500_000,
+            get_consolidation_test_utxos(),
+            FeeRate::from_sat_per_vb(1.0),
+            100_000,
             &drain_script,
         );
 
         assert_matches!(
             selection,
             Err(Error::InsufficientFunds {
-                available: 300_010,
-                ..
+                needed: 100_204,
+                available: 100_000
             })
         );
     }
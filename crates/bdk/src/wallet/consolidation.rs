@@ -0,0 +1,194 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! UTXO consolidation
+//!
+//! Wallets receiving many small payments end up with many small UTXOs, which are expensive to spend
+//! once fees rise. [`Wallet::plan_consolidation`] proposes transactions sweeping them into fewer
+//! UTXOs while fees are low, along with the fees each of them is expected to save in the long term.
+//!
+//! [`Wallet::plan_consolidation`]: super::Wallet::plan_consolidation
+
+use alloc::vec::Vec;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::Weight;
+
+use super::coin_selection::TXIN_BASE_WEIGHT;
+use crate::types::{FeeRate, WeightedUtxo};
+
+/// A transaction proposed by [`Wallet::plan_consolidation`], sweeping some of the wallet's UTXOs
+/// into a single output to a new change address.
+///
+/// [`Wallet::plan_consolidation`]: super::Wallet::plan_consolidation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consolidation {
+    /// The unsigned sweep transaction
+    pub psbt: Psbt,
+    /// The fee paid by the sweep
+    pub fee: u64,
+    /// The fees the sweep is expected to save
+    ///
+    /// This is the fee spending the swept UTXOs would cost at the long term fee rate, minus the fee
+    /// of the sweep and the fee spending its output at the long term fee rate.
+    pub estimated_savings: u64,
+}
+
+/// The UTXOs swept by a transaction, and the fees the sweep is expected to save
+#[derive(Debug)]
+pub(crate) struct Sweep {
+    pub utxos: Vec<WeightedUtxo>,
+    /// The fees spending `utxos` would cost at the long term fee rate
+    pub long_term_fee: u64,
+}
+
+/// The weight of the input spending `weighted_utxo`
+pub(crate) fn input_weight(weighted_utxo: &WeightedUtxo) -> Weight {
+    Weight::from_wu((TXIN_BASE_WEIGHT + weighted_utxo.satisfaction_weight) as u64)
+}
+
+/// Splits `utxos`, in order, into sweeps weighing at most `max_weight` with their single output
+/// (`base_weight` is the weight of a transaction without inputs), and keeps the ones saving fees.
+///
+/// A sweep saves fees when spending its inputs at `long_term_fee_rate` costs more than the sweep
+/// at `fee_rate`, plus spending its output (an input weighing `output_spend_weight`) at
+/// `long_term_fee_rate`. Sweeps of less than two UTXOs don't consolidate anything and are dropped.
+pub(crate) fn plan_sweeps(
+    utxos: Vec<WeightedUtxo>,
+    fee_rate: FeeRate,
+    long_term_fee_rate: FeeRate,
+    max_weight: Weight,
+    base_weight: Weight,
+    output_spend_weight: Weight,
+) -> Vec<Sweep> {
+    let mut chunks = Vec::new();
+    let mut chunk: Vec<WeightedUtxo> = Vec::new();
+    let mut weight = base_weight;
+    for utxo in utxos {
+        let input_weight = input_weight(&utxo);
+        if !chunk.is_empty() && weight + input_weight > max_weight {
+            chunks.push(core::mem::take(&mut chunk));
+            weight = base_weight;
+        }
+        weight += input_weight;
+        chunk.push(utxo);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+        .into_iter()
+        .filter(|utxos| utxos.len() > 1)
+        .filter_map(|utxos| {
+            let inputs_weight = utxos
+                .iter()
+                .fold(Weight::ZERO, |acc, utxo| acc + input_weight(utxo));
+            let long_term_fee = utxos
+                .iter()
+                .map(|utxo| long_term_fee_rate.fee_wu(input_weight(utxo)))
+                .sum::<u64>();
+            let cost = fee_rate.fee_wu(base_weight + inputs_weight)
+                + long_term_fee_rate.fee_wu(output_spend_weight);
+            (long_term_fee > cost).then(|| Sweep {
+                utxos,
+                long_term_fee,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{KeychainKind, LocalOutput, Utxo};
+    use bdk_chain::ConfirmationTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, ScriptBuf, TxOut, Txid};
+
+    // 272 WU inputs
+    const P2WPKH_SATISFACTION_SIZE: usize = 1 + 1 + 72 + 1 + 33 + 4;
+    const BASE_WEIGHT: Weight = Weight::from_wu(200);
+    const OUTPUT_SPEND_WEIGHT: Weight = Weight::from_wu(272);
+
+    fn utxos(count: u32) -> Vec<WeightedUtxo> {
+        (0..count)
+            .map(|vout| WeightedUtxo {
+                satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
+                utxo: Utxo::Local(LocalOutput {
+                    outpoint: OutPoint::new(Txid::all_zeros(), vout),
+                    txout: TxOut {
+                        value: 10_000,
+                        script_pubkey: ScriptBuf::new(),
+                    },
+                    keychain: KeychainKind::External,
+                    is_spent: false,
+                    derivation_index: vout,
+                    confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
+                }),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_sweeps_max_weight() {
+        // room for 3 inputs per sweep, the last sweep gets the remaining 2
+        let sweeps = plan_sweeps(
+            utxos(8),
+            FeeRate::from_sat_per_vb(1.0),
+            FeeRate::from_sat_per_vb(50.0),
+            BASE_WEIGHT + Weight::from_wu(3 * 272),
+            BASE_WEIGHT,
+            OUTPUT_SPEND_WEIGHT,
+        );
+        let sizes: Vec<_> = sweeps.iter().map(|sweep| sweep.utxos.len()).collect();
+        assert_eq!(sizes, vec![3, 3, 2]);
+        assert_eq!(sweeps[0].long_term_fee, 3 * 68 * 50);
+    }
+
+    #[test]
+    fn test_plan_sweeps_single_utxo() {
+        // the last sweep would only have one input
+        let sweeps = plan_sweeps(
+            utxos(4),
+            FeeRate::from_sat_per_vb(1.0),
+            FeeRate::from_sat_per_vb(50.0),
+            BASE_WEIGHT + Weight::from_wu(3 * 272),
+            BASE_WEIGHT,
+            OUTPUT_SPEND_WEIGHT,
+        );
+        assert_eq!(sweeps.len(), 1);
+        assert_eq!(sweeps[0].utxos.len(), 3);
+    }
+
+    #[test]
+    fn test_plan_sweeps_no_savings() {
+        // spending later costs as much as spending now, the sweep only adds fees
+        let sweeps = plan_sweeps(
+            utxos(10),
+            FeeRate::from_sat_per_vb(10.0),
+            FeeRate::from_sat_per_vb(10.0),
+            Weight::MAX,
+            BASE_WEIGHT,
+            OUTPUT_SPEND_WEIGHT,
+        );
+        assert!(sweeps.is_empty());
+
+        // two inputs save less than the cost of the sweep and of spending its output
+        let sweeps = plan_sweeps(
+            utxos(2),
+            FeeRate::from_sat_per_vb(1.0),
+            FeeRate::from_sat_per_vb(2.0),
+            Weight::MAX,
+            BASE_WEIGHT,
+            OUTPUT_SPEND_WEIGHT,
+        );
+        assert!(sweeps.is_empty());
+    }
+}
@@ -14,7 +14,7 @@
 use crate::descriptor::policy::PolicyError;
 use crate::descriptor::DescriptorError;
 use crate::wallet::coin_selection;
-use crate::{descriptor, FeeRate, KeychainKind};
+use crate::{descriptor, silent_payments, FeeRate, KeychainKind};
 use alloc::string::String;
 use bitcoin::{absolute, psbt, OutPoint, Sequence, Txid};
 use core::fmt;
@@ -115,8 +115,14 @@ pub enum CreateTxError<P> {
     UnknownUtxo,
     /// Missing non_witness_utxo on foreign utxo for given `OutPoint`
     MissingNonWitnessUtxo(OutPoint),
+    /// A manually selected UTXO can't be spent with the assets given to the [`TxBuilder`]
+    ///
+    /// [`TxBuilder`]: crate::wallet::tx_builder::TxBuilder
+    NoSpendingPlan(OutPoint),
     /// Miniscript PSBT error
     MiniscriptPsbt(MiniscriptPsbtError),
+    /// Error computing the outputs of the silent payment recipients
+    SilentPayment(silent_payments::SendError),
 }
 
 impl<P> fmt::Display for CreateTxError<P>
@@ -205,9 +211,17 @@ where
             CreateTxError::MissingNonWitnessUtxo(outpoint) => {
                 write!(f, "Missing non_witness_utxo on foreign utxo {}", outpoint)
             }
+            CreateTxError::NoSpendingPlan(outpoint) => {
+                write!(
+                    f,
+                    "No spending plan for utxo {} with the given assets",
+                    outpoint
+                )
+            }
             CreateTxError::MiniscriptPsbt(err) => {
                 write!(f, "Miniscript PSBT error: {}", err)
             }
+            CreateTxError::SilentPayment(err) => err.fmt(f),
         }
     }
 }
@@ -236,6 +250,12 @@ impl<P> From<psbt::Error> for CreateTxError<P> {
     }
 }
 
+impl<P> From<silent_payments::SendError> for CreateTxError<P> {
+    fn from(err: silent_payments::SendError) -> Self {
+        CreateTxError::SilentPayment(err)
+    }
+}
+
 impl<P> From<coin_selection::Error> for CreateTxError<P> {
     fn from(err: coin_selection::Error) -> Self {
         CreateTxError::CoinSelection(err)
@@ -290,3 +310,84 @@ impl fmt::Display for BuildFeeBumpError {
 
 #[cfg(feature = "std")]
 impl std::error::Error for BuildFeeBumpError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::build_cpfp`]
+///
+/// [`Wallet::build_cpfp`]: super::Wallet::build_cpfp
+pub enum BuildCpfpError {
+    /// Thrown when a tx is not found in the internal database
+    TransactionNotFound(Txid),
+    /// Happens when trying to pay for a transaction that is already confirmed
+    TransactionConfirmed(Txid),
+    /// The transaction has no unspent output owned by the wallet, or they are all frozen
+    NoSpendableOutput(Txid),
+    /// The fee of the transaction with this txid, the parent or one of its unconfirmed ancestors,
+    /// can't be calculated because the wallet doesn't know the outputs it spends. They can be
+    /// added with [`Wallet::insert_txout`].
+    ///
+    /// [`Wallet::insert_txout`]: super::Wallet::insert_txout
+    MissingPrevouts(Txid),
+}
+
+impl fmt::Display for BuildCpfpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TransactionNotFound(txid) => {
+                write!(
+                    f,
+                    "Transaction not found in the internal database with txid: {}",
+                    txid
+                )
+            }
+            Self::TransactionConfirmed(txid) => {
+                write!(f, "Transaction already confirmed with txid: {}", txid)
+            }
+            Self::NoSpendableOutput(txid) => {
+                write!(f, "Transaction has no spendable output with txid: {}", txid)
+            }
+            Self::MissingPrevouts(txid) => write!(
+                f,
+                "Outputs spent by the transaction with txid {} are unknown",
+                txid
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildCpfpError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::build_batch`]
+///
+/// [`Wallet::build_batch`]: super::Wallet::build_batch
+pub enum BuildBatchError {
+    /// There are no queued payments to batch
+    NoQueuedPayments,
+    /// The last batch is neither confirmed nor unconfirmed in the wallet, so it can be neither
+    /// replaced nor followed by a new batch
+    ///
+    /// Either the last batch is not inserted into the wallet yet (e.g. it is not broadcast), or it
+    /// was replaced by a conflicting transaction. See [`Wallet::cancel_batch`].
+    ///
+    /// [`Wallet::cancel_batch`]: super::Wallet::cancel_batch
+    LastBatchUnknown,
+    /// The last batch can't be replaced
+    FeeBump(BuildFeeBumpError),
+}
+
+impl fmt::Display for BuildBatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoQueuedPayments => write!(f, "There are no queued payments"),
+            Self::LastBatchUnknown => {
+                write!(f, "Last batch is not in the wallet's best chain or mempool")
+            }
+            Self::FeeBump(err) => write!(f, "Last batch can't be replaced: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildBatchError {}
@@ -0,0 +1,133 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet events
+//!
+//! [`Wallet::apply_update`] returns the [`WalletEvent`]s caused by the update, so that applications
+//! can notify their users without comparing the wallet's transactions and balance by hand.
+//!
+//! [`Wallet::apply_update`]: super::Wallet::apply_update
+
+use crate::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use bdk_chain::keychain::Balance;
+use bdk_chain::{Anchor, ChainPosition, ConfirmationTimeHeightAnchor, TxGraph};
+use bitcoin::Txid;
+
+/// An event caused by applying an update to the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletEvent {
+    /// A new transaction was added to the wallet.
+    TxReceived {
+        /// The txid of the transaction.
+        txid: Txid,
+        /// Where the transaction is in the wallet's best chain or mempool.
+        chain_position: ChainPosition<ConfirmationTimeHeightAnchor>,
+    },
+    /// A transaction was confirmed, or moved to another block by a reorg.
+    TxConfirmed {
+        /// The txid of the transaction.
+        txid: Txid,
+        /// The confirmation height.
+        height: u32,
+        /// The confirmation time of the block.
+        time: u64,
+    },
+    /// A transaction was replaced or otherwise conflicted, it is not part of the wallet's history
+    /// anymore.
+    TxReplaced {
+        /// The txid of the transaction.
+        txid: Txid,
+        /// The transactions of the wallet's history that conflict with it. This is empty when it
+        /// was evicted because one of its ancestors was replaced.
+        replaced_by: Vec<Txid>,
+    },
+    /// A confirmed transaction is unconfirmed again because its block was reorged out of the chain.
+    TxDropped {
+        /// The txid of the transaction.
+        txid: Txid,
+    },
+    /// The balance of the wallet changed.
+    BalanceChanged {
+        /// The balance before the update.
+        old: Balance,
+        /// The balance after the update.
+        new: Balance,
+    },
+}
+
+/// Canonical transactions of the wallet with their chain positions.
+pub(crate) type TxPositions = BTreeMap<Txid, ChainPosition<ConfirmationTimeHeightAnchor>>;
+
+/// Compute the events between two states of the wallet: the events of each transaction come in
+/// txid order, a [`WalletEvent::BalanceChanged`] comes last.
+pub(crate) fn diff(
+    graph: &TxGraph<ConfirmationTimeHeightAnchor>,
+    old: (&TxPositions, Balance),
+    new: (&TxPositions, Balance),
+) -> Vec<WalletEvent> {
+    let (old_txs, old_balance) = old;
+    let (new_txs, new_balance) = new;
+    let mut events = Vec::new();
+
+    let txids = old_txs
+        .keys()
+        .chain(new_txs.keys())
+        .collect::<BTreeSet<_>>();
+    for &txid in txids {
+        match (old_txs.get(&txid), new_txs.get(&txid)) {
+            (None, Some(&chain_position)) => events.push(WalletEvent::TxReceived {
+                txid,
+                chain_position,
+            }),
+            (Some(ChainPosition::Confirmed(old)), Some(ChainPosition::Confirmed(new)))
+                if same_block(old, new) => {}
+            (_, Some(ChainPosition::Confirmed(anchor))) => events.push(WalletEvent::TxConfirmed {
+                txid,
+                height: anchor.confirmation_height,
+                time: anchor.confirmation_time,
+            }),
+            (Some(ChainPosition::Confirmed(_)), Some(ChainPosition::Unconfirmed(_))) => {
+                events.push(WalletEvent::TxDropped { txid })
+            }
+            (Some(_), None) => {
+                let replaced_by = match graph.get_tx(txid) {
+                    Some(tx) => graph
+                        .walk_conflicts(tx, |_, conflict| Some(conflict))
+                        .filter(|conflict| new_txs.contains_key(conflict))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                events.push(WalletEvent::TxReplaced { txid, replaced_by })
+            }
+            _ => {}
+        }
+    }
+
+    if old_balance != new_balance {
+        events.push(WalletEvent::BalanceChanged {
+            old: old_balance,
+            new: new_balance,
+        });
+    }
+    events
+}
+
+/// Whether two anchors of a transaction confirm it in the same block. Anchors to a later block than
+/// the confirmation block only tell the confirmation height and time.
+fn same_block(old: &ConfirmationTimeHeightAnchor, new: &ConfirmationTimeHeightAnchor) -> bool {
+    let anchors_confirmation_block =
+        |a: &ConfirmationTimeHeightAnchor| a.anchor_block().height == a.confirmation_height;
+    if anchors_confirmation_block(old) && anchors_confirmation_block(new) {
+        return old.anchor_block == new.anchor_block;
+    }
+    old.confirmation_height == new.confirmation_height
+        && old.confirmation_time == new.confirmation_time
+}
@@ -0,0 +1,150 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Transaction history
+//!
+//! [`Wallet::history`] lists the wallet's transactions as [`TxSummary`]s, one page at a time,
+//! filtered and ordered as asked by a [`HistoryQuery`].
+//!
+//! ```
+//! # use bdk::wallet::history::{HistoryOrder, HistoryQuery};
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let wallet = doctest_wallet!();
+//! // the second page of 10 transactions confirmed in the first 100_000 blocks, oldest first
+//! let page = wallet.history(&HistoryQuery {
+//!     heights: Some(0..=100_000),
+//!     order: HistoryOrder::OldestFirst,
+//!     offset: 10,
+//!     limit: Some(10),
+//!     ..Default::default()
+//! });
+//! for summary in page {
+//!     println!("{}: {} sats", summary.txid, summary.net_value);
+//! }
+//! ```
+//!
+//! [`Wallet::history`]: super::Wallet::history
+
+use crate::collections::BTreeSet;
+use crate::types::{FeeRate, KeychainKind};
+use crate::wallet::labels::Label;
+use alloc::vec::Vec;
+use bdk_chain::ConfirmationTime;
+use bitcoin::Txid;
+use core::ops::RangeInclusive;
+
+/// The order of the transactions returned by [`Wallet::history`].
+///
+/// Transactions are ordered by confirmation height, unconfirmed transactions by the time they were
+/// last seen and come after the confirmed ones. Transactions at the same position are ordered by
+/// txid.
+///
+/// [`Wallet::history`]: super::Wallet::history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryOrder {
+    /// Most recent transactions first.
+    NewestFirst,
+    /// Oldest transactions first.
+    OldestFirst,
+}
+
+impl Default for HistoryOrder {
+    fn default() -> Self {
+        Self::NewestFirst
+    }
+}
+
+/// Which transactions [`Wallet::history`] returns.
+///
+/// The default query returns all of the wallet's transactions, newest first.
+///
+/// [`Wallet::history`]: super::Wallet::history
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    /// Only return transactions confirmed at these heights. Unconfirmed transactions are excluded
+    /// when this is set.
+    pub heights: Option<RangeInclusive<u32>>,
+    /// Only return transactions confirmed in blocks with these timestamps, or last seen unconfirmed
+    /// at these times.
+    pub times: Option<RangeInclusive<u64>>,
+    /// The order of the transactions.
+    pub order: HistoryOrder,
+    /// Whether to also return the transactions which were replaced or conflicted and so are not part
+    /// of the wallet's history anymore.
+    pub include_replaced: bool,
+    /// The number of transactions to skip, for pagination.
+    pub offset: usize,
+    /// The maximum number of transactions to return, for pagination.
+    pub limit: Option<usize>,
+}
+
+/// A summary of a wallet transaction returned by [`Wallet::history`].
+///
+/// [`Wallet::history`]: super::Wallet::history
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxSummary {
+    /// The txid of the transaction.
+    pub txid: Txid,
+    /// The value the transaction sends from the wallet's script pubkeys.
+    pub sent: u64,
+    /// The value the transaction sends to the wallet's script pubkeys.
+    pub received: u64,
+    /// The net change of the wallet's balance, i.e. `received - sent`.
+    pub net_value: i64,
+    /// The fee of the transaction, or `None` if some of its previous outputs are unknown.
+    pub fee: Option<u64>,
+    /// The fee rate of the transaction, or `None` if some of its previous outputs are unknown.
+    pub fee_rate: Option<FeeRate>,
+    /// Whether the transaction is confirmed, with the time it was last seen if not.
+    pub confirmation_time: ConfirmationTime,
+    /// The number of confirmations relative to the wallet's tip, `0` if it is unconfirmed.
+    pub confirmations: u32,
+    /// The keychains of the wallet's inputs and outputs of the transaction.
+    pub keychains: BTreeSet<KeychainKind>,
+    /// The transactions this one conflicts with which are not part of the history, i.e. the ones it
+    /// replaced.
+    pub replaces: Vec<Txid>,
+    /// The transactions of the history this one conflicts with, i.e. the ones that replaced it.
+    ///
+    /// This is only ever non-empty with [`HistoryQuery::include_replaced`], and may be empty for a
+    /// replaced transaction if it was evicted because one of its ancestors was replaced.
+    pub replaced_by: Vec<Txid>,
+    /// The label of the transaction, see [`Wallet::set_label`].
+    ///
+    /// [`Wallet::set_label`]: super::Wallet::set_label
+    pub label: Option<Label>,
+}
+
+impl HistoryQuery {
+    pub(crate) fn matches(&self, confirmation_time: &ConfirmationTime) -> bool {
+        let (height, time) = match *confirmation_time {
+            ConfirmationTime::Confirmed { height, time } => (Some(height), time),
+            ConfirmationTime::Unconfirmed { last_seen } => (None, last_seen),
+        };
+        let height_matches = match &self.heights {
+            Some(heights) => height.map_or(false, |height| heights.contains(&height)),
+            None => true,
+        };
+        let time_matches = match &self.times {
+            Some(times) => times.contains(&time),
+            None => true,
+        };
+        height_matches && time_matches
+    }
+}
+
+/// The key ordering transactions from the oldest to the newest.
+pub(crate) fn order_key(confirmation_time: &ConfirmationTime, txid: Txid) -> (bool, u64, Txid) {
+    match *confirmation_time {
+        ConfirmationTime::Confirmed { height, .. } => (false, height as u64, txid),
+        ConfirmationTime::Unconfirmed { last_seen } => (true, last_seen, txid),
+    }
+}
@@ -0,0 +1,420 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet labels
+//!
+//! Labels attach a text to transactions, addresses, public keys, inputs, outputs and extended
+//! public keys, as defined by [BIP329]. They are set with [`Wallet::set_label`] and persisted with
+//! the rest of the wallet's [`ChangeSet`](super::ChangeSet). Transaction labels are part of
+//! [`Wallet::history`], the others are looked up with [`Wallet::label`].
+//!
+//! Labels are exported to and imported from the BIP329 JSON Lines format, so that they can move
+//! between wallet software. The BIP329 `spendable` flag of an output is the wallet's frozen state
+//! of the output: importing `"spendable":false` freezes it with [`Wallet::freeze_utxo`], and frozen
+//! outputs are exported with `"spendable":false`.
+//!
+//! ```
+//! # use bdk::wallet::labels::LabelRef;
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let mut wallet = doctest_wallet!();
+//! let import = r#"{"type":"addr","ref":"2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt","label":"Alice"}"#;
+//! assert_eq!(wallet.import_labels(import)?.imported, 1);
+//!
+//! let address = wallet.get_address(wallet::AddressIndex::New).address;
+//! wallet.set_label(LabelRef::address(&address), "Deposit");
+//!
+//! for line in wallet.export_labels().lines() {
+//!     println!("{}", line);
+//! }
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP329]: https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+//! [`Wallet::set_label`]: super::Wallet::set_label
+//! [`Wallet::history`]: super::Wallet::history
+//! [`Wallet::label`]: super::Wallet::label
+//! [`Wallet::freeze_utxo`]: super::Wallet::freeze_utxo
+
+use crate::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bdk_chain::Append;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::ExtendedPubKey;
+use bitcoin::{Address, Network, OutPoint, PublicKey, Txid};
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// What a label refers to.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LabelRef {
+    /// A transaction.
+    Tx(Txid),
+    /// An address.
+    Address(Address<NetworkUnchecked>),
+    /// A public key.
+    PubKey(PublicKey),
+    /// A transaction input, referred to by the output it spends.
+    Input(OutPoint),
+    /// A transaction output.
+    Output(OutPoint),
+    /// An extended public key.
+    Xpub(ExtendedPubKey),
+}
+
+impl LabelRef {
+    /// Refer to `address`.
+    pub fn address(address: &Address) -> Self {
+        Self::Address(
+            Address::from_str(&address.to_string()).expect("a valid address must parse back"),
+        )
+    }
+
+    /// The BIP329 `type` of the reference.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Tx(_) => "tx",
+            Self::Address(_) => "addr",
+            Self::PubKey(_) => "pubkey",
+            Self::Input(_) => "input",
+            Self::Output(_) => "output",
+            Self::Xpub(_) => "xpub",
+        }
+    }
+
+    /// Parse a BIP329 `type` and `ref`.
+    ///
+    /// Returns `Ok(None)` if the type is unknown, and an error if `reference` is not valid for
+    /// `type_name`.
+    pub fn from_bip329(type_name: &str, reference: &str) -> Result<Option<Self>, InvalidLabelRef> {
+        let parsed = match type_name {
+            "tx" => Txid::from_str(reference).ok().map(Self::Tx),
+            "addr" => Address::from_str(reference).ok().map(Self::Address),
+            "pubkey" => PublicKey::from_str(reference).ok().map(Self::PubKey),
+            "input" => OutPoint::from_str(reference).ok().map(Self::Input),
+            "output" => OutPoint::from_str(reference).ok().map(Self::Output),
+            "xpub" => ExtendedPubKey::from_str(reference).ok().map(Self::Xpub),
+            _ => return Ok(None),
+        };
+        parsed.map(Some).ok_or_else(|| InvalidLabelRef {
+            type_name: type_name.to_string(),
+            reference: reference.to_string(),
+        })
+    }
+}
+
+/// Displays the BIP329 `ref` of the reference.
+impl fmt::Display for LabelRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tx(txid) => write!(f, "{}", txid),
+            Self::Address(address) => write!(f, "{}", address.clone().assume_checked()),
+            Self::PubKey(pubkey) => write!(f, "{}", pubkey),
+            Self::Input(outpoint) | Self::Output(outpoint) => write!(f, "{}", outpoint),
+            Self::Xpub(xpub) => write!(f, "{}", xpub),
+        }
+    }
+}
+
+/// A label, with the optional fields of BIP329.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Label {
+    /// The text of the label.
+    pub text: String,
+    /// The abbreviated descriptor the labeled item was derived from, e.g. `wpkh([d34db33f/84'/0'/0'])`.
+    pub origin: Option<String>,
+}
+
+impl From<String> for Label {
+    fn from(text: String) -> Self {
+        Self { text, origin: None }
+    }
+}
+
+impl From<&str> for Label {
+    fn from(text: &str) -> Self {
+        text.to_string().into()
+    }
+}
+
+/// The changes made to the wallet's labels, a `None` label is a removed one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeSet(pub BTreeMap<LabelRef, Option<Label>>);
+
+impl Append for ChangeSet {
+    fn append(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A BIP329 record, i.e. a line of the JSON Lines format.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(rename = "type")]
+    type_name: String,
+    #[serde(rename = "ref")]
+    reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spendable: Option<bool>,
+}
+
+/// Export `labels` in the BIP329 JSON Lines format, with the `frozen` outputs as not spendable.
+pub(crate) fn export<'a>(
+    labels: impl Iterator<Item = (&'a LabelRef, &'a Label)>,
+    frozen: &BTreeSet<OutPoint>,
+) -> String {
+    let mut jsonl = String::new();
+    let mut push = |reference: &LabelRef, label: Option<&Label>| {
+        let spendable = match reference {
+            LabelRef::Output(outpoint) if frozen.contains(outpoint) => Some(false),
+            _ => None,
+        };
+        let record = Record {
+            type_name: reference.type_name().to_string(),
+            reference: reference.to_string(),
+            label: label.map(|label| label.text.clone()),
+            origin: label.and_then(|label| label.origin.clone()),
+            spendable,
+        };
+        jsonl.push_str(&serde_json::to_string(&record).expect("a record must serialize"));
+        jsonl.push('\n');
+    };
+    let mut labeled_outputs = BTreeSet::new();
+    for (reference, label) in labels {
+        if let LabelRef::Output(outpoint) = reference {
+            labeled_outputs.insert(*outpoint);
+        }
+        push(reference, Some(label));
+    }
+    for outpoint in frozen.difference(&labeled_outputs) {
+        push(&LabelRef::Output(*outpoint), None);
+    }
+    jsonl
+}
+
+/// The records of a BIP329 JSON Lines import.
+#[derive(Debug, Default)]
+pub(crate) struct Import {
+    /// The labels of the records of known types.
+    pub labels: Vec<(LabelRef, Label)>,
+    /// The `spendable` flag of the output records which have one.
+    pub spendable: Vec<(OutPoint, bool)>,
+    /// The records skipped because they label an address of another network.
+    pub skipped: Vec<SkippedLabel>,
+}
+
+/// Parse the labels of `jsonl`, in the BIP329 JSON Lines format, skipping records of unknown
+/// types and addresses of another network than `network`.
+pub(crate) fn import(jsonl: &str, network: Network) -> Result<Import, ImportLabelsError> {
+    let mut import = Import::default();
+    for (i, line) in jsonl.lines().enumerate() {
+        let line_number = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(line).map_err(|error| ImportLabelsError::Json {
+                line: line_number,
+                error,
+            })?;
+        let reference =
+            match LabelRef::from_bip329(&record.type_name, &record.reference).map_err(|error| {
+                ImportLabelsError::InvalidRef {
+                    line: line_number,
+                    error,
+                }
+            })? {
+                Some(reference) => reference,
+                None => continue,
+            };
+        match &reference {
+            LabelRef::Address(address) if !address.is_valid_for_network(network) => {
+                import.skipped.push(SkippedLabel {
+                    line: line_number,
+                    reference: record.reference,
+                });
+                continue;
+            }
+            LabelRef::Output(outpoint) => {
+                if let Some(spendable) = record.spendable {
+                    import.spendable.push((*outpoint, spendable));
+                }
+            }
+            _ => {}
+        }
+        if let Some(text) = record.label {
+            import.labels.push((
+                reference,
+                Label {
+                    text,
+                    origin: record.origin,
+                },
+            ));
+        }
+    }
+    Ok(import)
+}
+
+/// The result of [`Wallet::import_labels`].
+///
+/// [`Wallet::import_labels`]: super::Wallet::import_labels
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelsImport {
+    /// The number of labels imported.
+    pub imported: usize,
+    /// The records skipped because they label an address of another network than the wallet's.
+    pub skipped: Vec<SkippedLabel>,
+}
+
+/// A BIP329 record skipped by [`Wallet::import_labels`] because it labels an address of another
+/// network than the wallet's.
+///
+/// [`Wallet::import_labels`]: super::Wallet::import_labels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedLabel {
+    /// The line number of the record, starting at 1.
+    pub line: usize,
+    /// The address.
+    pub reference: String,
+}
+
+/// A BIP329 `ref` which is not valid for its `type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLabelRef {
+    /// The BIP329 `type`.
+    pub type_name: String,
+    /// The invalid `ref`.
+    pub reference: String,
+}
+
+impl fmt::Display for InvalidLabelRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid {} label ref: {}",
+            self.type_name, self.reference
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidLabelRef {}
+
+/// Error returned from [`Wallet::import_labels`].
+///
+/// [`Wallet::import_labels`]: super::Wallet::import_labels
+#[derive(Debug)]
+pub enum ImportLabelsError {
+    /// A line is not a valid BIP329 JSON record.
+    Json {
+        /// The line number, starting at 1.
+        line: usize,
+        /// The JSON error.
+        error: serde_json::Error,
+    },
+    /// The `ref` of a record is not valid for its `type`.
+    InvalidRef {
+        /// The line number, starting at 1.
+        line: usize,
+        /// The invalid reference.
+        error: InvalidLabelRef,
+    },
+}
+
+impl fmt::Display for ImportLabelsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json { line, error } => {
+                write!(f, "invalid label record on line {}: {}", line, error)
+            }
+            Self::InvalidRef { line, error } => write!(f, "{} on line {}", error, line),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ImportLabelsError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bip329_round_trip() {
+        let jsonl = r#"{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Transaction","origin":"wpkh([d34db33f/84'/0'/0'])"}
+{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"Address"}
+{"type":"pubkey","ref":"0283409659355b6d1cc3c32decd5d561abaac86c37a353b52895a5e6c196d6f448","label":"Public Key"}
+{"type":"input","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0","label":"Input"}
+{"type":"output","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1","label":"Output","spendable":false}
+{"type":"xpub","ref":"xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8","label":"Extended Public Key"}
+"#;
+        let imported = import(jsonl, Network::Bitcoin).unwrap();
+        let labels = imported.labels;
+        assert_eq!(labels.len(), 6);
+        let outpoint = OutPoint::from_str(
+            "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1",
+        )
+        .unwrap();
+        assert_eq!(imported.spendable, vec![(outpoint, false)]);
+        assert_eq!(
+            labels[0].1.origin.as_deref(),
+            Some("wpkh([d34db33f/84'/0'/0'])")
+        );
+        let frozen = [outpoint].into();
+        assert_eq!(export(labels.iter().map(|(r, l)| (r, l)), &frozen), jsonl);
+    }
+
+    #[test]
+    fn bip329_import_errors() {
+        // unknown types are skipped, records without a label only carry their spendable flag
+        let imported = import(
+            "{\"type\":\"unknown\",\"ref\":\"x\"}\n\n{\"type\":\"input\",\"ref\":\"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0\",\"label\":\"\"}\n{\"type\":\"output\",\"ref\":\"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0\",\"spendable\":false}",
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert_eq!(imported.labels.len(), 1);
+        assert_eq!(imported.labels[0].1.text, "");
+        assert_eq!(imported.spendable.len(), 1);
+
+        assert!(matches!(
+            import("{\"type\":\"tx\"}", Network::Bitcoin),
+            Err(ImportLabelsError::Json { line: 1, .. })
+        ));
+        assert!(matches!(
+            import("\n{\"type\":\"tx\",\"ref\":\"00\"}", Network::Bitcoin),
+            Err(ImportLabelsError::InvalidRef { line: 2, .. })
+        ));
+
+        // addresses of another network are skipped
+        let imported = import(
+            "{\"type\":\"addr\",\"ref\":\"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c\",\"label\":\"a\"}\n{\"type\":\"addr\",\"ref\":\"2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt\",\"label\":\"b\"}",
+            Network::Testnet,
+        )
+        .unwrap();
+        assert_eq!(imported.labels.len(), 1);
+        assert_eq!(
+            imported.skipped,
+            vec![SkippedLabel {
+                line: 1,
+                reference: "bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c".into(),
+            }]
+        );
+    }
+}
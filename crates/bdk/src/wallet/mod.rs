@@ -12,7 +12,7 @@
 //! Wallet
 //!
 //! This module defines the [`Wallet`] structure.
-use crate::collections::{BTreeMap, HashMap, HashSet};
+use crate::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use alloc::{
     boxed::Box,
     string::{String, ToString},
@@ -25,26 +25,43 @@ use bdk_chain::{
     keychain::{self, KeychainTxOutIndex},
     local_chain::{self, CannotConnectError, CheckPoint, CheckPointIter, LocalChain},
     tx_graph::{CanonicalTx, TxGraph},
-    Append, BlockId, ChainPosition, ConfirmationTime, ConfirmationTimeHeightAnchor, FullTxOut,
-    IndexedTxGraph, Persist, PersistBackend,
+    Append, BlockId, BroadcastError, Broadcaster, ChainPosition, ConfirmationTime,
+    ConfirmationTimeHeightAnchor, DescriptorExt, FullTxOut, IndexedTxGraph, Persist,
+    PersistBackend,
 };
-use bitcoin::secp256k1::{All, Secp256k1};
+#[cfg(feature = "async")]
+use bdk_chain::{BroadcasterAsync, PersistBackendAsync};
+use bitcoin::key::{KeyPair, TapTweak, TweakedPublicKey, XOnlyPublicKey};
+use bitcoin::secp256k1::{All, Parity, Secp256k1, SecretKey};
 use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
 use bitcoin::{
     absolute, Address, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxOut, Txid,
     Weight, Witness,
 };
-use bitcoin::{consensus::encode::serialize, BlockHash};
+use bitcoin::{consensus::encode::serialize, hashes::Hash, BlockHash};
 use bitcoin::{constants::genesis_block, psbt};
 use core::fmt;
 use core::ops::Deref;
 use descriptor::error::Error as DescriptorError;
+#[cfg(feature = "planning")]
+use miniscript::descriptor::DescriptorPublicKey;
+use miniscript::descriptor::{Descriptor, ShInner};
 use miniscript::psbt::{PsbtExt, PsbtInputExt, PsbtInputSatisfier};
+use rand::Rng;
 
 use bdk_chain::tx_graph::CalculateFeeError;
+#[cfg(feature = "planning")]
+use bdk_tmp_plan::{Plan, RequiredSignatures};
 
 pub mod coin_selection;
+pub mod consolidation;
+pub mod event;
 pub mod export;
+pub mod history;
+pub mod labels;
+pub mod musig;
+pub mod payjoin;
+pub mod payment_queue;
 pub mod signer;
 pub mod tx_builder;
 pub(crate) mod utils;
@@ -58,20 +75,29 @@ pub use utils::IsDust;
 
 #[allow(deprecated)]
 use coin_selection::DefaultCoinSelectionAlgorithm;
+use consolidation::Consolidation;
+use event::WalletEvent;
+use history::{HistoryOrder, HistoryQuery, TxSummary};
+use labels::{ImportLabelsError, Label, LabelRef, LabelsImport};
+use payjoin::{PayjoinError, PayjoinParams};
+use payment_queue::{Batch, PaymentBatch, PaymentId, PaymentQueue, PaymentStatus};
 use signer::{SignOptions, SignerOrdering, SignersContainer, TransactionSigner};
-use tx_builder::{BumpFee, CreateTx, FeePolicy, TxBuilder, TxParams};
+use tx_builder::{BumpFee, CreateTx, FeePolicy, PayBatch, TxBuilder, TxParams};
 use utils::{check_nsequence_rbf, After, Older, SecpCtx};
 
-use crate::descriptor::policy::BuildSatisfaction;
+use crate::descriptor::policy::{BuildSatisfaction, Condition};
 use crate::descriptor::{
     self, calc_checksum, into_wallet_descriptor_checked, DerivedDescriptor, DescriptorMeta,
     ExtendedDescriptor, ExtractPolicy, IntoWalletDescriptor, Policy, XKeyUtils,
 };
 use crate::psbt::PsbtUtils;
 use crate::signer::SignerError;
+use crate::silent_payments::{self, SendError, SilentPaymentAddress};
 use crate::types::*;
 use crate::wallet::coin_selection::Excess::{Change, NoChange};
-use crate::wallet::error::{BuildFeeBumpError, CreateTxError, MiniscriptPsbtError};
+use crate::wallet::error::{
+    BuildBatchError, BuildCpfpError, BuildFeeBumpError, CreateTxError, MiniscriptPsbtError,
+};
 
 const COINBASE_MATURITY: u32 = 100;
 
@@ -88,11 +114,16 @@ const COINBASE_MATURITY: u32 = 100;
 pub struct Wallet<D = ()> {
     signers: Arc<SignersContainer>,
     change_signers: Arc<SignersContainer>,
+    custom_signers: BTreeMap<KeychainKind, Arc<SignersContainer>>,
     chain: LocalChain,
     indexed_graph: IndexedTxGraph<ConfirmationTimeHeightAnchor, KeychainTxOutIndex<KeychainKind>>,
     persist: Persist<D, ChangeSet>,
     network: Network,
     secp: SecpCtx,
+    payment_queue: PaymentQueue,
+    labels: BTreeMap<LabelRef, Label>,
+    frozen: BTreeSet<OutPoint>,
+    min_confirmations: BTreeMap<KeychainKind, u32>,
 }
 
 /// An update to [`Wallet`].
@@ -114,6 +145,10 @@ pub struct Update {
 }
 
 /// The changes made to a wallet by applying an [`Update`].
+///
+/// The fields added after `network` default to empty when they are missing, so that changesets
+/// written by earlier versions in a self-describing format still load. Files of the legacy
+/// `bdk_file_store` format are migrated with [`LegacyChangeSet`].
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, Default)]
 pub struct ChangeSet {
     /// Changes to the [`LocalChain`].
@@ -131,6 +166,28 @@ pub struct ChangeSet {
 
     /// Stores the network type of the wallet.
     pub network: Option<Network>,
+
+    /// Descriptors of the [`KeychainKind::Custom`] keychains added with [`Wallet::add_keychain`].
+    #[serde(default)]
+    pub descriptors: BTreeMap<KeychainKind, ExtendedDescriptor>,
+
+    /// Changes to the wallet's [`labels`].
+    #[serde(default)]
+    pub labels: labels::ChangeSet,
+
+    /// Outpoints frozen (`true`) or unfrozen (`false`) with [`Wallet::freeze_utxo`] and
+    /// [`Wallet::unfreeze_utxo`].
+    #[serde(default)]
+    pub frozen: BTreeMap<OutPoint, bool>,
+
+    /// Minimum confirmations of the outputs of a keychain to be spent, set with
+    /// [`Wallet::set_min_confirmations`].
+    #[serde(default)]
+    pub min_confirmations: BTreeMap<KeychainKind, u32>,
+
+    /// Changes to the [`payment_queue`].
+    #[serde(default)]
+    pub payment_queue: payment_queue::ChangeSet,
 }
 
 impl Append for ChangeSet {
@@ -144,6 +201,60 @@ impl Append for ChangeSet {
             );
             self.network = other.network;
         }
+        // the descriptor of a keychain never changes
+        for (keychain, descriptor) in other.descriptors {
+            self.descriptors.entry(keychain).or_insert(descriptor);
+        }
+        Append::append(&mut self.labels, other.labels);
+        self.frozen.extend(other.frozen);
+        self.min_confirmations.extend(other.min_confirmations);
+        Append::append(&mut self.payment_queue, other.payment_queue);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+            && self.indexed_tx_graph.is_empty()
+            && self.descriptors.is_empty()
+            && self.labels.is_empty()
+            && self.frozen.is_empty()
+            && self.min_confirmations.is_empty()
+            && self.payment_queue.is_empty()
+    }
+}
+
+/// The [`ChangeSet`] of the legacy, unversioned `bdk_file_store` format, which did not have the
+/// fields for custom keychains, labels, frozen outputs, minimum confirmations and the payment queue
+/// yet.
+///
+/// The bincode encoding of a [`ChangeSet`] changes whenever a field is added, so files of the
+/// legacy format must be opened with `bdk_file_store::Store::open_migrating::<LegacyChangeSet, _>`
+/// to migrate them.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, Default)]
+pub struct LegacyChangeSet {
+    /// Changes to the [`LocalChain`].
+    ///
+    /// [`LocalChain`]: local_chain::LocalChain
+    pub chain: local_chain::ChangeSet,
+
+    /// Changes to [`IndexedTxGraph`].
+    ///
+    /// [`IndexedTxGraph`]: bdk_chain::indexed_tx_graph::IndexedTxGraph
+    pub indexed_tx_graph: indexed_tx_graph::ChangeSet<
+        ConfirmationTimeHeightAnchor,
+        keychain::ChangeSet<KeychainKind>,
+    >,
+
+    /// Stores the network type of the wallet.
+    pub network: Option<Network>,
+}
+
+impl Append for LegacyChangeSet {
+    fn append(&mut self, other: Self) {
+        Append::append(&mut self.chain, other.chain);
+        Append::append(&mut self.indexed_tx_graph, other.indexed_tx_graph);
+        if other.network.is_some() {
+            self.network = other.network;
+        }
     }
 
     fn is_empty(&self) -> bool {
@@ -151,6 +262,35 @@ impl Append for ChangeSet {
     }
 }
 
+impl From<LegacyChangeSet> for ChangeSet {
+    fn from(legacy: LegacyChangeSet) -> Self {
+        Self {
+            chain: legacy.chain,
+            indexed_tx_graph: legacy.indexed_tx_graph,
+            network: legacy.network,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<payment_queue::ChangeSet> for ChangeSet {
+    fn from(payment_queue: payment_queue::ChangeSet) -> Self {
+        Self {
+            payment_queue,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<labels::ChangeSet> for ChangeSet {
+    fn from(labels: labels::ChangeSet) -> Self {
+        Self {
+            labels,
+            ..Default::default()
+        }
+    }
+}
+
 impl From<local_chain::ChangeSet> for ChangeSet {
     fn from(chain: local_chain::ChangeSet) -> Self {
         Self {
@@ -278,6 +418,22 @@ where
     pub fn get_internal_address(&mut self, address_index: AddressIndex) -> AddressInfo {
         self.try_get_internal_address(address_index).unwrap()
     }
+
+    /// Infallibly return a derived address using the descriptor of `keychain`.
+    ///
+    /// See [`Wallet::try_get_keychain_address`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the wallet does not have a [`KeychainKind::Custom`] `keychain`.
+    pub fn get_keychain_address(
+        &mut self,
+        keychain: KeychainKind,
+        address_index: AddressIndex,
+    ) -> AddressInfo {
+        self.try_get_keychain_address(keychain, address_index)
+            .unwrap()
+    }
 }
 
 /// The error type when constructing a fresh [`Wallet`].
@@ -411,6 +567,60 @@ where
 {
 }
 
+/// An error that may occur when adding a keychain to a [`Wallet`] with [`Wallet::add_keychain`].
+#[derive(Debug)]
+pub enum AddKeychainError {
+    /// There was problem with the passed-in descriptor.
+    Descriptor(crate::descriptor::DescriptorError),
+    /// The keychain already exists.
+    KeychainExists(KeychainKind),
+    /// The descriptor is already used by another keychain.
+    DescriptorExists(KeychainKind),
+}
+
+impl fmt::Display for AddKeychainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddKeychainError::Descriptor(e) => e.fmt(f),
+            AddKeychainError::KeychainExists(keychain) => {
+                write!(f, "keychain {:?} already exists", keychain)
+            }
+            AddKeychainError::DescriptorExists(keychain) => {
+                write!(f, "descriptor is already used by keychain {:?}", keychain)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AddKeychainError {}
+
+/// An error that may occur when deriving an address with [`Wallet::try_get_keychain_address`].
+#[derive(Debug)]
+pub enum GetAddressError<W> {
+    /// The wallet does not have the keychain.
+    UnknownKeychain(KeychainKind),
+    /// Persisting the revealed address failed.
+    Persist(W),
+}
+
+impl<W> fmt::Display for GetAddressError<W>
+where
+    W: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetAddressError::UnknownKeychain(keychain) => {
+                write!(f, "keychain {:?} does not exist in the wallet", keychain)
+            }
+            GetAddressError::Persist(e) => write!(f, "failed to write to persistence: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W> std::error::Error for GetAddressError<W> where W: fmt::Display + fmt::Debug {}
+
 /// An error that may occur when inserting a transaction into [`Wallet`].
 #[derive(Debug)]
 pub enum InsertTxError {
@@ -453,13 +663,69 @@ impl<D> Wallet<D> {
     where
         D: PersistBackend<ChangeSet>,
     {
+        let mut wallet = Self::new_staged(descriptor, change_descriptor, db, network, genesis_hash)
+            .map_err(NewError::Descriptor)?;
+        wallet.persist.commit().map_err(NewError::Write)?;
+        Ok(wallet)
+    }
+
+    /// Initialize an empty [`Wallet`] backed by an asynchronous persistence backend.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`Wallet::new`].
+    #[cfg(feature = "async")]
+    pub async fn new_async<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        db: D,
+        network: Network,
+    ) -> Result<Self, NewError<D::WriteError>>
+    where
+        D: PersistBackendAsync<ChangeSet>,
+    {
+        let genesis_hash = genesis_block(network).block_hash();
+        Self::new_with_genesis_hash_async(descriptor, change_descriptor, db, network, genesis_hash)
+            .await
+    }
+
+    /// Initialize an empty [`Wallet`] backed by an asynchronous persistence backend, with a custom
+    /// genesis hash.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`Wallet::new_with_genesis_hash`].
+    #[cfg(feature = "async")]
+    pub async fn new_with_genesis_hash_async<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        db: D,
+        network: Network,
+        genesis_hash: BlockHash,
+    ) -> Result<Self, NewError<D::WriteError>>
+    where
+        D: PersistBackendAsync<ChangeSet>,
+    {
+        let mut wallet = Self::new_staged(descriptor, change_descriptor, db, network, genesis_hash)
+            .map_err(NewError::Descriptor)?;
+        wallet
+            .persist
+            .commit_async()
+            .await
+            .map_err(NewError::Write)?;
+        Ok(wallet)
+    }
+
+    /// Initialize an empty [`Wallet`] with the initial changeset staged but not committed.
+    fn new_staged<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        db: D,
+        network: Network,
+        genesis_hash: BlockHash,
+    ) -> Result<Self, DescriptorError> {
         let secp = Secp256k1::new();
         let (chain, chain_changeset) = LocalChain::from_genesis_hash(genesis_hash);
         let mut index = KeychainTxOutIndex::<KeychainKind>::default();
 
         let (signers, change_signers) =
-            create_signers(&mut index, &secp, descriptor, change_descriptor, network)
-                .map_err(NewError::Descriptor)?;
+            create_signers(&mut index, &secp, descriptor, change_descriptor, network)?;
 
         let indexed_graph = IndexedTxGraph::new(index);
 
@@ -468,17 +734,26 @@ impl<D> Wallet<D> {
             chain: chain_changeset,
             indexed_tx_graph: indexed_graph.initial_changeset(),
             network: Some(network),
+            descriptors: BTreeMap::new(),
+            labels: labels::ChangeSet::default(),
+            frozen: BTreeMap::new(),
+            min_confirmations: BTreeMap::new(),
+            payment_queue: payment_queue::ChangeSet::default(),
         });
-        persist.commit().map_err(NewError::Write)?;
 
         Ok(Wallet {
             signers,
             change_signers,
+            custom_signers: BTreeMap::new(),
             network,
             chain,
             indexed_graph,
             persist,
             secp,
+            payment_queue: PaymentQueue::default(),
+            labels: BTreeMap::new(),
+            frozen: BTreeSet::new(),
+            min_confirmations: BTreeMap::new(),
         })
     }
 
@@ -498,15 +773,32 @@ impl<D> Wallet<D> {
         Self::load_from_changeset(descriptor, change_descriptor, db, changeset)
     }
 
-    fn load_from_changeset<E: IntoWalletDescriptor>(
+    /// Load [`Wallet`] from the given asynchronous persistence backend.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`Wallet::load`].
+    #[cfg(feature = "async")]
+    pub async fn load_async<E: IntoWalletDescriptor>(
         descriptor: E,
         change_descriptor: Option<E>,
-        db: D,
-        changeset: ChangeSet,
+        mut db: D,
     ) -> Result<Self, LoadError<D::LoadError>>
     where
-        D: PersistBackend<ChangeSet>,
+        D: PersistBackendAsync<ChangeSet>,
     {
+        let changeset = db
+            .load_from_persistence()
+            .await
+            .map_err(LoadError::Load)?
+            .ok_or(LoadError::NotInitialized)?;
+        Self::load_from_changeset(descriptor, change_descriptor, db, changeset)
+    }
+
+    fn load_from_changeset<E: IntoWalletDescriptor, L>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        db: D,
+        changeset: ChangeSet,
+    ) -> Result<Self, LoadError<L>> {
         let secp = Secp256k1::new();
         let network = changeset.network.ok_or(LoadError::MissingNetwork)?;
         let chain =
@@ -516,18 +808,47 @@ impl<D> Wallet<D> {
         let (signers, change_signers) =
             create_signers(&mut index, &secp, descriptor, change_descriptor, network)
                 .map_err(LoadError::Descriptor)?;
+        // the private keys of custom keychains are not persisted, their signers are attached by
+        // adding the keychain again with `Wallet::add_keychain`
+        for (keychain, descriptor) in changeset.descriptors {
+            index.add_keychain(keychain, descriptor);
+        }
 
-        let indexed_graph = IndexedTxGraph::new(index);
+        let mut indexed_graph = IndexedTxGraph::new(index);
+        indexed_graph.apply_changeset(changeset.indexed_tx_graph);
+        let labels = changeset
+            .labels
+            .0
+            .into_iter()
+            .filter_map(|(reference, label)| Some((reference, label?)))
+            .collect();
+        let frozen = changeset
+            .frozen
+            .into_iter()
+            .filter(|&(_, frozen)| frozen)
+            .map(|(outpoint, _)| outpoint)
+            .collect();
+        let min_confirmations = changeset
+            .min_confirmations
+            .into_iter()
+            .filter(|&(_, confirmations)| confirmations > 0)
+            .collect();
+        let payment_queue = PaymentQueue::from_changeset(changeset.payment_queue);
         let persist = Persist::new(db);
 
         Ok(Wallet {
             signers,
             change_signers,
+            custom_signers: BTreeMap::new(),
             chain,
             indexed_graph,
             persist,
             network,
             secp,
+            payment_queue,
+            labels,
+            frozen,
+            min_confirmations,
         })
     }
 
@@ -570,6 +891,88 @@ impl<D> Wallet<D> {
         D: PersistBackend<ChangeSet>,
     {
         let changeset = db.load_from_persistence().map_err(NewOrLoadError::Load)?;
+        let mut wallet = Self::new_or_load_staged(
+            descriptor,
+            change_descriptor,
+            db,
+            network,
+            genesis_hash,
+            changeset,
+        )?;
+        wallet.persist.commit().map_err(NewOrLoadError::Write)?;
+        Ok(wallet)
+    }
+
+    /// Either loads [`Wallet`] from the asynchronous persistence backend, or initializes it if it
+    /// does not exist.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`Wallet::new_or_load`].
+    #[cfg(feature = "async")]
+    pub async fn new_or_load_async<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        db: D,
+        network: Network,
+    ) -> Result<Self, NewOrLoadError<D::WriteError, D::LoadError>>
+    where
+        D: PersistBackendAsync<ChangeSet>,
+    {
+        let genesis_hash = genesis_block(network).block_hash();
+        Self::new_or_load_with_genesis_hash_async(
+            descriptor,
+            change_descriptor,
+            db,
+            network,
+            genesis_hash,
+        )
+        .await
+    }
+
+    /// Either loads [`Wallet`] from the asynchronous persistence backend, or initializes it if it
+    /// does not exist (with a custom genesis hash).
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`Wallet::new_or_load_with_genesis_hash`].
+    #[cfg(feature = "async")]
+    pub async fn new_or_load_with_genesis_hash_async<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        mut db: D,
+        network: Network,
+        genesis_hash: BlockHash,
+    ) -> Result<Self, NewOrLoadError<D::WriteError, D::LoadError>>
+    where
+        D: PersistBackendAsync<ChangeSet>,
+    {
+        let changeset = db
+            .load_from_persistence()
+            .await
+            .map_err(NewOrLoadError::Load)?;
+        let mut wallet = Self::new_or_load_staged(
+            descriptor,
+            change_descriptor,
+            db,
+            network,
+            genesis_hash,
+            changeset,
+        )?;
+        wallet
+            .persist
+            .commit_async()
+            .await
+            .map_err(NewOrLoadError::Write)?;
+        Ok(wallet)
+    }
+
+    /// Either loads [`Wallet`] from `changeset`, or initializes it (with the initial changeset
+    /// staged but not committed) if there is no `changeset`.
+    fn new_or_load_staged<E: IntoWalletDescriptor, W, L>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        db: D,
+        network: Network,
+        genesis_hash: BlockHash,
+        changeset: Option<ChangeSet>,
+    ) -> Result<Self, NewOrLoadError<W, L>> {
         match changeset {
             Some(changeset) => {
                 let wallet =
@@ -605,17 +1008,8 @@ impl<D> Wallet<D> {
                 }
                 Ok(wallet)
             }
-            None => Self::new_with_genesis_hash(
-                descriptor,
-                change_descriptor,
-                db,
-                network,
-                genesis_hash,
-            )
-            .map_err(|e| match e {
-                NewError::Descriptor(e) => NewOrLoadError::Descriptor(e),
-                NewError::Write(e) => NewOrLoadError::Write(e),
-            }),
+            None => Self::new_staged(descriptor, change_descriptor, db, network, genesis_hash)
+                .map_err(NewOrLoadError::Descriptor),
         }
     }
 
@@ -629,6 +1023,56 @@ impl<D> Wallet<D> {
         self.indexed_graph.index.keychains()
     }
 
+    /// Add a [`KeychainKind::Custom`] keychain with the given `id` and `descriptor`.
+    ///
+    /// Outputs of custom keychains count towards the wallet's balance and can be spent by
+    /// transactions created with [`build_tx`]. Addresses are derived with
+    /// [`try_get_keychain_address`]. Change is still sent to the [`KeychainKind::Internal`]
+    /// keychain.
+    ///
+    /// The public descriptor is staged (but not committed) so that the keychain is restored when the
+    /// wallet is loaded. Private keys are never persisted: adding the same keychain with its private
+    /// descriptor again after loading attaches its signers.
+    ///
+    /// [`build_tx`]: Self::build_tx
+    /// [`try_get_keychain_address`]: Self::try_get_keychain_address
+    pub fn add_keychain<E: IntoWalletDescriptor>(
+        &mut self,
+        id: u32,
+        descriptor: E,
+    ) -> Result<KeychainKind, AddKeychainError> {
+        let keychain = KeychainKind::custom(id);
+        let (descriptor, keymap) =
+            into_wallet_descriptor_checked(descriptor, &self.secp, self.network)
+                .map_err(AddKeychainError::Descriptor)?;
+        if let Some((&existing, _)) = self
+            .indexed_graph
+            .index
+            .keychains()
+            .iter()
+            .find(|(_, existing)| **existing == descriptor)
+        {
+            if existing != keychain {
+                return Err(AddKeychainError::DescriptorExists(existing));
+            }
+        } else if self.public_descriptor(keychain).is_some() {
+            return Err(AddKeychainError::KeychainExists(keychain));
+        }
+
+        let signers = Arc::new(SignersContainer::build(keymap, &descriptor, &self.secp));
+        self.custom_signers.insert(keychain, signers);
+        if self.public_descriptor(keychain).is_none() {
+            self.indexed_graph
+                .index
+                .add_keychain(keychain, descriptor.clone());
+            self.persist.stage(ChangeSet {
+                descriptors: [(keychain, descriptor)].into(),
+                ..Default::default()
+            });
+        }
+        Ok(keychain)
+    }
+
     /// Return a derived address using the external descriptor, see [`AddressIndex`] for
     /// available address index selection strategies. If none of the keys in the descriptor are derivable
     /// (i.e. does not end with /*) then the same address will always be returned for any [`AddressIndex`].
@@ -642,7 +1086,12 @@ impl<D> Wallet<D> {
     where
         D: PersistBackend<ChangeSet>,
     {
-        self._get_address(KeychainKind::External, address_index)
+        let must_commit = !matches!(address_index, AddressIndex::Peek(_));
+        let (info, _) = self._get_address(KeychainKind::External, address_index);
+        if must_commit {
+            self.persist.commit()?;
+        }
+        Ok(info)
     }
 
     /// Return a derived address using the internal (change) descriptor.
@@ -662,64 +1111,186 @@ impl<D> Wallet<D> {
     where
         D: PersistBackend<ChangeSet>,
     {
-        self._get_address(KeychainKind::Internal, address_index)
+        let must_commit = !matches!(address_index, AddressIndex::Peek(_));
+        let (info, _) = self._get_address(KeychainKind::Internal, address_index);
+        if must_commit {
+            self.persist.commit()?;
+        }
+        Ok(info)
     }
 
-    /// Return a derived address using the specified `keychain` (external/internal).
-    ///
-    /// If `keychain` is [`KeychainKind::External`], external addresses will be derived (used for
-    /// receiving funds).
+    /// Return a derived address using the descriptor of `keychain`.
     ///
-    /// If `keychain` is [`KeychainKind::Internal`], internal addresses will be derived (used for
-    /// creating change outputs). If the wallet does not have an internal keychain, it will use the
-    /// external keychain to derive change outputs.
+    /// If `keychain` is [`KeychainKind::Internal`] and the wallet doesn't have an internal
+    /// descriptor it will use the external descriptor.
     ///
-    /// See [`AddressIndex`] for available address index selection strategies. If none of the keys
-    /// in the descriptor are derivable (i.e. does not end with /*) then the same address will
-    /// always be returned for any [`AddressIndex`].
-    fn _get_address(
+    /// A [`GetAddressError::UnknownKeychain`] will result if the wallet does not have a
+    /// [`KeychainKind::Custom`] `keychain`, and a [`GetAddressError::Persist`] if unable to persist
+    /// the new address to the `PersistBackend`.
+    pub fn try_get_keychain_address(
         &mut self,
         keychain: KeychainKind,
         address_index: AddressIndex,
-    ) -> Result<AddressInfo, D::WriteError>
+    ) -> Result<AddressInfo, GetAddressError<D::WriteError>>
     where
         D: PersistBackend<ChangeSet>,
     {
-        let keychain = self.map_keychain(keychain);
-        let txout_index = &mut self.indexed_graph.index;
-        let (index, spk, changeset) = match address_index {
-            AddressIndex::New => {
-                let ((index, spk), index_changeset) = txout_index.reveal_next_spk(&keychain);
-                (index, spk.into(), Some(index_changeset))
-            }
-            AddressIndex::LastUnused => {
-                let ((index, spk), index_changeset) = txout_index.next_unused_spk(&keychain);
-                (index, spk.into(), Some(index_changeset))
-            }
-            AddressIndex::Peek(index) => {
-                let (index, spk) = txout_index
-                    .spks_of_keychain(&keychain)
-                    .take(index as usize + 1)
-                    .last()
-                    .unwrap();
-                (index, spk, None)
-            }
-        };
+        self.check_keychain(keychain)?;
+        let must_commit = !matches!(address_index, AddressIndex::Peek(_));
+        let (info, _) = self._get_address(keychain, address_index);
+        if must_commit {
+            self.persist.commit().map_err(GetAddressError::Persist)?;
+        }
+        Ok(info)
+    }
+
+    /// Return a derived address using the external descriptor, persisting the new address to an
+    /// asynchronous persistence backend.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`try_get_address`], but it only commits
+    /// if an address was revealed.
+    ///
+    /// [`try_get_address`]: Self::try_get_address
+    #[cfg(feature = "async")]
+    pub async fn try_get_address_async(
+        &mut self,
+        address_index: AddressIndex,
+    ) -> Result<AddressInfo, D::WriteError>
+    where
+        D: PersistBackendAsync<ChangeSet>,
+    {
+        let (info, revealed) = self._get_address(KeychainKind::External, address_index);
+        if revealed {
+            self.persist.commit_async().await?;
+        }
+        Ok(info)
+    }
+
+    /// Return a derived address using the internal (change) descriptor, persisting the new address
+    /// to an asynchronous persistence backend.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`try_get_internal_address`], but it only
+    /// commits if an address was revealed.
+    ///
+    /// [`try_get_internal_address`]: Self::try_get_internal_address
+    #[cfg(feature = "async")]
+    pub async fn try_get_internal_address_async(
+        &mut self,
+        address_index: AddressIndex,
+    ) -> Result<AddressInfo, D::WriteError>
+    where
+        D: PersistBackendAsync<ChangeSet>,
+    {
+        let (info, revealed) = self._get_address(KeychainKind::Internal, address_index);
+        if revealed {
+            self.persist.commit_async().await?;
+        }
+        Ok(info)
+    }
 
-        if let Some(changeset) = changeset {
+    /// Return a derived address using the descriptor of `keychain`, persisting the new address to
+    /// an asynchronous persistence backend.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`try_get_keychain_address`], but it only
+    /// commits if an address was revealed.
+    ///
+    /// [`try_get_keychain_address`]: Self::try_get_keychain_address
+    #[cfg(feature = "async")]
+    pub async fn try_get_keychain_address_async(
+        &mut self,
+        keychain: KeychainKind,
+        address_index: AddressIndex,
+    ) -> Result<AddressInfo, GetAddressError<D::WriteError>>
+    where
+        D: PersistBackendAsync<ChangeSet>,
+    {
+        self.check_keychain(keychain)?;
+        let (info, revealed) = self._get_address(keychain, address_index);
+        if revealed {
             self.persist
-                .stage(ChangeSet::from(indexed_tx_graph::ChangeSet::from(
-                    changeset,
-                )));
-            self.persist.commit()?;
+                .commit_async()
+                .await
+                .map_err(GetAddressError::Persist)?;
         }
+        Ok(info)
+    }
+
+    /// Returns an error if the wallet does not have `keychain`.
+    fn check_keychain<W>(&self, keychain: KeychainKind) -> Result<(), GetAddressError<W>> {
+        match self.public_descriptor(self.map_keychain(keychain)) {
+            Some(_) => Ok(()),
+            None => Err(GetAddressError::UnknownKeychain(keychain)),
+        }
+    }
+
+    /// Return a derived address using the specified `keychain`.
+    ///
+    /// If `keychain` is [`KeychainKind::External`], external addresses will be derived (used for
+    /// receiving funds).
+    ///
+    /// If `keychain` is [`KeychainKind::Internal`], internal addresses will be derived (used for
+    /// creating change outputs). If the wallet does not have an internal keychain, it will use the
+    /// external keychain to derive change outputs.
+    ///
+    /// If `keychain` is [`KeychainKind::Custom`], addresses of that keychain will be derived.
+    ///
+    /// See [`AddressIndex`] for available address index selection strategies. If none of the keys
+    /// in the descriptor are derivable (i.e. does not end with /*) then the same address will
+    /// always be returned for any [`AddressIndex`].
+    ///
+    /// Newly revealed addresses are staged but not committed. The returned `bool` tells whether
+    /// an address was revealed, i.e. whether anything was staged.
+    ///
+    /// Panics if the wallet does not have `keychain`, callers check it first.
+    fn _get_address(
+        &mut self,
+        keychain: KeychainKind,
+        address_index: AddressIndex,
+    ) -> (AddressInfo, bool) {
+        let keychain = self.map_keychain(keychain);
+        assert!(
+            self.public_descriptor(keychain).is_some(),
+            "keychain {:?} does not exist in the wallet",
+            keychain
+        );
+        let txout_index = &mut self.indexed_graph.index;
+        let (index, spk, changeset) = match address_index {
+            AddressIndex::New => {
+                let ((index, spk), index_changeset) = txout_index.reveal_next_spk(&keychain);
+                (index, spk.into(), Some(index_changeset))
+            }
+            AddressIndex::LastUnused => {
+                let ((index, spk), index_changeset) = txout_index.next_unused_spk(&keychain);
+                (index, spk.into(), Some(index_changeset))
+            }
+            AddressIndex::Peek(index) => {
+                let (index, spk) = txout_index
+                    .spks_of_keychain(&keychain)
+                    .take(index as usize + 1)
+                    .last()
+                    .unwrap();
+                (index, spk, None)
+            }
+        };
+
+        let revealed = match changeset {
+            Some(changeset) if !changeset.is_empty() => {
+                self.persist
+                    .stage(ChangeSet::from(indexed_tx_graph::ChangeSet::from(
+                        changeset,
+                    )));
+                true
+            }
+            _ => false,
+        };
 
-        Ok(AddressInfo {
+        let info = AddressInfo {
             index,
             address: Address::from_script(&spk, self.network)
                 .expect("descriptor must have address form"),
             keychain,
-        })
+        };
+        (info, revealed)
     }
 
     /// Return whether or not a `script` is part of this wallet (either internal or external)
@@ -743,7 +1314,50 @@ impl<D> Wallet<D> {
                 self.chain.tip().block_id(),
                 self.indexed_graph.index.outpoints().iter().cloned(),
             )
-            .map(|((k, i), full_txo)| new_local_utxo(k, i, full_txo))
+            .map(move |((k, i), full_txo)| self.new_local_utxo(k, i, full_txo))
+    }
+
+    /// Return the list of unspent outputs of the given `keychain`
+    pub fn list_keychain_unspent(
+        &self,
+        keychain: KeychainKind,
+    ) -> impl Iterator<Item = LocalOutput> + '_ {
+        self.indexed_graph
+            .graph()
+            .filter_chain_unspents(
+                &self.chain,
+                self.chain.tip().block_id(),
+                self.keychain_outpoints(keychain),
+            )
+            .map(move |((k, i), full_txo)| self.new_local_utxo(k, i, full_txo))
+    }
+
+    fn new_local_utxo(
+        &self,
+        keychain: KeychainKind,
+        derivation_index: u32,
+        full_txo: FullTxOut<ConfirmationTimeHeightAnchor>,
+    ) -> LocalOutput {
+        LocalOutput {
+            outpoint: full_txo.outpoint,
+            txout: full_txo.txout,
+            is_spent: full_txo.spent_by.is_some(),
+            confirmation_time: full_txo.chain_position.into(),
+            keychain,
+            derivation_index,
+        }
+    }
+
+    fn keychain_outpoints(
+        &self,
+        keychain: KeychainKind,
+    ) -> impl Iterator<Item = ((KeychainKind, u32), OutPoint)> + '_ {
+        self.indexed_graph
+            .index
+            .outpoints()
+            .iter()
+            .filter(move |((k, _), _)| *k == keychain)
+            .cloned()
     }
 
     /// List all relevant outputs (includes both spent and unspent, confirmed and unconfirmed).
@@ -757,7 +1371,7 @@ impl<D> Wallet<D> {
                 self.chain.tip().block_id(),
                 self.indexed_graph.index.outpoints().iter().cloned(),
             )
-            .map(|((k, i), full_txo)| new_local_utxo(k, i, full_txo))
+            .map(move |((k, i), full_txo)| self.new_local_utxo(k, i, full_txo))
     }
 
     /// Get all the checkpoints the wallet is currently storing indexed by height.
@@ -807,10 +1421,123 @@ impl<D> Wallet<D> {
                 self.chain.tip().block_id(),
                 core::iter::once((spk_i, op)),
             )
-            .map(|((k, i), full_txo)| new_local_utxo(k, i, full_txo))
+            .map(move |((k, i), full_txo)| self.new_local_utxo(k, i, full_txo))
             .next()
     }
 
+    /// Freeze the output at `outpoint`, so that it is not spent by transactions built with
+    /// [`build_tx`] (or the other transaction builders) until [`unfreeze_utxo`] is called. This
+    /// stages but does not [`commit`] the change.
+    ///
+    /// Unlike [`TxBuilder::add_unspendable`], this persists across builds and restarts. Frozen
+    /// outputs can still be spent by adding them manually with [`TxBuilder::add_utxo`]. An output
+    /// is unfrozen once a confirmed transaction spends it, so that the frozen set only holds
+    /// outputs which may still be spent.
+    ///
+    /// Returns whether the output was not frozen already.
+    ///
+    /// [`build_tx`]: Self::build_tx
+    /// [`unfreeze_utxo`]: Self::unfreeze_utxo
+    /// [`commit`]: Self::commit
+    pub fn freeze_utxo(&mut self, outpoint: OutPoint) -> bool {
+        let changed = self.frozen.insert(outpoint);
+        if changed {
+            self.persist.stage(ChangeSet {
+                frozen: [(outpoint, true)].into(),
+                ..Default::default()
+            });
+        }
+        changed
+    }
+
+    /// Unfreeze the output at `outpoint`, see [`freeze_utxo`]. This stages but does not [`commit`]
+    /// the change.
+    ///
+    /// Returns whether the output was frozen.
+    ///
+    /// [`freeze_utxo`]: Self::freeze_utxo
+    /// [`commit`]: Self::commit
+    pub fn unfreeze_utxo(&mut self, outpoint: OutPoint) -> bool {
+        let changed = self.frozen.remove(&outpoint);
+        if changed {
+            self.persist.stage(ChangeSet {
+                frozen: [(outpoint, false)].into(),
+                ..Default::default()
+            });
+        }
+        changed
+    }
+
+    /// Unfreeze the frozen outputs spent by a confirmed transaction.
+    fn prune_frozen(&mut self) -> ChangeSet {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+        let spent = self
+            .frozen
+            .iter()
+            .copied()
+            .filter(|&outpoint| {
+                graph.outspends(outpoint).iter().any(|&txid| {
+                    matches!(
+                        graph.get_chain_position(&self.chain, chain_tip, txid),
+                        Some(ChainPosition::Confirmed(_))
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut changeset = ChangeSet::default();
+        for outpoint in spent {
+            self.frozen.remove(&outpoint);
+            changeset.frozen.insert(outpoint, false);
+        }
+        changeset
+    }
+
+    /// Returns whether the output at `outpoint` is frozen, see [`freeze_utxo`].
+    ///
+    /// [`freeze_utxo`]: Self::freeze_utxo
+    pub fn is_frozen(&self, outpoint: OutPoint) -> bool {
+        self.frozen.contains(&outpoint)
+    }
+
+    /// Iterate over the frozen outpoints, see [`freeze_utxo`].
+    ///
+    /// [`freeze_utxo`]: Self::freeze_utxo
+    pub fn frozen_utxos(&self) -> impl Iterator<Item = OutPoint> + '_ {
+        self.frozen.iter().copied()
+    }
+
+    /// Set the minimum number of confirmations the outputs of `keychain` need to be spent by
+    /// transactions built with [`build_tx`] (or the other transaction builders). This stages but
+    /// does not [`commit`] the change.
+    ///
+    /// The default of `0` also spends unconfirmed outputs, `1` only confirmed ones. Like for frozen
+    /// outputs, outputs added manually with [`TxBuilder::add_utxo`] are spent regardless.
+    ///
+    /// [`build_tx`]: Self::build_tx
+    /// [`commit`]: Self::commit
+    pub fn set_min_confirmations(&mut self, keychain: KeychainKind, confirmations: u32) {
+        let previous = if confirmations > 0 {
+            self.min_confirmations.insert(keychain, confirmations)
+        } else {
+            self.min_confirmations.remove(&keychain)
+        };
+        if previous.unwrap_or(0) != confirmations {
+            self.persist.stage(ChangeSet {
+                min_confirmations: [(keychain, confirmations)].into(),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Returns the minimum number of confirmations the outputs of `keychain` need to be spent, see
+    /// [`set_min_confirmations`].
+    ///
+    /// [`set_min_confirmations`]: Self::set_min_confirmations
+    pub fn min_confirmations(&self, keychain: KeychainKind) -> u32 {
+        self.min_confirmations.get(&keychain).copied().unwrap_or(0)
+    }
+
     /// Inserts a [`TxOut`] at [`OutPoint`] into the wallet's transaction graph.
     ///
     /// This is used for providing a previous output's value so that we can use [`calculate_fee`]
@@ -827,10 +1554,7 @@ impl<D> Wallet<D> {
     /// [`list_unspent`]: Self::list_unspent
     /// [`list_output`]: Self::list_output
     /// [`commit`]: Self::commit
-    pub fn insert_txout(&mut self, outpoint: OutPoint, txout: TxOut)
-    where
-        D: PersistBackend<ChangeSet>,
-    {
+    pub fn insert_txout(&mut self, outpoint: OutPoint, txout: TxOut) {
         let additions = self.indexed_graph.insert_txout(outpoint, txout);
         self.persist.stage(ChangeSet::from(additions));
     }
@@ -999,10 +1723,7 @@ impl<D> Wallet<D> {
     pub fn insert_checkpoint(
         &mut self,
         block_id: BlockId,
-    ) -> Result<bool, local_chain::AlterCheckPointError>
-    where
-        D: PersistBackend<ChangeSet>,
-    {
+    ) -> Result<bool, local_chain::AlterCheckPointError> {
         let changeset = self.chain.insert_block(block_id)?;
         let changed = !changeset.is_empty();
         self.persist.stage(changeset.into());
@@ -1030,10 +1751,7 @@ impl<D> Wallet<D> {
         &mut self,
         tx: Transaction,
         position: ConfirmationTime,
-    ) -> Result<bool, InsertTxError>
-    where
-        D: PersistBackend<ChangeSet>,
-    {
+    ) -> Result<bool, InsertTxError> {
         let (anchor, last_seen) = match position {
             ConfirmationTime::Confirmed { height, time } => {
                 // anchor tx to checkpoint with lowest height that is >= position's height
@@ -1071,10 +1789,47 @@ impl<D> Wallet<D> {
         }
 
         let changed = !changeset.is_empty();
+        changeset.append(self.prune_frozen());
         self.persist.stage(changeset);
         Ok(changed)
     }
 
+    /// Broadcast `tx` with `broadcaster`. Once it is accepted, `tx` is inserted into the wallet as
+    /// unconfirmed and last seen at `seen_at` (a unix timestamp). This stages but does not
+    /// [`commit`] the change.
+    ///
+    /// Nothing is inserted if `tx` is rejected, the [`BroadcastError`] tells why.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn broadcast<B: Broadcaster>(
+        &mut self,
+        broadcaster: &B,
+        tx: Transaction,
+        seen_at: u64,
+    ) -> Result<(), BroadcastError<B::Error>> {
+        broadcaster.broadcast(&tx)?;
+        self.insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: seen_at })
+            .expect("inserting an unconfirmed transaction cannot fail");
+        Ok(())
+    }
+
+    /// Broadcast `tx` with `broadcaster`. Once it is accepted, `tx` is inserted into the wallet as
+    /// unconfirmed and last seen at `seen_at`.
+    ///
+    /// This is the [`BroadcasterAsync`] counterpart of [`Wallet::broadcast`].
+    #[cfg(feature = "async")]
+    pub async fn broadcast_async<B: BroadcasterAsync>(
+        &mut self,
+        broadcaster: &B,
+        tx: Transaction,
+        seen_at: u64,
+    ) -> Result<(), BroadcastError<B::Error>> {
+        broadcaster.broadcast(&tx).await?;
+        self.insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: seen_at })
+            .expect("inserting an unconfirmed transaction cannot fail");
+        Ok(())
+    }
+
     /// Iterate over the transactions in the wallet.
     pub fn transactions(
         &self,
@@ -1084,6 +1839,186 @@ impl<D> Wallet<D> {
             .list_chain_txs(&self.chain, self.chain.tip().block_id())
     }
 
+    /// Set the label of `reference`, replacing its previous label. This stages but does not
+    /// [`commit`] the change.
+    ///
+    /// See the [`labels`] module for more.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn set_label(&mut self, reference: LabelRef, label: impl Into<Label>) {
+        let label = label.into();
+        self.labels.insert(reference.clone(), label.clone());
+        self.persist.stage(ChangeSet::from(labels::ChangeSet(
+            [(reference, Some(label))].into(),
+        )));
+    }
+
+    /// Remove the label of `reference`, returning it. This stages but does not [`commit`] the
+    /// change.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn remove_label(&mut self, reference: &LabelRef) -> Option<Label> {
+        let label = self.labels.remove(reference)?;
+        self.persist.stage(ChangeSet::from(labels::ChangeSet(
+            [(reference.clone(), None)].into(),
+        )));
+        Some(label)
+    }
+
+    /// Returns the label of `reference`.
+    pub fn label(&self, reference: &LabelRef) -> Option<&Label> {
+        self.labels.get(reference)
+    }
+
+    /// Iterate over all labels.
+    pub fn labels(&self) -> impl Iterator<Item = (&LabelRef, &Label)> {
+        self.labels.iter()
+    }
+
+    /// Export all labels in the [BIP329] JSON Lines format. Outputs frozen with [`freeze_utxo`]
+    /// are exported as not spendable.
+    ///
+    /// [BIP329]: https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+    /// [`freeze_utxo`]: Self::freeze_utxo
+    pub fn export_labels(&self) -> String {
+        labels::export(self.labels.iter(), &self.frozen)
+    }
+
+    /// Import labels in the [BIP329] JSON Lines format, replacing the existing labels of the same
+    /// references. Outputs with a `spendable` flag are frozen or unfrozen accordingly, see
+    /// [`freeze_utxo`]. This stages but does not [`commit`] the changes.
+    ///
+    /// Records of unknown types are skipped. Records of addresses of another network than the
+    /// wallet's are skipped too, and reported in the returned [`LabelsImport`]. Nothing is imported
+    /// if any record is invalid.
+    ///
+    /// [BIP329]: https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+    /// [`freeze_utxo`]: Self::freeze_utxo
+    /// [`commit`]: Self::commit
+    pub fn import_labels(&mut self, jsonl: &str) -> Result<LabelsImport, ImportLabelsError> {
+        let import = labels::import(jsonl, self.network)?;
+        let imported = import.labels.len();
+        let mut changeset = labels::ChangeSet::default();
+        for (reference, label) in import.labels {
+            self.labels.insert(reference.clone(), label.clone());
+            changeset.0.insert(reference, Some(label));
+        }
+        self.persist.stage(ChangeSet::from(changeset));
+        for (outpoint, spendable) in import.spendable {
+            if spendable {
+                self.unfreeze_utxo(outpoint);
+            } else {
+                self.freeze_utxo(outpoint);
+            }
+        }
+        Ok(LabelsImport {
+            imported,
+            skipped: import.skipped,
+        })
+    }
+
+    /// Returns a page of the wallet's transaction history as [`TxSummary`]s, filtered and ordered
+    /// according to `query`.
+    ///
+    /// See the [`history`] module for an example.
+    pub fn history(&self, query: &HistoryQuery) -> Vec<TxSummary> {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+
+        let mut txs = graph
+            .full_txs()
+            .filter_map(|node| {
+                let confirmation_time =
+                    match graph.get_chain_position(&self.chain, chain_tip, node.txid) {
+                        Some(ChainPosition::Confirmed(anchor)) => ConfirmationTime::Confirmed {
+                            height: anchor.confirmation_height,
+                            time: anchor.confirmation_time,
+                        },
+                        Some(ChainPosition::Unconfirmed(last_seen)) => {
+                            ConfirmationTime::Unconfirmed { last_seen }
+                        }
+                        None if query.include_replaced => ConfirmationTime::Unconfirmed {
+                            last_seen: node.last_seen_unconfirmed,
+                        },
+                        None => return None,
+                    };
+                Some((node.tx, confirmation_time))
+            })
+            .filter(|(_, confirmation_time)| query.matches(confirmation_time))
+            .map(|(tx, confirmation_time)| {
+                let key = history::order_key(&confirmation_time, tx.txid());
+                (key, tx, confirmation_time)
+            })
+            .collect::<Vec<_>>();
+        txs.sort_unstable_by(|(a, _, _), (b, _, _)| match query.order {
+            HistoryOrder::OldestFirst => a.cmp(b),
+            HistoryOrder::NewestFirst => b.cmp(a),
+        });
+
+        txs.into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|((_, _, txid), tx, confirmation_time)| {
+                self.tx_summary(txid, tx, confirmation_time)
+            })
+            .collect()
+    }
+
+    fn tx_summary(
+        &self,
+        txid: Txid,
+        tx: &Transaction,
+        confirmation_time: ConfirmationTime,
+    ) -> TxSummary {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+        let index = &self.indexed_graph.index;
+
+        let (sent, received) = index.sent_and_received(tx);
+        let fee = self.calculate_fee(tx).ok();
+        let confirmations = match confirmation_time {
+            ConfirmationTime::Confirmed { height, .. } if height <= chain_tip.height => {
+                chain_tip.height - height + 1
+            }
+            _ => 0,
+        };
+        let keychains = tx
+            .input
+            .iter()
+            .filter_map(|txin| index.txout(txin.previous_output).map(|((k, _), _)| *k))
+            .chain(
+                tx.output
+                    .iter()
+                    .filter_map(|txout| index.index_of_spk(&txout.script_pubkey).map(|(k, _)| *k)),
+            )
+            .collect();
+
+        let conflicts = graph
+            .direct_conflitcs(tx)
+            .map(|(_, txid)| txid)
+            .collect::<BTreeSet<_>>();
+        let (replaced_by, replaces) = conflicts.into_iter().partition(|&conflict| {
+            graph
+                .get_chain_position(&self.chain, chain_tip, conflict)
+                .is_some()
+        });
+
+        TxSummary {
+            txid,
+            sent,
+            received,
+            net_value: received as i64 - sent as i64,
+            fee,
+            fee_rate: fee.map(|fee| FeeRate::from_wu(fee, tx.weight())),
+            confirmation_time,
+            confirmations,
+            keychains,
+            replaces,
+            replaced_by,
+            label: self.labels.get(&LabelRef::Tx(txid)).cloned(),
+        }
+    }
+
     /// Return the balance, separated into available, trusted-pending, untrusted-pending and immature
     /// values.
     pub fn get_balance(&self) -> Balance {
@@ -1095,6 +2030,17 @@ impl<D> Wallet<D> {
         )
     }
 
+    /// Return the balance of the given `keychain`, separated into available, trusted-pending,
+    /// untrusted-pending and immature values.
+    pub fn get_keychain_balance(&self, keychain: KeychainKind) -> Balance {
+        self.indexed_graph.graph().balance(
+            &self.chain,
+            self.chain.tip().block_id(),
+            self.keychain_outpoints(keychain),
+            |&(k, _), _| k == KeychainKind::Internal,
+        )
+    }
+
     /// Add an external signer
     ///
     /// See [the `signer` module](signer) for an example.
@@ -1107,6 +2053,9 @@ impl<D> Wallet<D> {
         let signers = match keychain {
             KeychainKind::External => Arc::make_mut(&mut self.signers),
             KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
+            KeychainKind::Custom(_) => {
+                Arc::make_mut(self.custom_signers.entry(keychain).or_default())
+            }
         };
 
         signers.add_external(signer.id(&self.secp), ordering, signer);
@@ -1131,6 +2080,11 @@ impl<D> Wallet<D> {
         match keychain {
             KeychainKind::External => Arc::clone(&self.signers),
             KeychainKind::Internal => Arc::clone(&self.change_signers),
+            KeychainKind::Custom(_) => self
+                .custom_signers
+                .get(&keychain)
+                .cloned()
+                .unwrap_or_default(),
         }
     }
 
@@ -1172,14 +2126,12 @@ impl<D> Wallet<D> {
         }
     }
 
-    pub(crate) fn create_tx<Cs: coin_selection::CoinSelectionAlgorithm>(
+    /// Create a transaction from `params`, staging (but not committing) any changes to the wallet.
+    pub(crate) fn create_tx<Cs: coin_selection::CoinSelectionAlgorithm, P>(
         &mut self,
         coin_selection: Cs,
         params: TxParams,
-    ) -> Result<psbt::PartiallySignedTransaction, CreateTxError<D::WriteError>>
-    where
-        D: PersistBackend<ChangeSet>,
-    {
+    ) -> Result<psbt::PartiallySignedTransaction, CreateTxError<P>> {
         let external_descriptor = self
             .indexed_graph
             .index
@@ -1198,16 +2150,27 @@ impl<D> Wallet<D> {
         let internal_policy = internal_descriptor
             .as_ref()
             .map(|desc| {
-                Ok::<_, CreateTxError<D::WriteError>>(
+                Ok::<_, CreateTxError<P>>(
                     desc.extract_policy(&self.change_signers, BuildSatisfaction::None, &self.secp)?
                         .unwrap(),
                 )
             })
             .transpose()?;
 
+        // When assets are provided the spending path of the keychains that can be planned is
+        // picked by their plans, per input, so their policy doesn't need a path and doesn't
+        // constrain the tx
+        #[cfg(feature = "planning")]
+        let planned = |keychain: KeychainKind| params.assets.is_some() && self.can_plan(keychain);
+        #[cfg(not(feature = "planning"))]
+        let planned = |_: KeychainKind| false;
+        let external_planned = planned(KeychainKind::External);
+        let internal_planned = planned(KeychainKind::Internal);
+
         // The policy allows spending external outputs, but it requires a policy path that hasn't been
         // provided
         if params.change_policy != tx_builder::ChangeSpendPolicy::OnlyChange
+            && !external_planned
             && external_policy.requires_path()
             && params.external_policy_path.is_none()
         {
@@ -1218,6 +2181,7 @@ impl<D> Wallet<D> {
         // Same for the internal_policy path, if present
         if let Some(internal_policy) = &internal_policy {
             if params.change_policy != tx_builder::ChangeSpendPolicy::ChangeForbidden
+                && !internal_planned
                 && internal_policy.requires_path()
                 && params.internal_policy_path.is_none()
             {
@@ -1227,15 +2191,20 @@ impl<D> Wallet<D> {
             };
         }
 
-        let external_requirements = external_policy.get_condition(
-            params
-                .external_policy_path
-                .as_ref()
-                .unwrap_or(&BTreeMap::new()),
-        )?;
+        let external_requirements = if external_planned {
+            Condition::default()
+        } else {
+            external_policy.get_condition(
+                params
+                    .external_policy_path
+                    .as_ref()
+                    .unwrap_or(&BTreeMap::new()),
+            )?
+        };
         let internal_requirements = internal_policy
+            .filter(|_| !internal_planned)
             .map(|policy| {
-                Ok::<_, CreateTxError<D::WriteError>>(
+                Ok::<_, CreateTxError<P>>(
                     policy.get_condition(
                         params
                             .internal_policy_path
@@ -1246,9 +2215,43 @@ impl<D> Wallet<D> {
             })
             .transpose()?;
 
-        let requirements =
+        let mut requirements =
             external_requirements.merge(&internal_requirements.unwrap_or_default())?;
 
+        // Custom keychains are only taken into account if their outputs may be spent
+        let custom_keychains = self
+            .indexed_graph
+            .index
+            .keychains()
+            .iter()
+            .filter(|(keychain, _)| matches!(keychain, KeychainKind::Custom(_)))
+            .filter(|(keychain, _)| {
+                params.change_policy != tx_builder::ChangeSpendPolicy::OnlyChange
+                    && params
+                        .spend_keychains
+                        .as_ref()
+                        .map_or(true, |keychains| keychains.contains(keychain))
+            });
+        for (keychain, descriptor) in custom_keychains {
+            if planned(*keychain) {
+                continue;
+            }
+            let policy = descriptor
+                .extract_policy(
+                    &self.get_signers(*keychain),
+                    BuildSatisfaction::None,
+                    &self.secp,
+                )?
+                .unwrap();
+            let policy_path = params.custom_policy_paths.get(keychain);
+            if policy.requires_path() && policy_path.is_none() {
+                return Err(CreateTxError::SpendingPolicyRequired(*keychain));
+            }
+            let custom_requirements =
+                policy.get_condition(policy_path.unwrap_or(&BTreeMap::new()))?;
+            requirements = requirements.merge(&custom_requirements)?;
+        }
+
         let version = match params.version {
             Some(tx_builder::Version(0)) => return Err(CreateTxError::Version0),
             Some(tx_builder::Version(1)) if requirements.csv.is_some() => {
@@ -1269,76 +2272,85 @@ impl<D> Wallet<D> {
             Some(h) => h,
         };
 
-        let lock_time = match params.locktime {
-            // When no nLockTime is specified, we try to prevent fee sniping, if possible
-            None => {
-                // Fee sniping can be partially prevented by setting the timelock
-                // to current_height. If we don't know the current_height,
-                // we default to 0.
-                let fee_sniping_height = current_height;
-
-                // We choose the biggest between the required nlocktime and the fee sniping
-                // height
-                match requirements.timelock {
-                    // No requirement, just use the fee_sniping_height
-                    None => fee_sniping_height,
-                    // There's a block-based requirement, but the value is lower than the fee_sniping_height
-                    Some(value @ absolute::LockTime::Blocks(_)) if value < fee_sniping_height => {
-                        fee_sniping_height
+        let lock_time_for = |requirements: &Condition| -> Result<_, CreateTxError<P>> {
+            Ok(match params.locktime {
+                // When no nLockTime is specified, we try to prevent fee sniping, if possible
+                None => {
+                    // Fee sniping can be partially prevented by setting the timelock
+                    // to current_height. If we don't know the current_height,
+                    // we default to 0.
+                    let fee_sniping_height = current_height;
+
+                    // We choose the biggest between the required nlocktime and the fee sniping
+                    // height
+                    match requirements.timelock {
+                        // No requirement, just use the fee_sniping_height
+                        None => fee_sniping_height,
+                        // There's a block-based requirement, but the value is lower than the fee_sniping_height
+                        Some(value @ absolute::LockTime::Blocks(_))
+                            if value < fee_sniping_height =>
+                        {
+                            fee_sniping_height
+                        }
+                        // There's a time-based requirement or a block-based requirement greater
+                        // than the fee_sniping_height use that value
+                        Some(value) => value,
                     }
-                    // There's a time-based requirement or a block-based requirement greater
-                    // than the fee_sniping_height use that value
-                    Some(value) => value,
                 }
-            }
-            // Specific nLockTime required and we have no constraints, so just set to that value
-            Some(x) if requirements.timelock.is_none() => x,
-            // Specific nLockTime required and it's compatible with the constraints
-            Some(x)
-                if requirements.timelock.unwrap().is_same_unit(x)
-                    && x >= requirements.timelock.unwrap() =>
-            {
-                x
-            }
-            // Invalid nLockTime required
-            Some(x) => {
-                return Err(CreateTxError::LockTime {
-                    requested: x,
-                    required: requirements.timelock.unwrap(),
-                })
-            }
+                // Specific nLockTime required and we have no constraints, so just set to that value
+                Some(x) if requirements.timelock.is_none() => x,
+                // Specific nLockTime required and it's compatible with the constraints
+                Some(x)
+                    if requirements.timelock.unwrap().is_same_unit(x)
+                        && x >= requirements.timelock.unwrap() =>
+                {
+                    x
+                }
+                // Invalid nLockTime required
+                Some(x) => {
+                    return Err(CreateTxError::LockTime {
+                        requested: x,
+                        required: requirements.timelock.unwrap(),
+                    })
+                }
+            })
         };
+        let mut lock_time = lock_time_for(&requirements)?;
+
+        let sequence_for =
+            |csv: Option<Sequence>, lock_time: absolute::LockTime| -> Result<_, CreateTxError<P>> {
+                Ok(match (params.rbf, csv) {
+                    // No RBF or CSV but there's an nLockTime, so the nSequence cannot be final
+                    (None, None) if lock_time != absolute::LockTime::ZERO => {
+                        Sequence::ENABLE_LOCKTIME_NO_RBF
+                    }
+                    // No RBF, CSV or nLockTime, make the transaction final
+                    (None, None) => Sequence::MAX,
 
-        let n_sequence = match (params.rbf, requirements.csv) {
-            // No RBF or CSV but there's an nLockTime, so the nSequence cannot be final
-            (None, None) if lock_time != absolute::LockTime::ZERO => {
-                Sequence::ENABLE_LOCKTIME_NO_RBF
-            }
-            // No RBF, CSV or nLockTime, make the transaction final
-            (None, None) => Sequence::MAX,
-
-            // No RBF requested, use the value from CSV. Note that this value is by definition
-            // non-final, so even if a timelock is enabled this nSequence is fine, hence why we
-            // don't bother checking for it here. The same is true for all the other branches below
-            (None, Some(csv)) => csv,
+                    // No RBF requested, use the value from CSV. Note that this value is by definition
+                    // non-final, so even if a timelock is enabled this nSequence is fine, hence why we
+                    // don't bother checking for it here. The same is true for all the other branches below
+                    (None, Some(csv)) => csv,
 
-            // RBF with a specific value but that value is too high
-            (Some(tx_builder::RbfValue::Value(rbf)), _) if !rbf.is_rbf() => {
-                return Err(CreateTxError::RbfSequence)
-            }
-            // RBF with a specific value requested, but the value is incompatible with CSV
-            (Some(tx_builder::RbfValue::Value(rbf)), Some(csv))
-                if !check_nsequence_rbf(rbf, csv) =>
-            {
-                return Err(CreateTxError::RbfSequenceCsv { rbf, csv })
-            }
+                    // RBF with a specific value but that value is too high
+                    (Some(tx_builder::RbfValue::Value(rbf)), _) if !rbf.is_rbf() => {
+                        return Err(CreateTxError::RbfSequence)
+                    }
+                    // RBF with a specific value requested, but the value is incompatible with CSV
+                    (Some(tx_builder::RbfValue::Value(rbf)), Some(csv))
+                        if !check_nsequence_rbf(rbf, csv) =>
+                    {
+                        return Err(CreateTxError::RbfSequenceCsv { rbf, csv })
+                    }
 
-            // RBF enabled with the default value with CSV also enabled. CSV takes precedence
-            (Some(tx_builder::RbfValue::Default), Some(csv)) => csv,
-            // Valid RBF, either default or with a specific value. We ignore the `CSV` value
-            // because we've already checked it before
-            (Some(rbf), _) => rbf.get_value(),
-        };
+                    // RBF enabled with the default value with CSV also enabled. CSV takes precedence
+                    (Some(tx_builder::RbfValue::Default), Some(csv)) => csv,
+                    // Valid RBF, either default or with a specific value. We ignore the `CSV` value
+                    // because we've already checked it before
+                    (Some(rbf), _) => rbf.get_value(),
+                })
+            };
+        let mut n_sequence = sequence_for(requirements.csv, lock_time)?;
 
         let (fee_rate, mut fee_amount) = match params
             .fee_policy
@@ -1365,7 +2377,12 @@ impl<D> Wallet<D> {
                         });
                     }
                 }
-                (*rate, 0)
+                // when paying for ancestors, the child also pays the fee they are missing for the
+                // whole package to reach the fee rate
+                let ancestors_deficit = params.paying_for_ancestors.map_or(0, |ancestors| {
+                    rate.fee_wu(ancestors.weight).saturating_sub(ancestors.fee)
+                });
+                (*rate, ancestors_deficit)
             }
         };
 
@@ -1408,6 +2425,27 @@ impl<D> Wallet<D> {
             outgoing += value;
         }
 
+        // the outputs of silent payments depend on the inputs, until they are selected the spend
+        // key stands in for the output key, which has the same weight
+        let first_silent_payment = tx.output.len();
+        for (index, (address, value)) in params.silent_payments.iter().enumerate() {
+            if !address.is_valid_for_network(self.network) {
+                return Err(SendError::InvalidNetwork(address.network).into());
+            }
+            if !params.allow_dust && value.is_dust(&address.placeholder_script()) {
+                return Err(CreateTxError::OutputBelowDustLimit(
+                    params.recipients.len() + index,
+                ));
+            }
+
+            tx.output.push(TxOut {
+                script_pubkey: address.placeholder_script(),
+                value: *value,
+            });
+
+            outgoing += value;
+        }
+
         fee_amount += fee_rate.fee_wu(tx.weight());
 
         // Segwit transactions' header is 2WU larger than legacy txs' header,
@@ -1423,25 +2461,52 @@ impl<D> Wallet<D> {
 
         if params.change_policy != tx_builder::ChangeSpendPolicy::ChangeAllowed
             && internal_descriptor.is_none()
+            && params.change_keychain.is_none()
         {
             return Err(CreateTxError::ChangePolicyDescriptor);
         }
 
         let (required_utxos, optional_utxos) = self.preselect_utxos(
             params.change_policy,
+            params.change_keychain,
+            params.spend_keychains.as_ref(),
             &params.unspendable,
             params.utxos.clone(),
             params.drain_wallet,
             params.manually_selected_only,
-            params.bumping_fee.is_some(), // we mandate confirmed transactions if we're bumping the fee
+            // we mandate confirmed transactions if we're bumping the fee, or paying for ancestors
+            // (other unconfirmed ancestors would be missing from the package)
+            params.bumping_fee.is_some() || params.paying_for_ancestors.is_some(),
             Some(current_height.to_consensus_u32()),
         );
 
+        // The timelocks required by the plans of the UTXOs spent with one
+        #[cfg(not(feature = "planning"))]
+        let plans = BTreeMap::<OutPoint, Condition>::new();
+        #[cfg(feature = "planning")]
+        let mut plans = BTreeMap::new();
+        #[cfg(feature = "planning")]
+        let (required_utxos, optional_utxos) = match &params.assets {
+            None => (required_utxos, optional_utxos),
+            Some(assets) => {
+                let assets = assets.to_plan_assets();
+                (
+                    self.plan_utxos(required_utxos, &params, &assets, current_height, &mut plans)?,
+                    self.plan_utxos(optional_utxos, &params, &assets, current_height, &mut plans)?,
+                )
+            }
+        };
+
         // get drain script
         let drain_script = match params.drain_to {
             Some(ref drain_recipient) => drain_recipient.clone(),
             None => {
-                let change_keychain = self.map_keychain(KeychainKind::Internal);
+                let change_keychain = match (tx.output.first(), params.change_keychain) {
+                    (Some(output), Some(keychain)) => {
+                        self.change_keychain_for(&output.script_pubkey, keychain)
+                    }
+                    _ => self.map_keychain(KeychainKind::Internal),
+                };
                 let ((index, spk), index_changeset) =
                     self.indexed_graph.index.next_unused_spk(&change_keychain);
                 let spk = spk.into();
@@ -1450,7 +2515,6 @@ impl<D> Wallet<D> {
                     .stage(ChangeSet::from(indexed_tx_graph::ChangeSet::from(
                         index_changeset,
                     )));
-                self.persist.commit().map_err(CreateTxError::Persist)?;
                 spk
             }
         };
@@ -1465,16 +2529,60 @@ impl<D> Wallet<D> {
         fee_amount += coin_selection.fee_amount;
         let excess = &coin_selection.excess;
 
+        let selected_plans = coin_selection
+            .selected
+            .iter()
+            .filter_map(|u| plans.get(&u.outpoint()))
+            .collect::<Vec<_>>();
+        if !selected_plans.is_empty() {
+            for plan in &selected_plans {
+                let plan_requirements = Condition {
+                    csv: None,
+                    timelock: plan.timelock,
+                };
+                requirements = requirements.merge(&plan_requirements)?;
+            }
+            lock_time = lock_time_for(&requirements)?;
+            n_sequence = sequence_for(requirements.csv, lock_time)?;
+            tx.lock_time = lock_time;
+
+            if selected_plans.iter().any(|plan| plan.csv.is_some()) {
+                match params.version {
+                    Some(tx_builder::Version(1)) => return Err(CreateTxError::Version1Csv),
+                    Some(_) => {}
+                    None => tx.version = 2,
+                }
+            }
+        }
+
         tx.input = coin_selection
             .selected
             .iter()
-            .map(|u| bitcoin::TxIn {
-                previous_output: u.outpoint(),
-                script_sig: ScriptBuf::default(),
-                sequence: n_sequence,
-                witness: Witness::new(),
+            .map(|u| {
+                let sequence = match plans.get(&u.outpoint()).and_then(|p| p.csv) {
+                    Some(csv) => sequence_for(Some(csv), lock_time)?,
+                    None => n_sequence,
+                };
+                Ok(bitcoin::TxIn {
+                    previous_output: u.outpoint(),
+                    script_sig: ScriptBuf::default(),
+                    sequence,
+                    witness: Witness::new(),
+                })
             })
-            .collect();
+            .collect::<Result<_, CreateTxError<P>>>()?;
+
+        if !params.silent_payments.is_empty() {
+            let output_keys =
+                self.silent_payment_output_keys(&coin_selection.selected, &params.silent_payments)?;
+            for (txout, key) in tx.output[first_silent_payment..]
+                .iter_mut()
+                .zip(output_keys)
+            {
+                txout.script_pubkey =
+                    ScriptBuf::new_v1_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(key));
+            }
+        }
 
         if tx.output.is_empty() {
             // Uh oh, our transaction has no outputs.
@@ -1482,9 +2590,12 @@ impl<D> Wallet<D> {
             // - We have a drain_to address and the utxos we must spend (this happens,
             // for example, when we RBF)
             // - We have a drain_to address and drain_wallet set
+            // - We pay for ancestors, the utxo we must spend is drained to a change address
             // Otherwise, we don't know who we should send the funds to, and how much
             // we should send!
-            if params.drain_to.is_some() && (params.drain_wallet || !params.utxos.is_empty()) {
+            if (params.drain_to.is_some() && (params.drain_wallet || !params.utxos.is_empty()))
+                || params.paying_for_ancestors.is_some()
+            {
                 if let NoChange {
                     dust_threshold,
                     remaining_amount,
@@ -1527,7 +2638,12 @@ impl<D> Wallet<D> {
         // sort input/outputs according to the chosen algorithm
         params.ordering.sort_tx(&mut tx);
 
+        let payment_batch = params.payment_batch.clone();
         let psbt = self.complete_transaction(tx, coin_selection.selected, params)?;
+        if let Some(batch) = payment_batch {
+            let changeset = self.payment_queue.record_batch(batch, &psbt.unsigned_tx);
+            self.persist.stage(ChangeSet::from(changeset));
+        }
         Ok(psbt)
     }
 
@@ -1661,47 +2777,505 @@ impl<D> Wallet<D> {
                     }
                 };
 
-                Ok(weighted_utxo)
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+                Ok(weighted_utxo)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if tx.output.len() > 1 {
+            let mut change_index = None;
+            for (index, txout) in tx.output.iter().enumerate() {
+                let change_type = self.map_keychain(KeychainKind::Internal);
+                match txout_index.index_of_spk(&txout.script_pubkey) {
+                    Some(&(keychain, _)) if keychain == change_type => change_index = Some(index),
+                    _ => {}
+                }
+            }
+
+            if let Some(change_index) = change_index {
+                tx.output.remove(change_index);
+            }
+        }
+
+        let params = TxParams {
+            // TODO: figure out what rbf option should be?
+            version: Some(tx_builder::Version(tx.version)),
+            recipients: tx
+                .output
+                .into_iter()
+                .map(|txout| (txout.script_pubkey, txout.value))
+                .collect(),
+            utxos: original_utxos,
+            bumping_fee: Some(tx_builder::PreviousFee {
+                absolute: fee,
+                rate: fee_rate.as_sat_per_vb(),
+            }),
+            ..Default::default()
+        };
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+            phantom: core::marker::PhantomData,
+        })
+    }
+
+    /// Returns a [`TxBuilder`] for a child transaction which pays for an unconfirmed parent
+    /// transaction and its unconfirmed ancestors (Child-Pays-For-Parent).
+    ///
+    /// Unlike [`build_fee_bump`], this works for transactions which don't signal RBF, or which the
+    /// wallet has only received. The child spends the largest unspent output of the parent owned by
+    /// the wallet and drains it to a change address, which is revealed by [`TxBuilder::finish`].
+    /// Other recipients can be added.
+    ///
+    /// With a [`fee_rate`], the child pays enough for the package of the child and the
+    /// unconfirmed ancestors (found with [`TxGraph::walk_ancestors`]) to reach it. Other inputs the
+    /// child may need must be confirmed. With an absolute fee, the child pays exactly that fee.
+    ///
+    /// The fees of the parent and its unconfirmed ancestors are calculated from the outputs they
+    /// spend. A transaction the wallet has only received usually spends outputs the wallet doesn't
+    /// know, which must be fetched (e.g. from the chain source) and added with [`insert_txout`]
+    /// first, otherwise [`BuildCpfpError::MissingPrevouts`] is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use bitcoin::Txid;
+    /// # use bdk::*;
+    /// # use bdk::wallet::ChangeSet;
+    /// # use bdk::wallet::error::CreateTxError;
+    /// # use bdk_chain::PersistBackend;
+    /// # use anyhow::Error;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let mut wallet = doctest_wallet!();
+    /// # let parent_txid: Txid = todo!();
+    /// // the parent is taking too long to confirm so we pay for it with a child
+    /// let mut psbt = {
+    ///     let mut builder = wallet.build_cpfp(parent_txid)?;
+    ///     builder.fee_rate(bdk::FeeRate::from_sat_per_vb(10.0));
+    ///     builder.finish()?
+    /// };
+    /// let _ = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// let child_tx = psbt.extract_tx();
+    /// // broadcast child_tx
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// [`build_fee_bump`]: Self::build_fee_bump
+    /// [`fee_rate`]: TxBuilder::fee_rate
+    /// [`TxGraph::walk_ancestors`]: bdk_chain::tx_graph::TxGraph::walk_ancestors
+    /// [`insert_txout`]: Self::insert_txout
+    pub fn build_cpfp(
+        &mut self,
+        parent_txid: Txid,
+    ) -> Result<TxBuilder<'_, D, DefaultCoinSelectionAlgorithm, CreateTx>, BuildCpfpError> {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+
+        let parent = graph
+            .get_tx(parent_txid)
+            .ok_or(BuildCpfpError::TransactionNotFound(parent_txid))?;
+        let pos = graph
+            .get_chain_position(&self.chain, chain_tip, parent_txid)
+            .ok_or(BuildCpfpError::TransactionNotFound(parent_txid))?;
+        if let ChainPosition::Confirmed(_) = pos {
+            return Err(BuildCpfpError::TransactionConfirmed(parent_txid));
+        }
+
+        let utxo = (0..parent.output.len() as u32)
+            .filter_map(|vout| self.get_utxo(OutPoint::new(parent_txid, vout)))
+            .filter(|utxo| !self.frozen.contains(&utxo.outpoint))
+            .max_by_key(|utxo| utxo.txout.value)
+            .ok_or(BuildCpfpError::NoSpendableOutput(parent_txid))?;
+
+        // the parent and its unconfirmed ancestors, confirmed ancestors end the walk
+        let package = core::iter::once(parent).chain(graph.walk_ancestors(parent, |_, tx| {
+            match graph.get_chain_position(&self.chain, chain_tip, tx.txid())? {
+                ChainPosition::Confirmed(_) => None,
+                ChainPosition::Unconfirmed(_) => Some(tx),
+            }
+        }));
+        let mut ancestors = tx_builder::Ancestors {
+            fee: 0,
+            weight: Weight::ZERO,
+        };
+        for tx in package {
+            ancestors.fee += self
+                .calculate_fee(tx)
+                .map_err(|_| BuildCpfpError::MissingPrevouts(tx.txid()))?;
+            ancestors.weight += tx.weight();
+        }
+
+        #[allow(deprecated)]
+        let satisfaction_weight = self
+            .get_descriptor_for_keychain(utxo.keychain)
+            .max_satisfaction_weight()
+            .unwrap();
+        let weighted_utxo = WeightedUtxo {
+            utxo: Utxo::Local(utxo),
+            satisfaction_weight,
+        };
+
+        // without `drain_to`, `create_tx` drains the output to a change address
+        let params = TxParams {
+            utxos: vec![weighted_utxo],
+            paying_for_ancestors: Some(ancestors),
+            ..Default::default()
+        };
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+            phantom: core::marker::PhantomData,
+        })
+    }
+
+    /// Queue a payment of `amount` to `script_pubkey`, to be paid by the next batch built with
+    /// [`build_batch`].
+    ///
+    /// The payment is staged, it must be committed for the wallet to remember it.
+    ///
+    /// [`build_batch`]: Self::build_batch
+    pub fn queue_payment(&mut self, script_pubkey: ScriptBuf, amount: u64) -> PaymentId {
+        let (id, changeset) = self.payment_queue.push(script_pubkey, amount);
+        self.persist.stage(ChangeSet::from(changeset));
+        id
+    }
+
+    /// Returns the status of the queued payment `id`, or `None` if there is no such payment.
+    ///
+    /// A payment is paid by a batch once a transaction spending the inputs of the batch and paying
+    /// the payment is in the wallet's best chain or mempool.
+    pub fn payment_status(&self, id: PaymentId) -> Option<PaymentStatus> {
+        let recipient = self.payment_queue.payment(id)?;
+        let mut batched = false;
+        for batch in self.payment_queue.batches_paying(id) {
+            batched = true;
+            if let Some((txid, pos)) = self.find_batch_tx(batch, core::iter::once(recipient)) {
+                return Some(match pos {
+                    ChainPosition::Confirmed(_) => PaymentStatus::Confirmed(txid),
+                    ChainPosition::Unconfirmed(_) => PaymentStatus::Broadcast(txid),
+                });
+            }
+        }
+        Some(if batched {
+            PaymentStatus::Batched
+        } else {
+            PaymentStatus::Queued
+        })
+    }
+
+    /// Find the transaction of `batch` in the wallet's best chain or mempool: a transaction which
+    /// spends its first input and pays all of the `recipients`.
+    fn find_batch_tx<'a>(
+        &self,
+        batch: &Batch,
+        recipients: impl Iterator<Item = (&'a ScriptBuf, u64)> + Clone,
+    ) -> Option<(Txid, ChainPosition<&ConfirmationTimeHeightAnchor>)> {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+        let first_input = batch.inputs.first()?;
+        graph.outspends(*first_input).iter().find_map(|&txid| {
+            let tx = graph.get_tx(txid)?;
+            let pays_all = recipients.clone().all(|(script_pubkey, amount)| {
+                tx.output
+                    .iter()
+                    .any(|txout| &txout.script_pubkey == script_pubkey && txout.value == amount)
+            });
+            if !pays_all {
+                return None;
+            }
+            let pos = graph.get_chain_position(&self.chain, chain_tip, txid)?;
+            Some((txid, pos))
+        })
+    }
+
+    /// Returns a [`TxBuilder`] for a batch transaction paying all the queued payments (see
+    /// [`queue_payment`]).
+    ///
+    /// If the last batch is still unconfirmed, the new batch replaces it like with
+    /// [`build_fee_bump`]: it keeps paying the payments of the last batch, adds the queued ones, and
+    /// must pay a higher fee rate. Otherwise the new batch is a new transaction. Batches always
+    /// signal RBF.
+    ///
+    /// The batch is recorded once the transaction is built, and staged to be committed with the rest
+    /// of the wallet. [`payment_status`] reports its payments as batched until the wallet sees the
+    /// transaction, e.g. once it is [`broadcast`], and then tells whether it is confirmed. The last
+    /// batch must be seen by the wallet before the next one can be built.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk::*;
+    /// # use bdk::wallet::ChangeSet;
+    /// # use bdk::wallet::error::CreateTxError;
+    /// # use bdk::wallet::payment_queue::PaymentStatus;
+    /// # use bdk_chain::PersistBackend;
+    /// # use anyhow::Error;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let mut wallet = doctest_wallet!();
+    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+    /// let payment = wallet.queue_payment(to_address.script_pubkey(), 50_000);
+    /// let mut psbt = {
+    ///     let mut builder = wallet.build_batch()?;
+    ///     builder.fee_rate(bdk::FeeRate::from_sat_per_vb(5.0));
+    ///     builder.finish()?
+    /// };
+    /// let _ = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// assert_eq!(wallet.payment_status(payment), Some(PaymentStatus::Batched));
+    /// let batch = psbt.extract_tx();
+    /// // broadcast batch, e.g. with `Wallet::broadcast`, which inserts it into the wallet
+    /// # wallet.insert_tx(batch.clone(), bdk_chain::ConfirmationTime::Unconfirmed { last_seen: 0 }).unwrap();
+    /// assert_eq!(
+    ///     wallet.payment_status(payment),
+    ///     Some(PaymentStatus::Broadcast(batch.txid()))
+    /// );
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// [`queue_payment`]: Self::queue_payment
+    /// [`build_fee_bump`]: Self::build_fee_bump
+    /// [`payment_status`]: Self::payment_status
+    /// [`broadcast`]: Self::broadcast
+    pub fn build_batch(
+        &mut self,
+    ) -> Result<TxBuilder<'_, D, DefaultCoinSelectionAlgorithm, PayBatch>, BuildBatchError> {
+        let (mut payments, recipients): (Vec<_>, Vec<_>) = self.payment_queue.queued().unzip();
+        if payments.is_empty() {
+            return Err(BuildBatchError::NoQueuedPayments);
+        }
+
+        let replaces = match self.payment_queue.last_batch() {
+            Some(batch) => {
+                let recipients = batch
+                    .payments
+                    .iter()
+                    .filter_map(|&id| self.payment_queue.payment(id));
+                match self.find_batch_tx(batch, recipients) {
+                    Some((txid, ChainPosition::Unconfirmed(_))) => {
+                        Some((txid, batch.payments.clone()))
+                    }
+                    Some((_, ChainPosition::Confirmed(_))) => None,
+                    None => return Err(BuildBatchError::LastBatchUnknown),
+                }
+            }
+            None => None,
+        };
+
+        let mut params = match replaces {
+            Some((txid, replaced_payments)) => {
+                payments.extend(replaced_payments);
+                self.build_fee_bump(txid)
+                    .map_err(BuildBatchError::FeeBump)?
+                    .params
+            }
+            None => TxParams::default(),
+        };
+        params.recipients.extend(recipients);
+        params.rbf = Some(tx_builder::RbfValue::Default);
+        params.payment_batch = Some(PaymentBatch { payments });
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+            phantom: core::marker::PhantomData,
+        })
+    }
+
+    /// Forget the last batch built with [`build_batch`]. The payments it added are queued again, the
+    /// ones it took over from the batch it replaced are paid by that batch again.
+    ///
+    /// Only do this if the last batch can't confirm, i.e. it was never broadcast or it was replaced
+    /// by a conflicting transaction. Returns the forgotten batch, if there is one. The change is
+    /// staged, it must be committed for the wallet to remember it.
+    ///
+    /// [`build_batch`]: Self::build_batch
+    pub fn cancel_batch(&mut self) -> Option<Batch> {
+        let (batch, changeset) = self.payment_queue.cancel_last_batch()?;
+        self.persist.stage(ChangeSet::from(changeset));
+        Some(batch)
+    }
+
+    /// Proposes transactions consolidating the wallet's UTXOs at `fee_rate`, to save fees compared
+    /// to spending them later at `long_term_fee_rate`.
+    ///
+    /// The confirmed UTXOs available for spending (e.g. not frozen with [`freeze_utxo`]) are swept,
+    /// smallest first, into a new change address by transactions weighing at most `max_weight`
+    /// (e.g. 400,000 WU, the largest standard transaction). UTXOs whose effective value at
+    /// `fee_rate` is below the dust value of their descriptor aren't worth spending and are left
+    /// alone, as are sweeps which don't save fees once the cost of spending their output is
+    /// accounted for. No consolidation is proposed if `fee_rate` isn't below `long_term_fee_rate`.
+    ///
+    /// The sweeps signal RBF and spend distinct UTXOs, so they can be broadcast together. Their
+    /// change addresses are only revealed once all of them are built. Like
+    /// [`TxBuilder::finish_staged`], the change addresses revealed are only staged, they must be
+    /// committed for the wallet to remember them.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use bdk::*;
+    /// # use bitcoin::Weight;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let mut wallet = doctest_wallet!();
+    /// // fees are cheap now but we expect 20 sat/vB in the long term
+    /// let consolidations = wallet.plan_consolidation(
+    ///     FeeRate::from_sat_per_vb(2.0),
+    ///     FeeRate::from_sat_per_vb(20.0),
+    ///     Weight::from_wu(400_000),
+    /// )?;
+    /// wallet.commit()?;
+    /// for mut consolidation in consolidations {
+    ///     println!("saving {} sats", consolidation.estimated_savings);
+    ///     let _ = wallet.sign(&mut consolidation.psbt, SignOptions::default())?;
+    ///     // broadcast consolidation.psbt.extract_tx()
+    /// }
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// [`freeze_utxo`]: Self::freeze_utxo
+    /// [`TxBuilder::finish_staged`]: tx_builder::TxBuilder::finish_staged
+    pub fn plan_consolidation(
+        &mut self,
+        fee_rate: FeeRate,
+        long_term_fee_rate: FeeRate,
+        max_weight: Weight,
+    ) -> Result<Vec<Consolidation>, CreateTxError<core::convert::Infallible>> {
+        let (_, candidates) = self.preselect_utxos(
+            tx_builder::ChangeSpendPolicy::ChangeAllowed,
+            None,
+            None,
+            &HashSet::new(),
+            Vec::new(),
+            false,
+            false,
+            true,
+            Some(self.chain.tip().height()),
+        );
+        let mut candidates = candidates
+            .into_iter()
+            .filter(|weighted| {
+                let dust_value = match &weighted.utxo {
+                    Utxo::Local(local) => self
+                        .get_descriptor_for_keychain(local.keychain)
+                        .dust_value(),
+                    Utxo::Foreign { .. } => unreachable!("only local utxos are preselected"),
+                };
+                let fee = fee_rate.fee_wu(consolidation::input_weight(weighted));
+                let effective_value = weighted.utxo.txout().value as i64 - fee as i64;
+                effective_value >= dust_value as i64
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|weighted| weighted.utxo.txout().value);
+
+        // the sweeps pay to the change descriptor, all of its scripts weigh the same
+        let change_keychain = self.map_keychain(KeychainKind::Internal);
+        let change_descriptor = self.get_descriptor_for_keychain(change_keychain).clone();
+        let change_script = change_descriptor
+            .at_derivation_index(0)
+            .expect("descriptor can't have hardened derivation")
+            .script_pubkey();
+        // `max_weight_to_satisfy` leaves out the scriptSig length and the number of witness items
+        let output_spend_weight = Weight::from_wu(
+            (coin_selection::TXIN_BASE_WEIGHT
+                + 4
+                + 1
+                + change_descriptor
+                    .max_weight_to_satisfy()
+                    .map_err(DescriptorError::Miniscript)?) as u64,
+        );
+        // segwit marker and flag, and up to 3 bytes for the number of inputs
+        let base_weight = Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: change_script,
+            }],
+        }
+        .weight()
+            + Weight::from_wu(2 + 3 * 4);
+
+        let sweeps = consolidation::plan_sweeps(
+            candidates,
+            fee_rate,
+            long_term_fee_rate,
+            max_weight,
+            base_weight,
+            output_spend_weight,
+        );
 
-        if tx.output.len() > 1 {
-            let mut change_index = None;
-            for (index, txout) in tx.output.iter().enumerate() {
-                let change_type = self.map_keychain(KeychainKind::Internal);
-                match txout_index.index_of_spk(&txout.script_pubkey) {
-                    Some(&(keychain, _)) if keychain == change_type => change_index = Some(index),
-                    _ => {}
-                }
-            }
+        // The change addresses are only revealed once all the sweeps are built, so that none is
+        // used up by a sweep that's thrown away
+        let change_indexes = self.next_unused_indexes(change_keychain, sweeps.len());
+        let mut consolidations = Vec::with_capacity(sweeps.len());
+        for (sweep, index) in sweeps.into_iter().zip(&change_indexes) {
+            let drain_to = change_descriptor
+                .at_derivation_index(*index)
+                .expect("descriptor can't have hardened derivation")
+                .script_pubkey();
+
+            let params = TxParams {
+                utxos: sweep.utxos,
+                manually_selected_only: true,
+                drain_to: Some(drain_to),
+                fee_policy: Some(FeePolicy::FeeRate(fee_rate)),
+                rbf: Some(tx_builder::RbfValue::Default),
+                ..Default::default()
+            };
+            let psbt = self.create_tx(DefaultCoinSelectionAlgorithm::default(), params)?;
+            let fee = psbt.fee_amount().expect("all the inputs are local");
+            let estimated_savings = sweep
+                .long_term_fee
+                .saturating_sub(fee + long_term_fee_rate.fee_wu(output_spend_weight));
+            consolidations.push(Consolidation {
+                psbt,
+                fee,
+                estimated_savings,
+            });
+        }
 
-            if let Some(change_index) = change_index {
-                tx.output.remove(change_index);
+        if let Some(&last_index) = change_indexes.iter().max() {
+            let (_, index_changeset) = self
+                .indexed_graph
+                .index
+                .reveal_to_target(&change_keychain, last_index);
+            for index in change_indexes {
+                self.indexed_graph.index.mark_used(&change_keychain, index);
             }
+            self.persist
+                .stage(ChangeSet::from(indexed_tx_graph::ChangeSet::from(
+                    index_changeset,
+                )));
         }
 
-        let params = TxParams {
-            // TODO: figure out what rbf option should be?
-            version: Some(tx_builder::Version(tx.version)),
-            recipients: tx
-                .output
-                .into_iter()
-                .map(|txout| (txout.script_pubkey, txout.value))
-                .collect(),
-            utxos: original_utxos,
-            bumping_fee: Some(tx_builder::PreviousFee {
-                absolute: fee,
-                rate: fee_rate.as_sat_per_vb(),
-            }),
-            ..Default::default()
-        };
+        Ok(consolidations)
+    }
 
-        Ok(TxBuilder {
-            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
-            params,
-            coin_selection: DefaultCoinSelectionAlgorithm::default(),
-            phantom: core::marker::PhantomData,
-        })
+    /// The indexes that `count` calls to [`KeychainTxOutIndex::next_unused_spk`] on `keychain`
+    /// would return if each one was marked used, without revealing them.
+    fn next_unused_indexes(&self, keychain: KeychainKind, count: usize) -> Vec<u32> {
+        let index = &self.indexed_graph.index;
+        let mut indexes = index
+            .unused_spks_of_keychain(&keychain)
+            .map(|(i, _)| i)
+            .take(count)
+            .collect::<Vec<_>>();
+        let (mut next_index, _) = index.next_index(&keychain);
+        let has_wildcard = self.get_descriptor_for_keychain(keychain).has_wildcard();
+        while indexes.len() < count {
+            indexes.push(next_index);
+            // descriptors without a wildcard have a single script, which is reused
+            if has_wildcard && next_index < bdk_chain::BIP32_MAX_INDEX {
+                next_index += 1;
+            }
+        }
+        indexes
     }
 
     /// Sign a transaction with all the wallet's signers, in the order specified by every signer's
@@ -1768,11 +3342,17 @@ impl<D> Wallet<D> {
             return Err(SignerError::NonStandardSighash);
         }
 
+        let custom_signers = self
+            .custom_signers
+            .values()
+            .flat_map(|signers| signers.signers())
+            .collect::<Vec<_>>();
         for signer in self
             .signers
             .signers()
             .iter()
             .chain(self.change_signers.signers().iter())
+            .chain(custom_signers.iter())
         {
             signer.sign_transaction(psbt, &sign_options, &self.secp)?;
         }
@@ -1787,13 +3367,10 @@ impl<D> Wallet<D> {
 
     /// Return the spending policies for the wallet's descriptor
     pub fn policies(&self, keychain: KeychainKind) -> Result<Option<Policy>, DescriptorError> {
-        let signers = match keychain {
-            KeychainKind::External => &self.signers,
-            KeychainKind::Internal => &self.change_signers,
-        };
+        let signers = self.get_signers(keychain);
 
         match self.public_descriptor(keychain) {
-            Some(desc) => Ok(desc.extract_policy(signers, BuildSatisfaction::None, &self.secp)?),
+            Some(desc) => Ok(desc.extract_policy(&signers, BuildSatisfaction::None, &self.secp)?),
             None => Ok(None),
         }
     }
@@ -1896,6 +3473,435 @@ impl<D> Wallet<D> {
         Ok(finished)
     }
 
+    /// Prepare the original PSBT of a [BIP78] payjoin, returning the parameters to send along.
+    ///
+    /// `psbt` must pay the receiver at `payee`, it is signed and finalized, and the derivation
+    /// paths of our keys are removed from it. The output paying `payee` is the only one the
+    /// receiver can substitute, and it may decrease our change by at most
+    /// `max_additional_fee_contribution` to pay for the fees of its inputs.
+    ///
+    /// See the [`payjoin`] module for the whole flow.
+    ///
+    /// [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+    pub fn payjoin_original(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        payee: &Script,
+        max_additional_fee_contribution: u64,
+    ) -> Result<PayjoinParams, PayjoinError> {
+        let payee_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey.as_script() == payee)
+            .ok_or(PayjoinError::NoPaymentToPayee)?;
+        if !self.sign(psbt, SignOptions::default())? {
+            return Err(PayjoinError::NotFinalized);
+        }
+
+        // the receiver doesn't need to know how our keys are derived
+        psbt.xpub.clear();
+        for input in &mut psbt.inputs {
+            input.bip32_derivation.clear();
+            input.tap_key_origins.clear();
+        }
+        for output in &mut psbt.outputs {
+            *output = psbt::Output::default();
+        }
+
+        let change = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .enumerate()
+            .position(|(index, txout)| index != payee_output && self.is_mine(&txout.script_pubkey));
+        Ok(PayjoinParams {
+            payee_output,
+            additional_fee_contribution: change
+                .map(|index| (index, max_additional_fee_contribution)),
+            disable_output_substitution: false,
+            min_fee_rate: None,
+        })
+    }
+
+    /// Answer the original PSBT of a [BIP78] payjoin paying us with a proposal.
+    ///
+    /// Some of our UTXOs, of the same type as the original inputs, are chosen by `coin_selection`
+    /// and added to the transaction, and their value is added to the output paying us. The fees of
+    /// the new inputs are paid by the sender as allowed by `params`, and by us for the rest. Only
+    /// our inputs are signed, the sender's are cleared so that it signs them again.
+    ///
+    /// The caller should check that the original transaction can be broadcast before answering,
+    /// since the sender broadcasts it if the payjoin fails.
+    ///
+    /// See the [`payjoin`] module for the whole flow.
+    ///
+    /// [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+    pub fn payjoin_proposal<Cs: coin_selection::CoinSelectionAlgorithm>(
+        &self,
+        original: &psbt::PartiallySignedTransaction,
+        params: &PayjoinParams,
+        coin_selection: Cs,
+    ) -> Result<psbt::PartiallySignedTransaction, PayjoinError> {
+        let original_tx = &original.unsigned_tx;
+        if original.inputs.len() != original_tx.input.len() {
+            return Err(PayjoinError::NotFinalized);
+        }
+
+        let mut input_type = None;
+        let mut input_value = 0;
+        for (n, (txin, psbt_input)) in original_tx.input.iter().zip(&original.inputs).enumerate() {
+            let outpoint = txin.previous_output;
+            if psbt_input.final_script_sig.is_none() && psbt_input.final_script_witness.is_none() {
+                return Err(PayjoinError::NotFinalized);
+            }
+            let txout = original
+                .get_utxo_for(n)
+                .ok_or(PayjoinError::MissingUtxo(outpoint))?;
+            if self.is_mine(&txout.script_pubkey) {
+                return Err(PayjoinError::OwnInput(outpoint));
+            }
+            let this_type = payjoin::input_type(&txout.script_pubkey);
+            if input_type.get_or_insert(this_type) != &this_type {
+                return Err(PayjoinError::MixedInputTypes);
+            }
+            input_value += txout.value;
+        }
+        let input_type = input_type.ok_or(PayjoinError::NotFinalized)?;
+        let output_value = original_tx
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum::<u64>();
+        let original_fee = input_value
+            .checked_sub(output_value)
+            .ok_or(PayjoinError::NotFinalized)?;
+        let payment_index = original_tx
+            .output
+            .iter()
+            .position(|txout| self.is_mine(&txout.script_pubkey))
+            .ok_or(PayjoinError::NoPaymentToUs)?;
+
+        let mut fee_rate = FeeRate::from_wu(original_fee, original.clone().extract_tx().weight());
+        if let Some(min_fee_rate) = params.min_fee_rate {
+            if min_fee_rate.as_sat_per_vb() > fee_rate.as_sat_per_vb() {
+                fee_rate = min_fee_rate;
+            }
+        }
+
+        // contribute UTXOs of the same type as the sender's inputs
+        let (_, optional_utxos) = self.preselect_utxos(
+            tx_builder::ChangeSpendPolicy::ChangeAllowed,
+            None,
+            None,
+            &HashSet::new(),
+            Vec::new(),
+            false,
+            false,
+            false,
+            Some(self.chain.tip().height()),
+        );
+        let optional_utxos = optional_utxos
+            .into_iter()
+            .filter(|weighted| {
+                payjoin::input_type(&weighted.utxo.txout().script_pubkey) == input_type
+            })
+            .collect();
+        let payment_script = &original_tx.output[payment_index].script_pubkey;
+        let selection =
+            coin_selection.coin_select(Vec::new(), optional_utxos, fee_rate, 1, payment_script)?;
+
+        let mut proposal = original.clone();
+        let mut input_weight = 0;
+        let sequence = original_tx.input[0].sequence;
+        let mut rng = rand::thread_rng();
+        for utxo in &selection.selected {
+            let utxo = match utxo {
+                Utxo::Local(local) => local.clone(),
+                Utxo::Foreign { .. } => unreachable!("only local UTXOs are selected"),
+            };
+            #[allow(deprecated)]
+            let satisfaction_weight = self
+                .get_descriptor_for_keychain(utxo.keychain)
+                .max_satisfaction_weight()
+                .unwrap();
+            input_weight += coin_selection::TXIN_BASE_WEIGHT + satisfaction_weight;
+            let outpoint = utxo.outpoint;
+            let psbt_input = self
+                .psbt_input::<core::convert::Infallible>(utxo, None, false)
+                .map_err(|_| PayjoinError::MissingUtxo(outpoint))?;
+            // insert our inputs at random positions
+            let index = rng.gen_range(0..=proposal.inputs.len());
+            proposal.unsigned_tx.input.insert(
+                index,
+                bitcoin::TxIn {
+                    previous_output: outpoint,
+                    sequence,
+                    ..Default::default()
+                },
+            );
+            proposal.inputs.insert(index, psbt_input);
+        }
+
+        // the sender pays the fees of our inputs as much as allowed, we pay the rest
+        let mut our_fee = fee_rate.fee_wu(Weight::from_wu(input_weight as u64));
+        if let Some((index, max_contribution)) = params.additional_fee_contribution {
+            if let Some(txout) = proposal.unsigned_tx.output.get_mut(index) {
+                if index != payment_index {
+                    let contribution = our_fee.min(max_contribution).min(txout.value);
+                    txout.value -= contribution;
+                    our_fee -= contribution;
+                }
+            }
+        }
+        let payment = &mut proposal.unsigned_tx.output[payment_index];
+        payment.value = (payment.value + selection.selected_amount()).saturating_sub(our_fee);
+
+        // sign while the sender's inputs still have their previous outputs, taproot signatures
+        // commit to all of them
+        self.sign(&mut proposal, SignOptions::default())?;
+        let original_inputs = original_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<HashSet<_>>();
+        for (txin, psbt_input) in proposal.unsigned_tx.input.iter().zip(&mut proposal.inputs) {
+            if original_inputs.contains(&txin.previous_output) {
+                *psbt_input = psbt::Input::default();
+            } else if psbt_input.final_script_sig.is_none()
+                && psbt_input.final_script_witness.is_none()
+            {
+                return Err(PayjoinError::NotFinalized);
+            } else {
+                *psbt_input = psbt::Input {
+                    witness_utxo: psbt_input.witness_utxo.take(),
+                    non_witness_utxo: psbt_input.non_witness_utxo.take(),
+                    final_script_sig: psbt_input.final_script_sig.take(),
+                    final_script_witness: psbt_input.final_script_witness.take(),
+                    ..Default::default()
+                };
+            }
+        }
+        for output in &mut proposal.outputs {
+            *output = psbt::Output::default();
+        }
+        proposal.xpub.clear();
+
+        Ok(proposal)
+    }
+
+    /// Check the proposal answering the original PSBT of a [BIP78] payjoin and sign it.
+    ///
+    /// The proposal is rejected if it doesn't spend all of the original inputs, if the receiver's
+    /// inputs are not of the same type as ours or don't validly spend the previous outputs it
+    /// claims, if any original output other than the payee's is missing or decreased by more than
+    /// allowed by `params`, or if the fee rate is lower than [`PayjoinParams::min_fee_rate`]. The
+    /// original transaction should be broadcast instead in that case.
+    ///
+    /// Returns the proposal with our inputs signed and finalized.
+    ///
+    /// See the [`payjoin`] module for the whole flow.
+    ///
+    /// [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+    pub fn process_payjoin_proposal(
+        &self,
+        original: &psbt::PartiallySignedTransaction,
+        mut proposal: psbt::PartiallySignedTransaction,
+        params: &PayjoinParams,
+    ) -> Result<psbt::PartiallySignedTransaction, PayjoinError> {
+        let original_tx = &original.unsigned_tx;
+        let proposal_tx = proposal.unsigned_tx.clone();
+        if proposal_tx.version != original_tx.version
+            || proposal_tx.lock_time != original_tx.lock_time
+            || proposal.inputs.len() != proposal_tx.input.len()
+            || proposal.outputs.len() != proposal_tx.output.len()
+        {
+            return Err(PayjoinError::InvalidProposal);
+        }
+
+        let mut original_inputs = HashMap::new();
+        for (n, txin) in original_tx.input.iter().enumerate() {
+            let txout = original
+                .get_utxo_for(n)
+                .ok_or(PayjoinError::MissingUtxo(txin.previous_output))?;
+            original_inputs.insert(txin.previous_output, (n, txin.sequence, txout));
+        }
+        let input_type = original
+            .get_utxo_for(0)
+            .and_then(|txout| payjoin::input_type(&txout.script_pubkey));
+        let original_input_value = original_inputs
+            .values()
+            .map(|(_, _, txout)| txout.value)
+            .sum::<u64>();
+
+        let mut input_value = 0;
+        let mut receiver_weight = 0;
+        let mut our_inputs = Vec::new();
+        let mut receiver_inputs = Vec::new();
+        let mut prevouts = Vec::new();
+        for (n, (txin, psbt_input)) in proposal_tx.input.iter().zip(&proposal.inputs).enumerate() {
+            let outpoint = txin.previous_output;
+            let finalized =
+                psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some();
+            match original_inputs.get(&outpoint) {
+                Some(&(original_index, sequence, ref txout)) => {
+                    if txin.sequence != sequence {
+                        return Err(PayjoinError::SequenceMismatch(outpoint));
+                    }
+                    if finalized
+                        || psbt_input.witness_utxo.is_some()
+                        || psbt_input.non_witness_utxo.is_some()
+                    {
+                        return Err(PayjoinError::SenderInputNotCleared(outpoint));
+                    }
+                    input_value += txout.value;
+                    prevouts.push(txout.clone());
+                    our_inputs.push((n, original_index));
+                }
+                None => {
+                    if !finalized {
+                        return Err(PayjoinError::NotFinalized);
+                    }
+                    let txout = proposal
+                        .get_utxo_for(n)
+                        .ok_or(PayjoinError::MissingUtxo(outpoint))?;
+                    if self.is_mine(&txout.script_pubkey) {
+                        return Err(PayjoinError::OwnInput(outpoint));
+                    }
+                    if payjoin::input_type(&txout.script_pubkey) != input_type {
+                        return Err(PayjoinError::InputTypeMismatch(outpoint));
+                    }
+                    if txin.sequence != original_tx.input[0].sequence {
+                        return Err(PayjoinError::SequenceMismatch(outpoint));
+                    }
+                    // legacy signatures don't commit to the value of the previous output
+                    if !txout.script_pubkey.is_witness_program()
+                        && psbt_input
+                            .non_witness_utxo
+                            .as_ref()
+                            .map_or(true, |tx| tx.txid() != outpoint.txid)
+                    {
+                        return Err(PayjoinError::InvalidReceiverInput(outpoint));
+                    }
+                    input_value += txout.value;
+                    prevouts.push(txout.clone());
+                    let txin = bitcoin::TxIn {
+                        script_sig: psbt_input.final_script_sig.clone().unwrap_or_default(),
+                        witness: psbt_input.final_script_witness.clone().unwrap_or_default(),
+                        ..txin.clone()
+                    };
+                    receiver_weight += txin.segwit_weight();
+                    receiver_inputs.push((n, txin));
+                }
+            }
+        }
+        if let Some(missing) = original_inputs.keys().find(|outpoint| {
+            !proposal_tx
+                .input
+                .iter()
+                .any(|txin| txin.previous_output == **outpoint)
+        }) {
+            return Err(PayjoinError::MissingInput(*missing));
+        }
+
+        // every original output but the payee's, and the payee's if it can't be substituted,
+        // must be kept
+        let (fee_output, max_contribution) = match params.additional_fee_contribution {
+            Some((index, max_contribution)) => (Some(index), max_contribution),
+            None => (None, 0),
+        };
+        let mut matched = HashSet::new();
+        let mut contribution = 0;
+        for (index, txout) in original_tx.output.iter().enumerate() {
+            if index == params.payee_output && !params.disable_output_substitution {
+                continue;
+            }
+            let proposal_index = proposal_tx
+                .output
+                .iter()
+                .enumerate()
+                .position(|(i, proposed)| {
+                    proposed.script_pubkey == txout.script_pubkey && !matched.contains(&i)
+                })
+                .ok_or(PayjoinError::MissingOutput(index))?;
+            matched.insert(proposal_index);
+            let decrease = txout
+                .value
+                .saturating_sub(proposal_tx.output[proposal_index].value);
+            if decrease > 0 {
+                if Some(index) != fee_output
+                    || index == params.payee_output
+                    || !self.is_mine(&txout.script_pubkey)
+                {
+                    return Err(PayjoinError::OutputDecreased(index));
+                }
+                contribution = decrease;
+            }
+        }
+
+        // we only pay for the fees of the receiver's inputs, at the original fee rate, and our
+        // contribution is the decrease of the fee output since no other output of ours shrinks
+        let original_output_value = original_tx
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum::<u64>();
+        let original_fee = original_input_value.saturating_sub(original_output_value);
+        let output_value = proposal_tx
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum::<u64>();
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or(PayjoinError::InvalidProposal)?;
+        let original_fee_rate =
+            FeeRate::from_wu(original_fee, original.clone().extract_tx().weight());
+        let max_contribution =
+            max_contribution.min(original_fee_rate.fee_wu(Weight::from_wu(receiver_weight as u64)));
+        if contribution > max_contribution {
+            return Err(PayjoinError::FeeContributionTooHigh {
+                contribution,
+                max: max_contribution,
+            });
+        }
+
+        // the values of the receiver's previous outputs are committed to by its signatures
+        let mut signed_tx = proposal_tx.clone();
+        for (n, txin) in &receiver_inputs {
+            signed_tx.input[*n] = txin.clone();
+        }
+        for (n, txin) in receiver_inputs {
+            if !payjoin::verify_input(&self.secp, &signed_tx, n, &prevouts) {
+                return Err(PayjoinError::InvalidReceiverInput(txin.previous_output));
+            }
+        }
+
+        // restore our inputs and sign them again
+        for (n, original_index) in our_inputs {
+            let outpoint = proposal_tx.input[n].previous_output;
+            let utxo = self
+                .get_utxo(outpoint)
+                .ok_or(PayjoinError::MissingUtxo(outpoint))?;
+            let sighash_type = original.inputs[original_index].sighash_type;
+            proposal.inputs[n] = self
+                .psbt_input::<core::convert::Infallible>(utxo, sighash_type, false)
+                .map_err(|_| PayjoinError::MissingUtxo(outpoint))?;
+        }
+        if !self.sign(&mut proposal, SignOptions::default())? {
+            return Err(PayjoinError::NotFinalized);
+        }
+
+        if let Some(required) = params.min_fee_rate {
+            let actual = FeeRate::from_wu(fee, proposal.clone().extract_tx().weight());
+            if actual.as_sat_per_vb() < required.as_sat_per_vb() {
+                return Err(PayjoinError::FeeRateTooLow { required, actual });
+            }
+        }
+
+        Ok(proposal)
+    }
+
     /// Return the secp256k1 context used for all signing operations
     pub fn secp_ctx(&self) -> &SecpCtx {
         &self.secp
@@ -1943,6 +3949,25 @@ impl<D> Wallet<D> {
         }
     }
 
+    /// The keychain to pay the change of a payment to `script_pubkey` to: the internal keychain
+    /// if it has the same script type, otherwise `keychain` if it does.
+    fn change_keychain_for(&self, script_pubkey: &Script, keychain: KeychainKind) -> KeychainKind {
+        let change_keychain = self.map_keychain(KeychainKind::Internal);
+        let recipient_type = utils::script_type(script_pubkey);
+        [change_keychain, keychain]
+            .into_iter()
+            .filter(|keychain| self.keychains().contains_key(keychain))
+            .find(|keychain| {
+                let script_type = self
+                    .get_descriptor_for_keychain(*keychain)
+                    .at_derivation_index(0)
+                    .ok()
+                    .and_then(|descriptor| utils::script_type(&descriptor.script_pubkey()));
+                recipient_type.is_some() && script_type == recipient_type
+            })
+            .unwrap_or(change_keychain)
+    }
+
     fn get_descriptor_for_txout(&self, txout: &TxOut) -> Option<DerivedDescriptor> {
         let &(keychain, child) = self
             .indexed_graph
@@ -1952,6 +3977,88 @@ impl<D> Wallet<D> {
         descriptor.at_derivation_index(child).ok()
     }
 
+    /// Whether the UTXOs of `keychain` can be spent with a [`Plan`].
+    ///
+    /// The other ones are spent as if no assets were given, with their maximum satisfaction weight.
+    #[cfg(feature = "planning")]
+    fn can_plan(&self, keychain: KeychainKind) -> bool {
+        bdk_tmp_plan::can_plan(self.get_descriptor_for_keychain(keychain))
+    }
+
+    /// Spend the `utxos` that can be planned with their cheapest plan, which also gives their
+    /// exact weight, and record the timelocks it requires in `plans`.
+    ///
+    /// The UTXOs that can't be spent with the `assets` are left out, unless they were selected
+    /// manually, in which case this fails with [`CreateTxError::NoSpendingPlan`].
+    #[cfg(feature = "planning")]
+    fn plan_utxos<P>(
+        &self,
+        utxos: Vec<WeightedUtxo>,
+        params: &TxParams,
+        assets: &bdk_tmp_plan::Assets<DescriptorPublicKey>,
+        current_height: absolute::LockTime,
+        plans: &mut BTreeMap<OutPoint, Condition>,
+    ) -> Result<Vec<WeightedUtxo>, CreateTxError<P>> {
+        let mut planned = Vec::with_capacity(utxos.len());
+        for mut weighted in utxos {
+            if let Utxo::Local(local) = &weighted.utxo {
+                if self.can_plan(local.keychain) {
+                    let plan = match self.spending_plan(local, assets, current_height) {
+                        Some(plan) => plan,
+                        None if params
+                            .utxos
+                            .iter()
+                            .any(|u| u.utxo.outpoint() == local.outpoint) =>
+                        {
+                            return Err(CreateTxError::NoSpendingPlan(local.outpoint))
+                        }
+                        None => continue,
+                    };
+                    weighted.satisfaction_weight = plan_satisfaction_weight(&plan, params.sighash);
+                    plans.insert(
+                        local.outpoint,
+                        Condition {
+                            csv: plan.required_sequence(),
+                            timelock: plan.required_locktime(),
+                        },
+                    );
+                }
+            }
+            planned.push(weighted);
+        }
+        Ok(planned)
+    }
+
+    /// Returns the cheapest plan to spend `utxo` with `assets`, if there's any.
+    ///
+    /// Unless given in the `assets`, the maximum nLockTime is `current_height` and the age of the
+    /// output is its number of confirmations.
+    #[cfg(feature = "planning")]
+    fn spending_plan(
+        &self,
+        utxo: &LocalOutput,
+        assets: &bdk_tmp_plan::Assets<DescriptorPublicKey>,
+        current_height: absolute::LockTime,
+    ) -> Option<Plan<DescriptorPublicKey>> {
+        let descriptor = self
+            .get_descriptor_for_keychain(utxo.keychain)
+            .at_derivation_index(utxo.derivation_index)
+            .ok()?;
+        let mut assets = assets.clone();
+        if assets.max_locktime.is_none() {
+            assets.max_locktime = Some(current_height);
+        }
+        if assets.txo_age.is_none() {
+            if let ConfirmationTime::Confirmed { height, .. } = utxo.confirmation_time {
+                let confirmations = (self.chain.tip().height() + 1).saturating_sub(height);
+                assets.txo_age = Some(Sequence::from_height(
+                    confirmations.min(u16::MAX as u32) as u16
+                ));
+            }
+        }
+        bdk_tmp_plan::plan_satisfaction(&descriptor, &assets)
+    }
+
     fn get_available_utxos(&self) -> Vec<(LocalOutput, usize)> {
         self.list_unspent()
             .map(|utxo| {
@@ -1967,12 +4074,76 @@ impl<D> Wallet<D> {
             .collect()
     }
 
+    /// Computes the output keys of the silent payments of a transaction spending `utxos`.
+    fn silent_payment_output_keys(
+        &self,
+        utxos: &[Utxo],
+        silent_payments: &[(SilentPaymentAddress, u64)],
+    ) -> Result<Vec<XOnlyPublicKey>, SendError> {
+        let mut input_keys = Vec::new();
+        for utxo in utxos {
+            match utxo {
+                Utxo::Local(local) => input_keys.extend(self.silent_payment_input_key(local)?),
+                Utxo::Foreign { outpoint, .. } => return Err(SendError::ForeignInput(*outpoint)),
+            }
+        }
+        let outpoints = utxos.iter().map(Utxo::outpoint).collect::<Vec<_>>();
+        let addresses = silent_payments
+            .iter()
+            .map(|(address, _)| *address)
+            .collect::<Vec<_>>();
+        silent_payments::sender_output_keys(&self.secp, &outpoints, &input_keys, &addresses)
+    }
+
+    /// The private key `utxo` contributes to silent payments, `None` if it is not eligible.
+    fn silent_payment_input_key(&self, utxo: &LocalOutput) -> Result<Option<SecretKey>, SendError> {
+        let derived_descriptor = self
+            .get_descriptor_for_keychain(utxo.keychain)
+            .at_derivation_index(utxo.derivation_index)
+            .expect("child can't be hardened");
+        let signers = self.get_signers(utxo.keychain);
+        let missing_key = SendError::MissingPrivateKey(utxo.outpoint);
+
+        let key = match &derived_descriptor {
+            Descriptor::Wpkh(wpkh) => wpkh.as_inner(),
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::Wpkh(wpkh) => wpkh.as_inner(),
+                _ => return Ok(None),
+            },
+            Descriptor::Pkh(pkh) => pkh.as_inner(),
+            Descriptor::Tr(tr) => {
+                // taproot inputs contribute the key of the output, not the internal one
+                let internal_key = signers
+                    .find_secret_key(tr.internal_key(), &self.secp)
+                    .ok_or(missing_key)?;
+                let keypair = KeyPair::from_secret_key(&self.secp, &internal_key)
+                    .tap_tweak(&self.secp, tr.spend_info().merkle_root())
+                    .to_inner();
+                let secret_key = match keypair.x_only_public_key().1 {
+                    Parity::Even => keypair.secret_key(),
+                    Parity::Odd => keypair.secret_key().negate(),
+                };
+                return Ok(Some(secret_key));
+            }
+            _ => return Ok(None),
+        };
+        match key.derive_public_key(&self.secp) {
+            Ok(public_key) if public_key.compressed => signers
+                .find_secret_key(key, &self.secp)
+                .map(Some)
+                .ok_or(missing_key),
+            _ => Ok(None),
+        }
+    }
+
     /// Given the options returns the list of utxos that must be used to form the
     /// transaction and any further that may be used if needed.
     #[allow(clippy::too_many_arguments)]
     fn preselect_utxos(
         &self,
         change_policy: tx_builder::ChangeSpendPolicy,
+        change_keychain: Option<KeychainKind>,
+        spend_keychains: Option<&BTreeSet<KeychainKind>>,
         unspendable: &HashSet<OutPoint>,
         manually_selected: Vec<WeightedUtxo>,
         must_use_all_available: bool,
@@ -1992,7 +4163,7 @@ impl<D> Wallet<D> {
         });
         let mut must_spend = manually_selected;
 
-        // NOTE: we are intentionally ignoring `unspendable` here. i.e manual
+        // NOTE: we are intentionally ignoring `unspendable` and frozen utxos here. i.e manual
         // selection overrides unspendable.
         if manual_only {
             return (must_spend, vec![]);
@@ -2020,6 +4191,15 @@ impl<D> Wallet<D> {
                 if must_only_use_confirmed_tx && !confirmation_time.is_confirmed() {
                     return false;
                 }
+                let confirmations = match confirmation_time {
+                    ConfirmationTime::Confirmed { height, .. } => {
+                        (chain_tip.height + 1).saturating_sub(height)
+                    }
+                    ConfirmationTime::Unconfirmed { .. } => 0,
+                };
+                if confirmations < self.min_confirmations(u.0.keychain) {
+                    return false;
+                }
                 if tx.is_coin_base() {
                     debug_assert!(
                         confirmation_time.is_confirmed(),
@@ -2042,8 +4222,10 @@ impl<D> Wallet<D> {
 
         let mut i = 0;
         may_spend.retain(|u| {
-            let retain = change_policy.is_satisfied_by(&u.0)
+            let retain = change_policy.is_satisfied_by(&u.0, change_keychain)
+                && spend_keychains.map_or(true, |keychains| keychains.contains(&u.0.keychain))
                 && !unspendable.contains(&u.0.outpoint)
+                && !self.frozen.contains(&u.0.outpoint)
                 && satisfies_confirmed[i];
             i += 1;
             retain
@@ -2064,15 +4246,12 @@ impl<D> Wallet<D> {
         (must_spend, may_spend)
     }
 
-    fn complete_transaction(
+    fn complete_transaction<P>(
         &self,
         tx: Transaction,
         selected: Vec<Utxo>,
         params: TxParams,
-    ) -> Result<psbt::PartiallySignedTransaction, CreateTxError<D::WriteError>>
-    where
-        D: PersistBackend<ChangeSet>,
-    {
+    ) -> Result<psbt::PartiallySignedTransaction, CreateTxError<P>> {
         let mut psbt = psbt::PartiallySignedTransaction::from_unsigned_tx(tx)?;
 
         if params.add_global_xpubs {
@@ -2110,7 +4289,7 @@ impl<D> Wallet<D> {
             match utxo {
                 Utxo::Local(utxo) => {
                     *psbt_input =
-                        match self.get_psbt_input(utxo, params.sighash, params.only_witness_utxo) {
+                        match self.psbt_input(utxo, params.sighash, params.only_witness_utxo) {
                             Ok(psbt_input) => psbt_input,
                             Err(e) => match e {
                                 CreateTxError::UnknownUtxo => psbt::Input {
@@ -2156,6 +4335,15 @@ impl<D> Wallet<D> {
     where
         D: PersistBackend<ChangeSet>,
     {
+        self.psbt_input(utxo, sighash_type, only_witness_utxo)
+    }
+
+    fn psbt_input<P>(
+        &self,
+        utxo: LocalOutput,
+        sighash_type: Option<psbt::PsbtSighashType>,
+        only_witness_utxo: bool,
+    ) -> Result<psbt::Input, CreateTxError<P>> {
         // Try to find the prev_script in our db to figure out if this is internal or external,
         // and the derivation index
         let &(keychain, child) = self
@@ -2250,11 +4438,32 @@ impl<D> Wallet<D> {
     /// Usually you create an `update` by interacting with some blockchain data source and inserting
     /// transactions related to your wallet into it.
     ///
+    /// Returns the [`WalletEvent`]s caused by the update: transactions received, confirmed,
+    /// replaced or dropped by a reorg, and the balance change. Only the transactions of the update,
+    /// the ones they conflict with, the ones anchored to blocks the update replaces or adds, and the
+    /// descendants of all of these are compared, but the balance is computed before and after
+    /// applying the update.
+    ///
     /// [`commit`]: Self::commit
-    pub fn apply_update(&mut self, update: Update) -> Result<(), CannotConnectError>
-    where
-        D: PersistBackend<ChangeSet>,
-    {
+    pub fn apply_update(&mut self, update: Update) -> Result<Vec<WalletEvent>, CannotConnectError> {
+        let txids = self.txids_affected_by(&update);
+        let old_txs = self.tx_positions(&txids);
+        let old_balance = self.get_balance();
+
+        let changeset = self.apply_update_changeset(update)?;
+        if changeset.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.persist.stage(changeset);
+
+        Ok(event::diff(
+            self.indexed_graph.graph(),
+            (&old_txs, old_balance),
+            (&self.tx_positions(&txids), self.get_balance()),
+        ))
+    }
+
+    fn apply_update_changeset(&mut self, update: Update) -> Result<ChangeSet, CannotConnectError> {
         let mut changeset = match update.chain {
             Some(chain_update) => ChangeSet::from(self.chain.apply_update(chain_update)?),
             None => ChangeSet::default(),
@@ -2270,9 +4479,70 @@ impl<D> Wallet<D> {
         changeset.append(ChangeSet::from(
             self.indexed_graph.apply_update(update.graph),
         ));
+        changeset.append(self.prune_frozen());
+        Ok(changeset)
+    }
 
-        self.persist.stage(changeset);
-        Ok(())
+    /// The transactions whose chain position `update` may change: the transactions of the update,
+    /// the wallet transactions they conflict with, the wallet transactions anchored to blocks the
+    /// chain update replaces or adds, and the descendants of all of these.
+    fn txids_affected_by(&self, update: &Update) -> BTreeSet<Txid> {
+        let graph = self.indexed_graph.graph();
+        let mut txids = update
+            .graph
+            .all_anchors()
+            .iter()
+            .map(|(_, txid)| *txid)
+            .collect::<BTreeSet<_>>();
+        for tx in update.graph.full_txs() {
+            txids.insert(tx.txid);
+            txids.extend(graph.walk_conflicts(tx.tx, |_, conflict| Some(conflict)));
+        }
+
+        // the lowest height at which the chain update disagrees with the wallet's chain
+        let changed_from = update.chain.as_ref().and_then(|chain_update| {
+            chain_update
+                .tip
+                .iter()
+                .take_while(|cp| self.chain.blocks().get(&cp.height()) != Some(&cp.hash()))
+                .last()
+                .map(|cp| cp.height())
+        });
+        if let Some(height) = changed_from {
+            let from = ConfirmationTimeHeightAnchor {
+                anchor_block: BlockId {
+                    height,
+                    hash: BlockHash::all_zeros(),
+                },
+                confirmation_height: 0,
+                confirmation_time: 0,
+            };
+            txids.extend(
+                graph
+                    .all_anchors()
+                    .range((from, Txid::all_zeros())..)
+                    .map(|(_, txid)| *txid),
+            );
+        }
+
+        let descendants = txids
+            .iter()
+            .flat_map(|&txid| graph.walk_descendants(txid, |_, descendant| Some(descendant)))
+            .collect::<Vec<_>>();
+        txids.extend(descendants);
+        txids
+    }
+
+    fn tx_positions(&self, txids: &BTreeSet<Txid>) -> event::TxPositions {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+        txids
+            .iter()
+            .filter_map(|&txid| {
+                let position = graph.get_chain_position(&self.chain, chain_tip, txid)?;
+                Some((txid, position.cloned()))
+            })
+            .collect()
     }
 
     /// Commits all currently [`staged`] changed to the persistence backend returning and error when
@@ -2288,13 +4558,25 @@ impl<D> Wallet<D> {
         self.persist.commit().map(|c| c.is_some())
     }
 
-    /// Returns the changes that will be staged with the next call to [`commit`].
+    /// Commits all currently [`staged`] changes to the asynchronous persistence backend.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`commit`], returning whether there were
+    /// any changes to commit.
     ///
+    /// [`staged`]: Self::staged
     /// [`commit`]: Self::commit
-    pub fn staged(&self) -> &ChangeSet
+    #[cfg(feature = "async")]
+    pub async fn commit_async(&mut self) -> Result<bool, D::WriteError>
     where
-        D: PersistBackend<ChangeSet>,
+        D: PersistBackendAsync<ChangeSet>,
     {
+        self.persist.commit_async().await.map(|c| c.is_some())
+    }
+
+    /// Returns the changes that will be staged with the next call to [`commit`].
+    ///
+    /// [`commit`]: Self::commit
+    pub fn staged(&self) -> &ChangeSet {
         self.persist.staged()
     }
 
@@ -2351,18 +4633,22 @@ where
     Ok(wallet_name)
 }
 
-fn new_local_utxo(
-    keychain: KeychainKind,
-    derivation_index: u32,
-    full_txo: FullTxOut<ConfirmationTimeHeightAnchor>,
-) -> LocalOutput {
-    LocalOutput {
-        outpoint: full_txo.outpoint,
-        txout: full_txo.txout,
-        is_spent: full_txo.spent_by.is_some(),
-        confirmation_time: full_txo.chain_position.into(),
-        keychain,
-        derivation_index,
+/// The satisfaction weight of `plan`, taking into account the extra sighash byte of schnorr
+/// signatures that don't use [`TapSighashType::Default`].
+#[cfg(feature = "planning")]
+fn plan_satisfaction_weight(
+    plan: &Plan<DescriptorPublicKey>,
+    sighash: Option<psbt::PsbtSighashType>,
+) -> usize {
+    // The size of ECDSA signatures already includes their sighash byte
+    let signatures = match plan.requirements().signatures {
+        RequiredSignatures::TapKey { .. } => 1,
+        RequiredSignatures::TapScript { plan_keys, .. } => plan_keys.len(),
+        RequiredSignatures::Legacy { .. } | RequiredSignatures::Segwitv0 { .. } => 0,
+    };
+    match sighash.map(|sighash| sighash.taproot_hash_ty()) {
+        None | Some(Ok(TapSighashType::Default)) => plan.expected_weight(),
+        _ => plan.expected_weight() + signatures,
     }
 }
 
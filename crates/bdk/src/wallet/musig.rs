@@ -0,0 +1,1196 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! MuSig2
+//!
+//! [BIP327] multi-signatures for the key path of `tr()` descriptors whose internal key aggregates
+//! the keys of several participants. In a descriptor string the aggregate key can be written as
+//! `musig(KEY,KEY,...)`, with the participant public keys in hex, optionally followed by
+//! unhardened derivation steps as in [BIP390], e.g. `tr(musig(KEY,KEY)/0/*)`. Extended keys
+//! aren't supported inside `musig()`.
+//!
+//! While parsing, `musig()` is replaced with the aggregate key as an extended key ([BIP328]), so
+//! the descriptor of the wallet doesn't contain the participant keys and doesn't round-trip: keep
+//! the original string to share it with the other participants.
+//!
+//! Each participant adds a [`MusigSigner`] to its wallet. Signing takes two rounds, and the nonces
+//! and partial signatures are exchanged through the PSBT fields proposed in [BIP373]:
+//!
+//! 1. every participant signs the PSBT, which only adds its public nonce;
+//! 2. once the PSBTs are combined, every participant signs again and adds its partial signature.
+//!
+//! When all the partial signatures are in the PSBT, the signer aggregates them into the key spend
+//! signature, and the PSBT can be finalized. The partial signatures of the other participants are
+//! verified first, so that an invalid one is attributed to its participant. The secret nonces are
+//! kept by the signer and never written to the PSBT, so both rounds must be signed with the same
+//! [`MusigSigner`].
+//!
+//! ```
+//! # use bdk::bitcoin::secp256k1::{Secp256k1, SecretKey};
+//! # use bdk::bitcoin::Network;
+//! # use bdk::wallet::musig::{KeyAggContext, MusigSigner};
+//! # use bdk::wallet::signer::SignerOrdering;
+//! # use bdk::{KeychainKind, Wallet};
+//! # use std::sync::Arc;
+//! let secp = Secp256k1::new();
+//! let alice = SecretKey::from_slice(&[1; 32])?;
+//! # let bob = SecretKey::from_slice(&[2; 32])?.public_key(&secp);
+//! let keys = vec![alice.public_key(&secp), bob];
+//!
+//! let descriptor = format!("tr(musig({},{}))", keys[0], keys[1]);
+//! let mut wallet = Wallet::new_no_persist(&descriptor, None, Network::Testnet)?;
+//! let signer = MusigSigner::new(alice, KeyAggContext::new(keys)?, &secp)?;
+//! wallet.add_signer(KeychainKind::External, SignerOrdering::default(), Arc::new(signer));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [BIP327]: https://github.com/bitcoin/bips/blob/master/bip-0327.mediawiki
+//! [BIP328]: https://github.com/bitcoin/bips/blob/master/bip-0328.mediawiki
+//! [BIP390]: https://github.com/bitcoin/bips/blob/master/bip-0390.mediawiki
+//! [BIP373]: https://github.com/bitcoin/bips/blob/master/bip-0373.mediawiki
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::bip32::{ChainCode, ChildNumber, ExtendedPubKey};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::psbt::{self, raw};
+use bitcoin::secp256k1::{self, constants, PublicKey, Scalar};
+use bitcoin::Network;
+
+#[cfg(feature = "std")]
+use {
+    super::signer::{ComputeSighash, SignerCommon, SignerError, SignerId, TransactionSigner},
+    super::utils::SecpCtx,
+    crate::collections::BTreeMap,
+    crate::SignOptions,
+    alloc::collections::btree_map::Entry,
+    bitcoin::bip32::{DerivationPath, Fingerprint},
+    bitcoin::secp256k1::{schnorr, Message, SecretKey, Signing, Verification},
+    bitcoin::taproot::{TapNodeHash, TapTweakHash},
+    bitcoin::{taproot, OutPoint},
+    miniscript::Tap,
+    std::sync::Mutex,
+};
+
+/// Key type of the participant keys of an aggregate key, proposed in BIP373
+const PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS: u8 = 0x1a;
+/// Key type of a participant's public nonce, proposed in BIP373
+const PSBT_IN_MUSIG2_PUB_NONCE: u8 = 0x1b;
+/// Key type of a participant's partial signature, proposed in BIP373
+const PSBT_IN_MUSIG2_PARTIAL_SIG: u8 = 0x1c;
+/// The chain code of aggregate keys used as extended keys, from BIP328
+const AGGREGATE_CHAIN_CODE: [u8; 32] = [
+    0x86, 0x80, 0x87, 0xca, 0x02, 0xa6, 0xf9, 0x74, 0xc4, 0x59, 0x89, 0x24, 0xc3, 0x6b, 0x57, 0x76,
+    0x2d, 0x32, 0xcb, 0x45, 0x71, 0x71, 0x67, 0xe3, 0x00, 0x62, 0x2c, 0x71, 0x67, 0xe3, 0x89, 0x65,
+];
+
+/// Errors related to MuSig2 keys and signing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// There are no participant keys, or they add up to the point at infinity
+    InvalidKeys,
+    /// A `musig()` expression is malformed or contains keys other than public keys
+    InvalidKeyExpression,
+    /// The key of the signer isn't one of the participant keys
+    NotParticipant,
+    /// A public nonce in the PSBT is invalid
+    InvalidNonce,
+    /// The partial signature of the participant in the PSBT is invalid
+    InvalidPartialSignature(PublicKey),
+    /// The secret nonce of our public nonce in the PSBT is unknown, or was already used
+    MissingSecretNonce,
+    /// The aggregate signature doesn't verify
+    InvalidSignature,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKeys => write!(f, "Invalid MuSig2 participant keys"),
+            Self::InvalidKeyExpression => write!(f, "Invalid or unsupported musig() expression"),
+            Self::NotParticipant => write!(f, "The signer is not a participant of the MuSig2 key"),
+            Self::InvalidNonce => write!(f, "Invalid MuSig2 public nonce"),
+            Self::InvalidPartialSignature(key) => {
+                write!(f, "Invalid MuSig2 partial signature of {}", key)
+            }
+            Self::MissingSecretNonce => write!(f, "Missing or already used MuSig2 secret nonce"),
+            Self::InvalidSignature => write!(f, "Invalid MuSig2 aggregate signature"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// The participant keys of a MuSig2 aggregate key, as in `KeyAgg` of BIP327
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyAggContext {
+    keys: Vec<PublicKey>,
+    /// The hash of all the keys, committed to by the key coefficients
+    keys_hash: [u8; 32],
+    /// The first key different from the first one, whose coefficient is one
+    second_key: Option<PublicKey>,
+    aggregate: PublicKey,
+}
+
+impl KeyAggContext {
+    /// Aggregate the participant keys in the given order
+    ///
+    /// The order matters, and must be the one of the `musig()` expression in the descriptor.
+    pub fn new(keys: Vec<PublicKey>) -> Result<Self, Error> {
+        let secp = secp256k1::Secp256k1::verification_only();
+
+        let serialized = keys.iter().map(PublicKey::serialize).collect::<Vec<_>>();
+        let keys_hash = tagged_hash(
+            "KeyAgg list",
+            &serialized.iter().map(|k| &k[..]).collect::<Vec<_>>(),
+        );
+        let second_key = keys.iter().find(|k| Some(*k) != keys.first()).copied();
+
+        let terms = keys
+            .iter()
+            .map(|k| k.mul_tweak(&secp, &key_coefficient(&keys_hash, second_key, k)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::InvalidKeys)?;
+        let aggregate = PublicKey::combine_keys(&terms.iter().collect::<Vec<_>>())
+            .map_err(|_| Error::InvalidKeys)?;
+
+        Ok(KeyAggContext {
+            keys,
+            keys_hash,
+            second_key,
+            aggregate,
+        })
+    }
+
+    /// The participant keys
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    /// The aggregate key, before any tweak
+    pub fn aggregate_key(&self) -> PublicKey {
+        self.aggregate
+    }
+
+    /// The x-only aggregate key, used as the internal key of taproot outputs
+    pub fn x_only_aggregate_key(&self) -> XOnlyPublicKey {
+        self.aggregate.x_only_public_key().0
+    }
+
+    /// The aggregate key as an extended key, which addresses can be derived from as in [BIP328]
+    ///
+    /// [BIP328]: https://github.com/bitcoin/bips/blob/master/bip-0328.mediawiki
+    pub fn extended_key(&self, network: Network) -> ExtendedPubKey {
+        ExtendedPubKey {
+            network,
+            depth: 0,
+            parent_fingerprint: Default::default(),
+            child_number: ChildNumber::from_normal_idx(0).expect("valid index"),
+            public_key: self.aggregate,
+            chain_code: ChainCode::from(AGGREGATE_CHAIN_CODE),
+        }
+    }
+
+    fn coefficient(&self, key: &PublicKey) -> Scalar {
+        key_coefficient(&self.keys_hash, self.second_key, key)
+    }
+}
+
+fn key_coefficient(keys_hash: &[u8; 32], second_key: Option<PublicKey>, key: &PublicKey) -> Scalar {
+    if Some(*key) == second_key {
+        Scalar::ONE
+    } else {
+        hash_to_scalar(tagged_hash(
+            "KeyAgg coefficient",
+            &[keys_hash, &key.serialize()],
+        ))
+    }
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Interpret a hash as an integer modulo the curve order
+fn hash_to_scalar(hash: [u8; 32]) -> Scalar {
+    Scalar::from_be_bytes(hash).unwrap_or_else(|_| {
+        // The hash is lower than twice the curve order, so subtracting it once is enough
+        let mut reduced = [0u8; 32];
+        let mut borrow = 0;
+        for i in (0..32).rev() {
+            let diff = hash[i] as i16 - constants::CURVE_ORDER[i] as i16 - borrow;
+            reduced[i] = diff.rem_euclid(256) as u8;
+            borrow = (diff < 0) as i16;
+        }
+        Scalar::from_be_bytes(reduced).expect("reduced modulo the curve order")
+    })
+}
+
+/// Replace the `musig()` expressions of a descriptor with the aggregate of their keys as an
+/// extended key, which the derivation steps following the expression apply to
+pub(crate) fn expand_descriptor(descriptor: &str, network: Network) -> Result<String, Error> {
+    let mut expanded = String::with_capacity(descriptor.len());
+    let mut rest = descriptor;
+    while let Some(start) = rest.find("musig(") {
+        expanded.push_str(&rest[..start]);
+        let args = &rest[start + "musig(".len()..];
+        let end = args.find(')').ok_or(Error::InvalidKeyExpression)?;
+        let keys = args[..end]
+            .split(',')
+            .map(|key| PublicKey::from_str(key.trim()).map_err(|_| Error::InvalidKeyExpression))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rest = &args[end + 1..];
+        expanded.push_str(&KeyAggContext::new(keys)?.extended_key(network).to_string());
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+fn participants_field(key_agg: &KeyAggContext) -> (raw::Key, Vec<u8>) {
+    let key = raw::Key {
+        type_value: PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS,
+        key: key_agg.aggregate.serialize().to_vec(),
+    };
+    let value = key_agg.keys.iter().flat_map(|k| k.serialize()).collect();
+    (key, value)
+}
+
+/// The key of a participant's nonce or partial signature: the participant key followed by the
+/// aggregate key, both compressed
+fn participant_key(type_value: u8, key_agg: &KeyAggContext, participant: &PublicKey) -> raw::Key {
+    let mut key = participant.serialize().to_vec();
+    key.extend_from_slice(&key_agg.aggregate.serialize());
+    raw::Key { type_value, key }
+}
+
+/// Whether the input has the partial signature of `participant` for the key spend
+pub(crate) fn has_partial_signature(
+    input: &psbt::Input,
+    key_agg: &KeyAggContext,
+    participant: &PublicKey,
+) -> bool {
+    input.unknown.contains_key(&participant_key(
+        PSBT_IN_MUSIG2_PARTIAL_SIG,
+        key_agg,
+        participant,
+    ))
+}
+
+#[cfg(feature = "std")]
+/// A point of the curve, `None` being the point at infinity
+type Point = Option<PublicKey>;
+
+#[cfg(feature = "std")]
+fn add_points(a: Point, b: Point) -> Point {
+    match (a, b) {
+        (Some(a), Some(b)) => a.combine(&b).ok(),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[cfg(feature = "std")]
+fn mul_point<C: Verification>(secp: &secp256k1::Secp256k1<C>, point: Point, s: &Scalar) -> Point {
+    point?.mul_tweak(secp, s).ok()
+}
+
+#[cfg(feature = "std")]
+/// Serialize a point, the point at infinity being all zeros (`cbytes_ext` of BIP327)
+fn serialize_point(point: Point) -> [u8; 33] {
+    point.map_or([0; 33], |point| point.serialize())
+}
+
+#[cfg(feature = "std")]
+/// Add two scalars, `None` being zero
+fn add_scalars(a: Option<SecretKey>, b: Option<SecretKey>) -> Option<SecretKey> {
+    match (a, b) {
+        (Some(a), Some(b)) => a.add_tweak(&b.into()).ok(),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[cfg(feature = "std")]
+fn parse_pub_nonce(nonce: &[u8]) -> Result<[PublicKey; 2], Error> {
+    if nonce.len() != 66 {
+        return Err(Error::InvalidNonce);
+    }
+    let r1 = PublicKey::from_slice(&nonce[..33]).map_err(|_| Error::InvalidNonce)?;
+    let r2 = PublicKey::from_slice(&nonce[33..]).map_err(|_| Error::InvalidNonce)?;
+    Ok([r1, r2])
+}
+
+#[cfg(feature = "std")]
+/// The sum of the public nonces of the participants, as in `NonceAgg` of BIP327
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AggNonce([Point; 2]);
+
+#[cfg(feature = "std")]
+impl AggNonce {
+    fn new(pub_nonces: &[Vec<u8>]) -> Result<Self, Error> {
+        let mut agg_nonce: [Point; 2] = [None, None];
+        for nonce in pub_nonces {
+            let nonce = parse_pub_nonce(nonce)?;
+            for (agg, r) in agg_nonce.iter_mut().zip(nonce) {
+                *agg = add_points(*agg, Some(r));
+            }
+        }
+        Ok(AggNonce(agg_nonce))
+    }
+
+    fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0; 66];
+        bytes[..33].copy_from_slice(&serialize_point(self.0[0]));
+        bytes[33..].copy_from_slice(&serialize_point(self.0[1]));
+        bytes
+    }
+}
+
+#[cfg(feature = "std")]
+/// The aggregate key with the tweaks applied to it, as the `KeyGen Context` of BIP327
+#[derive(Debug, Clone, Copy)]
+struct TweakContext {
+    /// The tweaked key
+    q: PublicKey,
+    /// Whether the accumulated sign of the key is negative
+    gacc_negated: bool,
+    /// The accumulated tweak, `None` being zero
+    tacc: Option<SecretKey>,
+}
+
+#[cfg(feature = "std")]
+impl TweakContext {
+    fn new(key_agg: &KeyAggContext) -> Self {
+        TweakContext {
+            q: key_agg.aggregate,
+            gacc_negated: false,
+            tacc: None,
+        }
+    }
+
+    /// Apply a plain tweak, as in BIP32 derivation, or an x-only one, as in taproot
+    fn apply<C: Verification>(
+        self,
+        secp: &secp256k1::Secp256k1<C>,
+        tweak: &Scalar,
+        is_xonly: bool,
+    ) -> Result<Self, Error> {
+        let negate = is_xonly && self.is_q_odd();
+        let q = if negate { self.q.negate(secp) } else { self.q };
+        let q = q
+            .add_exp_tweak(secp, tweak)
+            .map_err(|_| Error::InvalidKeys)?;
+        let tacc = if negate {
+            self.tacc.map(SecretKey::negate)
+        } else {
+            self.tacc
+        };
+        let tacc = add_scalars(tacc, SecretKey::from_slice(&tweak.to_be_bytes()).ok());
+
+        Ok(TweakContext {
+            q,
+            gacc_negated: self.gacc_negated != negate,
+            tacc,
+        })
+    }
+
+    /// Apply the taproot tweak of a key spend with the given merkle root
+    fn taproot<C: Verification>(
+        self,
+        secp: &secp256k1::Secp256k1<C>,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Result<Self, Error> {
+        let tweak = TapTweakHash::from_key_and_tweak(self.q.x_only_public_key().0, merkle_root);
+        self.apply(secp, &tweak.to_scalar(), true)
+    }
+
+    fn is_q_odd(&self) -> bool {
+        self.q.x_only_public_key().1 == secp256k1::Parity::Odd
+    }
+
+    /// Whether the secret keys must be negated to sign for the x-only output key
+    fn negate_secret_keys(&self) -> bool {
+        self.is_q_odd() != self.gacc_negated
+    }
+}
+
+#[cfg(feature = "std")]
+/// A MuSig2 signing session for a message, once all the public nonces are known
+struct Session {
+    tweak_ctx: TweakContext,
+    /// The nonce coefficient
+    b: Scalar,
+    /// The final nonce
+    r: PublicKey,
+    /// The challenge
+    e: Scalar,
+    msg: [u8; 32],
+}
+
+#[cfg(feature = "std")]
+impl Session {
+    fn new<C: Signing + Verification>(
+        tweak_ctx: TweakContext,
+        agg_nonce: &AggNonce,
+        msg: [u8; 32],
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Self {
+        let q = tweak_ctx.q.x_only_public_key().0.serialize();
+        let b = hash_to_scalar(tagged_hash(
+            "MuSig/noncecoef",
+            &[&agg_nonce.serialize(), &q, &msg],
+        ));
+        let [r1, r2] = agg_nonce.0;
+        // a final nonce at infinity can only be caused by a dishonest participant, the signature
+        // is still produced so that they can be identified
+        let r = add_points(r1, mul_point(secp, r2, &b)).unwrap_or_else(|| {
+            SecretKey::from_slice(&Scalar::ONE.to_be_bytes())
+                .expect("one is valid")
+                .public_key(secp)
+        });
+        let e = hash_to_scalar(tagged_hash(
+            "BIP0340/challenge",
+            &[&r.x_only_public_key().0.serialize(), &q, &msg],
+        ));
+
+        Session {
+            tweak_ctx,
+            b,
+            r,
+            e,
+            msg,
+        }
+    }
+
+    fn is_r_odd(&self) -> bool {
+        self.r.x_only_public_key().1 == secp256k1::Parity::Odd
+    }
+
+    fn partial_sign(
+        &self,
+        key_agg: &KeyAggContext,
+        secret_key: &SecretKey,
+        public_key: &PublicKey,
+        k1: SecretKey,
+        k2: SecretKey,
+    ) -> Result<[u8; 32], Error> {
+        let (k1, k2) = if self.is_r_odd() {
+            (k1.negate(), k2.negate())
+        } else {
+            (k1, k2)
+        };
+        let d = if self.tweak_ctx.negate_secret_keys() {
+            secret_key.negate()
+        } else {
+            *secret_key
+        };
+
+        // s = k1 + b * k2 + e * a * d
+        let ead = d
+            .mul_tweak(&key_agg.coefficient(public_key))
+            .and_then(|d| d.mul_tweak(&self.e))
+            .ok();
+        let bk2 = k2.mul_tweak(&self.b).ok();
+        let s = add_scalars(add_scalars(Some(k1), bk2), ead);
+
+        Ok(s.map_or([0; 32], |s| s.secret_bytes()))
+    }
+
+    /// Verify the partial signature of a participant, as in `PartialSigVerify` of BIP327
+    fn verify_partial_signature<C: Signing + Verification>(
+        &self,
+        key_agg: &KeyAggContext,
+        public_key: &PublicKey,
+        pub_nonce: &[u8],
+        partial_sig: &[u8],
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> bool {
+        let s = match <[u8; 32]>::try_from(partial_sig)
+            .ok()
+            .and_then(|bytes| Scalar::from_be_bytes(bytes).ok())
+        {
+            Some(s) => s,
+            None => return false,
+        };
+        let [r1, r2] = match parse_pub_nonce(pub_nonce) {
+            Ok(nonce) => nonce,
+            Err(_) => return false,
+        };
+
+        // s * G = R1 + b * R2 + e * a * P, with the signs applied by the signer
+        let mut nonce = add_points(Some(r1), mul_point(secp, Some(r2), &self.b));
+        if self.is_r_odd() {
+            nonce = nonce.map(|nonce| nonce.negate(secp));
+        }
+        let mut key = *public_key;
+        if self.tweak_ctx.negate_secret_keys() {
+            key = key.negate(secp);
+        }
+        let key = mul_point(secp, Some(key), &key_agg.coefficient(public_key));
+        let expected = add_points(nonce, mul_point(secp, key, &self.e));
+        let actual = SecretKey::from_slice(&s.to_be_bytes())
+            .ok()
+            .map(|s| s.public_key(secp));
+
+        actual == expected
+    }
+
+    fn aggregate<C: Verification>(
+        &self,
+        partial_sigs: &[Vec<u8>],
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<schnorr::Signature, Error> {
+        // s = s_1 + ... + s_n + e * g * tacc
+        let mut s = self
+            .tweak_ctx
+            .tacc
+            .and_then(|tacc| tacc.mul_tweak(&self.e).ok());
+        if self.tweak_ctx.is_q_odd() {
+            s = s.map(SecretKey::negate);
+        }
+        for partial_sig in partial_sigs {
+            let partial_sig = <[u8; 32]>::try_from(partial_sig.as_slice())
+                .ok()
+                .and_then(|bytes| Scalar::from_be_bytes(bytes).ok())
+                .ok_or(Error::InvalidSignature)?;
+            s = add_scalars(s, SecretKey::from_slice(&partial_sig.to_be_bytes()).ok());
+        }
+
+        let mut sig = self.r.x_only_public_key().0.serialize().to_vec();
+        sig.extend_from_slice(&s.map_or([0; 32], |s| s.secret_bytes()));
+        let sig = schnorr::Signature::from_slice(&sig).map_err(|_| Error::InvalidSignature)?;
+        let msg = Message::from_slice(&self.msg).expect("32 bytes");
+        secp.verify_schnorr(&sig, &msg, &self.tweak_ctx.q.x_only_public_key().0)
+            .map_err(|_| Error::InvalidSignature)?;
+
+        Ok(sig)
+    }
+}
+
+/// The secret nonce of a signing session, which must be used only once
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct SecretNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+}
+
+#[cfg(feature = "std")]
+impl SecretNonce {
+    /// Simplified `NonceGen` of BIP327, mixing fresh randomness with the signer's key
+    fn generate(public_key: &PublicKey, key_agg: &KeyAggContext) -> Self {
+        use secp256k1::rand::Rng;
+        let rand: [u8; 32] = secp256k1::rand::thread_rng().gen();
+
+        let k = |i: u8| {
+            let hash = tagged_hash(
+                "MuSig/nonce",
+                &[
+                    &rand,
+                    &public_key.serialize(),
+                    &key_agg.x_only_aggregate_key().serialize(),
+                    &[i],
+                ],
+            );
+            SecretKey::from_slice(&hash_to_scalar(hash).to_be_bytes())
+                .expect("the nonce is not zero")
+        };
+
+        SecretNonce { k1: k(0), k2: k(1) }
+    }
+
+    fn pub_nonce<C: Signing>(&self, secp: &secp256k1::Secp256k1<C>) -> Vec<u8> {
+        let mut pub_nonce = self.k1.public_key(secp).serialize().to_vec();
+        pub_nonce.extend_from_slice(&self.k2.public_key(secp).serialize());
+        pub_nonce
+    }
+}
+
+/// MuSig2 signer of a participant in the key spend of a `tr()` descriptor
+///
+/// See the [module-level documentation](self) for the signing flow.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct MusigSigner {
+    secret_key: SecretKey,
+    public_key: PublicKey,
+    key_agg: KeyAggContext,
+    /// The fingerprint of the aggregate key as an extended key
+    fingerprint: Fingerprint,
+    /// The secret nonces of the inputs for which we added a public nonce, by public nonce
+    nonces: Mutex<BTreeMap<(OutPoint, Vec<u8>), SecretNonce>>,
+}
+
+#[cfg(feature = "std")]
+impl MusigSigner {
+    /// Create a signer for the participant key of `secret_key` in the aggregate key `key_agg`
+    pub fn new<C: Signing>(
+        secret_key: SecretKey,
+        key_agg: KeyAggContext,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<Self, Error> {
+        let public_key = secret_key.public_key(secp);
+        if !key_agg.keys.contains(&public_key) {
+            return Err(Error::NotParticipant);
+        }
+
+        Ok(MusigSigner {
+            secret_key,
+            public_key,
+            fingerprint: key_agg.extended_key(Network::Bitcoin).fingerprint(),
+            key_agg,
+            nonces: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// The aggregate key the signer co-signs for
+    pub fn key_agg(&self) -> &KeyAggContext {
+        &self.key_agg
+    }
+
+    /// The tweaks of the internal key of the input, if it is derived from our aggregate key
+    fn tweak_context(&self, input: &psbt::Input, secp: &SecpCtx) -> Option<TweakContext> {
+        let internal_key = input.tap_internal_key?;
+        let path = match input.tap_key_origins.get(&internal_key) {
+            Some((_, (fingerprint, path))) if *fingerprint == self.fingerprint => path.clone(),
+            _ if internal_key == self.key_agg.x_only_aggregate_key() => DerivationPath::master(),
+            _ => return None,
+        };
+
+        let mut xpub = self.key_agg.extended_key(Network::Bitcoin);
+        let mut tweak_ctx = TweakContext::new(&self.key_agg);
+        for child in &path {
+            let (tweak, _) = xpub.ckd_pub_tweak(*child).ok()?;
+            tweak_ctx = tweak_ctx.apply(secp, &tweak.into(), false).ok()?;
+            xpub = xpub.ckd_pub(secp, *child).ok()?;
+        }
+        if tweak_ctx.q.x_only_public_key().0 != internal_key {
+            return None;
+        }
+        tweak_ctx.taproot(secp, input.tap_merkle_root).ok()
+    }
+
+    fn sign_input(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: usize,
+        tweak_ctx: TweakContext,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        let outpoint = psbt.unsigned_tx.input[input_index].previous_output;
+        let input = &mut psbt.inputs[input_index];
+        let (key, value) = participants_field(&self.key_agg);
+        input.unknown.entry(key).or_insert(value);
+
+        // First round: add our nonce. Nonces of earlier rounds for the same input are kept, in
+        // case the PSBT they were added to is signed later.
+        let our_nonce_key =
+            participant_key(PSBT_IN_MUSIG2_PUB_NONCE, &self.key_agg, &self.public_key);
+        if let Entry::Vacant(entry) = input.unknown.entry(our_nonce_key.clone()) {
+            let nonce = SecretNonce::generate(&self.public_key, &self.key_agg);
+            let pub_nonce = nonce.pub_nonce(secp);
+            entry.insert(pub_nonce.clone());
+            self.nonces
+                .lock()
+                .expect("nonces lock poisoned")
+                .insert((outpoint, pub_nonce), nonce);
+            return Ok(());
+        }
+
+        // Second round: once all the nonces are there, add our partial signature
+        let pub_nonces = match self.participant_fields(input, PSBT_IN_MUSIG2_PUB_NONCE) {
+            Some(pub_nonces) => pub_nonces,
+            None => return Ok(()),
+        };
+        let (hash, hash_ty) = Tap::sighash(psbt, input_index, None)?;
+        let session = Session::new(
+            tweak_ctx,
+            &AggNonce::new(&pub_nonces)?,
+            hash.to_byte_array(),
+            secp,
+        );
+
+        let input = &psbt.inputs[input_index];
+        let our_sig_key =
+            participant_key(PSBT_IN_MUSIG2_PARTIAL_SIG, &self.key_agg, &self.public_key);
+        if !input.unknown.contains_key(&our_sig_key) {
+            let our_nonce = input.unknown[&our_nonce_key].clone();
+            let nonce = self
+                .nonces
+                .lock()
+                .expect("nonces lock poisoned")
+                .remove(&(outpoint, our_nonce))
+                .ok_or(Error::MissingSecretNonce)?;
+            let partial_sig = session.partial_sign(
+                &self.key_agg,
+                &self.secret_key,
+                &self.public_key,
+                nonce.k1,
+                nonce.k2,
+            )?;
+            psbt.inputs[input_index]
+                .unknown
+                .insert(our_sig_key, partial_sig.to_vec());
+        }
+
+        // Once all the partial signatures are there and valid, aggregate them
+        let input = &psbt.inputs[input_index];
+        if let Some(partial_sigs) = self.participant_fields(input, PSBT_IN_MUSIG2_PARTIAL_SIG) {
+            for ((key, pub_nonce), partial_sig) in
+                self.key_agg.keys.iter().zip(&pub_nonces).zip(&partial_sigs)
+            {
+                if !session.verify_partial_signature(
+                    &self.key_agg,
+                    key,
+                    pub_nonce,
+                    partial_sig,
+                    secp,
+                ) {
+                    return Err(Error::InvalidPartialSignature(*key).into());
+                }
+            }
+            let sig = session.aggregate(&partial_sigs, secp)?;
+            psbt.inputs[input_index].tap_key_sig = Some(taproot::Signature { sig, hash_ty });
+        }
+
+        Ok(())
+    }
+
+    /// The nonces or partial signatures of all the participants, if they are all in the input
+    fn participant_fields(&self, input: &psbt::Input, type_value: u8) -> Option<Vec<Vec<u8>>> {
+        self.key_agg
+            .keys
+            .iter()
+            .map(|k| {
+                input
+                    .unknown
+                    .get(&participant_key(type_value, &self.key_agg, k))
+                    .cloned()
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl SignerCommon for MusigSigner {
+    fn id(&self, _secp: &SecpCtx) -> SignerId {
+        self.fingerprint.into()
+    }
+
+    fn musig_key(&self) -> Option<(&KeyAggContext, PublicKey)> {
+        Some((&self.key_agg, self.public_key))
+    }
+}
+
+#[cfg(feature = "std")]
+impl TransactionSigner for MusigSigner {
+    fn sign_transaction(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        if !sign_options.sign_with_tap_internal_key {
+            return Ok(());
+        }
+        if psbt.inputs.len() != psbt.unsigned_tx.input.len() {
+            return Err(SignerError::InputIndexOutOfRange);
+        }
+
+        for input_index in 0..psbt.inputs.len() {
+            let input = &psbt.inputs[input_index];
+            if input.final_script_sig.is_some()
+                || input.final_script_witness.is_some()
+                || input.tap_key_sig.is_some()
+            {
+                continue;
+            }
+
+            if let Some(tweak_ctx) = self.tweak_context(input, secp) {
+                self.sign_input(psbt, input_index, tweak_ctx, secp)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::secp256k1::Secp256k1;
+
+    fn keys(n: u8) -> (Vec<SecretKey>, Vec<PublicKey>) {
+        let secp = Secp256k1::new();
+        let secret_keys = (1..=n)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect::<Vec<_>>();
+        let public_keys = secret_keys.iter().map(|sk| sk.public_key(&secp)).collect();
+        (secret_keys, public_keys)
+    }
+
+    #[test]
+    fn test_key_agg_order() {
+        let (_, mut public_keys) = keys(3);
+        let key_agg = KeyAggContext::new(public_keys.clone()).unwrap();
+        assert_eq!(key_agg, KeyAggContext::new(public_keys.clone()).unwrap());
+
+        public_keys.swap(0, 2);
+        assert_ne!(
+            key_agg.aggregate_key(),
+            KeyAggContext::new(public_keys).unwrap().aggregate_key()
+        );
+        assert_eq!(KeyAggContext::new(vec![]), Err(Error::InvalidKeys));
+    }
+
+    #[test]
+    fn test_expand_descriptor() {
+        let (_, public_keys) = keys(2);
+        let key_agg = KeyAggContext::new(public_keys.clone()).unwrap();
+        let aggregate = key_agg.extended_key(Network::Testnet);
+        let xpub = key_agg.extended_key(Network::Bitcoin);
+        assert!(aggregate.to_string().starts_with("tpub"));
+        assert!(xpub.to_string().starts_with("xpub"));
+        assert_eq!(xpub.public_key, key_agg.aggregate_key());
+
+        let descriptor = format!("tr(musig({}, {}))", public_keys[0], public_keys[1]);
+        assert_eq!(
+            expand_descriptor(&descriptor, Network::Testnet).unwrap(),
+            format!("tr({})", aggregate)
+        );
+        let descriptor = format!(
+            "tr(musig({},{})/0/*,pk({}))",
+            public_keys[0], public_keys[1], public_keys[0]
+        );
+        assert_eq!(
+            expand_descriptor(&descriptor, Network::Bitcoin).unwrap(),
+            format!("tr({}/0/*,pk({}))", xpub, public_keys[0])
+        );
+
+        assert_eq!(
+            expand_descriptor("tr(musig(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*))", Network::Testnet),
+            Err(Error::InvalidKeyExpression)
+        );
+    }
+
+    #[test]
+    fn test_sign_and_aggregate() {
+        let secp = Secp256k1::new();
+        let (secret_keys, public_keys) = keys(3);
+        let key_agg = KeyAggContext::new(public_keys.clone()).unwrap();
+        let msg = [42; 32];
+
+        for merkle_root in [None, Some(TapNodeHash::all_zeros())] {
+            let tweak_ctx = TweakContext::new(&key_agg)
+                .taproot(&secp, merkle_root)
+                .unwrap();
+            let nonces = public_keys
+                .iter()
+                .map(|pk| SecretNonce::generate(pk, &key_agg))
+                .collect::<Vec<_>>();
+            let pub_nonces = nonces
+                .iter()
+                .map(|n| n.pub_nonce(&secp))
+                .collect::<Vec<_>>();
+            let agg_nonce = AggNonce::new(&pub_nonces).unwrap();
+            let session = Session::new(tweak_ctx, &agg_nonce, msg, &secp);
+
+            let partial_sigs = nonces
+                .into_iter()
+                .zip(secret_keys.iter().zip(&public_keys))
+                .map(|(nonce, (sk, pk))| {
+                    session
+                        .partial_sign(&key_agg, sk, pk, nonce.k1, nonce.k2)
+                        .unwrap()
+                        .to_vec()
+                })
+                .collect::<Vec<_>>();
+            for ((pk, pub_nonce), partial_sig) in
+                public_keys.iter().zip(&pub_nonces).zip(&partial_sigs)
+            {
+                assert!(session.verify_partial_signature(
+                    &key_agg,
+                    pk,
+                    pub_nonce,
+                    partial_sig,
+                    &secp
+                ));
+            }
+            // the aggregate signature is verified against the output key
+            session.aggregate(&partial_sigs, &secp).unwrap();
+
+            let mut wrong = partial_sigs.clone();
+            wrong[0] = partial_sigs[1].clone();
+            assert!(!session.verify_partial_signature(
+                &key_agg,
+                &public_keys[0],
+                &pub_nonces[0],
+                &wrong[0],
+                &secp
+            ));
+            assert_eq!(
+                session.aggregate(&wrong, &secp),
+                Err(Error::InvalidSignature)
+            );
+        }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        Vec::<u8>::from_hex(s).unwrap()
+    }
+
+    fn pk(s: &str) -> PublicKey {
+        PublicKey::from_str(s).unwrap()
+    }
+
+    // Vectors of https://github.com/bitcoin/bips/tree/master/bip-0327/vectors
+
+    #[test]
+    fn test_bip327_key_agg_vectors() {
+        let keys = [
+            pk("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            pk("03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659"),
+            pk("023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66"),
+        ];
+        for (indices, expected) in [
+            (
+                vec![0, 1, 2],
+                "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C",
+            ),
+            (
+                vec![2, 1, 0],
+                "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B",
+            ),
+            (
+                vec![0, 0, 0],
+                "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935",
+            ),
+            (
+                vec![0, 0, 1, 1],
+                "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E",
+            ),
+        ] {
+            let key_agg = KeyAggContext::new(indices.iter().map(|i| keys[*i]).collect()).unwrap();
+            assert_eq!(
+                key_agg.x_only_aggregate_key(),
+                XOnlyPublicKey::from_str(expected).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_bip327_nonce_agg_vectors() {
+        let pub_nonces = [
+            hex("020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E66603BA47FBC1834437B3212E89A84D8425E7BF12E0245D98262268EBDCB385D50641"),
+            hex("03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B833"),
+        ];
+        assert_eq!(
+            AggNonce::new(&pub_nonces).unwrap().serialize().to_vec(),
+            hex("035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B024725377345BDE0E9C33AF3C43C0A29A9249F2F2956FA8CFEB55C8573D0262DC8")
+        );
+        assert_eq!(
+            AggNonce::new(&[pub_nonces[0][..65].to_vec()]),
+            Err(Error::InvalidNonce)
+        );
+    }
+
+    /// The signer key, secret nonce, public nonces and message of the signing vectors
+    fn sign_vectors_session() -> (SecretKey, SecretNonce, Vec<Vec<u8>>, [u8; 32]) {
+        let secret_key =
+            SecretKey::from_str("7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671")
+                .unwrap();
+        let secret_nonce = hex("508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F7");
+        let secret_nonce = SecretNonce {
+            k1: SecretKey::from_slice(&secret_nonce[..32]).unwrap(),
+            k2: SecretKey::from_slice(&secret_nonce[32..]).unwrap(),
+        };
+        let pub_nonces = vec![
+            hex("0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480"),
+            hex("0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798"),
+            hex("032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046"),
+            // the opposite of the signer's nonce, for an aggregate nonce at infinity
+            hex("0237C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0387BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480"),
+        ];
+        let msg = hex("F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF");
+        (
+            secret_key,
+            secret_nonce,
+            pub_nonces,
+            msg.try_into().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_bip327_sign_verify_vectors() {
+        let secp = Secp256k1::new();
+        let (secret_key, secret_nonce, pub_nonces, msg) = sign_vectors_session();
+        let public_key = secret_key.public_key(&secp);
+        assert_eq!(secret_nonce.pub_nonce(&secp), pub_nonces[0]);
+        let keys = [
+            public_key,
+            pk("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            pk("02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661"),
+        ];
+
+        for (key_indices, nonce_indices, expected) in [
+            (
+                vec![0, 1, 2],
+                vec![0, 1, 2],
+                "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB",
+            ),
+            (
+                vec![1, 0, 2],
+                vec![1, 0, 2],
+                "9FF2F7AAA856150CC8819254218D3ADEEB0535269051897724F9DB3789513A52",
+            ),
+            (
+                vec![1, 2, 0],
+                vec![1, 2, 0],
+                "FA23C359F6FAC4E7796BB93BC9F0532A95468C539BA20FF86D7C76ED92227900",
+            ),
+            // the aggregate nonce is at infinity
+            (
+                vec![0, 1],
+                vec![0, 3],
+                "AE386064B26105404798F75DE2EB9AF5EDA5387B064B83D049CB7C5E08879531",
+            ),
+        ] {
+            let key_agg =
+                KeyAggContext::new(key_indices.iter().map(|i| keys[*i]).collect()).unwrap();
+            let nonces = nonce_indices
+                .iter()
+                .map(|i| pub_nonces[*i].clone())
+                .collect::<Vec<_>>();
+            let agg_nonce = AggNonce::new(&nonces).unwrap();
+            let session = Session::new(TweakContext::new(&key_agg), &agg_nonce, msg, &secp);
+            let partial_sig = session
+                .partial_sign(
+                    &key_agg,
+                    &secret_key,
+                    &public_key,
+                    secret_nonce.k1,
+                    secret_nonce.k2,
+                )
+                .unwrap();
+            assert_eq!(partial_sig.to_vec(), hex(expected));
+            assert!(session.verify_partial_signature(
+                &key_agg,
+                &public_key,
+                &pub_nonces[0],
+                &partial_sig,
+                &secp
+            ));
+            // the partial signature doesn't verify with another nonce or key
+            assert!(!session.verify_partial_signature(
+                &key_agg,
+                &public_key,
+                &pub_nonces[1],
+                &partial_sig,
+                &secp
+            ));
+            assert!(!session.verify_partial_signature(
+                &key_agg,
+                &keys[1],
+                &pub_nonces[0],
+                &partial_sig,
+                &secp
+            ));
+        }
+        let agg_nonce = AggNonce::new(&[pub_nonces[0].clone(), pub_nonces[3].clone()]).unwrap();
+        assert_eq!(agg_nonce.serialize(), [0; 66]);
+    }
+
+    #[test]
+    fn test_bip327_tweak_vectors() {
+        let secp = Secp256k1::new();
+        let (secret_key, secret_nonce, pub_nonces, msg) = sign_vectors_session();
+        let public_key = secret_key.public_key(&secp);
+        let keys = [
+            public_key,
+            pk("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            pk("02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659"),
+        ];
+        let key_agg = KeyAggContext::new(vec![keys[1], keys[2], keys[0]]).unwrap();
+        let agg_nonce = AggNonce::new(&[
+            pub_nonces[1].clone(),
+            pub_nonces[2].clone(),
+            pub_nonces[0].clone(),
+        ])
+        .unwrap();
+        let tweaks = [
+            "E8F791FF9225A2AF0102AFFF4A9A723D9612A682A25EBE79802B263CDFCD83BB",
+            "AE2EA797CC0FE72AC5B97B97F3C6957D7E4199A167A58EB08BCAFFDA70AC0455",
+            "F52ECBC565B3D8BEA2DFD5B75A4F457E54369809322E4120831626F290FA87E0",
+            "1969AD73CC177FA0B4FCED6DF1F7BF9907E665FDE9BA196A74FED0A3CF5AEF9D",
+        ];
+
+        for (is_xonly, expected) in [
+            (
+                vec![true],
+                "E28A5C66E61E178C2BA19DB77B6CF9F7E2F0F56C17918CD13135E60CC848FE91",
+            ),
+            (
+                vec![false],
+                "38B0767798252F21BF5702C48028B095428320F73A4B14DB1E25DE58543D2D2D",
+            ),
+            (
+                vec![false, true],
+                "408A0A21C4A0F5DACAF9646AD6EB6FECD7F7A11F03ED1F48DFFF2185BC2C2408",
+            ),
+            (
+                vec![false, false, true, true],
+                "45ABD206E61E3DF2EC9E264A6FEC8292141A633C28586388235541F9ADE75435",
+            ),
+            (
+                vec![true, false, true, false],
+                "B255FDCAC27B40C7CE7848E2D3B7BF5EA0ED756DA81565AC804CCCA3E1D5D239",
+            ),
+        ] {
+            let mut tweak_ctx = TweakContext::new(&key_agg);
+            for (tweak, is_xonly) in tweaks.iter().zip(is_xonly) {
+                let tweak = Scalar::from_be_bytes(hex(tweak).try_into().unwrap()).unwrap();
+                tweak_ctx = tweak_ctx.apply(&secp, &tweak, is_xonly).unwrap();
+            }
+            let session = Session::new(tweak_ctx, &agg_nonce, msg, &secp);
+            let partial_sig = session
+                .partial_sign(
+                    &key_agg,
+                    &secret_key,
+                    &public_key,
+                    secret_nonce.k1,
+                    secret_nonce.k2,
+                )
+                .unwrap();
+            assert_eq!(partial_sig.to_vec(), hex(expected));
+            assert!(session.verify_partial_signature(
+                &key_agg,
+                &public_key,
+                &pub_nonces[0],
+                &partial_sig,
+                &secp
+            ));
+        }
+    }
+}
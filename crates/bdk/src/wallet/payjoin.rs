@@ -0,0 +1,226 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! PayJoin
+//!
+//! [BIP78] payjoins, where the receiver of a payment adds its own inputs to the transaction so
+//! that the common input ownership heuristic doesn't hold. Sending the PSBTs back and forth is
+//! left to the application.
+//!
+//! The sender builds, signs and finalizes the *original* transaction as usual, then prepares it
+//! with [`Wallet::payjoin_original`]. The receiver turns it into a *proposal* with
+//! [`Wallet::payjoin_proposal`], contributing some of its UTXOs and signing only its own inputs.
+//! The sender checks the proposal and signs its inputs again with
+//! [`Wallet::process_payjoin_proposal`], and broadcasts it.
+//!
+//! If anything goes wrong, the sender broadcasts the original transaction instead, so the receiver
+//! should make sure it can be broadcast before answering with a proposal.
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk::wallet::coin_selection::DefaultCoinSelectionAlgorithm;
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let mut sender = doctest_wallet!();
+//! # let receiver = doctest_wallet!();
+//! # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+//! let mut original = {
+//!     let mut builder = sender.build_tx();
+//!     builder.add_recipient(to_address.script_pubkey(), 50_000);
+//!     builder.finish()?
+//! };
+//! // the sender's wallet pays up to 1000 sats of the fees of the receiver's inputs
+//! # let result =
+//! sender.payjoin_original(&mut original, &to_address.script_pubkey(), 1_000).and_then(|params| {
+//!     // the receiver gets `original` and `params`
+//!     let proposal =
+//!         receiver.payjoin_proposal(&original, &params, DefaultCoinSelectionAlgorithm::default())?;
+//!     // and sends `proposal` back
+//!     sender.process_payjoin_proposal(&original, proposal, &params)
+//! });
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+//! [`Wallet::payjoin_original`]: super::Wallet::payjoin_original
+//! [`Wallet::payjoin_proposal`]: super::Wallet::payjoin_proposal
+//! [`Wallet::process_payjoin_proposal`]: super::Wallet::process_payjoin_proposal
+
+use crate::types::FeeRate;
+use crate::wallet::coin_selection;
+use crate::wallet::signer::SignerError;
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::sighash::Prevouts;
+use bitcoin::{Address, AddressType, Network, OutPoint, Script, Transaction, TxOut};
+use core::fmt;
+use miniscript::interpreter::Interpreter;
+
+/// The parameters of a payjoin, chosen by the sender and sent along with the original PSBT.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayjoinParams {
+    /// The index of the output paying the receiver, the only one of the original outputs it can
+    /// substitute or change. Only used by the sender.
+    pub payee_output: usize,
+    /// The index of the output the receiver can decrease to pay for the fees of its inputs,
+    /// usually the sender's change, and by how much at most.
+    pub additional_fee_contribution: Option<(usize, u64)>,
+    /// Whether the receiver must not change the script pubkey or decrease the value of the output
+    /// paying it.
+    pub disable_output_substitution: bool,
+    /// The minimum fee rate of the proposal.
+    pub min_fee_rate: Option<FeeRate>,
+}
+
+/// Error creating or processing the PSBTs of a payjoin
+#[derive(Debug)]
+pub enum PayjoinError {
+    /// Error signing the PSBT
+    Signer(SignerError),
+    /// There was an error selecting the UTXOs to contribute
+    CoinSelection(coin_selection::Error),
+    /// Some inputs of the original PSBT, or our inputs of the proposal, aren't finalized
+    NotFinalized,
+    /// The previous output of the input is unknown
+    MissingUtxo(OutPoint),
+    /// The input spends one of our outputs, which the other party can't do
+    OwnInput(OutPoint),
+    /// The inputs of the original PSBT are of different types, so ours can't match them
+    MixedInputTypes,
+    /// The original PSBT doesn't pay us
+    NoPaymentToUs,
+    /// The original PSBT doesn't pay the payee
+    NoPaymentToPayee,
+    /// The proposal changed the version or the lock time of the transaction, or is malformed
+    InvalidProposal,
+    /// The proposal doesn't spend one of the inputs of the original PSBT
+    MissingInput(OutPoint),
+    /// The sequence of the input isn't the one of the original inputs
+    SequenceMismatch(OutPoint),
+    /// The receiver didn't clear the finalized scripts and previous output of our input
+    SenderInputNotCleared(OutPoint),
+    /// The input added by the receiver isn't of the type of the original inputs
+    InputTypeMismatch(OutPoint),
+    /// The input added by the receiver doesn't validly spend the previous output it claims
+    InvalidReceiverInput(OutPoint),
+    /// The output of the original PSBT at the given index is missing from the proposal
+    MissingOutput(usize),
+    /// The output of the original PSBT at the given index is lower in the proposal
+    OutputDecreased(usize),
+    /// The receiver decreased the fee output by more than allowed, or than the fees of its inputs
+    FeeContributionTooHigh {
+        /// The decrease of the fee output
+        contribution: u64,
+        /// The maximum contribution allowed
+        max: u64,
+    },
+    /// The fee rate of the proposal is lower than [`PayjoinParams::min_fee_rate`]
+    FeeRateTooLow {
+        /// The minimum fee rate
+        required: FeeRate,
+        /// The fee rate of the proposal
+        actual: FeeRate,
+    },
+}
+
+impl fmt::Display for PayjoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Signer(e) => e.fmt(f),
+            Self::CoinSelection(e) => e.fmt(f),
+            Self::NotFinalized => write!(f, "The inputs are not finalized"),
+            Self::MissingUtxo(outpoint) => write!(f, "Missing the previous output of {}", outpoint),
+            Self::OwnInput(outpoint) => write!(f, "Input {} spends one of our outputs", outpoint),
+            Self::MixedInputTypes => write!(f, "The original inputs are of different types"),
+            Self::NoPaymentToUs => write!(f, "The original transaction doesn't pay us"),
+            Self::NoPaymentToPayee => write!(f, "The original transaction doesn't pay the payee"),
+            Self::InvalidProposal => write!(f, "Invalid payjoin proposal"),
+            Self::MissingInput(outpoint) => write!(f, "Missing original input {}", outpoint),
+            Self::SequenceMismatch(outpoint) => {
+                write!(f, "Input {} doesn't have the original sequence", outpoint)
+            }
+            Self::SenderInputNotCleared(outpoint) => {
+                write!(f, "The receiver didn't clear our input {}", outpoint)
+            }
+            Self::InputTypeMismatch(outpoint) => write!(
+                f,
+                "Input {} is not of the type of the original inputs",
+                outpoint
+            ),
+            Self::InvalidReceiverInput(outpoint) => write!(
+                f,
+                "Input {} doesn't spend the previous output claimed by the receiver",
+                outpoint
+            ),
+            Self::MissingOutput(index) => write!(f, "Missing original output {}", index),
+            Self::OutputDecreased(index) => write!(f, "Original output {} was decreased", index),
+            Self::FeeContributionTooHigh { contribution, max } => write!(
+                f,
+                "Fee contribution too high: {} sat, at most {} sat allowed",
+                contribution, max
+            ),
+            Self::FeeRateTooLow { required, actual } => write!(
+                f,
+                "Fee rate too low: {} sat/vbyte, at least {} sat/vbyte required",
+                actual.as_sat_per_vb(),
+                required.as_sat_per_vb()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayjoinError {}
+
+impl From<SignerError> for PayjoinError {
+    fn from(err: SignerError) -> Self {
+        PayjoinError::Signer(err)
+    }
+}
+
+impl From<coin_selection::Error> for PayjoinError {
+    fn from(err: coin_selection::Error) -> Self {
+        PayjoinError::CoinSelection(err)
+    }
+}
+
+/// The type of the inputs spending `script_pubkey`, which the receiver's inputs must match.
+pub(crate) fn input_type(script_pubkey: &Script) -> Option<AddressType> {
+    // the network doesn't matter for the type
+    Address::from_script(script_pubkey, Network::Bitcoin)
+        .ok()?
+        .address_type()
+}
+
+/// Whether the finalized input `input_index` of `tx` validly spends `prevouts[input_index]`.
+///
+/// Segwit signatures commit to the value of the previous output, so the values claimed by the
+/// receiver for its inputs can be relied on once they are checked.
+pub(crate) fn verify_input<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+) -> bool {
+    let txin = &tx.input[input_index];
+    let interpreter = match Interpreter::from_txdata(
+        &prevouts[input_index].script_pubkey,
+        &txin.script_sig,
+        &txin.witness,
+        txin.sequence,
+        tx.lock_time,
+    ) {
+        Ok(interpreter) => interpreter,
+        Err(_) => return false,
+    };
+    let prevouts = Prevouts::All(prevouts);
+    let mut constraints = interpreter.iter(secp, tx, input_index, &prevouts);
+    constraints.all(|constraint| constraint.is_ok())
+}
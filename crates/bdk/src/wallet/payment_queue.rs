@@ -0,0 +1,189 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2023 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Payment queue
+//!
+//! Payments queued with [`Wallet::queue_payment`] are paid together by the batch transaction built
+//! with [`Wallet::build_batch`]. As long as the last batch is unconfirmed, the next batch replaces it
+//! (RBF) with the newly queued payments added, so that there is at most one unconfirmed batch.
+//!
+//! The queued payments and the batches built are persisted with the rest of the wallet, see
+//! [`ChangeSet`]. A batch is identified by the outpoints it spends rather than by its txid, which
+//! may change once it is signed. [`Wallet::payment_status`] finds the transaction paying a payment
+//! in the wallet's transaction graph, so a payment is only reported as paid once the wallet has
+//! seen its batch.
+//!
+//! [`Wallet::queue_payment`]: super::Wallet::queue_payment
+//! [`Wallet::build_batch`]: super::Wallet::build_batch
+//! [`Wallet::payment_status`]: super::Wallet::payment_status
+
+use crate::collections::BTreeMap;
+use alloc::vec::Vec;
+use bdk_chain::Append;
+use bitcoin::{OutPoint, ScriptBuf, Transaction, Txid};
+use serde::{Deserialize, Serialize};
+
+/// Identifies a payment queued with [`Wallet::queue_payment`].
+///
+/// [`Wallet::queue_payment`]: super::Wallet::queue_payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct PaymentId(u64);
+
+/// The status of a queued payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentStatus {
+    /// The payment waits for the next batch.
+    Queued,
+    /// A batch paying the payment was built, but the wallet hasn't seen its transaction yet. It
+    /// must be broadcast (e.g. with [`Wallet::broadcast`]) or cancelled with
+    /// [`Wallet::cancel_batch`].
+    ///
+    /// [`Wallet::broadcast`]: super::Wallet::broadcast
+    /// [`Wallet::cancel_batch`]: super::Wallet::cancel_batch
+    Batched,
+    /// The payment is paid by the unconfirmed batch transaction with this txid.
+    Broadcast(Txid),
+    /// The payment is paid by the confirmed batch transaction with this txid.
+    Confirmed(Txid),
+}
+
+/// A batch transaction built with [`Wallet::build_batch`].
+///
+/// [`Wallet::build_batch`]: super::Wallet::build_batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Batch {
+    /// The outpoints spent by the batch. A batch replacing it spends them too.
+    pub inputs: Vec<OutPoint>,
+    /// The payments paid by the batch, including the ones of the batch it replaces.
+    pub payments: Vec<PaymentId>,
+}
+
+/// Changes to the payment queue, part of the wallet's [`ChangeSet`].
+///
+/// [`ChangeSet`]: super::ChangeSet
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeSet {
+    /// Queued payments, with the script pubkey and amount they pay.
+    pub payments: BTreeMap<PaymentId, (ScriptBuf, u64)>,
+    /// The batches built, by the order in which they were built. A cancelled batch is `None`.
+    pub batches: BTreeMap<u64, Option<Batch>>,
+}
+
+impl Append for ChangeSet {
+    fn append(&mut self, other: Self) {
+        self.payments.extend(other.payments);
+        self.batches.extend(other.batches);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.payments.is_empty() && self.batches.is_empty()
+    }
+}
+
+/// The payments paid by a batch transaction being built.
+#[derive(Debug, Clone)]
+pub(crate) struct PaymentBatch {
+    /// The payments of the batch, including the ones of the batch it replaces.
+    pub payments: Vec<PaymentId>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PaymentQueue {
+    payments: BTreeMap<PaymentId, (ScriptBuf, u64)>,
+    batches: BTreeMap<u64, Batch>,
+}
+
+impl PaymentQueue {
+    pub(crate) fn from_changeset(changeset: ChangeSet) -> Self {
+        Self {
+            payments: changeset.payments,
+            batches: changeset
+                .batches
+                .into_iter()
+                .filter_map(|(index, batch)| Some((index, batch?)))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, script_pubkey: ScriptBuf, amount: u64) -> (PaymentId, ChangeSet) {
+        let id = PaymentId(
+            self.payments
+                .keys()
+                .next_back()
+                .map_or(0, |PaymentId(last)| last + 1),
+        );
+        self.payments.insert(id, (script_pubkey.clone(), amount));
+        let changeset = ChangeSet {
+            payments: [(id, (script_pubkey, amount))].into(),
+            ..Default::default()
+        };
+        (id, changeset)
+    }
+
+    /// The script pubkey and amount of payment `id`.
+    pub(crate) fn payment(&self, id: PaymentId) -> Option<(&ScriptBuf, u64)> {
+        let (script_pubkey, amount) = self.payments.get(&id)?;
+        Some((script_pubkey, *amount))
+    }
+
+    /// The batches paying payment `id`, the last built first.
+    pub(crate) fn batches_paying(&self, id: PaymentId) -> impl Iterator<Item = &Batch> {
+        self.batches
+            .values()
+            .rev()
+            .filter(move |batch| batch.payments.contains(&id))
+    }
+
+    pub(crate) fn last_batch(&self) -> Option<&Batch> {
+        self.batches.values().next_back()
+    }
+
+    /// The payments which are not paid by any batch, with their recipients.
+    pub(crate) fn queued(&self) -> impl Iterator<Item = (PaymentId, (ScriptBuf, u64))> + '_ {
+        self.payments
+            .iter()
+            .filter(|(id, _)| {
+                !self
+                    .batches
+                    .values()
+                    .any(|batch| batch.payments.contains(id))
+            })
+            .map(|(&id, recipient)| (id, recipient.clone()))
+    }
+
+    /// Record that `batch` is built as `tx`.
+    pub(crate) fn record_batch(&mut self, batch: PaymentBatch, tx: &Transaction) -> ChangeSet {
+        let index = self.next_batch_index();
+        let batch = Batch {
+            inputs: tx.input.iter().map(|txin| txin.previous_output).collect(),
+            payments: batch.payments,
+        };
+        self.batches.insert(index, batch.clone());
+        ChangeSet {
+            batches: [(index, Some(batch))].into(),
+            ..Default::default()
+        }
+    }
+
+    /// Forget the last batch, the payments only it pays are queued again.
+    pub(crate) fn cancel_last_batch(&mut self) -> Option<(Batch, ChangeSet)> {
+        let index = *self.batches.keys().next_back()?;
+        let batch = self.batches.remove(&index)?;
+        let changeset = ChangeSet {
+            batches: [(index, None)].into(),
+            ..Default::default()
+        };
+        Some((batch, changeset))
+    }
+
+    fn next_batch_index(&self) -> u64 {
+        self.batches.keys().next_back().map_or(0, |last| last + 1)
+    }
+}
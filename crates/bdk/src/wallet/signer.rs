@@ -86,7 +86,7 @@ use core::cmp::Ordering;
 use core::fmt;
 use core::ops::{Bound::Included, Deref};
 
-use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint};
+use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint, KeySource};
 use bitcoin::hashes::hash160;
 use bitcoin::secp256k1::Message;
 use bitcoin::sighash::{EcdsaSighashType, TapSighash, TapSighashType};
@@ -95,8 +95,8 @@ use bitcoin::{key::TapTweak, key::XOnlyPublicKey, secp256k1};
 use bitcoin::{PrivateKey, PublicKey};
 
 use miniscript::descriptor::{
-    Descriptor, DescriptorMultiXKey, DescriptorPublicKey, DescriptorSecretKey, DescriptorXKey,
-    InnerXKey, KeyMap, SinglePriv, SinglePubKey,
+    DefiniteDescriptorKey, Descriptor, DescriptorMultiXKey, DescriptorPublicKey,
+    DescriptorSecretKey, DescriptorXKey, InnerXKey, KeyMap, SinglePriv, SinglePubKey,
 };
 use miniscript::{Legacy, Segwitv0, SigType, Tap, ToPublicKey};
 
@@ -104,6 +104,7 @@ use super::utils::SecpCtx;
 use crate::descriptor::{DescriptorMeta, XKeyUtils};
 use crate::psbt::PsbtUtils;
 use crate::wallet::error::MiniscriptPsbtError;
+use crate::wallet::musig::{self, KeyAggContext};
 
 /// Identifier of a signer in the `SignersContainers`. Used as a key to find the right signer among
 /// multiple of them
@@ -162,6 +163,8 @@ pub enum SignerError {
     SighashError(sighash::Error),
     /// Miniscript PSBT error
     MiniscriptPsbt(MiniscriptPsbtError),
+    /// Error while signing with MuSig2
+    Musig(musig::Error),
     /// Error while signing using hardware wallets
     #[cfg(feature = "hardware-signer")]
     HWIError(hwi::error::Error),
@@ -180,6 +183,12 @@ impl From<sighash::Error> for SignerError {
     }
 }
 
+impl From<musig::Error> for SignerError {
+    fn from(e: musig::Error) -> Self {
+        SignerError::Musig(e)
+    }
+}
+
 impl fmt::Display for SignerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -196,6 +205,7 @@ impl fmt::Display for SignerError {
             Self::InvalidSighash => write!(f, "Invalid SIGHASH for the signing context in use"),
             Self::SighashError(err) => write!(f, "Error while computing the hash to sign: {}", err),
             Self::MiniscriptPsbt(err) => write!(f, "Miniscript PSBT error: {}", err),
+            Self::Musig(err) => write!(f, "MuSig2 error: {}", err),
             #[cfg(feature = "hardware-signer")]
             Self::HWIError(err) => write!(f, "Error while signing using hardware wallets: {}", err),
         }
@@ -259,6 +269,14 @@ pub trait SignerCommon: fmt::Debug + Send + Sync {
     fn descriptor_secret_key(&self) -> Option<DescriptorSecretKey> {
         None
     }
+
+    /// Return the MuSig2 aggregate key the signer co-signs for, and the participant key of the
+    /// signer
+    ///
+    /// This is used to extract the policy of `tr()` descriptors with a `musig()` internal key.
+    fn musig_key(&self) -> Option<(&KeyAggContext, secp256k1::PublicKey)> {
+        None
+    }
 }
 
 /// PSBT Input signer
@@ -748,6 +766,50 @@ impl SignersContainer {
             .map(|(_, v)| v)
             .next()
     }
+
+    /// Finds the private key of `key` among the keys of the signers
+    pub(crate) fn find_secret_key(
+        &self,
+        key: &DefiniteDescriptorKey,
+        secp: &SecpCtx,
+    ) -> Option<secp256k1::SecretKey> {
+        let public_key = XOnlyPublicKey::from(key.derive_public_key(secp).ok()?.inner);
+        let key_source = (key.master_fingerprint(), key.full_derivation_path()?);
+        self.0
+            .values()
+            .filter_map(|signer| signer.descriptor_secret_key())
+            .flat_map(|secret_key| match secret_key {
+                DescriptorSecretKey::Single(single) => vec![single.key.inner],
+                DescriptorSecretKey::XPrv(xkey) => derive_matching_key(&xkey, &key_source, secp)
+                    .into_iter()
+                    .collect(),
+                DescriptorSecretKey::MultiXPrv(multikey) => multikey_to_xkeys(multikey)
+                    .iter()
+                    .filter_map(|xkey| derive_matching_key(xkey, &key_source, secp))
+                    .collect(),
+            })
+            .find(|secret_key| XOnlyPublicKey::from(secret_key.public_key(secp)) == public_key)
+    }
+}
+
+/// Derives the private key of `xkey` at `key_source`, if it is one of its keys
+fn derive_matching_key(
+    xkey: &DescriptorXKey<ExtendedPrivKey>,
+    key_source: &KeySource,
+    secp: &SecpCtx,
+) -> Option<secp256k1::SecretKey> {
+    xkey.matches(key_source, secp)?;
+    let full_path = &key_source.1;
+    let path = match &xkey.origin {
+        Some((_fingerprint, origin_path)) => DerivationPath::from(
+            &full_path.into_iter().cloned().collect::<Vec<ChildNumber>>()[origin_path.len()..],
+        ),
+        None => full_path.clone(),
+    };
+    xkey.xkey
+        .derive_priv(secp, &path)
+        .ok()
+        .map(|derived| derived.private_key)
 }
 
 /// Options for a software signer
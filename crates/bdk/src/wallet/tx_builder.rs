@@ -41,19 +41,33 @@
 //! ```
 
 use crate::collections::BTreeMap;
+use crate::collections::BTreeSet;
 use crate::collections::HashSet;
 use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use bdk_chain::FeeEstimator;
+#[cfg(feature = "async")]
+use bdk_chain::FeeEstimatorAsync;
 use bdk_chain::PersistBackend;
 use core::cell::RefCell;
+use core::convert::Infallible;
 use core::fmt;
 use core::marker::PhantomData;
 
 use bitcoin::psbt::{self, PartiallySignedTransaction as Psbt};
-use bitcoin::{absolute, script::PushBytes, OutPoint, ScriptBuf, Sequence, Transaction, Txid};
+use bitcoin::{
+    absolute, script::PushBytes, OutPoint, ScriptBuf, Sequence, Transaction, Txid, Weight,
+};
 
 use super::coin_selection::{CoinSelectionAlgorithm, DefaultCoinSelectionAlgorithm};
+use super::payment_queue::PaymentBatch;
 use super::ChangeSet;
+use crate::silent_payments::SilentPaymentAddress;
 use crate::types::{FeeRate, KeychainKind, LocalOutput, WeightedUtxo};
+#[cfg(feature = "planning")]
+use bitcoin::hashes::{hash160, ripemd160, sha256};
+#[cfg(feature = "planning")]
+use miniscript::{hash256, DescriptorPublicKey};
+
 use crate::wallet::CreateTxError;
 use crate::{Utxo, Wallet};
 
@@ -71,11 +85,16 @@ impl TxBuilderContext for CreateTx {}
 pub struct BumpFee;
 impl TxBuilderContext for BumpFee {}
 
+/// Marker type to indicate the [`TxBuilder`] is being used to pay a batch of queued payments.
+#[derive(Debug, Default, Clone)]
+pub struct PayBatch;
+impl TxBuilderContext for PayBatch {}
+
 /// A transaction builder
 ///
-/// A `TxBuilder` is created by calling [`build_tx`] or [`build_fee_bump`] on a wallet. After
-/// assigning it, you set options on it until finally calling [`finish`] to consume the builder and
-/// generate the transaction.
+/// A `TxBuilder` is created by calling [`build_tx`], [`build_fee_bump`], [`build_cpfp`] or
+/// [`build_batch`] on a wallet. After assigning it, you set options on it until finally calling
+/// [`finish`] to consume the builder and generate the transaction.
 ///
 /// Each option setting method on `TxBuilder` takes and returns `&mut self` so you can chain calls
 /// as in the following example:
@@ -123,6 +142,8 @@ impl TxBuilderContext for BumpFee {}
 ///
 /// [`build_tx`]: Wallet::build_tx
 /// [`build_fee_bump`]: Wallet::build_fee_bump
+/// [`build_cpfp`]: Wallet::build_cpfp
+/// [`build_batch`]: Wallet::build_batch
 /// [`finish`]: Self::finish
 /// [`coin_selection`]: Self::coin_selection
 #[derive(Debug)]
@@ -138,11 +159,14 @@ pub struct TxBuilder<'a, D, Cs, Ctx> {
 #[derive(Default, Debug, Clone)]
 pub(crate) struct TxParams {
     pub(crate) recipients: Vec<(ScriptBuf, u64)>,
+    pub(crate) silent_payments: Vec<(SilentPaymentAddress, u64)>,
     pub(crate) drain_wallet: bool,
     pub(crate) drain_to: Option<ScriptBuf>,
     pub(crate) fee_policy: Option<FeePolicy>,
     pub(crate) internal_policy_path: Option<BTreeMap<String, Vec<usize>>>,
     pub(crate) external_policy_path: Option<BTreeMap<String, Vec<usize>>>,
+    pub(crate) custom_policy_paths: BTreeMap<KeychainKind, BTreeMap<String, Vec<usize>>>,
+    pub(crate) spend_keychains: Option<BTreeSet<KeychainKind>>,
     pub(crate) utxos: Vec<WeightedUtxo>,
     pub(crate) unspendable: HashSet<OutPoint>,
     pub(crate) manually_selected_only: bool,
@@ -156,8 +180,13 @@ pub(crate) struct TxParams {
     pub(crate) add_global_xpubs: bool,
     pub(crate) include_output_redeem_witness_script: bool,
     pub(crate) bumping_fee: Option<PreviousFee>,
+    pub(crate) paying_for_ancestors: Option<Ancestors>,
+    pub(crate) payment_batch: Option<PaymentBatch>,
     pub(crate) current_height: Option<absolute::LockTime>,
     pub(crate) allow_dust: bool,
+    #[cfg(feature = "planning")]
+    pub(crate) assets: Option<Assets>,
+    pub(crate) change_keychain: Option<KeychainKind>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -166,6 +195,13 @@ pub(crate) struct PreviousFee {
     pub rate: f32,
 }
 
+/// The unconfirmed ancestors a child transaction pays for (CPFP)
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Ancestors {
+    pub fee: u64,
+    pub weight: Weight,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum FeePolicy {
     FeeRate(FeeRate),
@@ -208,6 +244,35 @@ impl<'a, D, Cs: CoinSelectionAlgorithm, Ctx: TxBuilderContext> TxBuilder<'a, D,
         self
     }
 
+    /// Set the fee rate to the one `estimator` estimates for the transaction to be confirmed
+    /// within `target` blocks.
+    ///
+    /// This is the same as calling [`fee_rate`](Self::fee_rate) with the estimate, the fee rate
+    /// is only estimated once, when this method is called. Returns the error of `estimator` if
+    /// it has no estimate for `target`.
+    pub fn fee_target<E: FeeEstimator>(
+        &mut self,
+        estimator: &E,
+        target: u16,
+    ) -> Result<&mut Self, E::Error> {
+        let fee_rate = estimator.estimate_fee_rate(target)?;
+        Ok(self.fee_rate(fee_rate.into()))
+    }
+
+    /// Set the fee rate to the one `estimator` estimates for the transaction to be confirmed
+    /// within `target` blocks.
+    ///
+    /// This is the async version of [`fee_target`](Self::fee_target).
+    #[cfg(feature = "async")]
+    pub async fn fee_target_async<E: FeeEstimatorAsync>(
+        &mut self,
+        estimator: &E,
+        target: u16,
+    ) -> Result<&mut Self, E::Error> {
+        let fee_rate = estimator.estimate_fee_rate(target).await?;
+        Ok(self.fee_rate(fee_rate.into()))
+    }
+
     /// Set an absolute fee
     /// The fee_absolute method refers to the absolute transaction fee in satoshis (sats).
     /// If anyone sets both the fee_absolute method and the fee_rate method,
@@ -289,12 +354,15 @@ impl<'a, D, Cs: CoinSelectionAlgorithm, Ctx: TxBuilderContext> TxBuilder<'a, D,
         policy_path: BTreeMap<String, Vec<usize>>,
         keychain: KeychainKind,
     ) -> &mut Self {
-        let to_update = match keychain {
-            KeychainKind::Internal => &mut self.params.internal_policy_path,
-            KeychainKind::External => &mut self.params.external_policy_path,
+        match keychain {
+            KeychainKind::Internal => self.params.internal_policy_path = Some(policy_path),
+            KeychainKind::External => self.params.external_policy_path = Some(policy_path),
+            KeychainKind::Custom(_) => {
+                self.params
+                    .custom_policy_paths
+                    .insert(keychain, policy_path);
+            }
         };
-
-        *to_update = Some(policy_path);
         self
     }
 
@@ -435,6 +503,11 @@ impl<'a, D, Cs: CoinSelectionAlgorithm, Ctx: TxBuilderContext> TxBuilder<'a, D,
     ///
     /// It's important to note that the "must-be-spent" utxos added with [`TxBuilder::add_utxo`]
     /// have priority over these. See the docs of the two linked methods for more details.
+    ///
+    /// This only applies to this transaction, use [`Wallet::freeze_utxo`] to make utxos
+    /// unspendable by all transactions.
+    ///
+    /// [`Wallet::freeze_utxo`]: crate::Wallet::freeze_utxo
     pub fn unspendable(&mut self, unspendable: Vec<OutPoint>) -> &mut Self {
         self.params.unspendable = unspendable.into_iter().collect();
         self
@@ -498,6 +571,18 @@ impl<'a, D, Cs: CoinSelectionAlgorithm, Ctx: TxBuilderContext> TxBuilder<'a, D,
         self
     }
 
+    /// Only spend outputs of the given `keychains`
+    ///
+    /// This effectively adds all the outputs of other keychains to the "unspendable" list. See
+    /// [`TxBuilder::unspendable`]. Manually selected UTXOs are spent regardless of their keychain.
+    pub fn only_spend_from_keychains(
+        &mut self,
+        keychains: impl IntoIterator<Item = KeychainKind>,
+    ) -> &mut Self {
+        self.params.spend_keychains = Some(keychains.into_iter().collect());
+        self
+    }
+
     /// Set a specific [`ChangeSpendPolicy`]. See [`TxBuilder::do_not_spend_change`] and
     /// [`TxBuilder::only_spend_change`] for some shortcuts.
     pub fn change_policy(&mut self, change_policy: ChangeSpendPolicy) -> &mut Self {
@@ -566,6 +651,27 @@ impl<'a, D, Cs: CoinSelectionAlgorithm, Ctx: TxBuilderContext> TxBuilder<'a, D,
     where
         D: PersistBackend<ChangeSet>,
     {
+        // a new change address is revealed unless we drain to a given script
+        let must_commit = self.params.drain_to.is_none();
+        let mut wallet = self.wallet.borrow_mut();
+        let psbt = wallet.create_tx(self.coin_selection, self.params)?;
+        if must_commit {
+            wallet.persist.commit().map_err(CreateTxError::Persist)?;
+        }
+        Ok(psbt)
+    }
+
+    /// Finish building the transaction, staging (but not committing) any changes to the wallet.
+    ///
+    /// Unlike [`finish`], this does not require a synchronous [`PersistBackend`] so it can be used
+    /// with wallets backed by an asynchronous one. Changes such as a newly revealed change address
+    /// are persisted with the next commit of the wallet.
+    ///
+    /// Returns a new [`Psbt`] per [`BIP174`].
+    ///
+    /// [`finish`]: Self::finish
+    /// [`BIP174`]: https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
+    pub fn finish_staged(self) -> Result<Psbt, CreateTxError<Infallible>> {
         self.wallet
             .borrow_mut()
             .create_tx(self.coin_selection, self.params)
@@ -608,6 +714,55 @@ impl<'a, D, Cs: CoinSelectionAlgorithm, Ctx: TxBuilderContext> TxBuilder<'a, D,
         self
     }
 
+    /// Spend the UTXOs of the wallet with the cheapest spending plan allowed by `assets`.
+    ///
+    /// The `assets` describe what is available to satisfy the descriptors: the keys that can
+    /// sign, the hash pre-images that are known, the maximum `nLockTime` that can be used and the
+    /// age of the outputs being spent. For every UTXO the cheapest way of spending it with these
+    /// assets is picked and:
+    ///
+    /// 1. Its exact satisfaction weight is used during coin selection, instead of the weight of
+    ///    the most expensive spending path of the descriptor.
+    /// 2. The nLockTime of the transaction and the nSequence of the input are set to what the plan
+    ///    requires. There's no need to provide a [`TxBuilder::policy_path`] for the keychain.
+    /// 3. UTXOs that can't be spent with the `assets` are not selected. Manually selected UTXOs
+    ///    that can't be spent make [`TxBuilder::finish`] fail with
+    ///    [`CreateTxError::NoSpendingPlan`].
+    ///
+    /// If `max_locktime` is not set the current height is used, if `txo_age` is not set the number
+    /// of confirmations of each UTXO is used.
+    ///
+    /// **Note**: only `wpkh`, `wsh` and `tr` descriptors can be planned at the moment, and their
+    /// miniscript can't use `andor`, `or_b`, `or_c`, `or_d`, `thresh` or `sortedmulti`. The UTXOs
+    /// of other descriptors are spent as if no assets were provided.
+    ///
+    /// This method is only available with the `planning` feature.
+    #[cfg(feature = "planning")]
+    pub fn assets(&mut self, assets: Assets) -> &mut Self {
+        self.params.assets = Some(assets);
+        self
+    }
+
+    /// Pay the change to `keychain` if it has the same script type as the first recipient
+    ///
+    /// A change output of a different type than the payment is easy to tell apart. With this
+    /// option the change goes to the internal keychain if its script type matches, otherwise to
+    /// `keychain` if it does. If neither does, the internal keychain is used anyway.
+    ///
+    /// `keychain` should be a custom keychain dedicated to change (see [`Wallet::add_keychain`]),
+    /// not one whose addresses are handed out to receive payments. Its outputs are considered
+    /// change by the [`ChangeSpendPolicy`] of this builder, so the same keychain should be given
+    /// to every builder that sets one.
+    ///
+    /// This has no effect if the change is sent to a specific script with [`TxBuilder::drain_to`].
+    /// See [`PrivacyCoinSelection`] to also pick inputs of the same type.
+    ///
+    /// [`PrivacyCoinSelection`]: super::coin_selection::PrivacyCoinSelection
+    pub fn match_change_script_type(&mut self, keychain: KeychainKind) -> &mut Self {
+        self.params.change_keychain = Some(keychain);
+        self
+    }
+
     /// Set whether or not the dust limit is checked.
     ///
     /// **Note**: by avoiding a dust limit check you may end up with a transaction that is non-standard.
@@ -617,6 +772,41 @@ impl<'a, D, Cs: CoinSelectionAlgorithm, Ctx: TxBuilderContext> TxBuilder<'a, D,
     }
 }
 
+/// What is available to satisfy the descriptors of the wallet, see [`TxBuilder::assets`]
+#[cfg(feature = "planning")]
+#[derive(Debug, Clone, Default)]
+pub struct Assets {
+    /// The keys that can sign, or keys they derive from
+    pub keys: Vec<DescriptorPublicKey>,
+    /// The age of the outputs being spent, for relative timelocks
+    pub txo_age: Option<Sequence>,
+    /// The maximum nLockTime of the transaction, for absolute timelocks
+    pub max_locktime: Option<absolute::LockTime>,
+    /// The SHA256 images whose pre-images are known
+    pub sha256: Vec<sha256::Hash>,
+    /// The HASH256 images whose pre-images are known
+    pub hash256: Vec<hash256::Hash>,
+    /// The RIPEMD160 images whose pre-images are known
+    pub ripemd160: Vec<ripemd160::Hash>,
+    /// The HASH160 images whose pre-images are known
+    pub hash160: Vec<hash160::Hash>,
+}
+
+#[cfg(feature = "planning")]
+impl Assets {
+    pub(crate) fn to_plan_assets(&self) -> bdk_tmp_plan::Assets<DescriptorPublicKey> {
+        bdk_tmp_plan::Assets {
+            keys: self.keys.clone(),
+            txo_age: self.txo_age,
+            max_locktime: self.max_locktime,
+            sha256: self.sha256.clone(),
+            hash256: self.hash256.clone(),
+            ripemd160: self.ripemd160.clone(),
+            hash160: self.hash160.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 /// Error returned from [`TxBuilder::add_utxo`] and [`TxBuilder::add_utxos`]
 pub enum AddUtxoError {
@@ -714,6 +904,27 @@ impl<'a, D, Cs: CoinSelectionAlgorithm> TxBuilder<'a, D, Cs, CreateTx> {
         self
     }
 
+    /// Add a [BIP352] silent payment recipient to the internal list
+    ///
+    /// The output paying `address` is derived from the private keys of the inputs of the
+    /// transaction once they are selected, so the transaction can only spend the wallet's own
+    /// UTXOs and their keys must be available to the wallet's signers. It comes after the
+    /// recipients added with [`add_recipient`] before the outputs are ordered.
+    ///
+    /// The inputs of the transaction must not be changed afterwards, e.g. by bumping its fee,
+    /// otherwise the receiver won't find the output.
+    ///
+    /// [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+    /// [`add_recipient`]: Self::add_recipient
+    pub fn add_silent_payment_recipient(
+        &mut self,
+        address: SilentPaymentAddress,
+        amount: u64,
+    ) -> &mut Self {
+        self.params.silent_payments.push((address, amount));
+        self
+    }
+
     /// Add data as an output, using OP_RETURN
     pub fn add_data<T: AsRef<PushBytes>>(&mut self, data: &T) -> &mut Self {
         let script = ScriptBuf::new_op_return(data);
@@ -897,11 +1108,18 @@ impl Default for ChangeSpendPolicy {
 }
 
 impl ChangeSpendPolicy {
-    pub(crate) fn is_satisfied_by(&self, utxo: &LocalOutput) -> bool {
+    /// Whether `utxo` can be spent, the outputs of `change_keychain` being change too
+    pub(crate) fn is_satisfied_by(
+        &self,
+        utxo: &LocalOutput,
+        change_keychain: Option<KeychainKind>,
+    ) -> bool {
+        let is_change =
+            utxo.keychain == KeychainKind::Internal || Some(utxo.keychain) == change_keychain;
         match self {
             ChangeSpendPolicy::ChangeAllowed => true,
-            ChangeSpendPolicy::OnlyChange => utxo.keychain == KeychainKind::Internal,
-            ChangeSpendPolicy::ChangeForbidden => utxo.keychain == KeychainKind::External,
+            ChangeSpendPolicy::OnlyChange => is_change,
+            ChangeSpendPolicy::ChangeForbidden => !is_change,
         }
     }
 }
@@ -1039,7 +1257,7 @@ mod test {
         let change_spend_policy = ChangeSpendPolicy::default();
         let filtered = get_test_utxos()
             .into_iter()
-            .filter(|u| change_spend_policy.is_satisfied_by(u))
+            .filter(|u| change_spend_policy.is_satisfied_by(u, None))
             .count();
 
         assert_eq!(filtered, 2);
@@ -1050,7 +1268,7 @@ mod test {
         let change_spend_policy = ChangeSpendPolicy::ChangeForbidden;
         let filtered = get_test_utxos()
             .into_iter()
-            .filter(|u| change_spend_policy.is_satisfied_by(u))
+            .filter(|u| change_spend_policy.is_satisfied_by(u, None))
             .collect::<Vec<_>>();
 
         assert_eq!(filtered.len(), 1);
@@ -1062,13 +1280,31 @@ mod test {
         let change_spend_policy = ChangeSpendPolicy::OnlyChange;
         let filtered = get_test_utxos()
             .into_iter()
-            .filter(|u| change_spend_policy.is_satisfied_by(u))
+            .filter(|u| change_spend_policy.is_satisfied_by(u, None))
             .collect::<Vec<_>>();
 
         assert_eq!(filtered.len(), 1);
         assert_eq!(filtered[0].keychain, KeychainKind::Internal);
     }
 
+    #[test]
+    fn test_change_spend_policy_change_keychain() {
+        let mut utxos = get_test_utxos();
+        utxos[0].keychain = KeychainKind::custom(0);
+        let change_keychain = Some(KeychainKind::custom(0));
+
+        let only_change = utxos
+            .iter()
+            .filter(|u| ChangeSpendPolicy::OnlyChange.is_satisfied_by(u, change_keychain))
+            .count();
+        assert_eq!(only_change, 2);
+        let no_change = utxos
+            .iter()
+            .filter(|u| ChangeSpendPolicy::ChangeForbidden.is_satisfied_by(u, change_keychain))
+            .count();
+        assert_eq!(no_change, 0);
+    }
+
     #[test]
     fn test_default_tx_version_1() {
         let version = Version::default();
@@ -10,7 +10,7 @@
 // licenses.
 
 use bitcoin::secp256k1::{All, Secp256k1};
-use bitcoin::{absolute, Script, Sequence};
+use bitcoin::{absolute, Address, AddressType, Network, Script, Sequence};
 
 use miniscript::{MiniscriptKey, Satisfier, ToPublicKey};
 
@@ -112,6 +112,14 @@ impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for Older {
 
 pub(crate) type SecpCtx = Secp256k1<All>;
 
+/// The address type of a script pubkey, `None` if it's not a standard one
+pub(crate) fn script_type(script: &Script) -> Option<AddressType> {
+    // the network doesn't matter, it's not part of the type
+    Address::from_script(script, Network::Bitcoin)
+        .ok()?
+        .address_type()
+}
+
 #[cfg(test)]
 mod test {
     // When nSequence is lower than this flag the timelock is interpreted as block-height-based,
@@ -1,18 +1,37 @@
 use std::str::FromStr;
+use std::sync::Arc;
 
 use assert_matches::assert_matches;
 use bdk::descriptor::calc_checksum;
+use bdk::descriptor::policy::{BuildSatisfaction, Satisfaction, SatisfiableItem};
+use bdk::descriptor::ExtractPolicy;
+#[cfg(feature = "planning")]
+use bdk::miniscript::DescriptorPublicKey;
 use bdk::psbt::PsbtUtils;
-use bdk::signer::{SignOptions, SignerError};
+use bdk::signer::{SignOptions, SignerError, SignerOrdering};
+use bdk::silent_payments::{SendError, SilentPaymentAddress, SilentPaymentIndex};
 use bdk::wallet::coin_selection::{self, LargestFirstCoinSelection};
-use bdk::wallet::error::CreateTxError;
+use bdk::wallet::error::{BuildBatchError, BuildCpfpError, CreateTxError};
+use bdk::wallet::event::WalletEvent;
+use bdk::wallet::history::{HistoryOrder, HistoryQuery, TxSummary};
+use bdk::wallet::labels::{ImportLabelsError, Label, LabelRef, SkippedLabel};
+use bdk::wallet::musig::{self, KeyAggContext, MusigSigner};
+use bdk::wallet::payjoin::{PayjoinError, PayjoinParams};
+use bdk::wallet::payment_queue::PaymentStatus;
 use bdk::wallet::tx_builder::AddForeignUtxoError;
+#[cfg(feature = "planning")]
+use bdk::wallet::tx_builder::Assets;
 use bdk::wallet::AddressIndex::*;
-use bdk::wallet::{AddressIndex, AddressInfo, Balance, Wallet};
+use bdk::wallet::{
+    AddKeychainError, AddressIndex, AddressInfo, Balance, GetAddressError, LegacyChangeSet, Update,
+    Wallet,
+};
 use bdk::{FeeRate, KeychainKind};
-use bdk_chain::COINBASE_MATURITY;
-use bdk_chain::{BlockId, ConfirmationTime};
+use bdk_chain::Append;
+use bdk_chain::{BlockId, ConfirmationTime, ConfirmationTimeHeightAnchor, TxGraph};
+use bdk_chain::{BroadcastError, Broadcaster, ChainPosition, FeeEstimator, COINBASE_MATURITY};
 use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
 use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
 use bitcoin::ScriptBuf;
 use bitcoin::{
@@ -158,6 +177,374 @@ fn new_or_load() {
     }
 }
 
+#[cfg(feature = "async")]
+#[derive(Debug, Default, Clone)]
+struct AsyncMemoryDb(std::sync::Arc<std::sync::Mutex<Option<bdk::wallet::ChangeSet>>>);
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl bdk_chain::PersistBackendAsync<bdk::wallet::ChangeSet> for AsyncMemoryDb {
+    type WriteError = core::convert::Infallible;
+    type LoadError = core::convert::Infallible;
+
+    async fn write_changes(
+        &mut self,
+        changeset: &bdk::wallet::ChangeSet,
+    ) -> Result<(), Self::WriteError> {
+        let mut db = self.0.lock().unwrap();
+        match db.as_mut() {
+            Some(aggregate) => aggregate.append(changeset.clone()),
+            None => *db = Some(changeset.clone()),
+        }
+        Ok(())
+    }
+
+    async fn load_from_persistence(
+        &mut self,
+    ) -> Result<Option<bdk::wallet::ChangeSet>, Self::LoadError> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_persist_recovers_wallet() {
+    let db = AsyncMemoryDb::default();
+
+    // init wallet and reveal an address
+    let address = {
+        let mut wallet = Wallet::new_async(get_test_wpkh(), None, db.clone(), Network::Testnet)
+            .await
+            .expect("must init wallet");
+        let address = wallet
+            .try_get_address_async(New)
+            .await
+            .expect("must persist address");
+        assert!(wallet.staged().is_empty());
+        address
+    };
+
+    // recover wallet
+    {
+        let mut wallet = Wallet::load_async(get_test_wpkh(), None, db.clone())
+            .await
+            .expect("must recover wallet");
+        assert_eq!(wallet.network(), Network::Testnet);
+        assert_eq!(
+            wallet
+                .spk_index()
+                .last_revealed_index(&KeychainKind::External),
+            Some(address.index)
+        );
+        let last_unused = wallet
+            .try_get_address_async(LastUnused)
+            .await
+            .expect("must persist address");
+        assert_eq!(last_unused.address, address.address);
+    }
+
+    // new_or_load loads the existing wallet
+    {
+        let wallet = Wallet::new_or_load_async(get_test_wpkh(), None, db, Network::Testnet)
+            .await
+            .expect("must recover wallet");
+        assert_eq!(
+            wallet
+                .spk_index()
+                .last_revealed_index(&KeychainKind::External),
+            Some(address.index)
+        );
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_get_address_only_commits_revealed_address() {
+    let mut wallet = Wallet::new_async(
+        get_test_tr_single_sig_xprv(),
+        None,
+        AsyncMemoryDb::default(),
+        Network::Testnet,
+    )
+    .await
+    .expect("must init wallet");
+    let address = wallet
+        .try_get_address_async(New)
+        .await
+        .expect("must persist address");
+
+    // unrelated changes are left staged if no address is revealed
+    wallet
+        .insert_checkpoint(BlockId {
+            height: 3_000,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    let last_unused = wallet
+        .try_get_address_async(LastUnused)
+        .await
+        .expect("must persist address");
+    assert_eq!(last_unused, address);
+    assert!(!wallet.staged().is_empty());
+
+    let new = wallet
+        .try_get_address_async(New)
+        .await
+        .expect("must persist address");
+    assert_eq!(new.index, address.index + 1);
+    assert!(wallet.staged().is_empty());
+}
+
+#[test]
+fn get_address_commits_staged_changes() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    wallet.commit().unwrap();
+    let address = wallet.get_address(New);
+
+    wallet
+        .insert_checkpoint(BlockId {
+            height: 3_000,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    assert_eq!(wallet.get_address(Peek(0)).index, 0);
+    assert!(!wallet.staged().is_empty());
+
+    // staged changes are committed even if no address is revealed
+    assert_eq!(wallet.get_address(LastUnused), address);
+    assert!(wallet.staged().is_empty());
+}
+
+#[test]
+fn finish_staged_does_not_commit_change_address() {
+    let (mut wallet, _) =
+        get_funded_wallet_with_change(get_test_wpkh(), Some(get_test_tr_single_sig_xprv()));
+    wallet.commit().unwrap();
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    builder.finish_staged().expect("must build tx");
+    assert_eq!(
+        wallet
+            .staged()
+            .indexed_tx_graph
+            .indexer
+            .as_inner()
+            .get(&KeychainKind::Internal),
+        Some(&0)
+    );
+}
+
+#[test]
+fn custom_keychain_balance_and_spend() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let keychain = wallet
+        .add_keychain(0, get_test_tr_single_sig_xprv())
+        .expect("must add keychain");
+    assert_eq!(keychain, KeychainKind::custom(0));
+
+    let addr = wallet.get_keychain_address(keychain, New);
+    assert_eq!(addr.index, 0);
+    let tx = Transaction {
+        version: 1,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: addr.script_pubkey(),
+            value: 30_000,
+        }],
+    };
+    let height = wallet.latest_checkpoint().height();
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Confirmed { height, time: 0 })
+        .unwrap();
+
+    assert_eq!(wallet.get_keychain_balance(keychain).confirmed, 30_000);
+    assert_eq!(
+        wallet.get_balance().confirmed,
+        wallet
+            .get_keychain_balance(KeychainKind::External)
+            .confirmed
+            + 30_000
+    );
+    let custom_unspent = wallet.list_keychain_unspent(keychain).collect::<Vec<_>>();
+    assert_eq!(custom_unspent.len(), 1);
+    assert_eq!(custom_unspent[0].outpoint, OutPoint::new(tx.txid(), 0));
+
+    let recipient = wallet.get_address(New).script_pubkey();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient, 20_000)
+        .only_spend_from_keychains([keychain]);
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.input[0].previous_output,
+        OutPoint::new(tx.txid(), 0)
+    );
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized);
+}
+
+#[test]
+fn add_keychain_rejects_existing() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    assert_matches!(
+        wallet.add_keychain(0, get_test_wpkh()),
+        Err(AddKeychainError::DescriptorExists(KeychainKind::External))
+    );
+    wallet.add_keychain(0, get_test_tr_single_sig()).unwrap();
+    assert_matches!(
+        wallet.add_keychain(0, get_test_tr_repeated_key()),
+        Err(AddKeychainError::KeychainExists(keychain)) if keychain == KeychainKind::custom(0)
+    );
+    // adding the same keychain again is allowed
+    wallet.add_keychain(0, get_test_tr_single_sig()).unwrap();
+}
+
+#[test]
+fn load_recovers_custom_keychain() {
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.db");
+    let keychain = KeychainKind::custom(3);
+
+    let wallet_keychains = {
+        let db = bdk_file_store::Store::create_new(DB_MAGIC, &file_path).expect("must create db");
+        let mut wallet =
+            Wallet::new(get_test_wpkh(), None, db, Network::Testnet).expect("must init wallet");
+        wallet.add_keychain(3, get_test_tr_single_sig()).unwrap();
+        wallet.try_get_keychain_address(keychain, New).unwrap();
+        wallet.keychains().clone()
+    };
+
+    let db = bdk_file_store::Store::open(DB_MAGIC, &file_path).expect("must recover db");
+    let mut wallet = Wallet::load(get_test_wpkh(), None, db).expect("must recover wallet");
+    assert_eq!(wallet.keychains(), &wallet_keychains);
+    assert_eq!(wallet.derivation_index(keychain), Some(0));
+    // private keys are not persisted
+    assert!(wallet.get_signers(keychain).ids().is_empty());
+    wallet.add_keychain(3, get_test_tr_single_sig()).unwrap();
+    assert_eq!(wallet.get_signers(keychain).ids().len(), 1);
+}
+
+#[test]
+fn get_address_of_unknown_keychain() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let staged = wallet.staged().clone();
+    assert_matches!(
+        wallet.try_get_keychain_address(KeychainKind::custom(1), New),
+        Err(GetAddressError::UnknownKeychain(keychain)) if keychain == KeychainKind::custom(1)
+    );
+    assert_eq!(wallet.staged(), &staged);
+}
+
+#[test]
+fn load_migrates_legacy_file() {
+    use bincode::Options;
+
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.db");
+    let legacy_path = temp_dir.path().join("legacy.db");
+
+    {
+        let db = bdk_file_store::Store::create_new(DB_MAGIC, &file_path).expect("must create db");
+        let mut wallet = Wallet::new(get_test_tr_single_sig_xprv(), None, db, Network::Testnet)
+            .expect("must init wallet");
+        for _ in 0..3 {
+            wallet.try_get_address(New).unwrap();
+        }
+    }
+
+    // write the wallet's changeset in the legacy format: bincode directly after the magic bytes
+    let changeset = bdk_file_store::Store::<bdk::wallet::ChangeSet>::open(DB_MAGIC, &file_path)
+        .expect("must open db")
+        .aggregate_changesets()
+        .expect("must load")
+        .expect("must exist");
+    let legacy = LegacyChangeSet {
+        chain: changeset.chain,
+        indexed_tx_graph: changeset.indexed_tx_graph,
+        network: changeset.network,
+    };
+    let mut data = DB_MAGIC.to_vec();
+    bincode::DefaultOptions::new()
+        .with_varint_encoding()
+        .serialize_into(&mut data, &legacy)
+        .unwrap();
+    std::fs::write(&legacy_path, data).unwrap();
+
+    assert!(bdk_file_store::Store::<bdk::wallet::ChangeSet>::open(DB_MAGIC, &legacy_path).is_err());
+    let db = bdk_file_store::Store::open_migrating::<LegacyChangeSet, _>(DB_MAGIC, &legacy_path)
+        .expect("must migrate db");
+    let wallet = Wallet::load(get_test_tr_single_sig_xprv(), None, db).expect("must load wallet");
+    assert_eq!(wallet.network(), Network::Testnet);
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(2));
+}
+
+#[test]
+fn load_recovers_labels() {
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.db");
+    let txid = Txid::all_zeros();
+
+    let exported = {
+        let db = bdk_file_store::Store::create_new(DB_MAGIC, &file_path).expect("must create db");
+        let mut wallet =
+            Wallet::new(get_test_wpkh(), None, db, Network::Testnet).expect("must init wallet");
+        let address = wallet.try_get_address(New).unwrap().address;
+        wallet.set_label(LabelRef::Tx(txid), "rent");
+        wallet.set_label(LabelRef::address(&address), "deposit");
+        let pubkey = LabelRef::PubKey(
+            bitcoin::PublicKey::from_str(
+                "0283409659355b6d1cc3c32decd5d561abaac86c37a353b52895a5e6c196d6f448",
+            )
+            .unwrap(),
+        );
+        wallet.set_label(pubkey.clone(), "removed");
+        assert!(wallet.remove_label(&pubkey).is_some());
+        wallet.commit().expect("must commit");
+        wallet.export_labels()
+    };
+
+    let db = bdk_file_store::Store::open(DB_MAGIC, &file_path).expect("must recover db");
+    let wallet = Wallet::load(get_test_wpkh(), None, db).expect("must recover wallet");
+    assert_eq!(wallet.labels().count(), 2);
+    assert_eq!(
+        wallet.label(&LabelRef::Tx(txid)).map(|l| l.text.as_str()),
+        Some("rent")
+    );
+    assert_eq!(wallet.export_labels(), exported);
+}
+
+#[test]
+fn load_recovers_spending_policies() {
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.db");
+    let frozen = OutPoint::new(Txid::all_zeros(), 1);
+    let unfrozen = OutPoint::new(Txid::all_zeros(), 2);
+
+    {
+        let db = bdk_file_store::Store::create_new(DB_MAGIC, &file_path).expect("must create db");
+        let mut wallet =
+            Wallet::new(get_test_wpkh(), None, db, Network::Testnet).expect("must init wallet");
+        assert!(wallet.freeze_utxo(frozen));
+        assert!(!wallet.freeze_utxo(frozen));
+        assert!(wallet.freeze_utxo(unfrozen));
+        assert!(wallet.unfreeze_utxo(unfrozen));
+        wallet.set_min_confirmations(KeychainKind::External, 6);
+        wallet.set_min_confirmations(KeychainKind::Internal, 3);
+        wallet.set_min_confirmations(KeychainKind::Internal, 0);
+        wallet.commit().expect("must commit");
+    }
+
+    let db = bdk_file_store::Store::open(DB_MAGIC, &file_path).expect("must recover db");
+    let wallet = Wallet::load(get_test_wpkh(), None, db).expect("must recover wallet");
+    assert_eq!(wallet.frozen_utxos().collect::<Vec<_>>(), vec![frozen]);
+    assert!(!wallet.is_frozen(unfrozen));
+    assert_eq!(wallet.min_confirmations(KeychainKind::External), 6);
+    assert_eq!(wallet.min_confirmations(KeychainKind::Internal), 0);
+}
+
 #[test]
 fn test_descriptor_checksum() {
     let (wallet, _) = get_funded_wallet(get_test_wpkh());
@@ -651,633 +1038,1061 @@ fn test_create_tx_custom_fee_rate() {
 }
 
 #[test]
-fn test_create_tx_absolute_fee() {
+#[cfg(feature = "waste-metric")]
+fn test_create_tx_waste_metric_coin_selection() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
+    let mut builder = wallet
+        .build_tx()
+        .coin_selection(coin_selection::WasteMetricCoinSelection::default());
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(100);
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .fee_rate(FeeRate::from_sat_per_vb(5.0));
     let psbt = builder.finish().unwrap();
     let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(fee.unwrap_or(0), 100);
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(psbt.unsigned_tx.output[0].value, 50_000 - fee.unwrap_or(0));
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
 }
 
 #[test]
-fn test_create_tx_absolute_zero_fee() {
+fn test_create_tx_knapsack_coin_selection() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
+    let mut builder = wallet
+        .build_tx()
+        .coin_selection(coin_selection::KnapsackCoinSelection::default());
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(0);
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .fee_rate(FeeRate::from_sat_per_vb(5.0));
     let psbt = builder.finish().unwrap();
     let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(fee.unwrap_or(0), 0);
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(psbt.unsigned_tx.output[0].value, 50_000 - fee.unwrap_or(0));
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_create_tx_absolute_high_fee() {
+fn test_create_tx_privacy_coin_selection_match_change_script_type() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(60_000);
-    let _ = builder.finish().unwrap();
-}
+    // a taproot keychain to receive payments, and one for the change
+    wallet.add_keychain(0, get_test_tr_single_sig()).unwrap();
+    wallet
+        .add_keychain(1, get_test_tr_single_sig_xprv())
+        .unwrap();
+    let key = bitcoin::key::XOnlyPublicKey::from_str(
+        "b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55",
+    )
+    .unwrap();
+    let taproot_recipient = ScriptBuf::new_v1_p2tr_tweaked(
+        bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(key),
+    );
+    let change_script = |psbt: &psbt::PartiallySignedTransaction| {
+        psbt.unsigned_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey != taproot_recipient)
+            .unwrap()
+            .script_pubkey
+            .clone()
+    };
 
-#[test]
-fn test_create_tx_add_change() {
-    use bdk::wallet::tx_builder::TxOrdering;
+    let mut builder = wallet
+        .build_tx()
+        .coin_selection(coin_selection::PrivacyCoinSelection::new());
+    builder.add_recipient(taproot_recipient.clone(), 25_000);
+    let psbt = builder.finish().unwrap();
+    assert!(change_script(&psbt).is_v0_p2wpkh());
 
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
+    let mut builder = wallet
+        .build_tx()
+        .coin_selection(coin_selection::PrivacyCoinSelection::new());
     builder
-        .add_recipient(addr.script_pubkey(), 25_000)
-        .ordering(TxOrdering::Untouched);
+        .add_recipient(taproot_recipient.clone(), 25_000)
+        .match_change_script_type(KeychainKind::custom(1));
     let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(psbt.unsigned_tx.output.len(), 2);
-    assert_eq!(psbt.unsigned_tx.output[0].value, 25_000);
-    assert_eq!(psbt.unsigned_tx.output[1].value, 25_000 - fee.unwrap_or(0));
+    let change = change_script(&psbt);
+    assert!(change.is_v1_p2tr());
+    assert!(wallet.is_mine(&change));
+    assert_eq!(wallet.derivation_index(KeychainKind::custom(0)), None);
+    assert_eq!(wallet.derivation_index(KeychainKind::custom(1)), Some(0));
+}
+
+/// The public key of `cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW`, as an asset
+#[cfg(feature = "planning")]
+fn get_test_asset_key() -> DescriptorPublicKey {
+    let secp = Secp256k1::new();
+    let sk = bitcoin::PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")
+        .unwrap();
+    DescriptorPublicKey::from_str(&sk.public_key(&secp).to_string()).unwrap()
 }
 
 #[test]
-fn test_create_tx_skip_change_dust() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+#[cfg(feature = "planning")]
+fn test_create_tx_assets_timelock() {
+    let descriptor = "tr(b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55,{and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),after(100000)),pk(8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642)})";
+    let (mut wallet, _) = get_funded_wallet(descriptor);
     let addr = wallet.get_address(New);
+
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), 49_800);
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::SpendingPolicyRequired(
+            KeychainKind::External
+        ))
+    );
 
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(psbt.unsigned_tx.output[0].value, 49_800);
-    assert_eq!(fee.unwrap_or(0), 200);
-}
+    // the leaf can't be used before block 100_000
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(Assets {
+            keys: vec![get_test_asset_key()],
+            ..Default::default()
+        });
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
 
-#[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_create_tx_drain_to_dust_amount() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    // very high fee rate, so that the only output would be below dust
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_rate(FeeRate::from_sat_per_vb(453.0));
-    builder.finish().unwrap();
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(Assets {
+            keys: vec![get_test_asset_key()],
+            max_locktime: Some(absolute::LockTime::from_height(100_000).unwrap()),
+            ..Default::default()
+        });
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 100_000);
+    assert_eq!(
+        psbt.unsigned_tx.input[0].sequence,
+        Sequence::ENABLE_LOCKTIME_NO_RBF
+    );
 }
 
 #[test]
-fn test_create_tx_ordering_respected() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+#[cfg(feature = "planning")]
+fn test_create_tx_assets_relative_timelock() {
+    let descriptor = "tr(b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55,{and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(6)),pk(8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642)})";
+    let (mut wallet, _) = get_funded_wallet(descriptor);
     let addr = wallet.get_address(New);
+
+    // the age of the utxo is taken from its confirmations
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), 30_000)
-        .add_recipient(addr.script_pubkey(), 10_000)
-        .ordering(bdk::wallet::tx_builder::TxOrdering::Bip69Lexicographic);
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(Assets {
+            keys: vec![get_test_asset_key()],
+            ..Default::default()
+        });
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
+
+    wallet
+        .insert_checkpoint(BlockId {
+            height: 2_005,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(Assets {
+            keys: vec![get_test_asset_key()],
+            ..Default::default()
+        });
     let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(psbt.unsigned_tx.output.len(), 3);
-    assert_eq!(psbt.unsigned_tx.output[0].value, 10_000 - fee.unwrap_or(0));
-    assert_eq!(psbt.unsigned_tx.output[1].value, 10_000);
-    assert_eq!(psbt.unsigned_tx.output[2].value, 30_000);
+    assert_eq!(psbt.unsigned_tx.version, 2);
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
+
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .version(1)
+        .assets(Assets {
+            keys: vec![get_test_asset_key()],
+            ..Default::default()
+        });
+    assert_matches!(builder.finish(), Err(CreateTxError::Version1Csv));
 }
 
 #[test]
-fn test_create_tx_default_sighash() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), 30_000);
-    let psbt = builder.finish().unwrap();
+#[cfg(feature = "planning")]
+fn test_create_tx_assets_cheapest_plan() {
+    let descriptor = "tr(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,{and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(6)),pk(8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642)})";
+    let drain_fee = |assets: Option<Assets>| {
+        let (mut wallet, _) = get_funded_wallet(descriptor);
+        let addr = wallet.get_address(New);
+        let policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let path = vec![(policy.id, vec![0])].into_iter().collect();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_wallet()
+            .drain_to(addr.script_pubkey())
+            .policy_path(path, KeychainKind::External)
+            .fee_rate(FeeRate::from_sat_per_vb(10.0));
+        if let Some(assets) = assets {
+            builder.assets(assets);
+        }
+        let psbt = builder.finish().unwrap();
+        (
+            psbt.unsigned_tx.input[0].sequence,
+            check_fee!(wallet, psbt).unwrap(),
+        )
+    };
 
-    assert_eq!(psbt.inputs[0].sighash_type, None);
+    let (sequence, fee) = drain_fee(None);
+    // the key path is used, so the input doesn't need a relative timelock
+    let (planned_sequence, planned_fee) = drain_fee(Some(Assets {
+        keys: vec![get_test_asset_key()],
+        ..Default::default()
+    }));
+    assert_eq!(sequence, planned_sequence);
+    assert_ne!(planned_sequence, Sequence(6));
+    // without a plan the weight of the largest leaf is assumed
+    assert!(planned_fee < fee);
 }
 
 #[test]
-fn test_create_tx_custom_sighash() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+#[cfg(feature = "planning")]
+fn test_create_tx_assets_manually_selected_no_plan() {
+    let (mut wallet, txid) = get_funded_wallet(get_test_tr_repeated_key());
     let addr = wallet.get_address(New);
+    let outpoint = OutPoint { txid, vout: 0 };
+
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), 30_000)
-        .sighash(EcdsaSighashType::Single.into());
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(
-        psbt.inputs[0].sighash_type,
-        Some(EcdsaSighashType::Single.into())
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .add_utxo(outpoint)
+        .unwrap()
+        .assets(Assets::default());
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::NoSpendingPlan(op)) if op == outpoint
     );
 }
 
 #[test]
-fn test_create_tx_input_hd_keypaths() {
-    use bitcoin::bip32::{DerivationPath, Fingerprint};
-    use core::str::FromStr;
-
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+#[cfg(feature = "planning")]
+fn test_create_tx_assets_multi_a() {
+    let descriptor = "tr(b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55,multi_a(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642))";
+    let (mut wallet, txid) = get_funded_wallet(descriptor);
     let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+    let outpoint = OutPoint { txid, vout: 0 };
 
-    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
-    assert_eq!(
-        psbt.inputs[0].bip32_derivation.values().next().unwrap(),
-        &(
-            Fingerprint::from_str("d34db33f").unwrap(),
-            DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
-        )
+    // one signature is missing, the utxo is skipped or can't be spent if selected manually
+    let assets = Assets {
+        keys: vec![get_test_asset_key()],
+        ..Default::default()
+    };
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(assets.clone());
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .add_utxo(outpoint)
+        .unwrap()
+        .assets(assets);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::NoSpendingPlan(op)) if op == outpoint
     );
+
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(Assets {
+            keys: vec![
+                get_test_asset_key(),
+                DescriptorPublicKey::from_str(
+                    "8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642",
+                )
+                .unwrap(),
+            ],
+            ..Default::default()
+        });
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input[0].previous_output, outpoint);
 }
 
 #[test]
-fn test_create_tx_output_hd_keypaths() {
-    use bitcoin::bip32::{DerivationPath, Fingerprint};
-    use core::str::FromStr;
+#[cfg(feature = "planning")]
+fn test_create_tx_assets_wsh() {
+    let descriptor = "wsh(or_i(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(6)),pk(028aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642)))";
+    let (mut wallet, _) = get_funded_wallet(descriptor);
+    let addr = wallet.get_address(New);
+    let assets = Assets {
+        keys: vec![get_test_asset_key()],
+        ..Default::default()
+    };
 
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+    // the utxo doesn't have 6 confirmations yet
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(assets.clone());
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
 
-    let addr = wallet.get_address(New);
+    wallet
+        .insert_checkpoint(BlockId {
+            height: 2_005,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .assets(assets);
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
-    let expected_derivation_path = format!("m/44'/0'/0'/0/{}", addr.index);
-    assert_eq!(
-        psbt.outputs[0].bip32_derivation.values().next().unwrap(),
-        &(
-            Fingerprint::from_str("d34db33f").unwrap(),
-            DerivationPath::from_str(&expected_derivation_path).unwrap()
-        )
-    );
+    assert_eq!(psbt.unsigned_tx.version, 2);
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
 }
 
 #[test]
-fn test_create_tx_set_redeem_script_p2sh() {
-    use bitcoin::hashes::hex::FromHex;
-
-    let (mut wallet, _) =
-        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(
-        psbt.inputs[0].redeem_script,
-        Some(ScriptBuf::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-            )
+#[cfg(feature = "planning")]
+fn test_create_tx_assets_unsupported_descriptor() {
+    // `or_b` can't be planned, so the utxo is spent with its maximum satisfaction weight
+    let descriptor = "tr(b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55,or_b(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),s:pk(8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642)))";
+    let drain_fee = |assets: Option<Assets>| {
+        let (mut wallet, txid) = get_funded_wallet(descriptor);
+        let addr = wallet.get_address(New);
+        let mut builder = wallet.build_tx();
+        builder
+            .add_utxo(OutPoint { txid, vout: 0 })
             .unwrap()
-        ))
-    );
-    assert_eq!(psbt.inputs[0].witness_script, None);
+            .manually_selected_only()
+            .drain_to(addr.script_pubkey())
+            .fee_rate(FeeRate::from_sat_per_vb(10.0));
+        if let Some(assets) = assets {
+            builder.assets(assets);
+        }
+        let psbt = builder.finish().unwrap();
+        check_fee!(wallet, psbt).unwrap()
+    };
+
+    let fee = drain_fee(None);
+    let planned_fee = drain_fee(Some(Assets {
+        keys: vec![get_test_asset_key()],
+        ..Default::default()
+    }));
+    assert_eq!(fee, planned_fee);
 }
 
-#[test]
-fn test_create_tx_set_witness_script_p2wsh() {
-    use bitcoin::hashes::hex::FromHex;
+/// Estimates 5 sat/vB for the next block and 2 sat/vB beyond.
+struct TestFeeEstimator;
 
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+impl FeeEstimator for TestFeeEstimator {
+    type Error = String;
 
-    assert_eq!(psbt.inputs[0].redeem_script, None);
-    assert_eq!(
-        psbt.inputs[0].witness_script,
-        Some(ScriptBuf::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-            )
-            .unwrap()
-        ))
-    );
+    fn estimate_fee_rate(&self, target: u16) -> Result<bitcoin::FeeRate, Self::Error> {
+        match target {
+            0 => Err("no estimate for a target of 0 blocks".to_string()),
+            1 => Ok(bitcoin::FeeRate::from_sat_per_vb(5).unwrap()),
+            _ => Ok(bitcoin::FeeRate::from_sat_per_vb(2).unwrap()),
+        }
+    }
 }
 
 #[test]
-fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+fn test_create_tx_fee_target() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .fee_target(&TestFeeEstimator, 1)
+        .unwrap();
     let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
 
-    let script = ScriptBuf::from_hex(
-        "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
-    )
-    .unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .fee_target(&TestFeeEstimator, 6)
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.0), @add_signature);
 
-    assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_v0_p2wsh()));
-    assert_eq!(psbt.inputs[0].witness_script, Some(script));
+    let mut builder = wallet.build_tx();
+    assert!(builder.fee_target(&TestFeeEstimator, 0).is_err());
+    // a constant fee rate is an estimator too
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .fee_target(&bitcoin::FeeRate::from_sat_per_kwu(750), 6)
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(3.0), @add_signature);
 }
 
-#[test]
-fn test_create_tx_non_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_create_tx_fee_target_async() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder
+        .fee_target_async(&bitcoin::FeeRate::from_sat_per_vb(4).unwrap(), 6)
+        .await
+        .unwrap()
+        .add_recipient(addr.script_pubkey(), 25_000);
     let psbt = builder.finish().unwrap();
-
-    assert!(psbt.inputs[0].non_witness_utxo.is_some());
-    assert!(psbt.inputs[0].witness_utxo.is_none());
+    let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(4.0), @add_signature);
 }
 
 #[test]
-fn test_create_tx_only_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+fn test_create_tx_absolute_fee() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
     builder
         .drain_to(addr.script_pubkey())
-        .only_witness_utxo()
-        .drain_wallet();
+        .drain_wallet()
+        .fee_absolute(100);
     let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    assert!(psbt.inputs[0].non_witness_utxo.is_none());
-    assert!(psbt.inputs[0].witness_utxo.is_some());
+    assert_eq!(fee.unwrap_or(0), 100);
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(psbt.unsigned_tx.output[0].value, 50_000 - fee.unwrap_or(0));
 }
 
 #[test]
-fn test_create_tx_shwpkh_has_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+fn test_create_tx_absolute_zero_fee() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_absolute(0);
     let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    assert!(psbt.inputs[0].witness_utxo.is_some());
+    assert_eq!(fee.unwrap_or(0), 0);
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(psbt.unsigned_tx.output[0].value, 50_000 - fee.unwrap_or(0));
 }
 
 #[test]
-fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.get_address(New);
+#[should_panic(expected = "InsufficientFunds")]
+fn test_create_tx_absolute_high_fee() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    assert!(psbt.inputs[0].non_witness_utxo.is_some());
-    assert!(psbt.inputs[0].witness_utxo.is_some());
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_absolute(60_000);
+    let _ = builder.finish().unwrap();
 }
 
 #[test]
-fn test_create_tx_add_utxo() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let small_output_tx = Transaction {
-        input: vec![],
-        output: vec![TxOut {
-            value: 25_000,
-            script_pubkey: wallet.get_address(New).address.script_pubkey(),
-        }],
-        version: 0,
-        lock_time: absolute::LockTime::ZERO,
-    };
-    wallet
-        .insert_tx(
-            small_output_tx.clone(),
-            ConfirmationTime::Unconfirmed { last_seen: 0 },
-        )
-        .unwrap();
+fn test_create_tx_add_change() {
+    use bdk::wallet::tx_builder::TxOrdering;
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), 30_000)
-        .add_utxo(OutPoint {
-            txid: small_output_tx.txid(),
-            vout: 0,
-        })
-        .unwrap();
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .ordering(TxOrdering::Untouched);
     let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(
-        psbt.unsigned_tx.input.len(),
-        2,
-        "should add an additional input since 25_000 < 30_000"
-    );
-    assert_eq!(
-        sent_received.0, 75_000,
-        "total should be sum of both inputs"
-    );
+    assert_eq!(psbt.unsigned_tx.output.len(), 2);
+    assert_eq!(psbt.unsigned_tx.output[0].value, 25_000);
+    assert_eq!(psbt.unsigned_tx.output[1].value, 25_000 - fee.unwrap_or(0));
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_create_tx_manually_selected_insufficient() {
+fn test_create_tx_skip_change_dust() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let small_output_tx = Transaction {
-        input: vec![],
-        output: vec![TxOut {
-            value: 25_000,
-            script_pubkey: wallet.get_address(New).address.script_pubkey(),
-        }],
-        version: 0,
-        lock_time: absolute::LockTime::ZERO,
-    };
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 49_800);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    wallet
-        .insert_tx(
-            small_output_tx.clone(),
-            ConfirmationTime::Unconfirmed { last_seen: 0 },
-        )
-        .unwrap();
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(psbt.unsigned_tx.output[0].value, 49_800);
+    assert_eq!(fee.unwrap_or(0), 200);
+}
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+#[test]
+#[should_panic(expected = "InsufficientFunds")]
+fn test_create_tx_drain_to_dust_amount() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
+    // very high fee rate, so that the only output would be below dust
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), 30_000)
-        .add_utxo(OutPoint {
-            txid: small_output_tx.txid(),
-            vout: 0,
-        })
-        .unwrap()
-        .manually_selected_only();
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_rate(FeeRate::from_sat_per_vb(453.0));
     builder.finish().unwrap();
 }
 
 #[test]
-#[should_panic(expected = "SpendingPolicyRequired(External)")]
-fn test_create_tx_policy_path_required() {
-    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_ordering_respected() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), 30_000);
-    builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), 30_000)
+        .add_recipient(addr.script_pubkey(), 10_000)
+        .ordering(bdk::wallet::tx_builder::TxOrdering::Bip69Lexicographic);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(psbt.unsigned_tx.output.len(), 3);
+    assert_eq!(psbt.unsigned_tx.output[0].value, 10_000 - fee.unwrap_or(0));
+    assert_eq!(psbt.unsigned_tx.output[1].value, 10_000);
+    assert_eq!(psbt.unsigned_tx.output[2].value, 30_000);
 }
 
 #[test]
-fn test_create_tx_policy_path_no_csv() {
-    let descriptors = get_test_wpkh();
-    let mut wallet = Wallet::new_no_persist(descriptors, None, Network::Regtest).unwrap();
-
-    let tx = Transaction {
-        version: 0,
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![],
-        output: vec![TxOut {
-            value: 50_000,
-            script_pubkey: wallet.get_address(New).script_pubkey(),
-        }],
-    };
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+fn test_create_tx_default_sighash() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 30_000);
+    let psbt = builder.finish().unwrap();
 
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #0 is just the key "A"
-    let path = vec![(root_id, vec![0])].into_iter().collect();
+    assert_eq!(psbt.inputs[0].sighash_type, None);
+}
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+#[test]
+fn test_create_tx_custom_sighash() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
     builder
         .add_recipient(addr.script_pubkey(), 30_000)
-        .policy_path(path, KeychainKind::External);
+        .sighash(EcdsaSighashType::Single.into());
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFF));
+    assert_eq!(
+        psbt.inputs[0].sighash_type,
+        Some(EcdsaSighashType::Single.into())
+    );
 }
 
 #[test]
-fn test_create_tx_policy_path_use_csv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #1 is or(pk(B),older(144))
-    let path = vec![(root_id, vec![1])].into_iter().collect();
+fn test_create_tx_input_hd_keypaths() {
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use core::str::FromStr;
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+    let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), 30_000)
-        .policy_path(path, KeychainKind::External);
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(144));
+    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
+    assert_eq!(
+        psbt.inputs[0].bip32_derivation.values().next().unwrap(),
+        &(
+            Fingerprint::from_str("d34db33f").unwrap(),
+            DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
+        )
+    );
 }
 
 #[test]
-fn test_create_tx_policy_path_ignored_subtree_with_csv() {
-    let (mut wallet, _) = get_funded_wallet("wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),or_i(and_v(v:pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(30)),and_v(v:pkh(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(90)))))");
+fn test_create_tx_output_hd_keypaths() {
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use core::str::FromStr;
 
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #0 is pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)
-    let path = vec![(root_id, vec![0])].into_iter().collect();
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+    let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), 30_000)
-        .policy_path(path, KeychainKind::External);
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
+    assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
+    let expected_derivation_path = format!("m/44'/0'/0'/0/{}", addr.index);
+    assert_eq!(
+        psbt.outputs[0].bip32_derivation.values().next().unwrap(),
+        &(
+            Fingerprint::from_str("d34db33f").unwrap(),
+            DerivationPath::from_str(&expected_derivation_path).unwrap()
+        )
+    );
 }
 
 #[test]
-fn test_create_tx_global_xpubs_with_origin() {
-    use bitcoin::bip32;
+fn test_create_tx_set_redeem_script_p2sh() {
     use bitcoin::hashes::hex::FromHex;
 
-    let (mut wallet, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+    let (mut wallet, _) =
+        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
     let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), 25_000)
-        .add_global_xpubs();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    let key = bip32::ExtendedPubKey::from_str("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
-    let fingerprint = bip32::Fingerprint::from_hex("73756c7f").unwrap();
-    let path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
-
-    assert_eq!(psbt.xpub.len(), 1);
-    assert_eq!(psbt.xpub.get(&key), Some(&(fingerprint, path)));
+    assert_eq!(
+        psbt.inputs[0].redeem_script,
+        Some(ScriptBuf::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+            )
+            .unwrap()
+        ))
+    );
+    assert_eq!(psbt.inputs[0].witness_script, None);
 }
 
 #[test]
-fn test_add_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet(get_test_wpkh());
-    let (wallet2, _) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+fn test_create_tx_set_witness_script_p2wsh() {
+    use bitcoin::hashes::hex::FromHex;
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let utxo = wallet2.list_unspent().next().expect("must take!");
-    #[allow(deprecated)]
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_satisfaction_weight()
-        .unwrap();
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    let psbt_input = psbt::Input {
-        witness_utxo: Some(utxo.txout.clone()),
-        ..Default::default()
-    };
+    assert_eq!(psbt.inputs[0].redeem_script, None);
+    assert_eq!(
+        psbt.inputs[0].witness_script,
+        Some(ScriptBuf::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+            )
+            .unwrap()
+        ))
+    );
+}
 
-    let mut builder = wallet1.build_tx();
+#[test]
+fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
+
+    let script = ScriptBuf::from_hex(
+        "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
+    )
+    .unwrap();
+
+    assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_v0_p2wsh()));
+    assert_eq!(psbt.inputs[0].witness_script, Some(script));
+}
+
+#[test]
+fn test_create_tx_non_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
+
+    assert!(psbt.inputs[0].non_witness_utxo.is_some());
+    assert!(psbt.inputs[0].witness_utxo.is_none());
+}
+
+#[test]
+fn test_create_tx_only_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), 60_000)
+        .drain_to(addr.script_pubkey())
         .only_witness_utxo()
-        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
-        .unwrap();
-    let mut psbt = builder.finish().unwrap();
-    wallet1.insert_txout(utxo.outpoint, utxo.txout);
-    let fee = check_fee!(wallet1, psbt);
-    let sent_received = wallet1.sent_and_received(&psbt.clone().extract_tx());
+        .drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    assert_eq!(
-        sent_received.0 - sent_received.1,
-        10_000 + fee.unwrap_or(0),
-        "we should have only net spent ~10_000"
-    );
+    assert!(psbt.inputs[0].non_witness_utxo.is_none());
+    assert!(psbt.inputs[0].witness_utxo.is_some());
+}
 
-    assert!(
-        psbt.unsigned_tx
-            .input
-            .iter()
-            .any(|input| input.previous_output == utxo.outpoint),
-        "foreign_utxo should be in there"
-    );
+#[test]
+fn test_create_tx_shwpkh_has_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    let finished = wallet1
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
-        .unwrap();
+    assert!(psbt.inputs[0].witness_utxo.is_some());
+}
 
-    assert!(
-        !finished,
-        "only one of the inputs should have been signed so far"
-    );
+#[test]
+fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    let finished = wallet2
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-    assert!(finished, "all the inputs should have been signed now");
+    assert!(psbt.inputs[0].non_witness_utxo.is_some());
+    assert!(psbt.inputs[0].witness_utxo.is_some());
 }
 
 #[test]
-#[should_panic(
-    expected = "MissingTxOut([OutPoint { txid: 0x21d7fb1bceda00ab4069fc52d06baa13470803e9050edd16f5736e5d8c4925fd, vout: 0 }])"
-)]
-fn test_calculate_fee_with_missing_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet(get_test_wpkh());
-    let (wallet2, _) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+fn test_create_tx_add_utxo() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let small_output_tx = Transaction {
+        input: vec![],
+        output: vec![TxOut {
+            value: 25_000,
+            script_pubkey: wallet.get_address(New).address.script_pubkey(),
+        }],
+        version: 0,
+        lock_time: absolute::LockTime::ZERO,
+    };
+    wallet
+        .insert_tx(
+            small_output_tx.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
 
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let utxo = wallet2.list_unspent().next().expect("must take!");
-    #[allow(deprecated)]
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_satisfaction_weight()
-        .unwrap();
-
-    let psbt_input = psbt::Input {
-        witness_utxo: Some(utxo.txout.clone()),
-        ..Default::default()
-    };
-
-    let mut builder = wallet1.build_tx();
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), 60_000)
-        .only_witness_utxo()
-        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+        .add_recipient(addr.script_pubkey(), 30_000)
+        .add_utxo(OutPoint {
+            txid: small_output_tx.txid(),
+            vout: 0,
+        })
         .unwrap();
     let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx();
-    wallet1.calculate_fee(&tx).unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+
+    assert_eq!(
+        psbt.unsigned_tx.input.len(),
+        2,
+        "should add an additional input since 25_000 < 30_000"
+    );
+    assert_eq!(
+        sent_received.0, 75_000,
+        "total should be sum of both inputs"
+    );
 }
 
 #[test]
-fn test_add_foreign_utxo_invalid_psbt_input() {
+#[should_panic(expected = "InsufficientFunds")]
+fn test_create_tx_manually_selected_insufficient() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let outpoint = wallet.list_unspent().next().expect("must exist").outpoint;
-    #[allow(deprecated)]
-    let foreign_utxo_satisfaction = wallet
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_satisfaction_weight()
+    let small_output_tx = Transaction {
+        input: vec![],
+        output: vec![TxOut {
+            value: 25_000,
+            script_pubkey: wallet.get_address(New).address.script_pubkey(),
+        }],
+        version: 0,
+        lock_time: absolute::LockTime::ZERO,
+    };
+
+    wallet
+        .insert_tx(
+            small_output_tx.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
         .unwrap();
 
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
-    let result =
-        builder.add_foreign_utxo(outpoint, psbt::Input::default(), foreign_utxo_satisfaction);
-    assert!(matches!(result, Err(AddForeignUtxoError::MissingUtxo)));
+    builder
+        .add_recipient(addr.script_pubkey(), 30_000)
+        .add_utxo(OutPoint {
+            txid: small_output_tx.txid(),
+            vout: 0,
+        })
+        .unwrap()
+        .manually_selected_only();
+    builder.finish().unwrap();
 }
 
 #[test]
-fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
-    let (mut wallet1, txid1) = get_funded_wallet(get_test_wpkh());
-    let (wallet2, txid2) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+#[should_panic(expected = "SpendingPolicyRequired(External)")]
+fn test_create_tx_policy_path_required() {
+    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
 
-    let utxo2 = wallet2.list_unspent().next().unwrap();
-    let tx1 = wallet1.get_tx(txid1).unwrap().tx_node.tx.clone();
-    let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx.clone();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 30_000);
+    builder.finish().unwrap();
+}
 
-    #[allow(deprecated)]
-    let satisfaction_weight = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
+#[test]
+fn test_create_tx_policy_path_no_csv() {
+    let descriptors = get_test_wpkh();
+    let mut wallet = Wallet::new_no_persist(descriptors, None, Network::Regtest).unwrap();
+
+    let tx = Transaction {
+        version: 0,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            value: 50_000,
+            script_pubkey: wallet.get_address(New).script_pubkey(),
+        }],
+    };
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #0 is just the key "A"
+    let path = vec![(root_id, vec![0])].into_iter().collect();
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 30_000)
+        .policy_path(path, KeychainKind::External);
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFF));
+}
+
+#[test]
+fn test_create_tx_policy_path_use_csv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #1 is or(pk(B),older(144))
+    let path = vec![(root_id, vec![1])].into_iter().collect();
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 30_000)
+        .policy_path(path, KeychainKind::External);
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(144));
+}
+
+#[test]
+fn test_create_tx_policy_path_ignored_subtree_with_csv() {
+    let (mut wallet, _) = get_funded_wallet("wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),or_i(and_v(v:pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(30)),and_v(v:pkh(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(90)))))");
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #0 is pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)
+    let path = vec![(root_id, vec![0])].into_iter().collect();
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 30_000)
+        .policy_path(path, KeychainKind::External);
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
+}
+
+#[test]
+fn test_create_tx_global_xpubs_with_origin() {
+    use bitcoin::bip32;
+    use bitcoin::hashes::hex::FromHex;
+
+    let (mut wallet, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .add_global_xpubs();
+    let psbt = builder.finish().unwrap();
+
+    let key = bip32::ExtendedPubKey::from_str("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
+    let fingerprint = bip32::Fingerprint::from_hex("73756c7f").unwrap();
+    let path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+
+    assert_eq!(psbt.xpub.len(), 1);
+    assert_eq!(psbt.xpub.get(&key), Some(&(fingerprint, path)));
+}
+
+#[test]
+fn test_add_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet(get_test_wpkh());
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let utxo = wallet2.list_unspent().next().expect("must take!");
+    #[allow(deprecated)]
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_satisfaction_weight()
+        .unwrap();
+
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
+
+    let mut builder = wallet1.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 60_000)
+        .only_witness_utxo()
+        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+        .unwrap();
+    let mut psbt = builder.finish().unwrap();
+    wallet1.insert_txout(utxo.outpoint, utxo.txout);
+    let fee = check_fee!(wallet1, psbt);
+    let sent_received = wallet1.sent_and_received(&psbt.clone().extract_tx());
+
+    assert_eq!(
+        sent_received.0 - sent_received.1,
+        10_000 + fee.unwrap_or(0),
+        "we should have only net spent ~10_000"
+    );
+
+    assert!(
+        psbt.unsigned_tx
+            .input
+            .iter()
+            .any(|input| input.previous_output == utxo.outpoint),
+        "foreign_utxo should be in there"
+    );
+
+    let finished = wallet1
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert!(
+        !finished,
+        "only one of the inputs should have been signed so far"
+    );
+
+    let finished = wallet2
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(finished, "all the inputs should have been signed now");
+}
+
+#[test]
+#[should_panic(
+    expected = "MissingTxOut([OutPoint { txid: 0x21d7fb1bceda00ab4069fc52d06baa13470803e9050edd16f5736e5d8c4925fd, vout: 0 }])"
+)]
+fn test_calculate_fee_with_missing_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet(get_test_wpkh());
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let utxo = wallet2.list_unspent().next().expect("must take!");
+    #[allow(deprecated)]
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_satisfaction_weight()
+        .unwrap();
+
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
+
+    let mut builder = wallet1.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 60_000)
+        .only_witness_utxo()
+        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx();
+    wallet1.calculate_fee(&tx).unwrap();
+}
+
+#[test]
+fn test_add_foreign_utxo_invalid_psbt_input() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let outpoint = wallet.list_unspent().next().expect("must exist").outpoint;
+    #[allow(deprecated)]
+    let foreign_utxo_satisfaction = wallet
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_satisfaction_weight()
+        .unwrap();
+
+    let mut builder = wallet.build_tx();
+    let result =
+        builder.add_foreign_utxo(outpoint, psbt::Input::default(), foreign_utxo_satisfaction);
+    assert!(matches!(result, Err(AddForeignUtxoError::MissingUtxo)));
+}
+
+#[test]
+fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
+    let (mut wallet1, txid1) = get_funded_wallet(get_test_wpkh());
+    let (wallet2, txid2) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
+    let utxo2 = wallet2.list_unspent().next().unwrap();
+    let tx1 = wallet1.get_tx(txid1).unwrap().tx_node.tx.clone();
+    let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx.clone();
+
+    #[allow(deprecated)]
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
         .max_satisfaction_weight()
         .unwrap();
 
@@ -1424,850 +2239,2370 @@ fn test_create_tx_global_xpubs_master_without_origin() {
     );
 }
 
+/// Records the broadcast transactions, or rejects them with `reject_reason`.
+#[derive(Default)]
+struct TestBroadcaster {
+    broadcasted: std::cell::RefCell<Vec<Txid>>,
+    reject_reason: Option<&'static str>,
+}
+
+impl Broadcaster for TestBroadcaster {
+    type Error = core::convert::Infallible;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>> {
+        if let Some(reason) = self.reject_reason {
+            return Err(BroadcastError::from_reject_reason(reason).expect("must be a rejection"));
+        }
+        self.broadcasted.borrow_mut().push(tx.txid());
+        Ok(())
+    }
+}
+
 #[test]
-#[should_panic(expected = "IrreplaceableTransaction")]
-fn test_bump_fee_irreplaceable_tx() {
+fn test_broadcast_inserts_accepted_tx() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = wallet.get_address(New);
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), 25_000);
-    let psbt = builder.finish().unwrap();
-
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+
+    let rejecting = TestBroadcaster {
+        reject_reason: Some("min relay fee not met, 100 < 141"),
+        ..Default::default()
+    };
+    assert_matches!(
+        wallet.broadcast(&rejecting, tx.clone(), 1_000),
+        Err(BroadcastError::InsufficientFee(_))
+    );
+    assert!(wallet.get_tx(txid).is_none());
+
+    let broadcaster = TestBroadcaster::default();
+    wallet.broadcast(&broadcaster, tx, 1_000).unwrap();
+    assert_eq!(*broadcaster.broadcasted.borrow(), vec![txid]);
+    assert_eq!(
+        wallet.get_tx(txid).unwrap().chain_position,
+        ChainPosition::Unconfirmed(1_000)
+    );
+}
+
+#[test]
+#[should_panic(expected = "IrreplaceableTransaction")]
+fn test_bump_fee_irreplaceable_tx() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    let psbt = builder.finish().unwrap();
+
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "TransactionConfirmed")]
+fn test_bump_fee_confirmed_tx() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    let psbt = builder.finish().unwrap();
+
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+
+    wallet
+        .insert_tx(
+            tx,
+            ConfirmationTime::Confirmed {
+                height: 42,
+                time: 42_000,
+            },
+        )
+        .unwrap();
+
+    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "FeeRateTooLow")]
+fn test_bump_fee_low_fee_rate() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(1.0));
+    builder.finish().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "FeeTooLow")]
+fn test_bump_fee_low_abs() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(10);
+    builder.finish().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "FeeTooLow")]
+fn test_bump_fee_zero_abs() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(0);
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_reduce_change() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let original_fee = check_fee!(wallet, psbt);
+
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(2.5)).enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert_eq!(
+        sent_received.1 + fee.unwrap_or(0),
+        original_sent_received.1 + original_fee.unwrap_or(0)
+    );
+    assert!(fee.unwrap_or(0) > original_fee.unwrap_or(0));
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        25_000
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(200);
+    builder.enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert_eq!(
+        sent_received.1 + fee.unwrap_or(0),
+        original_sent_received.1 + original_fee.unwrap_or(0)
+    );
+    assert!(
+        fee.unwrap_or(0) > original_fee.unwrap_or(0),
+        "{} > {}",
+        fee.unwrap_or(0),
+        original_fee.unwrap_or(0)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        25_000
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(0), 200);
+}
+
+#[test]
+fn test_bump_fee_reduce_single_recipient() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.clone().extract_tx();
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let original_fee = check_fee!(wallet, psbt);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_rate(FeeRate::from_sat_per_vb(2.5))
+        .allow_shrinking(addr.script_pubkey())
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert!(fee.unwrap_or(0) > original_fee.unwrap_or(0));
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(tx.output[0].value + fee.unwrap_or(0), sent_received.0);
+
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_reduce_single_recipient() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_fee = check_fee!(wallet, psbt);
+    let tx = psbt.extract_tx();
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .allow_shrinking(addr.script_pubkey())
+        .unwrap()
+        .fee_absolute(300);
+    let psbt = builder.finish().unwrap();
+    let tx = &psbt.unsigned_tx;
+    let sent_received = wallet.sent_and_received(tx);
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert!(fee.unwrap_or(0) > original_fee.unwrap_or(0));
+
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(tx.output[0].value + fee.unwrap_or(0), sent_received.0);
+
+    assert_eq!(fee.unwrap_or(0), 300);
+}
+
+#[test]
+fn test_bump_fee_drain_wallet() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    // receive an extra tx so that our wallet has two utxos.
+    let tx = Transaction {
+        version: 1,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            value: 25_000,
+            script_pubkey: wallet.get_address(New).script_pubkey(),
+        }],
+    };
+    wallet
+        .insert_tx(
+            tx.clone(),
+            ConfirmationTime::Confirmed {
+                height: wallet.latest_checkpoint().height(),
+                time: 42_000,
+            },
+        )
+        .unwrap();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(OutPoint {
+            txid: tx.txid(),
+            vout: 0,
+        })
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx();
+    let original_sent_received = wallet.sent_and_received(&tx);
+
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(original_sent_received.0, 25_000);
+
+    // for the new feerate, it should be enough to reduce the output, but since we specify
+    // `drain_wallet` we expect to spend everything
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .drain_wallet()
+        .allow_shrinking(addr.script_pubkey())
+        .unwrap()
+        .fee_rate(FeeRate::from_sat_per_vb(5.0));
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.extract_tx());
+
+    assert_eq!(sent_received.0, 75_000);
+}
+
+#[test]
+#[should_panic(expected = "InsufficientFunds")]
+fn test_bump_fee_remove_output_manually_selected_only() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
+    // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
+    // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
+    // existing output. In other words, bump_fee + manually_selected_only is always an error
+    // unless you've also set "allow_shrinking" OR there is a change output.
+    let init_tx = Transaction {
+        version: 1,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet.get_address(New).script_pubkey(),
+            value: 25_000,
+        }],
+    };
+    wallet
+        .insert_tx(
+            init_tx.clone(),
+            wallet
+                .transactions()
+                .last()
+                .unwrap()
+                .chain_position
+                .cloned()
+                .into(),
+        )
+        .unwrap();
+    let outpoint = OutPoint {
+        txid: init_tx.txid(),
+        vout: 0,
+    };
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(outpoint)
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx();
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(original_sent_received.0, 25_000);
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .manually_selected_only()
+        .fee_rate(FeeRate::from_sat_per_vb(255.0));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_add_input() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let init_tx = Transaction {
+        version: 1,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet.get_address(New).script_pubkey(),
+            value: 25_000,
+        }],
+    };
+    let pos = wallet
+        .transactions()
+        .last()
+        .unwrap()
+        .chain_position
+        .cloned()
+        .into();
+    wallet.insert_tx(init_tx, pos).unwrap();
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), 45_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx();
+    let original_details = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+    assert_eq!(sent_received.0, original_details.0 + 25_000);
+    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        45_000
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_add_input() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), 45_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx();
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(6_000);
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
+    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        45_000
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(0), 6_000);
+}
+
+#[test]
+fn test_bump_fee_no_change_add_input_and_change() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    // initially make a tx without change by using `drain_to`
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(op)
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let original_fee = check_fee!(wallet, psbt);
+
+    let tx = psbt.extract_tx();
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // now bump the fees without using `allow_shrinking`. the wallet should add an
+    // extra input and a change output, and leave the original output untouched
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    let original_send_all_amount = original_sent_received.0 - original_fee.unwrap_or(0);
+    assert_eq!(sent_received.0, original_sent_received.0 + 50_000);
+    assert_eq!(
+        sent_received.1,
+        75_000 - original_send_all_amount - fee.unwrap_or(0)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        original_send_all_amount
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        75_000 - original_send_all_amount - fee.unwrap_or(0)
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_add_input_change_dust() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), 45_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let original_fee = check_fee!(wallet, psbt);
+
+    let mut tx = psbt.extract_tx();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // to get realistic weight
+    }
+    let original_tx_weight = tx.weight();
+    assert_eq!(tx.input.len(), 1);
+    assert_eq!(tx.output.len(), 2);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    // We set a fee high enough that during rbf we are forced to add
+    // a new input and also that we have to remove the change
+    // that we had previously
+
+    // We calculate the new weight as:
+    //   original weight
+    // + extra input weight: 160 WU = (32 (prevout) + 4 (vout) + 4 (nsequence)) * 4
+    // + input satisfaction weight: 112 WU = 106 (witness) + 2 (witness len) + (1 (script len)) * 4
+    // - change output weight: 124 WU = (8 (value) + 1 (script len) + 22 (script)) * 4
+    let new_tx_weight =
+        original_tx_weight + Weight::from_wu(160) + Weight::from_wu(112) - Weight::from_wu(124);
+    // two inputs (50k, 25k) and one output (45k) - epsilon
+    // We use epsilon here to avoid asking for a slightly too high feerate
+    let fee_abs = 50_000 + 25_000 - 45_000 - 10;
+    builder.fee_rate(FeeRate::from_wu(fee_abs, new_tx_weight));
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(original_sent_received.1, 5_000 - original_fee.unwrap_or(0));
+
+    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
+    assert_eq!(fee.unwrap_or(0), 30_000);
+    assert_eq!(sent_received.1, 0);
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        45_000
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(140.0), @dust_change, @add_signature);
+}
+
+#[test]
+fn test_bump_fee_force_add_input() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), 45_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx();
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    // the new fee_rate is low enough that just reducing the change would be fine, but we force
+    // the addition of an extra input with `add_utxo()`
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .add_utxo(incoming_op)
+        .unwrap()
+        .fee_rate(FeeRate::from_sat_per_vb(5.0));
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
+    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        45_000
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_force_add_input() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), 45_000)
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx();
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    // skip saving the new utxos, we know they can't be used anyways
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // the new fee_rate is low enough that just reducing the change would be fine, but we force
+    // the addition of an extra input with `add_utxo()`
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.add_utxo(incoming_op).unwrap().fee_absolute(250);
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
+    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        45_000
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(0), 250);
+}
+
+#[test]
+#[should_panic(expected = "InsufficientFunds")]
+fn test_bump_fee_unconfirmed_inputs_only() {
+    // We try to bump the fee, but:
+    // - We can't reduce the change, as we have no change
+    // - All our UTXOs are unconfirmed
+    // So, we fail with "InsufficientFunds", as per RBF rule 2:
+    // The replacement transaction may only include an unconfirmed input
+    // if that input was included in one of the original transactions.
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(addr.script_pubkey())
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    // Now we receive one transaction with 0 confirmations. We won't be able to use that for
+    // fee bumping, as it's still unconfirmed!
+    receive_output(
+        &mut wallet,
+        25_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
+    );
+    let mut tx = psbt.extract_tx();
+    let txid = tx.txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(25.0));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_unconfirmed_input() {
+    // We create a tx draining the wallet and spending one confirmed
+    // and one unconfirmed UTXO. We check that we can fee bump normally
+    // (BIP125 rule 2 only apply to newly added unconfirmed input, you can
+    // always fee bump with an unconfirmed input if it was included in the
+    // original transaction)
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    // We receive a tx with 0 confirmations, which will be used as an input
+    // in the drain tx.
+    receive_output(&mut wallet, 25_000, ConfirmationTime::unconfirmed(0));
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(addr.script_pubkey())
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx();
+    let txid = tx.txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_rate(FeeRate::from_sat_per_vb(15.0))
+        .allow_shrinking(addr.script_pubkey())
+        .unwrap();
+    builder.finish().unwrap();
+}
+
+/// Send 25_000 sats to an external address at 1 sat/vB and insert the transaction as unconfirmed.
+fn send_unconfirmed(wallet: &mut Wallet) -> Transaction {
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .fee_rate(FeeRate::from_sat_per_vb(1.0));
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
     let tx = psbt.extract_tx();
-    let txid = tx.txid();
     wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
-    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    tx
+}
+
+/// Build and sign a child paying for `parent_txid` at `fee_rate`, returns it with its fee.
+fn build_cpfp(wallet: &mut Wallet, parent_txid: Txid, fee_rate: FeeRate) -> (Transaction, u64) {
+    let mut builder = wallet.build_cpfp(parent_txid).unwrap();
+    builder.fee_rate(fee_rate);
+    let mut psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    (psbt.extract_tx(), fee)
 }
 
 #[test]
-#[should_panic(expected = "TransactionConfirmed")]
-fn test_bump_fee_confirmed_tx() {
+fn test_cpfp_package_reaches_fee_rate() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), 25_000);
-    let psbt = builder.finish().unwrap();
+    let parent = send_unconfirmed(&mut wallet);
+    let parent_fee = wallet.calculate_fee(&parent).unwrap();
 
-    let tx = psbt.extract_tx();
-    let txid = tx.txid();
+    let fee_rate = FeeRate::from_sat_per_vb(10.0);
+    let (child, child_fee) = build_cpfp(&mut wallet, parent.txid(), fee_rate);
+    // the child drains our output of the parent to a change address
+    assert_eq!(child.input.len(), 1);
+    assert_eq!(child.input[0].previous_output.txid, parent.txid());
+    assert_eq!(child.output.len(), 1);
+    assert!(wallet.is_mine(&child.output[0].script_pubkey));
+
+    // the child alone pays more than the target, the package reaches it
+    assert!(FeeRate::from_wu(child_fee, child.weight()) > FeeRate::from_sat_per_vb(15.0));
+    let package_fee_rate =
+        FeeRate::from_wu(parent_fee + child_fee, parent.weight() + child.weight());
+    assert!(package_fee_rate >= fee_rate);
+    assert!(package_fee_rate < FeeRate::from_sat_per_vb(10.1));
+}
 
+#[test]
+fn test_cpfp_pays_for_unconfirmed_ancestors() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let grandparent = send_unconfirmed(&mut wallet);
+    let grandparent_fee = wallet.calculate_fee(&grandparent).unwrap();
+    let (parent, parent_fee) = build_cpfp(
+        &mut wallet,
+        grandparent.txid(),
+        FeeRate::from_sat_per_vb(1.0),
+    );
     wallet
         .insert_tx(
-            tx,
-            ConfirmationTime::Confirmed {
-                height: 42,
-                time: 42_000,
-            },
+            parent.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
         )
         .unwrap();
 
-    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    // the grandparent's output is spent by the parent now
+    assert_matches!(
+        wallet.build_cpfp(grandparent.txid()),
+        Err(BuildCpfpError::NoSpendableOutput(txid)) if txid == grandparent.txid()
+    );
+
+    let fee_rate = FeeRate::from_sat_per_vb(5.0);
+    let (child, child_fee) = build_cpfp(&mut wallet, parent.txid(), fee_rate);
+    let package_fee_rate = FeeRate::from_wu(
+        grandparent_fee + parent_fee + child_fee,
+        grandparent.weight() + parent.weight() + child.weight(),
+    );
+    assert!(package_fee_rate >= fee_rate);
+    assert!(package_fee_rate < FeeRate::from_sat_per_vb(5.1));
 }
 
 #[test]
-#[should_panic(expected = "FeeRateTooLow")]
-fn test_bump_fee_low_fee_rate() {
+fn test_cpfp_received_parent_with_foreign_prevouts() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), 25_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-
-    let tx = psbt.extract_tx();
-    let txid = tx.txid();
-
+    let foreign_prevout = OutPoint::new(
+        Txid::from_str("0c1a3ebd3f0cc8d8fa8e8b1e1e0c9be1b0a8f1a6e0a5b6c9c4c3d2e1f0a9b8c7").unwrap(),
+        0,
+    );
+    let parent = Transaction {
+        version: 2,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: foreign_prevout,
+            witness: bitcoin::Witness::from_slice(&[[0x00; P2WPKH_FAKE_WITNESS_SIZE]]),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: wallet.get_address(New).script_pubkey(),
+            value: 50_000,
+        }],
+    };
     wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .insert_tx(
+            parent.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
         .unwrap();
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb(1.0));
-    builder.finish().unwrap();
+    assert_matches!(
+        wallet.build_cpfp(parent.txid()),
+        Err(BuildCpfpError::MissingPrevouts(txid)) if txid == parent.txid()
+    );
+
+    wallet.insert_txout(
+        foreign_prevout,
+        TxOut {
+            script_pubkey: ScriptBuf::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()),
+            value: 50_200,
+        },
+    );
+    let fee_rate = FeeRate::from_sat_per_vb(10.0);
+    let (child, child_fee) = build_cpfp(&mut wallet, parent.txid(), fee_rate);
+    let package_fee_rate = FeeRate::from_wu(200 + child_fee, parent.weight() + child.weight());
+    assert!(package_fee_rate >= fee_rate);
+    assert!(package_fee_rate < FeeRate::from_sat_per_vb(10.1));
 }
 
 #[test]
-#[should_panic(expected = "FeeTooLow")]
-fn test_bump_fee_low_abs() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), 25_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-
-    let tx = psbt.extract_tx();
-    let txid = tx.txid();
+fn test_cpfp_reveals_change_address_in_finish() {
+    let (mut wallet, _) =
+        get_funded_wallet_with_change(get_test_wpkh(), Some(get_test_tr_single_sig_xprv()));
+    let parent = send_unconfirmed(&mut wallet);
+    let change_index = wallet.derivation_index(KeychainKind::Internal);
+    let staged = wallet.staged().clone();
 
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+    // a builder which is dropped doesn't reveal an address
+    let builder = wallet.build_cpfp(parent.txid()).unwrap();
+    drop(builder);
+    assert_eq!(
+        wallet.derivation_index(KeychainKind::Internal),
+        change_index
+    );
+    assert_eq!(wallet.staged(), &staged);
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(10);
-    builder.finish().unwrap();
+    let mut builder = wallet.build_cpfp(parent.txid()).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(5.0));
+    let child = builder.finish().unwrap().unsigned_tx;
+    assert_eq!(
+        wallet.derivation_index(KeychainKind::Internal),
+        Some(change_index.map_or(0, |index| index + 1))
+    );
+    assert!(wallet.is_mine(&child.output[0].script_pubkey));
 }
 
 #[test]
-#[should_panic(expected = "FeeTooLow")]
-fn test_bump_fee_zero_abs() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = wallet.get_address(New);
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), 25_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
+fn test_cpfp_confirmed_parent() {
+    let (mut wallet, txid) = get_funded_wallet(get_test_wpkh());
+    assert_matches!(
+        wallet.build_cpfp(txid),
+        Err(BuildCpfpError::TransactionConfirmed(_))
+    );
+    assert_matches!(
+        wallet.build_cpfp(Txid::all_zeros()),
+        Err(BuildCpfpError::TransactionNotFound(_))
+    );
+}
 
-    let tx = psbt.extract_tx();
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+/// Build and sign the next batch at `fee_rate`.
+fn build_batch<D: bdk_chain::PersistBackend<bdk::wallet::ChangeSet>>(
+    wallet: &mut Wallet<D>,
+    fee_rate: FeeRate,
+) -> Transaction {
+    let mut builder = wallet.build_batch().unwrap();
+    builder.fee_rate(fee_rate);
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    psbt.extract_tx()
+}
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(0);
-    builder.finish().unwrap();
+fn pays(tx: &Transaction, script_pubkey: &ScriptBuf, amount: u64) -> bool {
+    tx.output
+        .iter()
+        .any(|txout| &txout.script_pubkey == script_pubkey && txout.value == amount)
 }
 
 #[test]
-fn test_bump_fee_reduce_change() {
+fn test_batch_replaces_unconfirmed_batch() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+    let addr1 = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), 25_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let original_sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let original_fee = check_fee!(wallet, psbt);
+        .assume_checked()
+        .script_pubkey();
+    let addr2 = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
 
-    let tx = psbt.extract_tx();
-    let txid = tx.txid();
+    let payment1 = wallet.queue_payment(addr1.clone(), 10_000);
+    let payment2 = wallet.queue_payment(addr2.clone(), 5_000);
+    assert_eq!(wallet.payment_status(payment1), Some(PaymentStatus::Queued));
+
+    let batch = build_batch(&mut wallet, FeeRate::from_sat_per_vb(1.0));
+    assert!(batch.is_explicitly_rbf());
+    assert!(pays(&batch, &addr1, 10_000));
+    assert!(pays(&batch, &addr2, 5_000));
+    assert_eq!(
+        wallet.payment_status(payment1),
+        Some(PaymentStatus::Batched)
+    );
     wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .insert_tx(
+            batch.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
         .unwrap();
+    for payment in [payment1, payment2] {
+        assert_eq!(
+            wallet.payment_status(payment),
+            Some(PaymentStatus::Broadcast(batch.txid()))
+        );
+    }
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb(2.5)).enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(sent_received.0, original_sent_received.0);
+    // the next batch replaces the unconfirmed one and pays all of the payments
+    let payment3 = wallet.queue_payment(addr1.clone(), 7_000);
+    let replacement = build_batch(&mut wallet, FeeRate::from_sat_per_vb(5.0));
+    let spent_by = |tx: &Transaction, outpoint: OutPoint| {
+        tx.input.iter().any(|txin| txin.previous_output == outpoint)
+    };
+    assert!(batch
+        .input
+        .iter()
+        .all(|txin| spent_by(&replacement, txin.previous_output)));
+    assert!(pays(&replacement, &addr1, 10_000));
+    assert!(pays(&replacement, &addr2, 5_000));
+    assert!(pays(&replacement, &addr1, 7_000));
+    // the replaced batch is still the one in the mempool
     assert_eq!(
-        sent_received.1 + fee.unwrap_or(0),
-        original_sent_received.1 + original_fee.unwrap_or(0)
+        wallet.payment_status(payment1),
+        Some(PaymentStatus::Broadcast(batch.txid()))
+    );
+    assert_eq!(
+        wallet.payment_status(payment3),
+        Some(PaymentStatus::Batched)
     );
-    assert!(fee.unwrap_or(0) > original_fee.unwrap_or(0));
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 2);
+    // once the replacement confirms, the next batch is a new transaction
+    let height = wallet.latest_checkpoint().height();
+    wallet
+        .insert_tx(
+            replacement.clone(),
+            ConfirmationTime::Confirmed { height, time: 0 },
+        )
+        .unwrap();
+    for payment in [payment1, payment2, payment3] {
+        assert_eq!(
+            wallet.payment_status(payment),
+            Some(PaymentStatus::Confirmed(replacement.txid()))
+        );
+    }
+    let payment4 = wallet.queue_payment(addr2.clone(), 3_000);
+    let next = build_batch(&mut wallet, FeeRate::from_sat_per_vb(1.0));
+    assert!(batch
+        .input
+        .iter()
+        .all(|txin| !spent_by(&next, txin.previous_output)));
+    assert!(pays(&next, &addr2, 3_000));
+    assert!(!pays(&next, &addr1, 10_000));
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        25_000
+        wallet.payment_status(payment4),
+        Some(PaymentStatus::Batched)
     );
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
+        wallet.payment_status(payment1),
+        Some(PaymentStatus::Confirmed(replacement.txid()))
     );
+}
 
-    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
+#[test]
+fn test_batch_errors_and_cancel() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    assert_matches!(wallet.build_batch(), Err(BuildBatchError::NoQueuedPayments));
+    assert_eq!(wallet.cancel_batch(), None);
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(200);
-    builder.enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+    let payment1 = wallet.queue_payment(addr.clone(), 10_000);
+    // never inserted into the wallet
+    let batch = build_batch(&mut wallet, FeeRate::from_sat_per_vb(1.0));
+
+    let payment2 = wallet.queue_payment(addr.clone(), 5_000);
+    assert_matches!(wallet.build_batch(), Err(BuildBatchError::LastBatchUnknown));
+
+    let cancelled = wallet.cancel_batch().expect("must have a batch");
+    assert_eq!(cancelled.payments, vec![payment1]);
+    assert!(batch
+        .input
+        .iter()
+        .all(|txin| cancelled.inputs.contains(&txin.previous_output)));
+    assert_eq!(wallet.payment_status(payment1), Some(PaymentStatus::Queued));
+    let batch = build_batch(&mut wallet, FeeRate::from_sat_per_vb(1.0));
+    assert!(pays(&batch, &addr, 10_000));
+    assert!(pays(&batch, &addr, 5_000));
+    assert_eq!(
+        wallet.payment_status(payment2),
+        Some(PaymentStatus::Batched)
+    );
+}
 
-    assert_eq!(sent_received.0, original_sent_received.0);
+#[test]
+fn test_payment_queue_is_persisted() {
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.db");
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+
+    let (payment1, payment2, batch) = {
+        let db = bdk_file_store::Store::create_new(DB_MAGIC, &file_path).expect("must create db");
+        let mut wallet =
+            Wallet::new(get_test_wpkh(), None, db, Network::Testnet).expect("must init wallet");
+        let funding = Transaction {
+            version: 1,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                script_pubkey: wallet
+                    .try_get_address(LastUnused)
+                    .expect("must write")
+                    .script_pubkey(),
+                value: 50_000,
+            }],
+        };
+        wallet
+            .insert_tx(funding, ConfirmationTime::Unconfirmed { last_seen: 0 })
+            .unwrap();
+        let payment1 = wallet.queue_payment(addr.clone(), 10_000);
+        let batch = build_batch(&mut wallet, FeeRate::from_sat_per_vb(1.0));
+        let payment2 = wallet.queue_payment(addr.clone(), 5_000);
+        wallet.commit().expect("must commit");
+        (payment1, payment2, batch)
+    };
+
+    let db = bdk_file_store::Store::open(DB_MAGIC, &file_path).expect("must recover db");
+    let mut wallet = Wallet::load(get_test_wpkh(), None, db).expect("must recover wallet");
     assert_eq!(
-        sent_received.1 + fee.unwrap_or(0),
-        original_sent_received.1 + original_fee.unwrap_or(0)
+        wallet.payment_status(payment1),
+        Some(PaymentStatus::Batched)
     );
-    assert!(
-        fee.unwrap_or(0) > original_fee.unwrap_or(0),
-        "{} > {}",
-        fee.unwrap_or(0),
-        original_fee.unwrap_or(0)
+    assert_eq!(wallet.payment_status(payment2), Some(PaymentStatus::Queued));
+    wallet
+        .insert_tx(
+            batch.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
+    assert_eq!(
+        wallet.payment_status(payment1),
+        Some(PaymentStatus::Broadcast(batch.txid()))
     );
+    // the recovered batch is replaced by the next one
+    let replacement = build_batch(&mut wallet, FeeRate::from_sat_per_vb(5.0));
+    assert!(pays(&replacement, &addr, 10_000));
+    assert!(pays(&replacement, &addr, 5_000));
+}
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        25_000
+#[test]
+fn test_plan_consolidation() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let confirmed = ConfirmationTime::Confirmed {
+        height: 2_000,
+        time: 0,
+    };
+    let small: Vec<_> = (0..8)
+        .map(|i| receive_output(&mut wallet, 1_000 + i * 100, confirmed))
+        .collect();
+    // not worth spending at 2 sat/vB
+    let dust = receive_output(&mut wallet, 300, confirmed);
+    let unconfirmed = receive_output(
+        &mut wallet,
+        2_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
     );
+    let frozen = receive_output(&mut wallet, 5_000, confirmed);
+    assert!(wallet.freeze_utxo(frozen));
+
+    // room for 5 P2WPKH inputs per sweep
+    let consolidations = wallet
+        .plan_consolidation(
+            FeeRate::from_sat_per_vb(2.0),
+            FeeRate::from_sat_per_vb(50.0),
+            Weight::from_wu(1_600),
+        )
+        .unwrap();
+    assert_eq!(consolidations.len(), 2);
+
+    let inputs = |i: usize| -> Vec<OutPoint> {
+        consolidations[i]
+            .psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect()
+    };
+    // smallest first, the last sweep also gets the funding UTXO
+    assert_eq!(inputs(0).len(), 5);
+    assert!(small[..5]
+        .iter()
+        .all(|outpoint| inputs(0).contains(outpoint)));
+    assert_eq!(inputs(1).len(), 4);
+    assert!(small[5..]
+        .iter()
+        .all(|outpoint| inputs(1).contains(outpoint)));
+    for outpoint in [dust, unconfirmed, frozen] {
+        assert!(!inputs(0).contains(&outpoint) && !inputs(1).contains(&outpoint));
+    }
+
+    for consolidation in &consolidations {
+        let psbt = &consolidation.psbt;
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert!(wallet.is_mine(&psbt.unsigned_tx.output[0].script_pubkey));
+        assert!(psbt.unsigned_tx.is_explicitly_rbf());
+        assert_eq!(psbt.fee_amount(), Some(consolidation.fee));
+        assert!(psbt.unsigned_tx.weight() <= Weight::from_wu(1_600));
+        assert_fee_rate!(psbt, consolidation.fee, FeeRate::from_sat_per_vb(2.0), @add_signature);
+
+        // spending each input at 50 sat/vB, minus the sweep and spending its output later
+        let inputs = psbt.unsigned_tx.input.len() as u64;
+        assert_eq!(
+            consolidation.estimated_savings,
+            inputs * 68 * 50 - consolidation.fee - 68 * 50
+        );
+    }
+}
+
+#[test]
+fn test_plan_consolidation_change_addresses() {
+    let (mut wallet, _) =
+        get_funded_wallet_with_change(get_test_wpkh(), Some(get_test_tr_single_sig_xprv()));
+    let confirmed = ConfirmationTime::Confirmed {
+        height: 2_000,
+        time: 0,
+    };
+    for i in 0..8 {
+        receive_output(&mut wallet, 1_000 + i * 100, confirmed);
+    }
+    // revealed but still unused, the first sweep pays to it
+    let unused = wallet.get_internal_address(New);
+    assert_eq!(unused.index, 0);
+
+    let consolidations = wallet
+        .plan_consolidation(
+            FeeRate::from_sat_per_vb(2.0),
+            FeeRate::from_sat_per_vb(50.0),
+            Weight::from_wu(1_600),
+        )
+        .unwrap();
+    assert!(consolidations.len() >= 2);
+
+    let change_scripts = consolidations
+        .iter()
+        .map(|c| c.psbt.unsigned_tx.output[0].script_pubkey.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(change_scripts[0], unused.script_pubkey());
+    for (i, script) in change_scripts.iter().enumerate() {
+        assert_eq!(
+            wallet.spk_index().index_of_spk(script),
+            Some(&(KeychainKind::Internal, i as u32))
+        );
+    }
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
+        wallet.derivation_index(KeychainKind::Internal),
+        Some(consolidations.len() as u32 - 1)
     );
-
-    assert_eq!(fee.unwrap_or(0), 200);
+    // the next change address isn't one of the sweeps'
+    let next = wallet.get_internal_address(LastUnused);
+    assert!(!change_scripts.contains(&next.script_pubkey()));
 }
 
 #[test]
-fn test_bump_fee_reduce_single_recipient() {
+fn test_plan_consolidation_no_savings() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.clone().extract_tx();
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let original_fee = check_fee!(wallet, psbt);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+    for value in [10_000, 20_000, 30_000] {
+        receive_output_in_latest_block(&mut wallet, value);
+    }
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .fee_rate(FeeRate::from_sat_per_vb(2.5))
-        .allow_shrinking(addr.script_pubkey())
+    // spending the UTXOs later costs as much as now
+    let consolidations = wallet
+        .plan_consolidation(
+            FeeRate::from_sat_per_vb(10.0),
+            FeeRate::from_sat_per_vb(10.0),
+            Weight::from_wu(400_000),
+        )
         .unwrap();
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert!(fee.unwrap_or(0) > original_fee.unwrap_or(0));
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 1);
-    assert_eq!(tx.output[0].value + fee.unwrap_or(0), sent_received.0);
+    assert!(consolidations.is_empty());
 
-    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
+    let consolidations = wallet
+        .plan_consolidation(
+            FeeRate::from_sat_per_vb(1.0),
+            FeeRate::from_sat_per_vb(10.0),
+            Weight::from_wu(400_000),
+        )
+        .unwrap();
+    assert_eq!(consolidations.len(), 1);
+    assert_eq!(consolidations[0].psbt.unsigned_tx.input.len(), 4);
 }
 
 #[test]
-fn test_bump_fee_absolute_reduce_single_recipient() {
+fn test_apply_update_events() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let original_fee = check_fee!(wallet, psbt);
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .enable_rbf()
+        .fee_rate(FeeRate::from_sat_per_vb(1.0));
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
     let tx = psbt.extract_tx();
-    let original_sent_received = wallet.sent_and_received(&tx);
     let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+    let balance_changed = |events: &[WalletEvent]| matches!(events.last(), Some(WalletEvent::BalanceChanged { old, new }) if old != new);
+
+    // received
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_tx(tx.clone());
+    let _ = graph.insert_seen_at(txid, 1);
+    let events = wallet
+        .apply_update(Update {
+            graph,
+            ..Default::default()
+        })
         .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        events[0],
+        WalletEvent::TxReceived {
+            txid,
+            chain_position: ChainPosition::Unconfirmed(1)
+        }
+    );
+    assert!(balance_changed(&events));
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .allow_shrinking(addr.script_pubkey())
-        .unwrap()
-        .fee_absolute(300);
-    let psbt = builder.finish().unwrap();
-    let tx = &psbt.unsigned_tx;
-    let sent_received = wallet.sent_and_received(tx);
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert!(fee.unwrap_or(0) > original_fee.unwrap_or(0));
-
-    assert_eq!(tx.output.len(), 1);
-    assert_eq!(tx.output[0].value + fee.unwrap_or(0), sent_received.0);
-
-    assert_eq!(fee.unwrap_or(0), 300);
-}
+    // nothing new
+    assert_eq!(wallet.apply_update(Update::default()).unwrap(), vec![]);
 
-#[test]
-fn test_bump_fee_drain_wallet() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    // receive an extra tx so that our wallet has two utxos.
-    let tx = Transaction {
-        version: 1,
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![],
-        output: vec![TxOut {
-            value: 25_000,
-            script_pubkey: wallet.get_address(New).script_pubkey(),
-        }],
+    // confirmed
+    let tip = wallet.latest_checkpoint();
+    let block = BlockId {
+        height: tip.height() + 1,
+        hash: BlockHash::hash(b"block"),
     };
-    wallet
-        .insert_tx(
-            tx.clone(),
-            ConfirmationTime::Confirmed {
-                height: wallet.latest_checkpoint().height(),
-                time: 42_000,
-            },
-        )
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_anchor(
+        txid,
+        ConfirmationTimeHeightAnchor {
+            anchor_block: block,
+            confirmation_height: block.height,
+            confirmation_time: 300,
+        },
+    );
+    let events = wallet
+        .apply_update(Update {
+            graph,
+            chain: Some(tip.clone().push(block).unwrap().into_update(false)),
+            ..Default::default()
+        })
         .unwrap();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+    assert_eq!(
+        events[0],
+        WalletEvent::TxConfirmed {
+            txid,
+            height: block.height,
+            time: 300,
+        }
+    );
+    assert!(balance_changed(&events));
 
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .add_utxo(OutPoint {
-            txid: tx.txid(),
-            vout: 0,
+    // reorged to another block at the same height
+    let reorg_block = BlockId {
+        height: block.height,
+        hash: BlockHash::hash(b"reorg"),
+    };
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_anchor(
+        txid,
+        ConfirmationTimeHeightAnchor {
+            anchor_block: reorg_block,
+            confirmation_height: reorg_block.height,
+            confirmation_time: 300,
+        },
+    );
+    let events = wallet
+        .apply_update(Update {
+            graph,
+            chain: Some(tip.clone().push(reorg_block).unwrap().into_update(false)),
+            ..Default::default()
         })
-        .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx();
-    let original_sent_received = wallet.sent_and_received(&tx);
+        .unwrap();
+    assert_eq!(
+        events,
+        vec![WalletEvent::TxConfirmed {
+            txid,
+            height: reorg_block.height,
+            time: 300,
+        }]
+    );
 
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+    // reorged out
+    let second_reorg_block = BlockId {
+        height: block.height,
+        hash: BlockHash::hash(b"second reorg"),
+    };
+    let events = wallet
+        .apply_update(Update {
+            chain: Some(tip.push(second_reorg_block).unwrap().into_update(false)),
+            ..Default::default()
+        })
         .unwrap();
-    assert_eq!(original_sent_received.0, 25_000);
+    assert_eq!(events[0], WalletEvent::TxDropped { txid });
+    assert!(balance_changed(&events));
 
-    // for the new feerate, it should be enough to reduce the output, but since we specify
-    // `drain_wallet` we expect to spend everything
+    // replaced
     let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .drain_wallet()
-        .allow_shrinking(addr.script_pubkey())
-        .unwrap()
-        .fee_rate(FeeRate::from_sat_per_vb(5.0));
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.extract_tx());
-
-    assert_eq!(sent_received.0, 75_000);
+    builder.fee_rate(FeeRate::from_sat_per_vb(5.0));
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let replacement = psbt.extract_tx();
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_tx(replacement.clone());
+    let _ = graph.insert_seen_at(replacement.txid(), 2);
+    let events = wallet
+        .apply_update(Update {
+            graph,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(events.len(), 3);
+    assert!(events.contains(&WalletEvent::TxReceived {
+        txid: replacement.txid(),
+        chain_position: ChainPosition::Unconfirmed(2)
+    }));
+    assert!(events.contains(&WalletEvent::TxReplaced {
+        txid,
+        replaced_by: vec![replacement.txid()]
+    }));
+    assert!(balance_changed(&events));
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_bump_fee_remove_output_manually_selected_only() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
-    // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
-    // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
-    // existing output. In other words, bump_fee + manually_selected_only is always an error
-    // unless you've also set "allow_shrinking" OR there is a change output.
-    let init_tx = Transaction {
-        version: 1,
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet.get_address(New).script_pubkey(),
-            value: 25_000,
-        }],
-    };
-    wallet
-        .insert_tx(
-            init_tx.clone(),
-            wallet
-                .transactions()
-                .last()
-                .unwrap()
-                .chain_position
-                .cloned()
-                .into(),
-        )
-        .unwrap();
-    let outpoint = OutPoint {
-        txid: init_tx.txid(),
-        vout: 0,
-    };
+fn test_history() {
+    let (mut wallet, txid1) = get_funded_wallet(get_test_wpkh());
+    let txid0 = wallet.get_tx(txid1).unwrap().tx_node.input[0]
+        .previous_output
+        .txid;
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .add_utxo(outpoint)
-        .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx();
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .enable_rbf()
+        .fee_rate(FeeRate::from_sat_per_vb(1.0));
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let tx2 = psbt.extract_tx();
+    let txid2 = tx2.txid();
     wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .insert_tx(
+            tx2.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 300 },
+        )
         .unwrap();
-    assert_eq!(original_sent_received.0, 25_000);
+    let txids = |history: Vec<TxSummary>| history.iter().map(|s| s.txid).collect::<Vec<_>>();
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .manually_selected_only()
-        .fee_rate(FeeRate::from_sat_per_vb(255.0));
-    builder.finish().unwrap();
-}
+    let history = wallet.history(&HistoryQuery::default());
+    assert_eq!(txids(history.clone()), vec![txid2, txid1, txid0]);
 
-#[test]
-fn test_bump_fee_add_input() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let init_tx = Transaction {
-        version: 1,
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet.get_address(New).script_pubkey(),
-            value: 25_000,
-        }],
+    let fee2 = wallet.calculate_fee(&tx2).unwrap();
+    assert_eq!(history[0].sent, 50_000);
+    assert_eq!(history[0].net_value, -25_000 - fee2 as i64);
+    assert_eq!(history[0].fee, Some(fee2));
+    assert_eq!(
+        history[0].fee_rate,
+        Some(FeeRate::from_wu(fee2, tx2.weight()))
+    );
+    assert_eq!(
+        history[0].confirmation_time,
+        ConfirmationTime::Unconfirmed { last_seen: 300 }
+    );
+    assert_eq!(history[0].confirmations, 0);
+    assert_eq!(
+        history[0].keychains.iter().collect::<Vec<_>>(),
+        vec![&KeychainKind::External]
+    );
+    assert_eq!(history[1].net_value, -26_000);
+    assert_eq!(history[1].fee, Some(1_000));
+    assert_eq!(history[1].confirmations, 1);
+    assert_eq!(history[2].net_value, 76_000);
+    assert_eq!(history[2].fee, None);
+    assert_eq!(history[2].confirmations, 1_001);
+
+    // filters and pagination
+    let query = HistoryQuery {
+        heights: Some(0..=1_500),
+        ..Default::default()
     };
-    let pos = wallet
-        .transactions()
-        .last()
-        .unwrap()
-        .chain_position
-        .cloned()
-        .into();
-    wallet.insert_tx(init_tx, pos).unwrap();
+    assert_eq!(txids(wallet.history(&query)), vec![txid0]);
+    let query = HistoryQuery {
+        times: Some(150..=300),
+        order: HistoryOrder::OldestFirst,
+        ..Default::default()
+    };
+    assert_eq!(txids(wallet.history(&query)), vec![txid1, txid2]);
+    let query = HistoryQuery {
+        order: HistoryOrder::OldestFirst,
+        offset: 1,
+        limit: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(txids(wallet.history(&query)), vec![txid1]);
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), 45_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx();
-    let original_details = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
+    // replacements
+    let mut builder = wallet.build_fee_bump(txid2).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(5.0));
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let tx3 = psbt.extract_tx();
+    let txid3 = tx3.txid();
     wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .insert_tx(tx3, ConfirmationTime::Unconfirmed { last_seen: 400 })
         .unwrap();
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
-    assert_eq!(sent_received.0, original_details.0 + 25_000);
-    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+    let history = wallet.history(&HistoryQuery::default());
+    assert_eq!(txids(history.clone()), vec![txid3, txid1, txid0]);
+    assert_eq!(history[0].replaces, vec![txid2]);
+    assert!(history[0].replaced_by.is_empty());
+    let query = HistoryQuery {
+        include_replaced: true,
+        ..Default::default()
+    };
+    let history = wallet.history(&query);
+    assert_eq!(txids(history.clone()), vec![txid3, txid2, txid1, txid0]);
+    assert_eq!(history[1].replaced_by, vec![txid3]);
+}
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        45_000
+#[test]
+fn test_labels() {
+    let (mut wallet, txid) = get_funded_wallet(get_test_wpkh());
+    let outpoint = OutPoint { txid, vout: 0 };
+
+    let jsonl = format!(
+        "{{\"type\":\"tx\",\"ref\":\"{}\",\"label\":\"salary\"}}\n\
+         {{\"type\":\"output\",\"ref\":\"{}\",\"label\":\"cold\",\"spendable\":false}}\n\
+         {{\"type\":\"addr\",\"ref\":\"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c\",\"label\":\"mainnet\"}}\n\
+         {{\"type\":\"unknown\",\"ref\":\"?\",\"label\":\"skipped\"}}\n",
+        txid, outpoint
     );
+    let import = wallet.import_labels(&jsonl).unwrap();
+    assert_eq!(import.imported, 2);
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
+        import.skipped,
+        vec![SkippedLabel {
+            line: 3,
+            reference: "bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c".into(),
+        }]
     );
-
-    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+    assert!(!wallet.staged().labels.0.is_empty());
+
+    // the output is not spendable, so it is frozen
+    let label = wallet.label(&LabelRef::Output(outpoint)).unwrap();
+    assert_eq!(label.text, "cold");
+    assert!(wallet.is_frozen(outpoint));
+    assert_eq!(wallet.staged().frozen.get(&outpoint), Some(&true));
+    let history = wallet.history(&HistoryQuery::default());
+    assert_eq!(history[0].txid, txid);
+    assert_eq!(history[0].label, Some(Label::from("salary")));
+    assert_eq!(history[1].label, None);
+
+    // nothing is imported when a record is invalid
+    assert_matches!(
+        wallet.import_labels("{\"type\":\"tx\",\"ref\":\"x\",\"label\":\"a\"}\n{\"type\":\"tx\"}"),
+        Err(ImportLabelsError::InvalidRef { line: 1, .. })
+    );
+    assert_eq!(wallet.labels().count(), 2);
+
+    let exported = wallet.export_labels();
+    assert_eq!(exported.lines().count(), 2);
+    let (mut other, _) = get_funded_wallet(get_test_wpkh());
+    assert_eq!(other.import_labels(&exported).unwrap().imported, 2);
+    assert!(other.is_frozen(outpoint));
+    assert_eq!(other.export_labels(), exported);
+
+    // unfreezing the output makes it spendable in the export
+    assert!(other.unfreeze_utxo(outpoint));
+    assert!(!other.export_labels().contains("spendable"));
+    let reimport = format!(
+        "{{\"type\":\"output\",\"ref\":\"{}\",\"spendable\":true}}",
+        outpoint
+    );
+    assert_eq!(wallet.import_labels(&reimport).unwrap().imported, 0);
+    assert!(!wallet.is_frozen(outpoint));
 }
 
 #[test]
-fn test_bump_fee_absolute_add_input() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_frozen_utxos_are_not_spent() {
+    let (mut wallet, txid) = get_funded_wallet(get_test_wpkh());
+    let outpoint = OutPoint { txid, vout: 0 };
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+
+    assert!(wallet.freeze_utxo(outpoint));
+    assert!(wallet.is_frozen(outpoint));
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
+    let mut builder = wallet.build_tx();
+    builder.drain_wallet().drain_to(addr.script_pubkey());
+    assert!(builder.finish().is_err());
+
+    // manual selection overrides freezing
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), 45_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx();
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .add_utxo(outpoint)
         .unwrap();
+    assert!(builder.finish().is_ok());
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(6_000);
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
+    assert!(wallet.unfreeze_utxo(outpoint));
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    assert!(builder.finish().is_ok());
+}
 
-    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
-    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+#[test]
+fn test_frozen_utxo_is_unfrozen_once_spent() {
+    let (mut wallet, txid) = get_funded_wallet(get_test_wpkh());
+    let outpoint = OutPoint { txid, vout: 0 };
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        45_000
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
+    assert!(wallet.freeze_utxo(outpoint));
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), 25_000)
+        .add_utxo(outpoint)
+        .unwrap();
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let tx = psbt.extract_tx();
 
-    assert_eq!(fee.unwrap_or(0), 6_000);
+    // an unconfirmed spend may still be replaced
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert!(wallet.is_frozen(outpoint));
+
+    let height = wallet.latest_checkpoint().height();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Confirmed { height, time: 0 })
+        .unwrap();
+    assert!(!wallet.is_frozen(outpoint));
+    assert_eq!(wallet.frozen_utxos().count(), 0);
+    assert_eq!(wallet.staged().frozen.get(&outpoint), Some(&false));
 }
 
 #[test]
-fn test_bump_fee_no_change_add_input_and_change() {
+fn test_bump_fee_does_not_add_frozen_utxo() {
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let op = receive_output_in_latest_block(&mut wallet, 25_000);
+    let frozen = receive_output_in_latest_block(&mut wallet, 25_000);
+    wallet.freeze_utxo(frozen);
 
-    // initially make a tx without change by using `drain_to`
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .add_utxo(op)
-        .unwrap()
-        .manually_selected_only()
+        .add_recipient(addr.script_pubkey(), 45_000)
         .enable_rbf();
     let psbt = builder.finish().unwrap();
-    let original_sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let original_fee = check_fee!(wallet, psbt);
-
     let tx = psbt.extract_tx();
+    assert_eq!(tx.input.len(), 1);
     let txid = tx.txid();
     wallet
         .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
 
-    // now bump the fees without using `allow_shrinking`. the wallet should add an
-    // extra input and a change output, and leave the original output untouched
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
+
+    wallet.unfreeze_utxo(frozen);
     let mut builder = wallet.build_fee_bump(txid).unwrap();
     builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
     let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
+    assert!(psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .any(|txin| txin.previous_output == frozen));
+}
 
-    let original_send_all_amount = original_sent_received.0 - original_fee.unwrap_or(0);
-    assert_eq!(sent_received.0, original_sent_received.0 + 50_000);
-    assert_eq!(
-        sent_received.1,
-        75_000 - original_send_all_amount - fee.unwrap_or(0)
+#[test]
+fn test_min_confirmations_per_keychain() {
+    let (mut wallet, txid) = get_funded_wallet(get_test_wpkh());
+    let unconfirmed = receive_output(
+        &mut wallet,
+        30_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
     );
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let drain = |wallet: &mut Wallet| {
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(addr.script_pubkey());
+        builder.finish().map(|psbt| {
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect::<Vec<_>>()
+        })
+    };
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
+    // the funding output has a single confirmation at the tip
+    assert_eq!(drain(&mut wallet).unwrap().len(), 2);
+    wallet.set_min_confirmations(KeychainKind::External, 1);
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        original_send_all_amount
+        drain(&mut wallet).unwrap(),
+        vec![OutPoint { txid, vout: 0 }]
     );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        75_000 - original_send_all_amount - fee.unwrap_or(0)
+    wallet.set_min_confirmations(KeychainKind::External, 2);
+    assert!(drain(&mut wallet).is_err());
+    // other keychains are not affected
+    wallet.set_min_confirmations(KeychainKind::Internal, 2);
+    assert_eq!(wallet.min_confirmations(KeychainKind::External), 2);
+
+    wallet.set_min_confirmations(KeychainKind::External, 0);
+    assert!(drain(&mut wallet).unwrap().contains(&unconfirmed));
+}
+
+fn payjoin_original(
+    sender: &mut Wallet,
+    receiver: &mut Wallet,
+) -> (psbt::PartiallySignedTransaction, PayjoinParams) {
+    let receiver_address = receiver.get_address(New);
+    let third_party = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
+    let mut builder = sender.build_tx();
+    builder
+        .add_recipient(receiver_address.script_pubkey(), 20_000)
+        .add_recipient(third_party.script_pubkey(), 5_000)
+        .ordering(bdk::wallet::tx_builder::TxOrdering::Untouched)
+        .fee_rate(FeeRate::from_sat_per_vb(2.0));
+    let mut original = builder.finish().unwrap();
+    let mut not_paid = original.clone();
+    assert_matches!(
+        sender.payjoin_original(&mut not_paid, &ScriptBuf::new(), 0),
+        Err(PayjoinError::NoPaymentToPayee)
     );
+    let params = sender
+        .payjoin_original(&mut original, &receiver_address.script_pubkey(), 1_000)
+        .unwrap();
+    assert_eq!(params.payee_output, 0);
+    (original, params)
+}
 
-    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+/// Sign the receiver's inputs of a proposal again after changing it.
+fn resign_proposal(
+    receiver: &Wallet,
+    original: &psbt::PartiallySignedTransaction,
+    proposal: &mut psbt::PartiallySignedTransaction,
+) {
+    let original_utxos = original
+        .unsigned_tx
+        .input
+        .iter()
+        .enumerate()
+        .map(|(n, txin)| (txin.previous_output, original.get_utxo_for(n).unwrap()))
+        .collect::<std::collections::HashMap<_, _>>();
+    for (txin, psbt_input) in proposal.unsigned_tx.input.iter().zip(&mut proposal.inputs) {
+        *psbt_input = match receiver.get_utxo(txin.previous_output) {
+            Some(utxo) => receiver.get_psbt_input(utxo, None, false).unwrap(),
+            None => psbt::Input {
+                witness_utxo: Some(original_utxos[&txin.previous_output].clone()),
+                ..Default::default()
+            },
+        };
+    }
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
+    receiver.sign(proposal, sign_options).unwrap();
+    // only the receiver's inputs are kept, finalized
+    for psbt_input in &mut proposal.inputs {
+        *psbt_input = match psbt_input.final_script_witness.take() {
+            Some(witness) => psbt::Input {
+                witness_utxo: psbt_input.witness_utxo.take(),
+                final_script_witness: Some(witness),
+                ..Default::default()
+            },
+            None => psbt::Input::default(),
+        };
+    }
 }
 
 #[test]
-fn test_bump_fee_add_input_change_dust() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    receive_output_in_latest_block(&mut wallet, 25_000);
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), 45_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let original_sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let original_fee = check_fee!(wallet, psbt);
-
-    let mut tx = psbt.extract_tx();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // to get realistic weight
+fn test_payjoin() {
+    let (mut sender, _) = get_funded_wallet(get_test_wpkh());
+    let (mut receiver, _) =
+        get_funded_wallet("wpkh(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)");
+    let (original, params) = payjoin_original(&mut sender, &mut receiver);
+    assert!(original
+        .outputs
+        .iter()
+        .all(|o| o.bip32_derivation.is_empty()));
+    let (change_index, _) = params.additional_fee_contribution.unwrap();
+    let original_change = original.unsigned_tx.output[change_index].value;
+    let original_fee_rate = original.fee_rate().unwrap();
+
+    let proposal = receiver
+        .payjoin_proposal(
+            &original,
+            &params,
+            coin_selection::DefaultCoinSelectionAlgorithm::default(),
+        )
+        .unwrap();
+    assert_eq!(proposal.inputs.len(), 2);
+    for (txin, psbt_input) in proposal.unsigned_tx.input.iter().zip(&proposal.inputs) {
+        let ours = receiver.get_utxo(txin.previous_output).is_some();
+        assert_eq!(psbt_input.final_script_witness.is_some(), ours);
+        assert_eq!(psbt_input.witness_utxo.is_some(), ours);
     }
-    let original_tx_weight = tx.weight();
-    assert_eq!(tx.input.len(), 1);
-    assert_eq!(tx.output.len(), 2);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+
+    let psbt = sender
+        .process_payjoin_proposal(&original, proposal, &params)
+        .unwrap();
+    assert!(psbt.inputs.iter().all(|i| i.final_script_witness.is_some()));
+    assert!(psbt.fee_rate().unwrap().as_sat_per_vb() >= original_fee_rate.as_sat_per_vb());
+    let tx = psbt.extract_tx();
+    let change = tx.output[change_index].value;
+    assert!(change < original_change && change >= original_change - 1_000);
+    let payment = tx
+        .output
+        .iter()
+        .find(|txout| receiver.is_mine(&txout.script_pubkey))
         .unwrap();
+    // the receiver's input is added to its output, its fee is paid by our change
+    assert_eq!(payment.value, 20_000 + 50_000);
+}
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    // We set a fee high enough that during rbf we are forced to add
-    // a new input and also that we have to remove the change
-    // that we had previously
+#[test]
+fn test_payjoin_rejected() {
+    let (mut sender, _) = get_funded_wallet(get_test_wpkh());
+    let (mut receiver, _) =
+        get_funded_wallet("wpkh(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)");
+    let (original, params) = payjoin_original(&mut sender, &mut receiver);
+    let (change_index, _) = params.additional_fee_contribution.unwrap();
+    let proposal = receiver
+        .payjoin_proposal(
+            &original,
+            &params,
+            coin_selection::DefaultCoinSelectionAlgorithm::default(),
+        )
+        .unwrap();
+    let sender_input = proposal
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|txin| sender.get_utxo(txin.previous_output).is_some())
+        .unwrap();
 
-    // We calculate the new weight as:
-    //   original weight
-    // + extra input weight: 160 WU = (32 (prevout) + 4 (vout) + 4 (nsequence)) * 4
-    // + input satisfaction weight: 112 WU = 106 (witness) + 2 (witness len) + (1 (script len)) * 4
-    // - change output weight: 124 WU = (8 (value) + 1 (script len) + 22 (script)) * 4
-    let new_tx_weight =
-        original_tx_weight + Weight::from_wu(160) + Weight::from_wu(112) - Weight::from_wu(124);
-    // two inputs (50k, 25k) and one output (45k) - epsilon
-    // We use epsilon here to avoid asking for a slightly too high feerate
-    let fee_abs = 50_000 + 25_000 - 45_000 - 10;
-    builder.fee_rate(FeeRate::from_wu(fee_abs, new_tx_weight));
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
+    // the receiver takes more of our change than allowed
+    let mut stealing = proposal.clone();
+    stealing.unsigned_tx.output[change_index].value -= 5_000;
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, stealing, &params),
+        Err(PayjoinError::FeeContributionTooHigh { .. })
+    );
+
+    // nor decrease, drop or redirect the other outputs of the batch
+    let mut decreased = proposal.clone();
+    decreased.unsigned_tx.output[1].value -= 1_000;
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, decreased, &params),
+        Err(PayjoinError::OutputDecreased(1))
+    );
+    let mut redirected = proposal.clone();
+    redirected.unsigned_tx.output[1].script_pubkey = receiver.get_address(New).script_pubkey();
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, redirected, &params),
+        Err(PayjoinError::MissingOutput(1))
+    );
+
+    // the receiver's inputs must spend the values it claims
+    let receiver_input = 1 - sender_input;
+    let mut inflated = proposal.clone();
+    inflated.inputs[receiver_input]
+        .witness_utxo
+        .as_mut()
+        .unwrap()
+        .value += 10_000;
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, inflated, &params),
+        Err(PayjoinError::InvalidReceiverInput(_))
+    );
+
+    // the receiver can't decrease its own output if substitution is disabled
+    let payment_index = proposal
+        .unsigned_tx
+        .output
+        .iter()
+        .position(|txout| receiver.is_mine(&txout.script_pubkey))
+        .unwrap();
+    let mut substituted = proposal.clone();
+    substituted.unsigned_tx.output[payment_index].value = 10_000;
+    resign_proposal(&receiver, &original, &mut substituted);
+    let no_substitution = PayjoinParams {
+        disable_output_substitution: true,
+        ..params
+    };
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, substituted.clone(), &no_substitution),
+        Err(PayjoinError::OutputDecreased(_))
+    );
+    assert!(sender
+        .process_payjoin_proposal(&original, substituted, &params)
+        .is_ok());
 
-    assert_eq!(original_sent_received.1, 5_000 - original_fee.unwrap_or(0));
+    let mut not_cleared = proposal.clone();
+    not_cleared.inputs[sender_input] = original.inputs[0].clone();
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, not_cleared, &params),
+        Err(PayjoinError::SenderInputNotCleared(_))
+    );
 
-    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
-    assert_eq!(fee.unwrap_or(0), 30_000);
-    assert_eq!(sent_received.1, 0);
+    let mut missing_input = proposal.clone();
+    missing_input.unsigned_tx.input.remove(sender_input);
+    missing_input.inputs.remove(sender_input);
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, missing_input, &params),
+        Err(PayjoinError::MissingInput(_))
+    );
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 1);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        45_000
+    let too_low = PayjoinParams {
+        min_fee_rate: Some(FeeRate::from_sat_per_vb(10.0)),
+        ..params
+    };
+    assert_matches!(
+        sender.process_payjoin_proposal(&original, proposal, &too_low),
+        Err(PayjoinError::FeeRateTooLow { .. })
     );
 
-    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(140.0), @dust_change, @add_signature);
+    // the receiver only answers originals paying it, with inputs it can match
+    let (mut other, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    assert_matches!(
+        other.payjoin_proposal(
+            &original,
+            &params,
+            coin_selection::DefaultCoinSelectionAlgorithm::default()
+        ),
+        Err(PayjoinError::NoPaymentToUs)
+    );
+    let (original, params) = payjoin_original(&mut sender, &mut other);
+    assert_matches!(
+        other.payjoin_proposal(
+            &original,
+            &params,
+            coin_selection::DefaultCoinSelectionAlgorithm::default()
+        ),
+        Err(PayjoinError::CoinSelection(_))
+    );
 }
 
-#[test]
-fn test_bump_fee_force_add_input() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), 45_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx();
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+fn musig_wallets(descriptor: &str, secret_keys: &[SecretKey]) -> (Wallet, Wallet) {
+    let secp = Secp256k1::new();
+    let keys = secret_keys
+        .iter()
+        .map(|sk| sk.public_key(&secp))
+        .collect::<Vec<_>>();
+    let (mut alice, _) = get_funded_wallet(descriptor);
+    let (mut bob, _) = get_funded_wallet(descriptor);
+    for (wallet, secret_key) in [(&mut alice, secret_keys[0]), (&mut bob, secret_keys[1])] {
+        let key_agg = KeyAggContext::new(keys.clone()).unwrap();
+        let signer = MusigSigner::new(secret_key, key_agg, &secp).unwrap();
+        wallet.add_signer(
+            KeychainKind::External,
+            SignerOrdering::default(),
+            Arc::new(signer),
+        );
     }
-    wallet
-        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    // the new fee_rate is low enough that just reducing the change would be fine, but we force
-    // the addition of an extra input with `add_utxo()`
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .add_utxo(incoming_op)
-        .unwrap()
-        .fee_rate(FeeRate::from_sat_per_vb(5.0));
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
+    (alice, bob)
+}
 
-    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
-    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+/// The first signing round of both participants
+fn musig_first_round(
+    alice: &mut Wallet,
+    bob: &mut Wallet,
+    psbt: &mut psbt::PartiallySignedTransaction,
+) {
+    let mut bob_psbt = psbt.clone();
+    assert!(!alice.sign(psbt, SignOptions::default()).unwrap());
+    assert!(!bob.sign(&mut bob_psbt, SignOptions::default()).unwrap());
+    assert!(psbt.inputs[0].tap_key_sig.is_none());
+    psbt.combine(bob_psbt).unwrap();
+}
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+#[test]
+fn test_musig_sign() {
+    let secp = Secp256k1::new();
+    let secret_keys = [
+        SecretKey::from_slice(&[1; 32]).unwrap(),
+        SecretKey::from_slice(&[2; 32]).unwrap(),
+    ];
+    let keys = secret_keys
+        .iter()
+        .map(|sk| sk.public_key(&secp))
+        .collect::<Vec<_>>();
+    for descriptor in [
+        format!("tr(musig({},{}))", keys[0], keys[1]),
+        format!("tr(musig({},{})/0/*)", keys[0], keys[1]),
+    ] {
+        let (mut alice, mut bob) = musig_wallets(&descriptor, &secret_keys);
+
+        let policy = alice.policies(KeychainKind::External).unwrap().unwrap();
+        assert_matches!(&policy.item, SatisfiableItem::Musig { keys: participants, .. } if participants.len() == 2);
+        assert_matches!(&policy.contribution, Satisfaction::Partial { n: 2, m: 2, items, .. } if items == &vec![0]);
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
             .unwrap()
-            .value,
-        45_000
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .assume_checked();
+        let mut builder = alice.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let mut psbt = builder.finish().unwrap();
+
+        // first round, only the nonces are added
+        musig_first_round(&mut alice, &mut bob, &mut psbt);
+
+        // second round, the last signer aggregates the partial signatures
+        assert!(!alice.sign(&mut psbt, SignOptions::default()).unwrap());
+        let policy = alice
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .extract_policy(
+                &alice.get_signers(KeychainKind::External),
+                BuildSatisfaction::Psbt(&psbt),
+                &secp,
+            )
             .unwrap()
-            .value,
-        sent_received.1
-    );
+            .unwrap();
+        assert_matches!(&policy.satisfaction, Satisfaction::Partial { items, .. } if items == &vec![0]);
+        assert!(bob.sign(&mut psbt, SignOptions::default()).unwrap());
 
-    assert_fee_rate!(psbt, fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
+        let witness = &psbt.extract_tx().input[0].witness;
+        assert_eq!(witness.len(), 1);
+        assert_eq!(witness.to_vec()[0].len(), 64);
+    }
+
+    // addresses are derived from the aggregate key
+    let descriptor = format!("tr(musig({},{})/0/*)", keys[0], keys[1]);
+    let (mut alice, _) = musig_wallets(&descriptor, &secret_keys);
+    assert_ne!(
+        alice.get_address(New).address,
+        alice.get_address(New).address
+    );
 }
 
 #[test]
-fn test_bump_fee_absolute_force_add_input() {
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
-
+fn test_musig_nonces_and_partial_signatures() {
+    let secp = Secp256k1::new();
+    let secret_keys = [
+        SecretKey::from_slice(&[1; 32]).unwrap(),
+        SecretKey::from_slice(&[2; 32]).unwrap(),
+    ];
+    let keys = secret_keys
+        .iter()
+        .map(|sk| sk.public_key(&secp))
+        .collect::<Vec<_>>();
+    let descriptor = format!("tr(musig({},{})/0/*)", keys[0], keys[1]);
+    let (mut alice, mut bob) = musig_wallets(&descriptor, &secret_keys);
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), 45_000)
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx();
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    // skip saving the new utxos, we know they can't be used anyways
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+    let mut builder = alice.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    let unsigned = builder.finish().unwrap();
+
+    // starting another session for the same input doesn't lose the nonces of the first one
+    let mut psbt = unsigned.clone();
+    musig_first_round(&mut alice, &mut bob, &mut psbt);
+    let mut other_psbt = unsigned;
+    musig_first_round(&mut alice, &mut bob, &mut other_psbt);
+    assert!(!alice.sign(&mut psbt, SignOptions::default()).unwrap());
+
+    // an invalid partial signature is attributed to its participant
+    let alice_sig_key = psbt.inputs[0]
+        .unknown
+        .keys()
+        .find(|k| k.type_value == 0x1c && k.key[..33] == keys[0].serialize())
+        .cloned()
         .unwrap();
+    let bob_sig_key = {
+        let mut key = alice_sig_key.clone();
+        key.key[..33].copy_from_slice(&keys[1].serialize());
+        key
+    };
+    let mut tampered = psbt.clone();
+    tampered.inputs[0].unknown.get_mut(&alice_sig_key).unwrap()[31] ^= 1;
+    assert_matches!(
+        bob.sign(&mut tampered, SignOptions::default()),
+        Err(SignerError::Musig(musig::Error::InvalidPartialSignature(key))) if key == keys[0]
+    );
+    // bob's partial signature is valid, alice aggregates it
+    let bob_sig = tampered.inputs[0].unknown[&bob_sig_key].clone();
+    psbt.inputs[0].unknown.insert(bob_sig_key, bob_sig);
+    assert!(alice.sign(&mut psbt, SignOptions::default()).unwrap());
 
-    // the new fee_rate is low enough that just reducing the change would be fine, but we force
-    // the addition of an extra input with `add_utxo()`
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.add_utxo(incoming_op).unwrap().fee_absolute(250);
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.clone().extract_tx());
-    let fee = check_fee!(wallet, psbt);
+    assert!(!alice.sign(&mut other_psbt, SignOptions::default()).unwrap());
+    assert!(bob.sign(&mut other_psbt, SignOptions::default()).unwrap());
+}
 
-    assert_eq!(sent_received.0, original_sent_received.0 + 25_000);
-    assert_eq!(fee.unwrap_or(0) + sent_received.1, 30_000);
+#[test]
+fn test_musig_sign_without_nonce() {
+    let secp = Secp256k1::new();
+    let secret_keys = [
+        SecretKey::from_slice(&[1; 32]).unwrap(),
+        SecretKey::from_slice(&[2; 32]).unwrap(),
+    ];
+    let keys = secret_keys
+        .iter()
+        .map(|sk| sk.public_key(&secp))
+        .collect::<Vec<_>>();
+    let descriptor = format!("tr(musig({},{}))", keys[0], keys[1]);
+    let (mut wallet, _) = get_funded_wallet(&descriptor);
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        45_000
+    let signer = |secret_key| {
+        let key_agg = KeyAggContext::new(keys.clone()).unwrap();
+        Arc::new(MusigSigner::new(secret_key, key_agg, &secp).unwrap())
+    };
+    assert_matches!(
+        MusigSigner::new(
+            SecretKey::from_slice(&[3; 32]).unwrap(),
+            KeyAggContext::new(keys.clone()).unwrap(),
+            &secp
+        ),
+        Err(musig::Error::NotParticipant)
     );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
+    wallet.add_signer(
+        KeychainKind::External,
+        SignerOrdering::default(),
+        signer(secret_keys[0]),
+    );
+    wallet.add_signer(
+        KeychainKind::External,
+        SignerOrdering(200),
+        signer(secret_keys[1]),
     );
 
-    assert_eq!(fee.unwrap_or(0), 250);
+    let addr = wallet.get_address(New);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), 25_000);
+    let mut psbt = builder.finish().unwrap();
+    assert!(!wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+
+    // a new signer doesn't know the secret nonce of the first round
+    wallet.add_signer(
+        KeychainKind::External,
+        SignerOrdering(200),
+        signer(secret_keys[1]),
+    );
+    assert_matches!(
+        wallet.sign(&mut psbt, SignOptions::default()),
+        Err(SignerError::Musig(musig::Error::MissingSecretNonce))
+    );
 }
 
-#[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_bump_fee_unconfirmed_inputs_only() {
-    // We try to bump the fee, but:
-    // - We can't reduce the change, as we have no change
-    // - All our UTXOs are unconfirmed
-    // So, we fail with "InsufficientFunds", as per RBF rule 2:
-    // The replacement transaction may only include an unconfirmed input
-    // if that input was included in one of the original transactions.
-    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_wallet()
-        .drain_to(addr.script_pubkey())
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    // Now we receive one transaction with 0 confirmations. We won't be able to use that for
-    // fee bumping, as it's still unconfirmed!
-    receive_output(
-        &mut wallet,
-        25_000,
-        ConfirmationTime::Unconfirmed { last_seen: 0 },
+fn silent_payment_index() -> (SilentPaymentIndex, SecretKey) {
+    let secp = Secp256k1::new();
+    let scan_secret = SecretKey::from_slice(&[1; 32]).unwrap();
+    let spend_secret = SecretKey::from_slice(&[2; 32]).unwrap();
+    let index = SilentPaymentIndex::new(
+        scan_secret,
+        spend_secret.public_key(&secp),
+        Network::Regtest,
     );
-    let mut tx = psbt.extract_tx();
-    let txid = tx.txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    (index, spend_secret)
+}
+
+#[test]
+fn test_create_tx_silent_payment() {
+    for descriptor in [get_test_wpkh(), get_test_tr_single_sig_xprv()] {
+        let (mut wallet, _) = get_funded_wallet(descriptor);
+        let (mut index, spend_secret) = silent_payment_index();
+        let mut builder = wallet.build_tx();
+        builder.add_silent_payment_recipient(index.address(), 25_000);
+        let mut psbt = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let tx = psbt.extract_tx();
+
+        let found = index
+            .scan_tx(&tx, |outpoint| {
+                wallet.tx_graph().get_txout(outpoint).cloned()
+            })
+            .unwrap();
+        assert_eq!(found.0.len(), 1, "{}", descriptor);
+        let (outpoint, output) = found.0.into_iter().next().unwrap();
+        let txout = &tx.output[outpoint.vout as usize];
+        assert_eq!(txout.value, 25_000);
+        let output_key = output
+            .spending_key(&spend_secret)
+            .x_only_public_key(&Secp256k1::new())
+            .0;
+        assert_eq!(txout.script_pubkey.as_bytes()[2..], output_key.serialize());
     }
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb(25.0));
-    builder.finish().unwrap();
 }
 
 #[test]
-fn test_bump_fee_unconfirmed_input() {
-    // We create a tx draining the wallet and spending one confirmed
-    // and one unconfirmed UTXO. We check that we can fee bump normally
-    // (BIP125 rule 2 only apply to newly added unconfirmed input, you can
-    // always fee bump with an unconfirmed input if it was included in the
-    // original transaction)
+fn test_create_tx_silent_payment_errors() {
+    let (index, _) = silent_payment_index();
+
     let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    // We receive a tx with 0 confirmations, which will be used as an input
-    // in the drain tx.
-    receive_output(&mut wallet, 25_000, ConfirmationTime::unconfirmed(0));
+    let mainnet_address = SilentPaymentAddress {
+        network: Network::Bitcoin,
+        ..index.address()
+    };
     let mut builder = wallet.build_tx();
-    builder
-        .drain_wallet()
-        .drain_to(addr.script_pubkey())
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx();
-    let txid = tx.txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+    builder.add_silent_payment_recipient(mainnet_address, 25_000);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::SilentPayment(SendError::InvalidNetwork(_)))
+    );
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .fee_rate(FeeRate::from_sat_per_vb(15.0))
-        .allow_shrinking(addr.script_pubkey())
-        .unwrap();
-    builder.finish().unwrap();
+    // the private key of the internal key is unknown
+    let (mut wallet, txid) = get_funded_wallet(get_test_tr_with_taptree());
+    let mut builder = wallet.build_tx();
+    builder.add_silent_payment_recipient(index.address(), 25_000);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::SilentPayment(SendError::MissingPrivateKey(outpoint))) if outpoint.txid == txid
+    );
+
+    // inputs can't be combined with outputs whose script has no key
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
+    let mut builder = wallet.build_tx();
+    builder.add_silent_payment_recipient(index.address(), 25_000);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::SilentPayment(SendError::NoEligibleInputs))
+    );
 }
 
 #[test]
@@ -9,8 +9,11 @@
 //! mempool.
 #![warn(missing_docs)]
 
-use bdk_chain::{local_chain::CheckPoint, BlockId};
-use bitcoin::{block::Header, Block, BlockHash, Transaction};
+use bdk_chain::{
+    fee_rate_from_sat_per_kvb, local_chain::CheckPoint, BlockId, BroadcastError, Broadcaster,
+    FeeEstimator,
+};
+use bitcoin::{block::Header, Block, BlockHash, FeeRate, Transaction};
 pub use bitcoincore_rpc;
 use bitcoincore_rpc::bitcoincore_rpc_json;
 
@@ -259,6 +262,72 @@ where
     }
 }
 
+/// Estimates fee rates with the `estimatesmartfee` RPC of bitcoind.
+///
+/// This implements [`FeeEstimator`] for any [`bitcoincore_rpc::RpcApi`] client. The estimate mode
+/// is left to bitcoind's default. An error is returned if bitcoind does not have enough
+/// information for an estimate.
+#[derive(Debug, Clone, Copy)]
+pub struct RpcFeeEstimator<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> RpcFeeEstimator<'c, C> {
+    /// Create an estimator which requests fee estimates with `client`.
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> FeeEstimator for RpcFeeEstimator<'c, C> {
+    type Error = bitcoincore_rpc::Error;
+
+    fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error> {
+        let res = self.client.estimate_smart_fee(target, None)?;
+        match res.fee_rate {
+            Some(per_kvb) => Ok(fee_rate_from_sat_per_kvb(per_kvb.to_sat())),
+            None => Err(bitcoincore_rpc::Error::ReturnedError(
+                res.errors
+                    .map(|errors| errors.join(", "))
+                    .unwrap_or_else(|| "no fee estimate available".to_string()),
+            )),
+        }
+    }
+}
+
+/// Broadcasts transactions with the `sendrawtransaction` RPC of bitcoind.
+///
+/// This implements [`Broadcaster`] for any [`bitcoincore_rpc::RpcApi`] client. The reason
+/// bitcoind gives for rejecting a transaction is mapped to a [`BroadcastError`].
+#[derive(Debug, Clone, Copy)]
+pub struct RpcBroadcaster<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> RpcBroadcaster<'c, C> {
+    /// Create a broadcaster which broadcasts with `client`.
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> Broadcaster for RpcBroadcaster<'c, C> {
+    type Error = bitcoincore_rpc::Error;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>> {
+        match self.client.send_raw_transaction(tx) {
+            Ok(_) => Ok(()),
+            // RPC_VERIFY_ERROR, RPC_VERIFY_REJECTED and RPC_VERIFY_ALREADY_IN_CHAIN
+            Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(rpc_err)))
+                if (-27..=-25).contains(&rpc_err.code) =>
+            {
+                BroadcastError::from_reject_reason(rpc_err.message).map_or(Ok(()), Err)
+            }
+            Err(err) => Err(BroadcastError::Client(err)),
+        }
+    }
+}
+
 /// Extends [`bitcoincore_rpc::Error`].
 pub trait BitcoindRpcErrorExt {
     /// Returns whether the error is a "not found" error.
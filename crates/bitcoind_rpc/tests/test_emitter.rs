@@ -1,11 +1,11 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use bdk_bitcoind_rpc::Emitter;
+use bdk_bitcoind_rpc::{Emitter, RpcBroadcaster, RpcFeeEstimator};
 use bdk_chain::{
     bitcoin::{Address, Amount, BlockHash, Txid},
     keychain::Balance,
     local_chain::{self, CheckPoint, LocalChain},
-    Append, BlockId, IndexedTxGraph, SpkTxOutIndex,
+    Append, BlockId, BroadcastError, Broadcaster, FeeEstimator, IndexedTxGraph, SpkTxOutIndex,
 };
 use bitcoin::{
     address::NetworkChecked, block::Header, hash_types::TxMerkleNode, hashes::Hash,
@@ -864,3 +864,44 @@ fn no_agreement_point() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Without enough blocks and transactions to estimate from, [`RpcFeeEstimator`] returns the
+/// errors reported by `estimatesmartfee`.
+#[test]
+fn test_fee_estimator_without_estimates() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(10, None)?;
+
+    let estimator = RpcFeeEstimator::new(&env.client);
+    assert!(matches!(
+        estimator.estimate_fee_rate(6),
+        Err(bitcoincore_rpc::Error::ReturnedError(_))
+    ));
+    Ok(())
+}
+
+/// [`RpcBroadcaster`] maps the reason bitcoind rejects a transaction with to a [`BroadcastError`].
+#[test]
+fn test_broadcaster_maps_rejections() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(101, None)?;
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: ScriptBuf::new_v0_p2wsh(&WScriptHash::all_zeros()),
+        }],
+    };
+    let broadcaster = RpcBroadcaster::new(&env.client);
+    assert!(matches!(
+        broadcaster.broadcast(&tx),
+        Err(BroadcastError::MissingInputs(_))
+    ));
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use bdk_chain::{
+    local_chain::{self, CheckPoint},
+    BlockId,
+};
+use bitcoin::{
+    bip158::BlockFilter,
+    block::Header,
+    hash_types::{FilterHash, FilterHeader},
+    hashes::Hash,
+    Block, BlockHash, Script,
+};
+
+use crate::{
+    headers::HeaderChain,
+    peer::{MAX_GETCFILTERS_SIZE, MAX_HEADERS_RESULTS, MAX_LOCATOR_SIZE},
+    Error, Peer,
+};
+
+/// A structure that emits blocks which match the script pubkeys of interest, using the compact
+/// block filters served by a [`Peer`].
+///
+/// Refer to [module-level documentation] for more.
+///
+/// [module-level documentation]: crate
+#[derive(Debug)]
+pub struct Emitter<'p> {
+    peer: &'p mut Peer,
+
+    /// The checkpoint of the last-emitted block. Before the headers are synced this is the
+    /// checkpoint the emitter was constructed with, afterwards it is based on the last block of
+    /// that chain which is still in the peer's best chain.
+    last_cp: CheckPoint,
+
+    /// Headers of the peer's best chain that are still to be scanned, or `None` if the headers
+    /// have not been synced yet.
+    headers: Option<VecDeque<BlockId>>,
+
+    /// Fetched filters that are still to be matched.
+    filters: VecDeque<(BlockId, BlockFilter)>,
+
+    /// The last block that was scanned, whether or not it was emitted.
+    last_scanned: Option<BlockId>,
+
+    /// Heights of checkpoints which are no longer in the peer's best chain. The blocks which
+    /// replace them are included in the chain update so that the receiver evicts them.
+    displaced_heights: BTreeSet<u32>,
+
+    /// The first checkpoint which is no longer in the peer's best chain.
+    disconnected: Option<BlockId>,
+
+    /// The difficulty adjustment interval of the peer's network. Blocks at the start of an
+    /// interval are included in the chain update, so the next sync can check the difficulty
+    /// adjustment exactly.
+    interval: Option<u32>,
+
+    /// The filter header of the last block whose filter was fetched.
+    filter_header: Option<FilterHeader>,
+}
+
+impl<'p> Emitter<'p> {
+    /// Construct a new [`Emitter`] with the given `peer` and `last_cp`.
+    ///
+    /// `last_cp` is the checkpoint used to find the latest block which is still part of the peer's
+    /// best chain. Only blocks after that block are scanned.
+    pub fn new(peer: &'p mut Peer, last_cp: CheckPoint) -> Self {
+        Self {
+            peer,
+            last_cp,
+            headers: None,
+            filters: VecDeque::new(),
+            last_scanned: None,
+            displaced_heights: BTreeSet::new(),
+            disconnected: None,
+            interval: None,
+            filter_header: None,
+        }
+    }
+
+    /// Emit the next block height and block which matches any of the script pubkeys in `spks`
+    /// (if any).
+    ///
+    /// The filter of every block is matched against the `spks` given to the call that scans it, so
+    /// script pubkeys revealed by applying an emitted block are taken into account for the
+    /// following blocks.
+    ///
+    /// `Ok(None)` is returned once the tip of the peer's chain (as of the first call) is reached.
+    pub fn next_block<'s, I>(&mut self, spks: I) -> Result<Option<(u32, Block)>, Error>
+    where
+        I: IntoIterator<Item = &'s Script>,
+    {
+        let spks = spks.into_iter().map(Script::as_bytes).collect::<Vec<_>>();
+        loop {
+            let (block_id, filter) = match self.filters.pop_front() {
+                Some(next) => next,
+                None => {
+                    if !self.fetch_filters()? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
+            self.last_scanned = Some(block_id);
+            let is_displacing = self.displaced_heights.remove(&block_id.height)
+                || self
+                    .interval
+                    .map_or(false, |interval| block_id.height % interval == 0);
+            if !filter.match_any(&block_id.hash, &mut spks.iter().copied())? {
+                if is_displacing {
+                    self.push_checkpoint(block_id);
+                }
+                continue;
+            }
+
+            let block = self.peer.get_block(block_id.hash)?;
+            if !block.check_merkle_root() || !block.check_witness_commitment() {
+                return Err(Error::InvalidBlock(block_id.hash));
+            }
+            self.push_checkpoint(block_id);
+            return Ok(Some((block_id.height, block)));
+        }
+    }
+
+    /// The [`local_chain::Update`] that connects the blocks emitted so far and the last scanned
+    /// block to the chain of the checkpoint given to [`Emitter::new`].
+    pub fn chain_update(&self) -> local_chain::Update {
+        let tip = match self.last_scanned {
+            Some(block_id) if block_id.height > self.last_cp.height() => self
+                .last_cp
+                .clone()
+                .push(block_id)
+                .expect("height must be greater than the last checkpoint"),
+            _ => self.last_cp.clone(),
+        };
+        local_chain::Update {
+            tip,
+            introduce_older_blocks: false,
+        }
+    }
+
+    /// The first block of the chain of the checkpoint given to [`Emitter::new`] which is no longer
+    /// in the peer's best chain, if any.
+    ///
+    /// The [`Emitter::chain_update`] can only replace the blocks up to the height of the peer's
+    /// tip. If the peer's best chain is shorter than the chain it replaces, the blocks above it
+    /// must be disconnected with [`LocalChain::disconnect_from`] before the update is applied.
+    ///
+    /// [`LocalChain::disconnect_from`]: bdk_chain::local_chain::LocalChain::disconnect_from
+    pub fn disconnected_block(&self) -> Option<BlockId> {
+        self.disconnected
+    }
+
+    fn push_checkpoint(&mut self, block_id: BlockId) {
+        self.last_cp = self
+            .last_cp
+            .clone()
+            .push(block_id)
+            .expect("headers must be in ascending height order");
+    }
+
+    /// Fetch the next batch of filters, returns `false` if there are no more blocks to scan.
+    fn fetch_filters(&mut self) -> Result<bool, Error> {
+        if self.headers.is_none() {
+            self.sync_headers()?;
+        }
+        let headers = self.headers.as_mut().expect("headers must be synced");
+        if headers.is_empty() {
+            return Ok(false);
+        }
+
+        let batch = headers
+            .drain(..headers.len().min(MAX_GETCFILTERS_SIZE))
+            .collect::<Vec<_>>();
+        let start_height = batch[0].height;
+        let stop_hash = batch[batch.len() - 1].hash;
+        let cfheaders = self.peer.get_cfheaders(start_height, stop_hash)?;
+        if cfheaders.filter_hashes.len() != batch.len() {
+            return Err(Error::InvalidFilter(stop_hash));
+        }
+        // the filter header chain of the peer must connect, the first filter header is trusted
+        let mut filter_header = match self.filter_header {
+            Some(filter_header) if filter_header != cfheaders.previous_filter_header => {
+                return Err(Error::InvalidFilter(batch[0].hash));
+            }
+            _ => cfheaders.previous_filter_header,
+        };
+
+        let cfilters = self
+            .peer
+            .get_cfilters(start_height, stop_hash, batch.len())?;
+        for ((block_id, cfilter), filter_hash) in
+            batch.into_iter().zip(cfilters).zip(cfheaders.filter_hashes)
+        {
+            if cfilter.block_hash != block_id.hash {
+                return Err(Error::UnexpectedFilter {
+                    expected: block_id.hash,
+                    got: cfilter.block_hash,
+                });
+            }
+            let filter = BlockFilter::new(&cfilter.filter);
+            if FilterHash::hash(&filter.content) != filter_hash {
+                return Err(Error::InvalidFilter(block_id.hash));
+            }
+            filter_header = filter_hash.filter_header(&filter_header);
+            self.filters.push_back((block_id, filter));
+        }
+        self.filter_header = Some(filter_header);
+        Ok(true)
+    }
+
+    /// Download the headers of the peer's best chain after the last block of `last_cp` which is
+    /// still in that chain.
+    ///
+    /// The headers are validated against the proof of work rules of the peer's network. If they
+    /// replace blocks of `last_cp`, they must have at least as much work as the replaced blocks.
+    fn sync_headers(&mut self) -> Result<(), Error> {
+        let mut headers = VecDeque::<BlockId>::new();
+        let mut chain = None::<(HeaderChain, u32)>;
+        let mut locator = block_locator(&self.last_cp);
+        loop {
+            let batch = self.peer.get_headers(locator)?;
+            let batch_len = batch.len();
+            for header in batch {
+                let (chain, _) = match &mut chain {
+                    Some(chain) => chain,
+                    None => chain.insert(self.start_chain(&header)?),
+                };
+                let hash = chain.push(header)?;
+                headers.push_back(BlockId {
+                    height: chain.tip_height(),
+                    hash,
+                });
+            }
+            match headers.back() {
+                Some(last) if batch_len == MAX_HEADERS_RESULTS => locator = vec![last.hash],
+                _ => break,
+            }
+        }
+
+        if let (Some((chain, replaced)), Some(tip)) = (&chain, headers.back()) {
+            if *replaced > 0 {
+                // the work of the replaced blocks is unknown, they are assumed to have the
+                // difficulty of the last block they have in common with the peer's chain
+                let trusted_work = chain.trusted_work();
+                let mut replaced_work = trusted_work;
+                for _ in 1..*replaced {
+                    replaced_work = replaced_work + trusted_work;
+                }
+                // the peer may have switched to a chain of equal work which it saw first
+                if chain.work().map_or(true, |work| work < replaced_work) {
+                    return Err(Error::InsufficientWork { tip: tip.hash });
+                }
+            }
+        }
+        self.headers = Some(headers);
+        Ok(())
+    }
+
+    /// Start a [`HeaderChain`] at the checkpoint that `header` connects to, which becomes the last
+    /// checkpoint. Also returns the number of blocks of the replaced chain after it.
+    fn start_chain(&mut self, header: &Header) -> Result<(HeaderChain, u32), Error> {
+        let agreement_cp = self
+            .last_cp
+            .iter()
+            .find(|cp| cp.hash() == header.prev_blockhash)
+            .ok_or(Error::DisconnectedHeaders(header.block_hash()))?;
+        let agreement_height = agreement_cp.height();
+        let replaced = self.last_cp.height() - agreement_height;
+        // get rid of blocks which are no longer in the best chain
+        self.displaced_heights = self
+            .last_cp
+            .iter()
+            .take_while(|cp| cp.height() > agreement_height)
+            .map(|cp| cp.height())
+            .collect();
+        self.disconnected = self
+            .last_cp
+            .iter()
+            .take_while(|cp| cp.height() > agreement_height)
+            .last()
+            .map(|cp| cp.block_id());
+
+        let trusted_header = self.peer.get_header(agreement_cp.hash())?;
+        let mut chain = HeaderChain::new(self.peer.network(), agreement_height, trusted_header);
+        let interval = chain.interval();
+        self.interval = Some(interval);
+        // the first block of the difficulty adjustment period of the trusted header is needed to
+        // check the difficulty of the next period exactly
+        let period_start = agreement_height - agreement_height % interval;
+        if period_start < agreement_height {
+            let period_start_cp = agreement_cp
+                .iter()
+                .find(|cp| cp.height() <= period_start)
+                .filter(|cp| cp.height() == period_start);
+            if let Some(cp) = period_start_cp {
+                chain.set_period_start(period_start, self.peer.get_header(cp.hash())?);
+            }
+        }
+
+        self.last_cp = agreement_cp;
+        Ok((chain, replaced))
+    }
+}
+
+/// Hashes of the checkpoints of `cp`, most recent first, always ending with the earliest
+/// checkpoint (usually genesis).
+fn block_locator(cp: &CheckPoint) -> Vec<BlockHash> {
+    let mut locator = cp
+        .iter()
+        .take(MAX_LOCATOR_SIZE - 1)
+        .map(|cp| cp.hash())
+        .collect::<Vec<_>>();
+    if let Some(earliest) = cp.iter().last() {
+        if locator.last() != Some(&earliest.hash()) {
+            locator.push(earliest.hash());
+        }
+    }
+    locator
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+
+use bitcoin::{
+    block::Header,
+    consensus::Params,
+    pow::{CompactTarget, Target, Work},
+    BlockHash, Network,
+};
+
+use crate::Error;
+
+/// Headers of a chain which are validated against the proof of work rules of a network as they
+/// are pushed.
+///
+/// The chain starts at a trusted header, the header of a checkpoint. Only the headers of the last
+/// difficulty adjustment interval are kept, since the rules never look back further than that.
+#[derive(Debug)]
+pub(crate) struct HeaderChain {
+    params: Params,
+    /// The height of the first header of `headers`.
+    start_height: u32,
+    /// The trusted header followed by the pushed headers.
+    headers: VecDeque<Header>,
+    /// The trusted header of the first block of a difficulty adjustment period which started
+    /// before the trusted header, if it is known.
+    period_start: Option<(u32, Header)>,
+    /// The cumulative work of the pushed headers.
+    work: Option<Work>,
+}
+
+impl HeaderChain {
+    /// Start a chain at the trusted `header` at `height`.
+    pub(crate) fn new(network: Network, height: u32, header: Header) -> Self {
+        Self {
+            params: Params::new(network),
+            start_height: height,
+            headers: VecDeque::from(vec![header]),
+            period_start: None,
+            work: None,
+        }
+    }
+
+    /// The difficulty adjustment interval of the network.
+    pub(crate) fn interval(&self) -> u32 {
+        self.params.difficulty_adjustment_interval() as u32
+    }
+
+    /// Provide the trusted `header` at `height`, the first block of a difficulty adjustment period
+    /// which started before the trusted header.
+    ///
+    /// Without it, the target of the first block of the next period is only checked to be within
+    /// the bounds of a difficulty adjustment.
+    pub(crate) fn set_period_start(&mut self, height: u32, header: Header) {
+        self.period_start = Some((height, header));
+    }
+
+    /// The height of the last header.
+    pub(crate) fn tip_height(&self) -> u32 {
+        self.start_height + self.headers.len() as u32 - 1
+    }
+
+    /// The cumulative work of the pushed headers.
+    pub(crate) fn work(&self) -> Option<Work> {
+        self.work
+    }
+
+    /// The work of every block at the difficulty of the trusted header.
+    pub(crate) fn trusted_work(&self) -> Work {
+        self.headers[0].work()
+    }
+
+    /// Validate `header` as the next header of the chain and push it, returning its hash.
+    pub(crate) fn push(&mut self, header: Header) -> Result<BlockHash, Error> {
+        let hash = header.block_hash();
+        let prev = self.headers.back().expect("chain is never empty");
+        if header.prev_blockhash != prev.block_hash() {
+            return Err(Error::DisconnectedHeaders(hash));
+        }
+        let height = self.tip_height() + 1;
+        let target = header.target();
+        if target > pow_limit(self.params.network) {
+            return Err(Error::InvalidProofOfWork(hash));
+        }
+        if !self.has_valid_bits(height, &header) {
+            return Err(Error::InvalidDifficulty(hash));
+        }
+        header
+            .validate_pow(target)
+            .map_err(|_| Error::InvalidProofOfWork(hash))?;
+
+        self.work = Some(match self.work {
+            Some(work) => work + header.work(),
+            None => header.work(),
+        });
+        self.headers.push_back(header);
+        if self.headers.len() > self.interval() as usize + 1 {
+            self.headers.pop_front();
+            self.start_height += 1;
+        }
+        Ok(hash)
+    }
+
+    /// The header at `height`, if it is known.
+    fn get(&self, height: u32) -> Option<&Header> {
+        match self.period_start {
+            Some((start_height, ref header)) if start_height == height => Some(header),
+            _ => self
+                .headers
+                .get(height.checked_sub(self.start_height)? as usize),
+        }
+    }
+
+    /// Whether `header` at `height` has the bits the difficulty adjustment rules require.
+    ///
+    /// This follows `GetNextWorkRequired` of Bitcoin Core, except where the headers the rules look
+    /// back at are unknown.
+    fn has_valid_bits(&self, height: u32, header: &Header) -> bool {
+        let interval = self.interval();
+        let prev = self.get(height - 1).expect("previous header must be known");
+        if height % interval != 0 {
+            if !self.params.allow_min_difficulty_blocks {
+                return header.bits == prev.bits;
+            }
+            let limit_bits = pow_limit_bits(self.params.network);
+            // a block more than twice the target spacing after the previous one may have the
+            // minimum difficulty
+            let spacing = self.params.pow_target_spacing as u32;
+            if header.time > prev.time.saturating_add(2 * spacing) {
+                return header.bits == limit_bits;
+            }
+            // otherwise it has the difficulty of the last block which doesn't have the minimum
+            // difficulty because of the rule above, which may be before the trusted header
+            let mut height = height - 1;
+            while let Some(prev) = self.get(height) {
+                if height % interval == 0 || prev.bits != limit_bits {
+                    return header.bits == prev.bits;
+                }
+                height -= 1;
+            }
+            return true;
+        }
+        if self.params.no_pow_retargeting {
+            return header.bits == prev.bits;
+        }
+        match self.get(height - interval) {
+            Some(first) => {
+                let timespan = prev.time.saturating_sub(first.time);
+                header.bits == next_bits(prev.bits, timespan, &self.params)
+            }
+            // without the first block of the period the timespan is unknown, but the target can
+            // change by at most a factor of four
+            None => {
+                let prev_target = prev.target();
+                let limit = pow_limit(self.params.network);
+                let target = header.target();
+                target >= prev_target.min_difficulty_transition_threshold()
+                    && target <= prev_target.max_difficulty_transition_threshold().min(limit)
+            }
+        }
+    }
+}
+
+/// The bits of the minimum difficulty of `network`.
+fn pow_limit_bits(network: Network) -> CompactTarget {
+    CompactTarget::from_consensus(match network {
+        Network::Signet => 0x1e0377ae,
+        Network::Regtest => 0x207fffff,
+        _ => 0x1d00ffff,
+    })
+}
+
+/// The target of the minimum difficulty of `network`.
+fn pow_limit(network: Network) -> Target {
+    Target::from_compact(pow_limit_bits(network))
+}
+
+/// The bits of the first block of a difficulty adjustment period, the blocks of the previous
+/// period having taken `timespan` seconds and the last of them having `prev_bits`.
+///
+/// This follows `CalculateNextWorkRequired` of Bitcoin Core: the target is scaled by `timespan`
+/// over the target timespan, limited to a factor of four and to the minimum difficulty.
+fn next_bits(prev_bits: CompactTarget, timespan: u32, params: &Params) -> CompactTarget {
+    let target_timespan = params.pow_target_timespan;
+    let timespan = u64::from(timespan).clamp(target_timespan / 4, target_timespan * 4);
+
+    let bits = prev_bits.to_consensus();
+    let exponent = (bits >> 24) as i32;
+    let mantissa = u128::from(bits & 0x007f_ffff);
+    // the new target is `value * 256^shift`, with three extra bytes of precision so that the
+    // three most significant bytes kept by the compact form are exact
+    let mut value = ((mantissa * u128::from(timespan)) << 24) / u128::from(target_timespan);
+    let mut shift = exponent - 6;
+    if shift < 0 {
+        value >>= 8 * -shift;
+        shift = 0;
+    }
+
+    let value_len = (128 - value.leading_zeros() + 7) / 8;
+    let mut size = value_len as i32 + shift;
+    let mut compact = if value_len <= 3 {
+        (value << (8 * (3 - value_len))) as u32
+    } else {
+        (value >> (8 * (value_len - 3))) as u32
+    };
+    // the mantissa is signed, keep it positive
+    if compact & 0x0080_0000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+    let next = CompactTarget::from_consensus(((size as u32) << 24) | compact);
+
+    let limit = pow_limit_bits(params.network);
+    if Target::from_compact(next) > Target::from_compact(limit) {
+        limit
+    } else {
+        next
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::{constants::genesis_block, hash_types::TxMerkleNode, hashes::Hash};
+
+    /// Mine a regtest header on top of `prev` with `bits`.
+    fn mine(prev: &Header, bits: CompactTarget) -> Header {
+        let mut header = Header {
+            version: prev.version,
+            prev_blockhash: prev.block_hash(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: prev.time + 600,
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    #[test]
+    fn push_checks_difficulty() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut chain = HeaderChain::new(Network::Regtest, 0, genesis);
+        let header = mine(&genesis, genesis.bits);
+        assert_eq!(chain.push(header).unwrap(), header.block_hash());
+        assert_eq!(chain.tip_height(), 1);
+        assert_eq!(chain.work(), Some(header.work()));
+
+        // a harder target than required is not valid either
+        let harder = mine(&header, CompactTarget::from_consensus(0x2000ffff));
+        assert!(matches!(
+            chain.push(harder),
+            Err(Error::InvalidDifficulty(_))
+        ));
+
+        let easier = mine(&header, CompactTarget::from_consensus(0x2100ffff));
+        assert!(matches!(
+            chain.push(easier),
+            Err(Error::InvalidProofOfWork(_))
+        ));
+
+        assert!(matches!(
+            chain.push(mine(&genesis, genesis.bits)),
+            Err(Error::DisconnectedHeaders(_))
+        ));
+        assert_eq!(chain.tip_height(), 1);
+    }
+
+    // test vectors of `pow_tests.cpp` in Bitcoin Core
+    fn assert_next_bits(first_time: u32, last_time: u32, last_bits: u32, expected: u32) {
+        let params = Params::new(Network::Bitcoin);
+        assert_eq!(
+            next_bits(
+                CompactTarget::from_consensus(last_bits),
+                last_time - first_time,
+                &params
+            ),
+            CompactTarget::from_consensus(expected)
+        );
+    }
+
+    #[test]
+    fn next_bits_follows_timespan() {
+        assert_next_bits(1261130161, 1262152739, 0x1d00ffff, 0x1d00d86a);
+    }
+
+    #[test]
+    fn next_bits_is_limited_to_pow_limit() {
+        assert_next_bits(1231006505, 1233061996, 0x1d00ffff, 0x1d00ffff);
+    }
+
+    #[test]
+    fn next_bits_is_limited_to_a_quarter_of_the_timespan() {
+        assert_next_bits(1279008237, 1279297671, 0x1c05a3f4, 0x1c0168fd);
+    }
+
+    #[test]
+    fn next_bits_is_limited_to_four_times_the_timespan() {
+        assert_next_bits(1263163443, 1269211443, 0x1c387f6f, 0x1d00e1fd);
+    }
+}
@@ -0,0 +1,169 @@
+//! This crate is used for emitting blockchain data from peers serving [BIP157] compact block
+//! filters.
+//!
+//! [`Peer`] is a blocking connection to a node which serves compact block filters, e.g. Bitcoin
+//! Core started with `-blockfilterindex=1` and `-peerblockfilters=1`. [`Emitter`] downloads the
+//! block headers and [BIP158] filters from the peer, matches the filters against the script
+//! pubkeys of interest and only downloads the blocks that match. The script pubkeys never leave
+//! the client.
+//!
+//! Emitted blocks are meant to be applied with [`IndexedTxGraph::apply_block_relevant`]. Once
+//! [`Emitter::next_block`] returns `Ok(None)`, [`Emitter::chain_update`] is applied to the
+//! [`LocalChain`], after disconnecting the [`Emitter::disconnected_block`] if the peer's best chain
+//! replaced blocks of it.
+//!
+//! Headers are checked against the proof of work and difficulty adjustment rules of the network,
+//! and a chain which replaces known blocks must have at least as much work as them. Filters are
+//! checked against the filter headers served by the peer.
+//!
+//! ```rust,no_run
+//! # use bdk_chain::{
+//! #     bitcoin::{constants::genesis_block, Network},
+//! #     keychain::KeychainTxOutIndex,
+//! #     local_chain::LocalChain,
+//! #     ConfirmationHeightAnchor, IndexedTxGraph,
+//! # };
+//! # let (mut chain, _) =
+//! #     LocalChain::from_genesis_hash(genesis_block(Network::Regtest).block_hash());
+//! # let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, KeychainTxOutIndex<()>>::default();
+//! let mut peer = bdk_cbf::Peer::connect("127.0.0.1:18444".parse()?, Network::Regtest)?;
+//! let mut emitter = bdk_cbf::Emitter::new(&mut peer, chain.tip());
+//! // the filters are matched against all derived script pubkeys, including the lookahead
+//! while let Some((height, block)) =
+//!     emitter.next_block(graph.index.inner().all_spks().values().map(|spk| spk.as_script()))?
+//! {
+//!     let _ = graph.apply_block_relevant(block, height);
+//! }
+//! if let Some(block_id) = emitter.disconnected_block() {
+//!     let _ = chain.disconnect_from(block_id)?;
+//! }
+//! let _ = chain.apply_update(emitter.chain_update())?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+//! [BIP158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+//! [`IndexedTxGraph::apply_block_relevant`]: bdk_chain::IndexedTxGraph::apply_block_relevant
+//! [`LocalChain`]: bdk_chain::local_chain::LocalChain
+#![warn(missing_docs)]
+
+mod emitter;
+mod headers;
+mod peer;
+
+pub use bitcoin;
+use bitcoin::{
+    bip158,
+    consensus::encode,
+    network::constants::{Magic, ServiceFlags},
+    BlockHash,
+};
+pub use emitter::*;
+pub use peer::*;
+
+/// Errors that occur while communicating with a [`Peer`].
+#[derive(Debug)]
+pub enum Error {
+    /// Failure to read from or write to the connection.
+    Io(std::io::Error),
+    /// A message could not be decoded.
+    Encode(encode::Error),
+    /// A filter could not be matched.
+    Bip158(bip158::Error),
+    /// The peer does not serve witness blocks and compact block filters.
+    MissingServices(ServiceFlags),
+    /// The peer sent a message for another network.
+    UnexpectedMagic(Magic),
+    /// The header of the block with this hash does not connect to the known chain.
+    DisconnectedHeaders(BlockHash),
+    /// The header of the block with this hash does not have a valid proof of work.
+    InvalidProofOfWork(BlockHash),
+    /// The header of the block with this hash does not have the difficulty required by the
+    /// difficulty adjustment rules.
+    InvalidDifficulty(BlockHash),
+    /// The best chain of the peer replaces blocks of the known chain but has less work than them.
+    InsufficientWork {
+        /// The hash of the tip of the peer's best chain.
+        tip: BlockHash,
+    },
+    /// The peer sent the filter of another block than requested.
+    UnexpectedFilter {
+        /// The hash of the requested block.
+        expected: BlockHash,
+        /// The hash of the block the filter was sent for.
+        got: BlockHash,
+    },
+    /// The filter of the block with this hash does not match the filter headers sent by the peer,
+    /// or the filter headers do not connect.
+    InvalidFilter(BlockHash),
+    /// The peer does not have the block with this hash.
+    BlockNotFound(BlockHash),
+    /// The transactions of the block with this hash do not match its header.
+    InvalidBlock(BlockHash),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::Encode(e) => write!(f, "failed to decode message: {}", e),
+            Self::Bip158(e) => write!(f, "failed to match filter: {}", e),
+            Self::MissingServices(services) => write!(
+                f,
+                "peer does not serve witness blocks and compact block filters: {}",
+                services
+            ),
+            Self::UnexpectedMagic(magic) => {
+                write!(f, "peer sent a message for another network: {}", magic)
+            }
+            Self::DisconnectedHeaders(hash) => {
+                write!(f, "header of block {} does not connect", hash)
+            }
+            Self::InvalidProofOfWork(hash) => {
+                write!(f, "header of block {} has invalid proof of work", hash)
+            }
+            Self::InvalidDifficulty(hash) => {
+                write!(f, "header of block {} has invalid difficulty", hash)
+            }
+            Self::InsufficientWork { tip } => write!(
+                f,
+                "peer's best chain with tip {} has less work than the chain it replaces",
+                tip
+            ),
+            Self::InvalidFilter(hash) => write!(
+                f,
+                "filter of block {} does not match the filter headers",
+                hash
+            ),
+            Self::UnexpectedFilter { expected, got } => write!(
+                f,
+                "peer sent unexpected filter: expected={} got={}",
+                expected, got
+            ),
+            Self::BlockNotFound(hash) => write!(f, "peer does not have block {}", hash),
+            Self::InvalidBlock(hash) => {
+                write!(f, "transactions of block {} do not match its header", hash)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<encode::Error> for Error {
+    fn from(value: encode::Error) -> Self {
+        Self::Encode(value)
+    }
+}
+
+impl From<bip158::Error> for Error {
+    fn from(value: bip158::Error) -> Self {
+        Self::Bip158(value)
+    }
+}
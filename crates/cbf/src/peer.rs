@@ -0,0 +1,268 @@
+use std::{
+    io::{BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bitcoin::{
+    block::Header,
+    consensus::{encode, Decodable},
+    hashes::Hash,
+    network::{
+        address::Address,
+        constants::{Magic, ServiceFlags},
+        message::{NetworkMessage, RawNetworkMessage},
+        message_blockdata::{GetHeadersMessage, Inventory},
+        message_filter::{CFHeaders, CFilter, GetCFHeaders, GetCFilters},
+        message_network::VersionMessage,
+    },
+    Block, BlockHash, Network,
+};
+
+use crate::Error;
+
+/// The protocol version we announce to peers.
+const PROTOCOL_VERSION: u32 = 70016;
+
+/// The filter type of BIP158 basic filters.
+pub(crate) const BASIC_FILTER_TYPE: u8 = 0x00;
+
+/// The maximum number of headers a peer returns for a single `getheaders` request.
+pub(crate) const MAX_HEADERS_RESULTS: usize = 2000;
+
+/// The maximum number of filters that may be requested with a single `getcfilters` request.
+pub(crate) const MAX_GETCFILTERS_SIZE: usize = 1000;
+
+/// The maximum number of block locator hashes a peer accepts.
+pub(crate) const MAX_LOCATOR_SIZE: usize = 101;
+
+/// The default timeout for reading a message from the peer.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A blocking connection to a peer which serves [BIP157] compact block filters.
+///
+/// The connection only requests data from the peer. It does not relay transactions or announce
+/// blocks.
+///
+/// [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+#[derive(Debug)]
+pub struct Peer {
+    network: Network,
+    magic: Magic,
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+    services: ServiceFlags,
+    start_height: i32,
+}
+
+impl Peer {
+    /// Connect to the peer at `addr` and perform the version handshake.
+    ///
+    /// Returns [`Error::MissingServices`] if the peer does not serve witness blocks and compact
+    /// block filters.
+    pub fn connect(addr: SocketAddr, network: Network) -> Result<Self, Error> {
+        let stream = TcpStream::connect_timeout(&addr, DEFAULT_TIMEOUT)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let mut peer = Self {
+            network,
+            magic: network.magic(),
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            services: ServiceFlags::NONE,
+            start_height: 0,
+        };
+        peer.handshake(addr)?;
+        Ok(peer)
+    }
+
+    /// Set the timeout for reading a message from the peer, `None` blocks indefinitely.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.writer.set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// The network of the peer.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// The services announced by the peer.
+    pub fn services(&self) -> ServiceFlags {
+        self.services
+    }
+
+    /// The best height announced by the peer during the handshake.
+    pub fn start_height(&self) -> i32 {
+        self.start_height
+    }
+
+    fn handshake(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let version = VersionMessage {
+            version: PROTOCOL_VERSION,
+            services: ServiceFlags::NONE,
+            timestamp: now.as_secs() as i64,
+            receiver: Address::new(&addr, ServiceFlags::NONE),
+            sender: Address::new(&([0, 0, 0, 0], 0).into(), ServiceFlags::NONE),
+            // only used to detect connections to ourselves
+            nonce: now.as_nanos() as u64,
+            user_agent: concat!("/bdk_cbf:", env!("CARGO_PKG_VERSION"), "/").to_string(),
+            start_height: 0,
+            relay: false,
+        };
+        self.send(NetworkMessage::Version(version))?;
+
+        let (mut got_version, mut got_verack) = (false, false);
+        while !(got_version && got_verack) {
+            match self.recv()? {
+                NetworkMessage::Version(version) => {
+                    let required = ServiceFlags::WITNESS | ServiceFlags::COMPACT_FILTERS;
+                    if !version.services.has(required) {
+                        return Err(Error::MissingServices(version.services));
+                    }
+                    self.services = version.services;
+                    self.start_height = version.start_height;
+                    self.send(NetworkMessage::Verack)?;
+                    got_version = true;
+                }
+                NetworkMessage::Verack => got_verack = true,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Request the headers following the first block of `locator` that is in the peer's best
+    /// chain.
+    ///
+    /// At most [`MAX_HEADERS_RESULTS`] headers are returned.
+    pub(crate) fn get_headers(&mut self, locator: Vec<BlockHash>) -> Result<Vec<Header>, Error> {
+        self.send(NetworkMessage::GetHeaders(GetHeadersMessage {
+            version: PROTOCOL_VERSION,
+            locator_hashes: locator,
+            stop_hash: BlockHash::all_zeros(),
+        }))?;
+        self.recv_map(|msg| match msg {
+            NetworkMessage::Headers(headers) => Some(headers),
+            _ => None,
+        })
+    }
+
+    /// Request the header of the block of `hash`.
+    ///
+    /// A `getheaders` request without locator hashes returns the header of its stop hash only.
+    pub(crate) fn get_header(&mut self, hash: BlockHash) -> Result<Header, Error> {
+        self.send(NetworkMessage::GetHeaders(GetHeadersMessage {
+            version: PROTOCOL_VERSION,
+            locator_hashes: Vec::new(),
+            stop_hash: hash,
+        }))?;
+        self.recv_map(|msg| match msg {
+            NetworkMessage::Headers(headers) => Some(
+                headers
+                    .into_iter()
+                    .find(|header| header.block_hash() == hash)
+                    .ok_or(Error::BlockNotFound(hash)),
+            ),
+            _ => None,
+        })?
+    }
+
+    /// Request the basic filter hashes of the blocks from `start_height` up to the block of
+    /// `stop_hash`, along with the filter header of the block before.
+    pub(crate) fn get_cfheaders(
+        &mut self,
+        start_height: u32,
+        stop_hash: BlockHash,
+    ) -> Result<CFHeaders, Error> {
+        self.send(NetworkMessage::GetCFHeaders(GetCFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height,
+            stop_hash,
+        }))?;
+        self.recv_map(|msg| match msg {
+            NetworkMessage::CFHeaders(cfheaders)
+                if cfheaders.filter_type == BASIC_FILTER_TYPE
+                    && cfheaders.stop_hash == stop_hash =>
+            {
+                Some(cfheaders)
+            }
+            _ => None,
+        })
+    }
+
+    /// Request the basic filters of the blocks from `start_height` up to the block of `stop_hash`.
+    pub(crate) fn get_cfilters(
+        &mut self,
+        start_height: u32,
+        stop_hash: BlockHash,
+        count: usize,
+    ) -> Result<Vec<CFilter>, Error> {
+        debug_assert!(count <= MAX_GETCFILTERS_SIZE);
+        self.send(NetworkMessage::GetCFilters(GetCFilters {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height,
+            stop_hash,
+        }))?;
+        let mut filters = Vec::with_capacity(count);
+        while filters.len() < count {
+            filters.push(self.recv_map(|msg| match msg {
+                NetworkMessage::CFilter(filter) if filter.filter_type == BASIC_FILTER_TYPE => {
+                    Some(filter)
+                }
+                _ => None,
+            })?);
+        }
+        Ok(filters)
+    }
+
+    /// Request the block of `hash`, including witness data.
+    pub(crate) fn get_block(&mut self, hash: BlockHash) -> Result<Block, Error> {
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(hash)]))?;
+        self.recv_map(|msg| match msg {
+            NetworkMessage::Block(block) if block.block_hash() == hash => Some(Ok(block)),
+            NetworkMessage::NotFound(inv) if inv.contains(&Inventory::WitnessBlock(hash)) => {
+                Some(Err(Error::BlockNotFound(hash)))
+            }
+            _ => None,
+        })?
+    }
+
+    fn send(&mut self, payload: NetworkMessage) -> Result<(), Error> {
+        let msg = RawNetworkMessage {
+            magic: self.magic,
+            payload,
+        };
+        self.writer.write_all(&encode::serialize(&msg))?;
+        Ok(())
+    }
+
+    /// Receive the next message, answering pings along the way.
+    fn recv(&mut self) -> Result<NetworkMessage, Error> {
+        loop {
+            let msg = RawNetworkMessage::consensus_decode(&mut self.reader)?;
+            if msg.magic != self.magic {
+                return Err(Error::UnexpectedMagic(msg.magic));
+            }
+            match msg.payload {
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(nonce))?,
+                payload => return Ok(payload),
+            }
+        }
+    }
+
+    /// Receive messages until `f` maps one of them to `Some`, other messages are ignored.
+    fn recv_map<T, F>(&mut self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(NetworkMessage) -> Option<T>,
+    {
+        loop {
+            if let Some(item) = f(self.recv()?) {
+                return Ok(item);
+            }
+        }
+    }
+}
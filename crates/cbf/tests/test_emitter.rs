@@ -0,0 +1,213 @@
+use std::{net::SocketAddr, str::FromStr, time::Duration};
+
+use bdk_cbf::{Emitter, Peer};
+use bdk_chain::{
+    bitcoin::{Address, Amount, BlockHash, Network, Txid},
+    keychain::KeychainTxOutIndex,
+    local_chain::LocalChain,
+    miniscript::{Descriptor, DescriptorPublicKey},
+    ConfirmationHeightAnchor, IndexedTxGraph,
+};
+use bitcoind::bitcoincore_rpc::{self, RpcApi};
+
+const DESCRIPTOR: &str = "tr(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+
+struct TestEnv {
+    daemon: bitcoind::BitcoinD,
+    client: bitcoincore_rpc::Client,
+}
+
+impl TestEnv {
+    fn new() -> anyhow::Result<Self> {
+        let mut conf = bitcoind::Conf::default();
+        conf.p2p = bitcoind::P2P::Yes;
+        conf.args.push("-blockfilterindex=1");
+        conf.args.push("-peerblockfilters=1");
+        let daemon = match std::env::var_os("TEST_BITCOIND") {
+            Some(bitcoind_path) => bitcoind::BitcoinD::with_conf(bitcoind_path, &conf),
+            None => bitcoind::BitcoinD::from_downloaded_with_conf(&conf),
+        }?;
+        let client = bitcoincore_rpc::Client::new(
+            &daemon.rpc_url(),
+            bitcoincore_rpc::Auth::CookieFile(daemon.params.cookie_file.clone()),
+        )?;
+        Ok(Self { daemon, client })
+    }
+
+    fn mine_blocks(&self, count: usize) -> anyhow::Result<Vec<BlockHash>> {
+        let address = self.client.get_new_address(None, None)?.assume_checked();
+        let hashes = self.client.generate_to_address(count as _, &address)?;
+        self.wait_for_filter_index()?;
+        Ok(hashes)
+    }
+
+    fn reorg(&self, count: usize) -> anyhow::Result<Vec<BlockHash>> {
+        let mut hash = self.client.get_best_block_hash()?;
+        for _ in 0..count {
+            let prev_hash = self.client.get_block_info(&hash)?.previousblockhash;
+            self.client.invalidate_block(&hash)?;
+            match prev_hash {
+                Some(prev_hash) => hash = prev_hash,
+                None => break,
+            }
+        }
+        self.mine_blocks(count)
+    }
+
+    /// The filter index is built in the background, peers are not served filters of blocks
+    /// which are not indexed yet.
+    fn wait_for_filter_index(&self) -> anyhow::Result<()> {
+        let tip = self.client.get_best_block_hash()?;
+        for _ in 0..100 {
+            if self.client.get_block_filter(&tip).is_ok() {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        anyhow::bail!("filter index is not synced")
+    }
+
+    fn peer(&self) -> anyhow::Result<Peer> {
+        let addr = SocketAddr::from(self.daemon.params.p2p_socket.expect("p2p must be enabled"));
+        Ok(Peer::connect(addr, Network::Regtest)?)
+    }
+}
+
+fn sync(
+    peer: &mut Peer,
+    chain: &mut LocalChain,
+    graph: &mut IndexedTxGraph<ConfirmationHeightAnchor, KeychainTxOutIndex<()>>,
+) -> anyhow::Result<Vec<u32>> {
+    let mut emitted_heights = Vec::new();
+    let mut emitter = Emitter::new(peer, chain.tip());
+    while let Some((height, block)) = emitter.next_block(
+        graph
+            .index
+            .inner()
+            .all_spks()
+            .values()
+            .map(|spk| spk.as_script()),
+    )? {
+        emitted_heights.push(height);
+        let _ = graph.apply_block_relevant(block, height);
+    }
+    if let Some(block_id) = emitter.disconnected_block() {
+        let _ = chain.disconnect_from(block_id)?;
+    }
+    let _ = chain.apply_update(emitter.chain_update())?;
+    Ok(emitted_heights)
+}
+
+/// Only blocks which contain script pubkeys of the [`KeychainTxOutIndex`] (including its lookahead)
+/// are emitted, and the chain update connects them to the local chain.
+#[test]
+pub fn test_sync_keychain_spks() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(101)?;
+
+    let descriptor = Descriptor::<DescriptorPublicKey>::from_str(DESCRIPTOR)?;
+    let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, KeychainTxOutIndex<()>>::default();
+    graph.index.add_keychain((), descriptor.clone());
+    graph.index.set_lookahead(&(), 10);
+
+    // pay to an unrevealed script pubkey within the lookahead
+    let spk = descriptor.at_derivation_index(3)?.script_pubkey();
+    let address = Address::from_script(&spk, Network::Regtest)?;
+    let txid = env.client.send_to_address(
+        &address,
+        Amount::from_sat(10_000),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )?;
+    let block_hash = env.mine_blocks(1)?[0];
+    env.mine_blocks(5)?;
+
+    let genesis_hash = env.client.get_block_hash(0)?;
+    let (mut chain, _) = LocalChain::from_genesis_hash(genesis_hash);
+    let mut peer = env.peer()?;
+    let emitted_heights = sync(&mut peer, &mut chain, &mut graph)?;
+
+    assert_eq!(emitted_heights, vec![102]);
+    assert_eq!(
+        graph
+            .graph()
+            .full_txs()
+            .map(|tx| tx.txid)
+            .collect::<Vec<Txid>>(),
+        vec![txid]
+    );
+    assert_eq!(graph.index.last_revealed_index(&()), Some(3));
+    assert_eq!(chain.blocks().get(&102).copied(), Some(block_hash));
+    assert_eq!(chain.tip().height(), 107);
+    assert_eq!(chain.tip().hash(), env.client.get_best_block_hash()?);
+
+    // nothing new to emit
+    assert_eq!(sync(&mut peer, &mut chain, &mut graph)?, Vec::<u32>::new());
+    assert_eq!(chain.tip().hash(), env.client.get_best_block_hash()?);
+
+    Ok(())
+}
+
+/// Checkpoints which are reorged out of the peer's best chain are replaced by the chain update.
+#[test]
+pub fn test_reorg_replaces_checkpoints() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(10)?;
+
+    let descriptor = Descriptor::<DescriptorPublicKey>::from_str(DESCRIPTOR)?;
+    let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, KeychainTxOutIndex<()>>::default();
+    graph.index.add_keychain((), descriptor);
+    graph.index.set_lookahead(&(), 10);
+
+    let genesis_hash = env.client.get_block_hash(0)?;
+    let (mut chain, _) = LocalChain::from_genesis_hash(genesis_hash);
+    let mut peer = env.peer()?;
+    sync(&mut peer, &mut chain, &mut graph)?;
+    let old_tip = chain.tip().block_id();
+    assert_eq!(old_tip.height, 10);
+
+    let new_hashes = env.reorg(2)?;
+    sync(&mut peer, &mut chain, &mut graph)?;
+    assert_eq!(chain.tip().height(), 10);
+    assert_ne!(chain.tip().hash(), old_tip.hash);
+    assert_eq!(chain.tip().hash(), new_hashes[1]);
+
+    Ok(())
+}
+
+/// A best chain which replaces checkpoints with fewer blocks of the same difficulty has less work,
+/// and is rejected.
+#[test]
+pub fn test_reorg_with_less_work_is_rejected() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(10)?;
+
+    let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, KeychainTxOutIndex<()>>::default();
+    let genesis_hash = env.client.get_block_hash(0)?;
+    let (mut chain, _) = LocalChain::from_genesis_hash(genesis_hash);
+    let mut peer = env.peer()?;
+    sync(&mut peer, &mut chain, &mut graph)?;
+    let old_tip = chain.tip().block_id();
+
+    // replace 3 blocks with 2
+    let mut hash = old_tip.hash;
+    for _ in 0..3 {
+        let prev_hash = env.client.get_block_info(&hash)?.previousblockhash;
+        env.client.invalidate_block(&hash)?;
+        hash = prev_hash.expect("must not be genesis");
+    }
+    let new_hashes = env.mine_blocks(2)?;
+
+    let mut emitter = Emitter::new(&mut peer, chain.tip());
+    match emitter.next_block(core::iter::empty::<&bdk_chain::bitcoin::Script>()) {
+        Err(bdk_cbf::Error::InsufficientWork { tip }) => assert_eq!(tip, new_hashes[1]),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(chain.tip().block_id(), old_tip);
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use alloc::string::String;
+use bitcoin::Transaction;
+
+#[cfg(feature = "async")]
+use alloc::boxed::Box;
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+/// Broadcasts transactions to the Bitcoin network.
+///
+/// The chain source crates provide implementations backed by their servers (e.g. Esplora's
+/// `POST /tx`, Electrum's `blockchain.transaction.broadcast` and bitcoind's
+/// `sendrawtransaction`).
+pub trait Broadcaster {
+    /// The error of the client, for failures other than the transaction being rejected.
+    type Error: core::fmt::Debug;
+
+    /// Broadcast `tx`.
+    ///
+    /// A transaction which is already in the mempool of the server is considered to be broadcast
+    /// successfully.
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>>;
+}
+
+/// Asynchronously broadcasts transactions to the Bitcoin network.
+///
+/// This is the async counterpart of [`Broadcaster`].
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait BroadcasterAsync {
+    /// The error of the client, for failures other than the transaction being rejected.
+    type Error: core::fmt::Debug;
+
+    /// Broadcast `tx`.
+    ///
+    /// A transaction which is already in the mempool of the server is considered to be broadcast
+    /// successfully.
+    async fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>>;
+}
+
+/// An error from broadcasting a transaction.
+///
+/// The rejection variants hold the reason given by the server.
+#[derive(Debug)]
+pub enum BroadcastError<E> {
+    /// The transaction pays too little fee, either below the minimum relay or mempool fee rate, or
+    /// too little to replace the transactions it conflicts with.
+    InsufficientFee(String),
+    /// An input of the transaction does not exist or is already spent.
+    MissingInputs(String),
+    /// The transaction is already confirmed.
+    AlreadyInChain(String),
+    /// The transaction is rejected for another reason.
+    Rejected(String),
+    /// The client failed to broadcast the transaction.
+    Client(E),
+}
+
+impl<E> BroadcastError<E> {
+    /// Map the `reason` a node gives for rejecting a transaction to a [`BroadcastError`].
+    ///
+    /// The reasons are the ones of Bitcoin Core, which Electrum and Esplora servers forward.
+    /// Returns `None` if the reason is that the transaction is already in the mempool, as this is
+    /// not a failure to broadcast.
+    pub fn from_reject_reason(reason: impl Into<String>) -> Option<Self> {
+        let reason = reason.into();
+        let lowercase = reason.to_lowercase();
+        let contains = |patterns: &[&str]| patterns.iter().any(|p| lowercase.contains(p));
+
+        if contains(&["txn-already-in-mempool", "txn-already-known"]) {
+            None
+        } else if contains(&[
+            "min relay fee not met",
+            "mempool min fee not met",
+            "insufficient fee",
+        ]) {
+            Some(Self::InsufficientFee(reason))
+        } else if contains(&["missingorspent", "missing-inputs", "missing inputs"]) {
+            Some(Self::MissingInputs(reason))
+        } else if contains(&[
+            "already in block chain",
+            "already in utxo set",
+            "txn-already-confirmed",
+        ]) {
+            Some(Self::AlreadyInChain(reason))
+        } else {
+            Some(Self::Rejected(reason))
+        }
+    }
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for BroadcastError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InsufficientFee(reason) => write!(f, "insufficient fee: {}", reason),
+            Self::MissingInputs(reason) => write!(f, "missing inputs: {}", reason),
+            Self::AlreadyInChain(reason) => write!(f, "already in chain: {}", reason),
+            Self::Rejected(reason) => write!(f, "transaction rejected: {}", reason),
+            Self::Client(err) => write!(f, "failed to broadcast: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: core::fmt::Debug + core::fmt::Display> std::error::Error for BroadcastError<E> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Error = BroadcastError<()>;
+
+    #[test]
+    fn reject_reasons_are_mapped() {
+        assert!(matches!(
+            Error::from_reject_reason("min relay fee not met, 100 < 141"),
+            Some(Error::InsufficientFee(_))
+        ));
+        assert!(matches!(
+            Error::from_reject_reason(
+                r#"sendrawtransaction RPC error: {"code":-26,"message":"insufficient fee, rejecting replacement"}"#
+            ),
+            Some(Error::InsufficientFee(_))
+        ));
+        assert!(matches!(
+            Error::from_reject_reason("bad-txns-inputs-missingorspent"),
+            Some(Error::MissingInputs(_))
+        ));
+        assert!(matches!(
+            Error::from_reject_reason("Transaction outputs already in utxo set"),
+            Some(Error::AlreadyInChain(_))
+        ));
+        assert!(matches!(
+            Error::from_reject_reason("tx-size-small"),
+            Some(Error::Rejected(reason)) if reason == "tx-size-small"
+        ));
+        assert!(Error::from_reject_reason("txn-already-in-mempool").is_none());
+    }
+}
@@ -0,0 +1,90 @@
+use core::convert::Infallible;
+
+use bitcoin::FeeRate;
+
+#[cfg(feature = "async")]
+use alloc::boxed::Box;
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+/// A source of fee rate estimates.
+///
+/// The chain source crates provide implementations backed by their servers (e.g. Esplora's
+/// `fee-estimates`, Electrum's `blockchain.estimatefee` and bitcoind's `estimatesmartfee`). A
+/// [`FeeRate`] is itself an estimator which always returns that fee rate.
+pub trait FeeEstimator {
+    /// The error returned when no estimate can be obtained.
+    type Error: core::fmt::Debug;
+
+    /// Estimate the fee rate a transaction needs to pay to be confirmed within `target` blocks.
+    fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error>;
+}
+
+impl FeeEstimator for FeeRate {
+    type Error = Infallible;
+
+    fn estimate_fee_rate(&self, _target: u16) -> Result<FeeRate, Self::Error> {
+        Ok(*self)
+    }
+}
+
+impl<E: FeeEstimator + ?Sized> FeeEstimator for &E {
+    type Error = E::Error;
+
+    fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error> {
+        (**self).estimate_fee_rate(target)
+    }
+}
+
+/// Convert an estimate in sat/kvB, the unit fee estimates are usually given in, to a [`FeeRate`].
+///
+/// A [`FeeRate`] is in sat/kwu and a kvB is 4 kwu, so the fee rate is rounded up to the next whole
+/// sat/kwu. Estimators use this so that the same estimate results in the same fee rate whatever the
+/// source, and so that a transaction never pays less than the estimate.
+pub fn fee_rate_from_sat_per_kvb(sat_per_kvb: u64) -> FeeRate {
+    FeeRate::from_sat_per_kwu((sat_per_kvb + 3) / 4)
+}
+
+/// An asynchronous source of fee rate estimates.
+///
+/// This is the async counterpart of [`FeeEstimator`].
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait FeeEstimatorAsync {
+    /// The error returned when no estimate can be obtained.
+    type Error: core::fmt::Debug;
+
+    /// Estimate the fee rate a transaction needs to pay to be confirmed within `target` blocks.
+    async fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error>;
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl FeeEstimatorAsync for FeeRate {
+    type Error = Infallible;
+
+    async fn estimate_fee_rate(&self, _target: u16) -> Result<FeeRate, Self::Error> {
+        Ok(*self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fee_rate_from_sat_per_kvb_rounds_up() {
+        assert_eq!(fee_rate_from_sat_per_kvb(0), FeeRate::ZERO);
+        assert_eq!(fee_rate_from_sat_per_kvb(1), FeeRate::from_sat_per_kwu(1));
+        assert_eq!(
+            fee_rate_from_sat_per_kvb(4_000),
+            FeeRate::from_sat_per_kwu(1_000)
+        );
+        assert_eq!(
+            fee_rate_from_sat_per_kvb(4_001),
+            FeeRate::from_sat_per_kwu(1_001)
+        );
+    }
+}
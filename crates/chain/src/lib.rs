@@ -38,6 +38,10 @@ mod chain_oracle;
 pub use chain_oracle::*;
 mod persist;
 pub use persist::*;
+mod fee_estimator;
+pub use fee_estimator::*;
+mod broadcaster;
+pub use broadcaster::*;
 
 #[doc(hidden)]
 pub mod example_utils;
@@ -2,6 +2,11 @@ use core::convert::Infallible;
 
 use crate::Append;
 
+#[cfg(feature = "async")]
+use alloc::boxed::Box;
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
 /// `Persist` wraps a [`PersistBackend`] (`B`) to create a convenient staging area for changes (`C`)
 /// before they are persisted.
 ///
@@ -16,7 +21,6 @@ pub struct Persist<B, C> {
 
 impl<B, C> Persist<B, C>
 where
-    B: PersistBackend<C>,
     C: Default + Append,
 {
     /// Create a new [`Persist`] from [`PersistBackend`].
@@ -46,12 +50,40 @@ where
     /// # Error
     ///
     /// Returns a backend-defined error if this fails.
-    pub fn commit(&mut self) -> Result<Option<C>, B::WriteError> {
+    pub fn commit(&mut self) -> Result<Option<C>, B::WriteError>
+    where
+        B: PersistBackend<C>,
+    {
+        if self.stage.is_empty() {
+            return Ok(None);
+        }
+        self.backend
+            .write_changes(&self.stage)
+            // if written successfully, take and return `self.stage`
+            .map(|_| Some(core::mem::take(&mut self.stage)))
+    }
+
+    /// Commit the staged changes to the underlying asynchronous persistence backend.
+    ///
+    /// This is the [`PersistBackendAsync`] counterpart of [`commit`]. Changes that are committed
+    /// (if any) are returned.
+    ///
+    /// # Error
+    ///
+    /// Returns a backend-defined error if this fails.
+    ///
+    /// [`commit`]: Self::commit
+    #[cfg(feature = "async")]
+    pub async fn commit_async(&mut self) -> Result<Option<C>, B::WriteError>
+    where
+        B: PersistBackendAsync<C>,
+    {
         if self.stage.is_empty() {
             return Ok(None);
         }
         self.backend
             .write_changes(&self.stage)
+            .await
             // if written successfully, take and return `self.stage`
             .map(|_| Some(core::mem::take(&mut self.stage)))
     }
@@ -95,3 +127,47 @@ impl<C> PersistBackend<C> for () {
         Ok(None)
     }
 }
+
+/// An asynchronous persistence backend for [`Persist`].
+///
+/// This is the async counterpart of [`PersistBackend`], for backends such as async database drivers
+/// or remote stores. `C` represents the changeset; a datatype that records changes made to
+/// in-memory data structures that are to be persisted, or retrieved from persistence.
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait PersistBackendAsync<C> {
+    /// The error the backend returns when it fails to write.
+    type WriteError: core::fmt::Debug;
+
+    /// The error the backend returns when it fails to load changesets `C`.
+    type LoadError: core::fmt::Debug;
+
+    /// Writes a changeset to the persistence backend.
+    ///
+    /// The same guarantees as [`PersistBackend::write_changes`] apply.
+    async fn write_changes(&mut self, changeset: &C) -> Result<(), Self::WriteError>;
+
+    /// Return the aggregate changeset `C` from persistence.
+    async fn load_from_persistence(&mut self) -> Result<Option<C>, Self::LoadError>;
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<C> PersistBackendAsync<C> for ()
+where
+    C: Send + Sync,
+{
+    type WriteError = Infallible;
+
+    type LoadError = Infallible;
+
+    async fn write_changes(&mut self, _changeset: &C) -> Result<(), Self::WriteError> {
+        Ok(())
+    }
+
+    async fn load_from_persistence(&mut self) -> Result<Option<C>, Self::LoadError> {
+        Ok(None)
+    }
+}
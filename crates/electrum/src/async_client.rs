@@ -0,0 +1,679 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use bdk_chain::bitcoin::{
+    block::Header,
+    consensus::encode::{deserialize, serialize_hex},
+    hashes::hex::FromHex,
+    Script, Transaction, Txid,
+};
+use electrum_client::{
+    Error, GetHeadersRes, GetHistoryRes, HeaderNotification, Param, Request, ScriptHash,
+    ScriptStatus, ToElectrumScriptHash,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::join_all,
+    StreamExt,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+type ResponseSender = oneshot::Sender<Result<Value, Error>>;
+
+/// An asynchronous Electrum client.
+///
+/// Requests are pipelined over a single connection: the requests of a batch are written
+/// back-to-back without waiting for the responses in between, and concurrent calls share the
+/// connection. Requests are written by a background task and responses are read by another,
+/// which routes them to the callers by request id.
+///
+/// Calls are cancellation safe: the requests of a call are handed to the writing task as a whole,
+/// so dropping the future of a call never leaves a partly written request on the connection, and
+/// the call stops waiting for its responses.
+///
+/// The background tasks are spawned on the [`tokio`] runtime the client is created in, and are
+/// stopped when the client is dropped.
+pub struct AsyncClient {
+    writes: mpsc::UnboundedSender<Vec<u8>>,
+    requests: Arc<Mutex<Requests>>,
+    next_id: AtomicUsize,
+    reader: JoinHandle<()>,
+    writer: JoinHandle<()>,
+}
+
+/// A notification of a subscription, see [`AsyncClient::notifications`].
+#[derive(Debug)]
+pub enum Notification {
+    /// The tip of the server's best chain changed.
+    Header(HeaderNotification),
+    /// The status of a subscribed script changed.
+    ScriptStatus {
+        /// The script hash of the script.
+        script_hash: ScriptHash,
+        /// The new status of the script, `None` if the script no longer has history.
+        status: Option<ScriptStatus>,
+    },
+}
+
+/// Requests that are waiting for a response.
+#[derive(Default)]
+struct Requests {
+    pending: HashMap<usize, ResponseSender>,
+    /// Where notifications are delivered to, if anyone is listening.
+    notifications: Option<mpsc::UnboundedSender<Notification>>,
+    /// Set once the connection is closed, later requests fail right away.
+    closed: bool,
+}
+
+impl AsyncClient {
+    /// Connect to the Electrum server at `url` over plaintext TCP.
+    ///
+    /// `url` is of the form `tcp://host:port` or `host:port`. For TLS (or proxied) connections,
+    /// establish the stream yourself and use [`AsyncClient::from_stream`].
+    pub async fn new(url: &str) -> Result<Self, Error> {
+        if url.starts_with("ssl://") {
+            return Err(Error::Message(
+                "ssl:// is not supported, use `AsyncClient::from_stream` with a TLS stream"
+                    .to_string(),
+            ));
+        }
+        let stream = TcpStream::connect(url.trim_start_matches("tcp://")).await?;
+        stream.set_nodelay(true)?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Create a client which talks to an Electrum server over an established `stream`.
+    ///
+    /// This must be called from within a [`tokio`] runtime.
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let requests = Arc::new(Mutex::new(Requests::default()));
+        let (writes, pending_writes) = mpsc::unbounded();
+        let reader = tokio::spawn(read_responses(reader, requests.clone()));
+        let writer = tokio::spawn(write_requests(writer, pending_writes, requests.clone()));
+        Self {
+            writes,
+            requests,
+            next_id: AtomicUsize::new(0),
+            reader,
+            writer,
+        }
+    }
+
+    /// Get the header of the tip of the server's best chain, together with its height.
+    pub async fn block_headers_subscribe(&self) -> Result<HeaderNotification, Error> {
+        let value = self.call("blockchain.headers.subscribe", vec![]).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Get the header of the block at `height`.
+    pub async fn block_header(&self, height: usize) -> Result<Header, Error> {
+        let value = self
+            .call("blockchain.block.header", vec![Param::Usize(height)])
+            .await?;
+        deserialize_hex(value)
+    }
+
+    /// Get the headers of the blocks at `heights` in a single batch.
+    pub fn batch_block_header(
+        &self,
+        heights: impl IntoIterator<Item = u32>,
+    ) -> impl Future<Output = Result<Vec<Header>, Error>> + Send + '_ {
+        let calls = heights
+            .into_iter()
+            .map(|height| ("blockchain.block.header", vec![Param::U32(height)]))
+            .collect();
+        async move {
+            self.batch_call(calls)
+                .await
+                .into_iter()
+                .map(|value| deserialize_hex(value?))
+                .collect()
+        }
+    }
+
+    /// Get `count` consecutive headers starting from `start_height`.
+    pub async fn block_headers(
+        &self,
+        start_height: usize,
+        count: usize,
+    ) -> Result<GetHeadersRes, Error> {
+        let value = self
+            .call(
+                "blockchain.block.headers",
+                vec![Param::Usize(start_height), Param::Usize(count)],
+            )
+            .await?;
+        let mut res: GetHeadersRes = serde_json::from_value(value)?;
+        res.headers = res
+            .raw_headers
+            .chunks(80)
+            .take(res.count)
+            .map(deserialize)
+            .collect::<Result<_, _>>()?;
+        res.raw_headers.clear();
+        Ok(res)
+    }
+
+    /// Get the history of `script`.
+    pub async fn script_get_history(&self, script: &Script) -> Result<Vec<GetHistoryRes>, Error> {
+        self.batch_script_get_history([script])
+            .await
+            .map(|mut histories| histories.remove(0))
+    }
+
+    /// Get the histories of `scripts` in a single batch.
+    pub fn batch_script_get_history<'s>(
+        &self,
+        scripts: impl IntoIterator<Item = &'s Script>,
+    ) -> impl Future<Output = Result<Vec<Vec<GetHistoryRes>>, Error>> + Send + '_ {
+        let calls = scripts
+            .into_iter()
+            .map(|script| {
+                (
+                    "blockchain.scripthash.get_history",
+                    vec![script_hash_param(script)],
+                )
+            })
+            .collect();
+        async move {
+            self.batch_call(calls)
+                .await
+                .into_iter()
+                .map(|value| Ok(serde_json::from_value(value?)?))
+                .collect()
+        }
+    }
+
+    /// Subscribe to the status of `script`, returns the current status (`None` if the script has
+    /// no history).
+    ///
+    /// Changes of the status are delivered as [`Notification::ScriptStatus`].
+    pub async fn script_subscribe(&self, script: &Script) -> Result<Option<ScriptStatus>, Error> {
+        self.batch_script_subscribe([script])
+            .await
+            .map(|mut statuses| statuses.remove(0))
+    }
+
+    /// Subscribe to the statuses of `scripts` in a single batch.
+    pub fn batch_script_subscribe<'s>(
+        &self,
+        scripts: impl IntoIterator<Item = &'s Script>,
+    ) -> impl Future<Output = Result<Vec<Option<ScriptStatus>>, Error>> + Send + '_ {
+        let calls = scripts
+            .into_iter()
+            .map(|script| {
+                (
+                    "blockchain.scripthash.subscribe",
+                    vec![script_hash_param(script)],
+                )
+            })
+            .collect();
+        async move {
+            self.batch_call(calls)
+                .await
+                .into_iter()
+                .map(|value| Ok(serde_json::from_value(value?)?))
+                .collect()
+        }
+    }
+
+    /// Unsubscribe from the status of `script`, returns whether the script was subscribed to.
+    pub async fn script_unsubscribe(&self, script: &Script) -> Result<bool, Error> {
+        let value = self
+            .call(
+                "blockchain.scripthash.unsubscribe",
+                vec![script_hash_param(script)],
+            )
+            .await?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Receive the notifications of subscriptions made with this client.
+    ///
+    /// Only notifications which arrive after this call are delivered, and only to the receiver of
+    /// the latest call. The receiver ends once the connection is closed.
+    pub fn notifications(&self) -> mpsc::UnboundedReceiver<Notification> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut requests = lock(&self.requests);
+        // if the connection is already closed, the sender is dropped right away
+        if !requests.closed {
+            requests.notifications = Some(sender);
+        }
+        receiver
+    }
+
+    /// Estimate the fee rate (in BTC/kvB) needed for a transaction to be confirmed within `number`
+    /// blocks. The server returns `-1` if it does not have enough information for an estimate.
+    pub async fn estimate_fee(&self, number: usize) -> Result<f64, Error> {
+        let value = self
+            .call("blockchain.estimatefee", vec![Param::Usize(number)])
+            .await?;
+        value.as_f64().ok_or(Error::InvalidResponse(value))
+    }
+
+    /// Get the transaction of `txid`.
+    pub async fn transaction_get(&self, txid: &Txid) -> Result<Transaction, Error> {
+        self.batch_transaction_get([txid])
+            .await
+            .map(|mut txs| txs.remove(0))
+    }
+
+    /// Get the transactions of `txids` in a single batch.
+    pub fn batch_transaction_get<'t>(
+        &self,
+        txids: impl IntoIterator<Item = &'t Txid>,
+    ) -> impl Future<Output = Result<Vec<Transaction>, Error>> + Send + '_ {
+        let results = self.try_batch_transaction_get(txids);
+        async move { results.await.into_iter().collect() }
+    }
+
+    /// Broadcast `tx`, returns its txid.
+    pub async fn transaction_broadcast(&self, tx: &Transaction) -> Result<Txid, Error> {
+        let value = self
+            .call(
+                "blockchain.transaction.broadcast",
+                vec![Param::String(serialize_hex(tx))],
+            )
+            .await?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Get the transactions of `txids` in a single batch, with a result per transaction.
+    pub(crate) fn try_batch_transaction_get<'t>(
+        &self,
+        txids: impl IntoIterator<Item = &'t Txid>,
+    ) -> impl Future<Output = Vec<Result<Transaction, Error>>> + Send + '_ {
+        let calls = txids
+            .into_iter()
+            .map(|txid| {
+                (
+                    "blockchain.transaction.get",
+                    vec![Param::String(txid.to_string())],
+                )
+            })
+            .collect();
+        async move {
+            self.batch_call(calls)
+                .await
+                .into_iter()
+                .map(|value| deserialize_hex(value?))
+                .collect()
+        }
+    }
+
+    async fn call(&self, method: &'static str, params: Vec<Param>) -> Result<Value, Error> {
+        self.batch_call(vec![(method, params)]).await.remove(0)
+    }
+
+    /// Write all `calls` at once, then wait for all of their responses.
+    async fn batch_call(
+        &self,
+        calls: Vec<(&'static str, Vec<Param>)>,
+    ) -> Vec<Result<Value, Error>> {
+        let count = calls.len();
+        let mut raw = Vec::new();
+        let mut ids = Vec::with_capacity(count);
+        let mut receivers = Vec::with_capacity(count);
+        {
+            let mut requests = lock(&self.requests);
+            if requests.closed {
+                return (0..count).map(|_| Err(connection_closed())).collect();
+            }
+            for (method, params) in calls {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                serde_json::to_writer(&mut raw, &Request::new_id(id, method, params))
+                    .expect("request must serialize");
+                raw.push(b'\n');
+                let (sender, receiver) = oneshot::channel();
+                requests.pending.insert(id, sender);
+                ids.push(id);
+                receivers.push(receiver);
+            }
+            // queued while holding the lock, so that nothing is queued after the connection is
+            // closed and the pending requests are failed
+            if self.writes.unbounded_send(raw).is_err() {
+                for id in &ids {
+                    requests.pending.remove(id);
+                }
+                return (0..count).map(|_| Err(connection_closed())).collect();
+            }
+        }
+        // forget the requests if this future is dropped before all responses arrive
+        let _guard = PendingGuard {
+            requests: &self.requests,
+            ids,
+        };
+
+        join_all(receivers.into_iter().map(|receiver| async move {
+            receiver.await.unwrap_or_else(|_| Err(connection_closed()))
+        }))
+        .await
+    }
+}
+
+/// Removes requests from the pending requests when dropped.
+struct PendingGuard<'a> {
+    requests: &'a Mutex<Requests>,
+    ids: Vec<usize>,
+}
+
+impl<'a> Drop for PendingGuard<'a> {
+    fn drop(&mut self) {
+        let mut requests = lock(self.requests);
+        for id in &self.ids {
+            requests.pending.remove(id);
+        }
+    }
+}
+
+impl fmt::Debug for AsyncClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncClient")
+            .field("next_id", &self.next_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+/// Write the requests queued in `writes` to `writer` until the client is dropped or a write
+/// fails, in which case the connection is closed.
+async fn write_requests<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut writes: mpsc::UnboundedReceiver<Vec<u8>>,
+    requests: Arc<Mutex<Requests>>,
+) {
+    while let Some(raw) = writes.next().await {
+        let result = match writer.write_all(&raw).await {
+            Ok(()) => writer.flush().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            close(&requests, || {
+                io::Error::new(err.kind(), err.to_string()).into()
+            });
+            return;
+        }
+    }
+}
+
+/// Read responses from `reader` until the connection is closed, and route them to the pending
+/// requests.
+async fn read_responses<R: AsyncRead + Unpin>(reader: R, requests: Arc<Mutex<Requests>>) {
+    let mut lines = BufReader::new(reader).lines();
+    // a line that is not JSON means the connection can no longer be trusted
+    while let Ok(Some(line)) = lines.next_line().await {
+        match serde_json::from_str(&line) {
+            Ok(response) => handle_response(&requests, response),
+            Err(_) => break,
+        }
+    }
+
+    close(&requests, connection_closed);
+}
+
+/// Mark the connection as closed and fail the pending requests with `error`.
+fn close(requests: &Mutex<Requests>, error: impl Fn() -> Error) {
+    let mut requests = lock(requests);
+    requests.closed = true;
+    requests.notifications = None;
+    for (_, sender) in requests.pending.drain() {
+        let _ = sender.send(Err(error()));
+    }
+}
+
+fn handle_response(requests: &Mutex<Requests>, mut response: Value) {
+    // notifications do not have an id
+    let id = match response.get("id").and_then(Value::as_u64) {
+        Some(id) => id as usize,
+        None => return handle_notification(requests, response),
+    };
+    let sender = match lock(requests).pending.remove(&id) {
+        Some(sender) => sender,
+        None => return,
+    };
+    let result = match response.get_mut("error").map(Value::take) {
+        Some(Value::Null) | None => match response.get_mut("result") {
+            Some(result) => Ok(result.take()),
+            None => Err(Error::InvalidResponse(response)),
+        },
+        Some(error) => Err(Error::Protocol(error)),
+    };
+    let _ = sender.send(result);
+}
+
+fn handle_notification(requests: &Mutex<Requests>, mut notification: Value) {
+    let params = notification
+        .get_mut("params")
+        .map(Value::take)
+        .unwrap_or_default();
+    let notification = match notification.get("method").and_then(Value::as_str) {
+        Some("blockchain.headers.subscribe") => {
+            match serde_json::from_value::<[HeaderNotification; 1]>(params) {
+                Ok([header]) => Notification::Header(header),
+                Err(_) => return,
+            }
+        }
+        Some("blockchain.scripthash.subscribe") => {
+            match serde_json::from_value::<(ScriptHash, Option<ScriptStatus>)>(params) {
+                Ok((script_hash, status)) => Notification::ScriptStatus {
+                    script_hash,
+                    status,
+                },
+                Err(_) => return,
+            }
+        }
+        _ => return,
+    };
+    let mut requests = lock(requests);
+    if let Some(sender) = &requests.notifications {
+        if sender.unbounded_send(notification).is_err() {
+            // the receiver is dropped
+            requests.notifications = None;
+        }
+    }
+}
+
+fn script_hash_param(script: &Script) -> Param {
+    let script_hash =
+        serde_json::to_value(script.to_electrum_scripthash()).expect("script hash must serialize");
+    Param::String(
+        script_hash
+            .as_str()
+            .expect("script hash must serialize to a string")
+            .to_string(),
+    )
+}
+
+fn deserialize_hex<T: bdk_chain::bitcoin::consensus::Decodable>(value: Value) -> Result<T, Error> {
+    match value.as_str() {
+        Some(hex) => Ok(deserialize(&Vec::<u8>::from_hex(hex)?)?),
+        None => Err(Error::InvalidResponse(value)),
+    }
+}
+
+fn lock(requests: &Mutex<Requests>) -> MutexGuard<'_, Requests> {
+    requests.lock().expect("lock must not be poisoned")
+}
+
+pub(crate) fn connection_closed() -> Error {
+    Error::IOError(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "connection to the electrum server is closed",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_chain::bitcoin::{
+        block::Version, consensus::encode::serialize_hex, hashes::Hash, CompactTarget,
+    };
+    use futures::FutureExt;
+    use serde_json::json;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Read `count` requests from the client's side of `server`.
+    async fn read_requests(
+        server: &mut tokio::io::Lines<BufReader<DuplexStream>>,
+        count: usize,
+    ) -> Vec<Value> {
+        let mut requests = Vec::new();
+        for _ in 0..count {
+            let line = server.next_line().await.unwrap().unwrap();
+            requests.push(serde_json::from_str(&line).unwrap());
+        }
+        requests
+    }
+
+    async fn respond(server: &mut tokio::io::Lines<BufReader<DuplexStream>>, response: Value) {
+        let mut line = response.to_string();
+        line.push('\n');
+        server
+            .get_mut()
+            .get_mut()
+            .write_all(line.as_bytes())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn batch_requests_are_pipelined_and_routed_by_id() {
+        let (client_stream, server_stream) = duplex(1 << 16);
+        let client = AsyncClient::from_stream(client_stream);
+        let mut server = BufReader::new(server_stream).lines();
+
+        let heights = [1_u32, 2, 3];
+        let server = async move {
+            // all requests of the batch are written before any response
+            let requests = read_requests(&mut server, heights.len()).await;
+            for request in requests.iter().rev() {
+                assert_eq!(request["method"], "blockchain.block.header");
+                let height = request["params"][0].as_u64().unwrap();
+                let header = Header {
+                    version: Version::ONE,
+                    prev_blockhash: Hash::all_zeros(),
+                    merkle_root: Hash::all_zeros(),
+                    time: height as u32,
+                    bits: CompactTarget::from_consensus(0),
+                    nonce: 0,
+                };
+                let hex = serialize_hex(&header);
+                respond(
+                    &mut server,
+                    json!({ "jsonrpc": "2.0", "id": request["id"], "result": hex }),
+                )
+                .await;
+            }
+        };
+        let (headers, _) = tokio::join!(client.batch_block_header(heights), server);
+
+        let times = headers
+            .unwrap()
+            .into_iter()
+            .map(|header| header.time)
+            .collect::<Vec<_>>();
+        assert_eq!(times, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn protocol_errors_are_returned_per_request() {
+        let (client_stream, server_stream) = duplex(1 << 16);
+        let client = AsyncClient::from_stream(client_stream);
+        let mut server = BufReader::new(server_stream).lines();
+
+        let txids = [Txid::all_zeros(), Txid::all_zeros()];
+        let server = async move {
+            let requests = read_requests(&mut server, 2).await;
+            respond(
+                &mut server,
+                json!({ "jsonrpc": "2.0", "id": requests[0]["id"], "error": { "code": 2, "message": "not found" } }),
+            )
+            .await;
+            respond(
+                &mut server,
+                json!({ "jsonrpc": "2.0", "id": requests[1]["id"], "result": "not hex" }),
+            )
+            .await;
+        };
+        let (results, _) = tokio::join!(client.try_batch_transaction_get(&txids), server);
+
+        assert!(matches!(results[0], Err(Error::Protocol(_))));
+        assert!(matches!(results[1], Err(Error::Hex(_))));
+    }
+
+    #[tokio::test]
+    async fn dropped_calls_are_written_whole_and_forgotten() {
+        // a buffer smaller than a request, so a request can't be written without the server
+        // reading it
+        let (client_stream, server_stream) = duplex(8);
+        let client = AsyncClient::from_stream(client_stream);
+        let mut server = BufReader::new(server_stream).lines();
+
+        assert!(client.block_header(1).now_or_never().is_none());
+        assert!(lock(&client.requests).pending.is_empty());
+
+        let server = async move {
+            let requests = read_requests(&mut server, 2).await;
+            assert_eq!(requests[0]["params"][0], 1);
+            // the response to the dropped call is ignored
+            for request in &requests {
+                let header = Header {
+                    version: Version::ONE,
+                    prev_blockhash: Hash::all_zeros(),
+                    merkle_root: Hash::all_zeros(),
+                    time: request["params"][0].as_u64().unwrap() as u32,
+                    bits: CompactTarget::from_consensus(0),
+                    nonce: 0,
+                };
+                respond(
+                    &mut server,
+                    json!({ "jsonrpc": "2.0", "id": request["id"], "result": serialize_hex(&header) }),
+                )
+                .await;
+            }
+        };
+        let (header, _) = tokio::join!(client.block_header(2), server);
+        assert_eq!(header.unwrap().time, 2);
+        assert!(lock(&client.requests).pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn closed_connection_fails_pending_and_later_requests() {
+        let (client_stream, server_stream) = duplex(1 << 16);
+        let client = AsyncClient::from_stream(client_stream);
+        let mut server = BufReader::new(server_stream).lines();
+
+        let server = async move {
+            read_requests(&mut server, 1).await;
+            drop(server);
+        };
+        let (result, _) = tokio::join!(client.block_header(1), server);
+        assert!(matches!(result, Err(Error::IOError(_))));
+
+        let result = client.block_headers_subscribe().await;
+        assert!(matches!(result, Err(Error::IOError(_))));
+    }
+}
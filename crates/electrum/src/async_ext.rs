@@ -0,0 +1,547 @@
+use async_trait::async_trait;
+use bdk_chain::{
+    bitcoin::{OutPoint, ScriptBuf, Transaction, Txid},
+    local_chain::{self, CheckPoint},
+    BlockId, ConfirmationHeightAnchor, ConfirmationTimeHeightAnchor, TxGraph,
+};
+use electrum_client::{Error, HeaderNotification};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    electrum_ext::{
+        confirmation_heights, determine_tx_anchor, with_confirmation_times, CHAIN_SUFFIX_LENGTH,
+    },
+    AsyncClient, ElectrumUpdate, RelevantTxids,
+};
+
+impl RelevantTxids {
+    /// Finalizes the [`TxGraph`] update by fetching `missing` txids from the async `client`.
+    ///
+    /// This is the async counterpart of [`RelevantTxids::into_tx_graph`].
+    pub async fn into_tx_graph_async(
+        self,
+        client: &AsyncClient,
+        seen_at: Option<u64>,
+        missing: Vec<Txid>,
+    ) -> Result<TxGraph<ConfirmationHeightAnchor>, Error> {
+        let new_txs = client.batch_transaction_get(&missing).await?;
+        Ok(self.into_tx_graph_with(new_txs, seen_at))
+    }
+
+    /// Finalizes [`RelevantTxids`] with anchors of type [`ConfirmationTimeHeightAnchor`], using
+    /// the async `client`.
+    ///
+    /// This is the async counterpart of [`RelevantTxids::into_confirmation_time_tx_graph`].
+    pub async fn into_confirmation_time_tx_graph_async(
+        self,
+        client: &AsyncClient,
+        seen_at: Option<u64>,
+        missing: Vec<Txid>,
+    ) -> Result<TxGraph<ConfirmationTimeHeightAnchor>, Error> {
+        let graph = self.into_tx_graph_async(client, seen_at, missing).await?;
+        let relevant_heights = confirmation_heights(&graph);
+        let times = client
+            .batch_block_header(relevant_heights.clone())
+            .await?
+            .into_iter()
+            .map(|bh| bh.time as u64);
+        Ok(with_confirmation_times(
+            graph,
+            relevant_heights.into_iter().zip(times).collect(),
+        ))
+    }
+}
+
+/// Trait to extend [`AsyncClient`] functionality.
+///
+/// This is the async counterpart of [`ElectrumExt`], the requests of each step of the scan are
+/// batched and pipelined over the client's connection.
+///
+/// [`ElectrumExt`]: crate::ElectrumExt
+#[async_trait]
+pub trait ElectrumAsyncExt {
+    /// Scan the blockchain (via electrum) for the data specified and returns updates for
+    /// [`bdk_chain`] data structures.
+    ///
+    /// - `prev_tip`: the most recent blockchain tip present locally
+    /// - `keychain_spks`: keychains that we want to scan transactions for
+    /// - `txids`: transactions for which we want updated [`Anchor`]s
+    /// - `outpoints`: transactions associated with these outpoints (residing, spending) that we
+    ///   want to included in the update
+    ///
+    /// The scan for each keychain stops after a gap of `stop_gap` script pubkeys with no associated
+    /// transactions. `batch_size` specifies the max number of script pubkeys to request for in a
+    /// single batch request.
+    ///
+    /// [`Anchor`]: bdk_chain::Anchor
+    async fn scan<K: Ord + Clone + Send>(
+        &self,
+        prev_tip: CheckPoint,
+        keychain_spks: BTreeMap<
+            K,
+            impl IntoIterator<IntoIter = impl Iterator<Item = (u32, ScriptBuf)> + Send> + Send,
+        >,
+        txids: impl IntoIterator<IntoIter = impl Iterator<Item = Txid> + Send> + Send,
+        outpoints: impl IntoIterator<IntoIter = impl Iterator<Item = OutPoint> + Send> + Send,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<(ElectrumUpdate, BTreeMap<K, u32>), Error>;
+
+    /// Convenience method to call [`scan`] without requiring a keychain.
+    ///
+    /// [`scan`]: ElectrumAsyncExt::scan
+    async fn scan_without_keychain(
+        &self,
+        prev_tip: CheckPoint,
+        misc_spks: impl IntoIterator<IntoIter = impl Iterator<Item = ScriptBuf> + Send> + Send,
+        txids: impl IntoIterator<IntoIter = impl Iterator<Item = Txid> + Send> + Send,
+        outpoints: impl IntoIterator<IntoIter = impl Iterator<Item = OutPoint> + Send> + Send,
+        batch_size: usize,
+    ) -> Result<ElectrumUpdate, Error> {
+        let spk_iter = misc_spks
+            .into_iter()
+            .enumerate()
+            .map(|(i, spk)| (i as u32, spk));
+
+        let (electrum_update, _) = self
+            .scan(
+                prev_tip,
+                [((), spk_iter)].into(),
+                txids,
+                outpoints,
+                usize::MAX,
+                batch_size,
+            )
+            .await?;
+
+        Ok(electrum_update)
+    }
+}
+
+#[async_trait]
+impl ElectrumAsyncExt for AsyncClient {
+    async fn scan<K: Ord + Clone + Send>(
+        &self,
+        prev_tip: CheckPoint,
+        keychain_spks: BTreeMap<
+            K,
+            impl IntoIterator<IntoIter = impl Iterator<Item = (u32, ScriptBuf)> + Send> + Send,
+        >,
+        txids: impl IntoIterator<IntoIter = impl Iterator<Item = Txid> + Send> + Send,
+        outpoints: impl IntoIterator<IntoIter = impl Iterator<Item = OutPoint> + Send> + Send,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<(ElectrumUpdate, BTreeMap<K, u32>), Error> {
+        // a `Vec` rather than a `BTreeMap`, so that only `&mut K` is held across awaits
+        let mut request_spks = keychain_spks
+            .into_iter()
+            .map(|(k, s)| (k, s.into_iter()))
+            .collect::<Vec<_>>();
+        let mut scanned_spks = BTreeMap::<(K, u32), (ScriptBuf, bool)>::new();
+
+        let txids = txids.into_iter().collect::<Vec<_>>();
+        let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+
+        let (electrum_update, keychain_update) = loop {
+            let tip = construct_update_tip(self, prev_tip.clone()).await?;
+            let mut relevant_txids = RelevantTxids::default();
+            let cps = tip
+                .iter()
+                .take(10)
+                .map(|cp| (cp.height(), cp))
+                .collect::<BTreeMap<u32, CheckPoint>>();
+
+            if !request_spks.is_empty() {
+                if !scanned_spks.is_empty() {
+                    let rescan_spks = scanned_spks
+                        .iter()
+                        .map(|(i, (spk, _))| (i.clone(), spk.clone()))
+                        .collect::<Vec<_>>();
+                    scanned_spks.append(
+                        &mut populate_with_spks(
+                            self,
+                            &cps,
+                            &mut relevant_txids,
+                            &mut rescan_spks.into_iter(),
+                            stop_gap,
+                            batch_size,
+                        )
+                        .await?,
+                    );
+                }
+                for (keychain, keychain_spks) in &mut request_spks {
+                    scanned_spks.extend(
+                        populate_with_spks(
+                            self,
+                            &cps,
+                            &mut relevant_txids,
+                            keychain_spks,
+                            stop_gap,
+                            batch_size,
+                        )
+                        .await?
+                        .into_iter()
+                        .map(|(spk_i, spk)| ((keychain.clone(), spk_i), spk)),
+                    );
+                }
+            }
+
+            populate_with_txids(self, &cps, &mut relevant_txids, &txids).await?;
+
+            populate_with_outpoints(self, &cps, &mut relevant_txids, &outpoints).await?;
+
+            // check for reorgs during scan process
+            let server_blockhash = self.block_header(tip.height() as usize).await?.block_hash();
+            if tip.hash() != server_blockhash {
+                continue; // reorg
+            }
+
+            let chain_update = local_chain::Update {
+                tip,
+                introduce_older_blocks: true,
+            };
+
+            let keychain_update = request_spks
+                .into_iter()
+                .filter_map(|(k, _)| {
+                    scanned_spks
+                        .range((k.clone(), u32::MIN)..=(k.clone(), u32::MAX))
+                        .rev()
+                        .find(|(_, (_, active))| *active)
+                        .map(|((_, i), _)| (k, *i))
+                })
+                .collect::<BTreeMap<_, _>>();
+
+            break (
+                ElectrumUpdate {
+                    chain_update,
+                    relevant_txids,
+                },
+                keychain_update,
+            );
+        };
+
+        Ok((electrum_update, keychain_update))
+    }
+}
+
+/// Return a [`CheckPoint`] of the latest tip, that connects with `prev_tip`.
+pub(crate) async fn construct_update_tip(
+    client: &AsyncClient,
+    prev_tip: CheckPoint,
+) -> Result<CheckPoint, Error> {
+    let HeaderNotification { height, .. } = client.block_headers_subscribe().await?;
+    let new_tip_height = height as u32;
+
+    // If electrum returns a tip height that is lower than our previous tip, then checkpoints do
+    // not need updating. We just return the previous tip and use that as the point of agreement.
+    if new_tip_height < prev_tip.height() {
+        return Ok(prev_tip);
+    }
+
+    let mut new_blocks = {
+        let start_height = new_tip_height.saturating_sub(CHAIN_SUFFIX_LENGTH - 1);
+        let hashes = client
+            .block_headers(start_height as _, CHAIN_SUFFIX_LENGTH as _)
+            .await?
+            .headers
+            .into_iter()
+            .map(|h| h.block_hash());
+        (start_height..).zip(hashes).collect::<BTreeMap<u32, _>>()
+    };
+
+    // Find the "point of agreement" (if any). The hashes of checkpoints which are not within the
+    // fetched suffix are requested in batches of `CHAIN_SUFFIX_LENGTH`.
+    let mut agreement_cp = Option::<CheckPoint>::None;
+    let prev_cps = prev_tip.iter().collect::<Vec<_>>();
+    for prev_cps in prev_cps.chunks(CHAIN_SUFFIX_LENGTH as usize) {
+        let missing_heights = prev_cps
+            .iter()
+            .map(CheckPoint::height)
+            .filter(|height| !new_blocks.contains_key(height))
+            .collect::<Vec<_>>();
+        let missing_hashes = client
+            .batch_block_header(missing_heights.clone())
+            .await?
+            .into_iter()
+            .map(|h| h.block_hash());
+        new_blocks.extend(missing_heights.into_iter().zip(missing_hashes));
+        agreement_cp = prev_cps
+            .iter()
+            .find(|cp| new_blocks.get(&cp.height()) == Some(&cp.hash()))
+            .cloned();
+        if agreement_cp.is_some() {
+            break;
+        }
+    }
+
+    let agreement_height = agreement_cp.as_ref().map(CheckPoint::height);
+
+    let new_tip = new_blocks
+        .into_iter()
+        // Prune `new_blocks` to only include blocks that are actually new.
+        .filter(|(height, _)| Some(*height) > agreement_height)
+        .map(|(height, hash)| BlockId { height, hash })
+        .fold(agreement_cp, |prev_cp, block| {
+            Some(match prev_cp {
+                Some(cp) => cp.push(block).expect("must extend checkpoint"),
+                None => CheckPoint::new(block),
+            })
+        })
+        .expect("must have at least one checkpoint");
+
+    Ok(new_tip)
+}
+
+async fn populate_with_outpoints(
+    client: &AsyncClient,
+    cps: &BTreeMap<u32, CheckPoint>,
+    relevant_txids: &mut RelevantTxids,
+    outpoints: &[OutPoint],
+) -> Result<(), Error> {
+    let txs = client
+        .batch_transaction_get(outpoints.iter().map(|op| &op.txid))
+        .await?;
+    let spks = outpoints
+        .iter()
+        .zip(txs)
+        .filter_map(|(outpoint, tx)| {
+            debug_assert_eq!(tx.txid(), outpoint.txid);
+            let txout = tx.output.get(outpoint.vout as usize)?;
+            Some((*outpoint, txout.script_pubkey.clone()))
+        })
+        .collect::<Vec<_>>();
+    let histories = client
+        .batch_script_get_history(spks.iter().map(|(_, spk)| spk.as_script()))
+        .await?;
+
+    // fetch every transaction that might spend one of the outpoints at once
+    let candidate_txids = spks
+        .iter()
+        .zip(&histories)
+        .flat_map(|((outpoint, _), history)| {
+            history
+                .iter()
+                .map(|res| res.tx_hash)
+                .filter(move |txid| *txid != outpoint.txid)
+        })
+        .collect::<BTreeSet<Txid>>();
+    let candidates = candidate_txids
+        .iter()
+        .copied()
+        .zip(client.batch_transaction_get(&candidate_txids).await?)
+        .collect::<HashMap<Txid, Transaction>>();
+
+    for ((outpoint, _), history) in spks.into_iter().zip(histories) {
+        // attempt to find the following transactions (alongside their chain positions), and
+        // add to our sparsechain `update`:
+        let mut has_residing = false; // tx in which the outpoint resides
+        let mut has_spending = false; // tx that spends the outpoint
+        for res in history {
+            if has_residing && has_spending {
+                break;
+            }
+
+            if res.tx_hash == outpoint.txid {
+                if has_residing {
+                    continue;
+                }
+                has_residing = true;
+            } else {
+                if has_spending {
+                    continue;
+                }
+                has_spending = candidates[&res.tx_hash]
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output == outpoint);
+                if !has_spending {
+                    continue;
+                }
+            };
+
+            let anchor = determine_tx_anchor(cps, res.height, res.tx_hash);
+            let tx_entry = relevant_txids.0.entry(res.tx_hash).or_default();
+            if let Some(anchor) = anchor {
+                tx_entry.insert(anchor);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn populate_with_txids(
+    client: &AsyncClient,
+    cps: &BTreeMap<u32, CheckPoint>,
+    relevant_txids: &mut RelevantTxids,
+    txids: &[Txid],
+) -> Result<(), Error> {
+    let mut found = Vec::with_capacity(txids.len());
+    for (txid, tx) in txids
+        .iter()
+        .zip(client.try_batch_transaction_get(txids).await)
+    {
+        match tx {
+            Ok(tx) => found.push((*txid, tx)),
+            Err(Error::Protocol(_)) => continue,
+            Err(other_err) => return Err(other_err),
+        }
+    }
+
+    let histories = client
+        .batch_script_get_history(found.iter().map(|(_, tx)| {
+            tx.output
+                .first()
+                .map(|txo| txo.script_pubkey.as_script())
+                .expect("tx must have an output")
+        }))
+        .await?;
+
+    for ((txid, _), history) in found.into_iter().zip(histories) {
+        let anchor = match history.into_iter().find(|r| r.tx_hash == txid) {
+            Some(r) => determine_tx_anchor(cps, r.height, txid),
+            None => continue,
+        };
+
+        let tx_entry = relevant_txids.0.entry(txid).or_default();
+        if let Some(anchor) = anchor {
+            tx_entry.insert(anchor);
+        }
+    }
+    Ok(())
+}
+
+async fn populate_with_spks<I: Ord + Clone>(
+    client: &AsyncClient,
+    cps: &BTreeMap<u32, CheckPoint>,
+    relevant_txids: &mut RelevantTxids,
+    spks: &mut (impl Iterator<Item = (I, ScriptBuf)> + Send),
+    stop_gap: usize,
+    batch_size: usize,
+) -> Result<BTreeMap<I, (ScriptBuf, bool)>, Error> {
+    let mut unused_spk_count = 0_usize;
+    let mut scanned_spks = BTreeMap::new();
+
+    loop {
+        let spks = (0..batch_size)
+            .map_while(|_| spks.next())
+            .collect::<Vec<_>>();
+        if spks.is_empty() {
+            return Ok(scanned_spks);
+        }
+
+        let spk_histories = client
+            .batch_script_get_history(spks.iter().map(|(_, s)| s.as_script()))
+            .await?;
+
+        for ((spk_index, spk), spk_history) in spks.into_iter().zip(spk_histories) {
+            if spk_history.is_empty() {
+                scanned_spks.insert(spk_index, (spk, false));
+                unused_spk_count += 1;
+                if unused_spk_count > stop_gap {
+                    return Ok(scanned_spks);
+                }
+                continue;
+            } else {
+                scanned_spks.insert(spk_index, (spk, true));
+                unused_spk_count = 0;
+            }
+
+            for tx in spk_history {
+                let tx_entry = relevant_txids.0.entry(tx.tx_hash).or_default();
+                if let Some(anchor) = determine_tx_anchor(cps, tx.height, tx.tx_hash) {
+                    tx_entry.insert(anchor);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use bdk_chain::{
+        bitcoin::{absolute, constants::genesis_block, hashes::Hash, Network, TxIn, TxOut},
+        local_chain::LocalChain,
+    };
+
+    pub(crate) fn spk(i: u8) -> ScriptBuf {
+        ScriptBuf::from_bytes(vec![0x6a, 0x01, i])
+    }
+
+    pub(crate) fn tx(previous_output: OutPoint, script_pubkey: ScriptBuf) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_finds_spk_txid_and_outpoint_histories() {
+        let (server, client) = MockServer::start(12);
+        let receive_tx = tx(OutPoint::new(Txid::all_zeros(), 7), spk(4));
+        let receive_op = OutPoint::new(receive_tx.txid(), 0);
+        let spend_tx = tx(receive_op, spk(10));
+        let unrelated_tx = tx(OutPoint::new(Txid::all_zeros(), 8), spk(200));
+        server.insert_tx(3, receive_tx.clone(), &[&spk(4)]).await;
+        server
+            .insert_tx(5, spend_tx.clone(), &[&spk(4), &spk(10)])
+            .await;
+        server
+            .insert_tx(0, unrelated_tx.clone(), &[&spk(200)])
+            .await;
+
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let (mut chain, _) = LocalChain::from_genesis_hash(genesis_hash);
+        let keychain_spks = (0..20_u8).map(|i| (i as u32, spk(i)));
+        let (update, keychain_update) = client
+            .scan(
+                chain.tip(),
+                [((), keychain_spks)].into(),
+                [unrelated_tx.txid(), Txid::all_zeros()],
+                [receive_op],
+                5,
+                4,
+            )
+            .await
+            .unwrap();
+
+        // the gap between index 4 and 10 is within the stop gap
+        assert_eq!(keychain_update, [((), 10)].into());
+        let _ = chain.apply_update(update.chain_update).unwrap();
+        assert_eq!(chain.tip().block_id().height, 12);
+        assert_eq!(chain.tip().hash(), server.tip_hash());
+
+        let relevant_txids = update.relevant_txids;
+        let mut missing =
+            relevant_txids.missing_full_txs(&TxGraph::<ConfirmationHeightAnchor>::default());
+        missing.sort();
+        let mut expected = vec![receive_tx.txid(), spend_tx.txid(), unrelated_tx.txid()];
+        expected.sort();
+        assert_eq!(missing, expected);
+
+        let graph = relevant_txids
+            .into_confirmation_time_tx_graph_async(&client, None, missing)
+            .await
+            .unwrap();
+        let anchors = graph
+            .all_anchors()
+            .iter()
+            .map(|(anchor, txid)| (*txid, anchor.confirmation_height, anchor.confirmation_time))
+            .collect::<BTreeSet<_>>();
+        // unconfirmed transactions are not anchored, the block time is the block height
+        assert_eq!(
+            anchors,
+            [(receive_tx.txid(), 3, 3), (spend_tx.txid(), 5, 5)].into()
+        );
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+#[cfg(feature = "async")]
+use bdk_chain::BroadcasterAsync;
+use bdk_chain::{bitcoin::Transaction, BroadcastError, Broadcaster};
+use electrum_client::{ElectrumApi, Error};
+
+/// Broadcasts transactions with the `blockchain.transaction.broadcast` method of an Electrum
+/// server.
+///
+/// This implements [`Broadcaster`] for blocking clients (any [`ElectrumApi`]) and, with the
+/// `async` feature, `BroadcasterAsync` for `AsyncClient`.
+///
+/// The reason the server gives for rejecting a transaction is mapped to a [`BroadcastError`].
+#[derive(Debug, Clone, Copy)]
+pub struct ElectrumBroadcaster<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C> ElectrumBroadcaster<'c, C> {
+    /// Create a broadcaster which broadcasts with `client`.
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+}
+
+impl<'c, C: ElectrumApi> Broadcaster for ElectrumBroadcaster<'c, C> {
+    type Error = Error;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>> {
+        match self.client.transaction_broadcast(tx) {
+            Ok(_) => Ok(()),
+            Err(err) => map_error(err),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<'c> BroadcasterAsync for ElectrumBroadcaster<'c, crate::AsyncClient> {
+    type Error = Error;
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>> {
+        match self.client.transaction_broadcast(tx).await {
+            Ok(_) => Ok(()),
+            Err(err) => map_error(err),
+        }
+    }
+}
+
+/// The prefix of the message of the error ElectrumX and Fulcrum servers return when their node
+/// rejects a transaction. The reason given by the node follows on the next lines.
+const REJECTED_BY_NETWORK_RULES: &str = "the transaction was rejected by network rules.";
+
+/// The codes of the errors of Bitcoin Core's `sendrawtransaction` for rejected transactions
+/// (`RPC_VERIFY_ERROR`, `RPC_VERIFY_REJECTED` and `RPC_VERIFY_ALREADY_IN_CHAIN`), which electrs
+/// servers forward.
+const REJECTION_CODES: [i64; 3] = [-25, -26, -27];
+
+/// Servers reject transactions with a protocol error, of which the message holds the reason given
+/// by their node. Other protocol errors (e.g. an unknown method or a failure to reach the node) are
+/// returned as client errors.
+fn map_error(err: Error) -> Result<(), BroadcastError<Error>> {
+    let reason = match &err {
+        Error::Protocol(value) => {
+            let code = value.get("code").and_then(|code| code.as_i64());
+            let message = value.get("message").and_then(|message| message.as_str());
+            match (code, message) {
+                (_, Some(message)) if message.starts_with(REJECTED_BY_NETWORK_RULES) => message
+                    [REJECTED_BY_NETWORK_RULES.len()..]
+                    .trim_start()
+                    .lines()
+                    .next()
+                    .map(str::to_string),
+                (Some(code), Some(message)) if REJECTION_CODES.contains(&code) => {
+                    Some(message.to_string())
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    match reason {
+        Some(reason) => BroadcastError::from_reject_reason(reason).map_or(Ok(()), Err),
+        None => Err(BroadcastError::Client(err)),
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test {
+    use super::*;
+    use crate::{
+        async_ext::test::{spk, tx},
+        mock_server::{rejected_by_network_rules, MockServer},
+    };
+    use bdk_chain::bitcoin::{hashes::Hash, OutPoint, Txid};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn rejections_are_mapped() {
+        let (server, client) = MockServer::start(1);
+        let broadcaster = ElectrumBroadcaster::new(&client);
+
+        let tx = tx_with_input(0);
+        broadcaster.broadcast(&tx).await.unwrap();
+        assert_eq!(server.broadcasted(), vec![tx.clone()]);
+        // the server rejects a transaction which is already in its mempool, this is not an error
+        broadcaster.broadcast(&tx).await.unwrap();
+
+        // ElectrumX and Fulcrum
+        let tx = tx_with_input(1);
+        server.set_broadcast_error(json!({
+            "code": 1,
+            "message": rejected_by_network_rules("min relay fee not met, 0 < 110", &tx),
+        }));
+        assert!(matches!(
+            broadcaster.broadcast(&tx).await,
+            Err(BroadcastError::InsufficientFee(reason)) if reason == "min relay fee not met, 0 < 110"
+        ));
+
+        // electrs
+        server.set_broadcast_error(
+            json!({ "code": -25, "message": "bad-txns-inputs-missingorspent" }),
+        );
+        assert!(matches!(
+            broadcaster.broadcast(&tx).await,
+            Err(BroadcastError::MissingInputs(reason)) if reason == "bad-txns-inputs-missingorspent"
+        ));
+
+        // errors which are not rejections by the node
+        server.set_broadcast_error(
+            json!({ "code": 1, "message": "daemon error: connection refused" }),
+        );
+        assert!(matches!(
+            broadcaster.broadcast(&tx).await,
+            Err(BroadcastError::Client(Error::Protocol(_)))
+        ));
+        server.set_broadcast_error(json!({ "code": -32601, "message": "unknown method" }));
+        assert!(matches!(
+            broadcaster.broadcast(&tx).await,
+            Err(BroadcastError::Client(Error::Protocol(_)))
+        ));
+
+        drop(server);
+        assert!(matches!(
+            broadcaster.broadcast(&tx_with_input(2)).await,
+            Err(BroadcastError::Client(Error::IOError(_)))
+        ));
+    }
+
+    fn tx_with_input(vout: u32) -> Transaction {
+        tx(OutPoint::new(Txid::all_zeros(), vout), spk(0))
+    }
+}
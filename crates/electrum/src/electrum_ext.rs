@@ -12,7 +12,7 @@ use std::{
 };
 
 /// We include a chain suffix of a certain length for the purpose of robustness.
-const CHAIN_SUFFIX_LENGTH: u32 = 8;
+pub(crate) const CHAIN_SUFFIX_LENGTH: u32 = 8;
 
 /// Represents updates fetched from an Electrum server, but excludes full transactions.
 ///
@@ -20,7 +20,7 @@ const CHAIN_SUFFIX_LENGTH: u32 = 8;
 /// determine the full transactions missing from [`TxGraph`]. Then call [`Self::into_tx_graph`] to
 /// fetch the full transactions from Electrum and finalize the update.
 #[derive(Debug, Default, Clone)]
-pub struct RelevantTxids(HashMap<Txid, BTreeSet<ConfirmationHeightAnchor>>);
+pub struct RelevantTxids(pub(crate) HashMap<Txid, BTreeSet<ConfirmationHeightAnchor>>);
 
 impl RelevantTxids {
     /// Determine the full transactions that are missing from `graph`.
@@ -44,6 +44,15 @@ impl RelevantTxids {
         missing: Vec<Txid>,
     ) -> Result<TxGraph<ConfirmationHeightAnchor>, Error> {
         let new_txs = client.batch_transaction_get(&missing)?;
+        Ok(self.into_tx_graph_with(new_txs, seen_at))
+    }
+
+    /// Finalizes the [`TxGraph`] update with the already fetched `new_txs`.
+    pub(crate) fn into_tx_graph_with(
+        self,
+        new_txs: Vec<Transaction>,
+        seen_at: Option<u64>,
+    ) -> TxGraph<ConfirmationHeightAnchor> {
         let mut graph = TxGraph::<ConfirmationHeightAnchor>::new(new_txs);
         for (txid, anchors) in self.0 {
             if let Some(seen_at) = seen_at {
@@ -53,7 +62,7 @@ impl RelevantTxids {
                 let _ = graph.insert_anchor(txid, anchor);
             }
         }
-        Ok(graph)
+        graph
     }
 
     /// Finalizes [`RelevantTxids`] with `new_txs` and anchors of type
@@ -69,55 +78,61 @@ impl RelevantTxids {
         missing: Vec<Txid>,
     ) -> Result<TxGraph<ConfirmationTimeHeightAnchor>, Error> {
         let graph = self.into_tx_graph(client, seen_at, missing)?;
+        let relevant_heights = confirmation_heights(&graph);
+        let times = client
+            .batch_block_header(relevant_heights.clone())?
+            .into_iter()
+            .map(|bh| bh.time as u64);
+        Ok(with_confirmation_times(
+            graph,
+            relevant_heights.into_iter().zip(times).collect(),
+        ))
+    }
+}
 
-        let relevant_heights = {
-            let mut visited_heights = HashSet::new();
-            graph
-                .all_anchors()
-                .iter()
-                .map(|(a, _)| a.confirmation_height_upper_bound())
-                .filter(move |&h| visited_heights.insert(h))
-                .collect::<Vec<_>>()
-        };
+/// The distinct confirmation heights of the anchors in `graph`.
+pub(crate) fn confirmation_heights(graph: &TxGraph<ConfirmationHeightAnchor>) -> Vec<u32> {
+    let mut visited_heights = HashSet::new();
+    graph
+        .all_anchors()
+        .iter()
+        .map(|(a, _)| a.confirmation_height_upper_bound())
+        .filter(move |&h| visited_heights.insert(h))
+        .collect()
+}
 
-        let height_to_time = relevant_heights
-            .clone()
-            .into_iter()
-            .zip(
-                client
-                    .batch_block_header(relevant_heights)?
-                    .into_iter()
-                    .map(|bh| bh.time as u64),
-            )
-            .collect::<HashMap<u32, u64>>();
-
-        let graph_changeset = {
-            let old_changeset = TxGraph::default().apply_update(graph);
-            tx_graph::ChangeSet {
-                txs: old_changeset.txs,
-                txouts: old_changeset.txouts,
-                last_seen: old_changeset.last_seen,
-                anchors: old_changeset
-                    .anchors
-                    .into_iter()
-                    .map(|(height_anchor, txid)| {
-                        let confirmation_height = height_anchor.confirmation_height;
-                        let confirmation_time = height_to_time[&confirmation_height];
-                        let time_anchor = ConfirmationTimeHeightAnchor {
-                            anchor_block: height_anchor.anchor_block,
-                            confirmation_height,
-                            confirmation_time,
-                        };
-                        (time_anchor, txid)
-                    })
-                    .collect(),
-            }
-        };
+/// Convert the anchors of `graph` into [`ConfirmationTimeHeightAnchor`]s using the block times
+/// of `height_to_time`, which must contain every height of [`confirmation_heights`].
+pub(crate) fn with_confirmation_times(
+    graph: TxGraph<ConfirmationHeightAnchor>,
+    height_to_time: HashMap<u32, u64>,
+) -> TxGraph<ConfirmationTimeHeightAnchor> {
+    let graph_changeset = {
+        let old_changeset = TxGraph::default().apply_update(graph);
+        tx_graph::ChangeSet {
+            txs: old_changeset.txs,
+            txouts: old_changeset.txouts,
+            last_seen: old_changeset.last_seen,
+            anchors: old_changeset
+                .anchors
+                .into_iter()
+                .map(|(height_anchor, txid)| {
+                    let confirmation_height = height_anchor.confirmation_height;
+                    let confirmation_time = height_to_time[&confirmation_height];
+                    let time_anchor = ConfirmationTimeHeightAnchor {
+                        anchor_block: height_anchor.anchor_block,
+                        confirmation_height,
+                        confirmation_time,
+                    };
+                    (time_anchor, txid)
+                })
+                .collect(),
+        }
+    };
 
-        let mut new_graph = TxGraph::default();
-        new_graph.apply_changeset(graph_changeset);
-        Ok(new_graph)
-    }
+    let mut new_graph = TxGraph::default();
+    new_graph.apply_changeset(graph_changeset);
+    new_graph
 }
 
 /// Combination of chain and transactions updates from electrum
@@ -362,7 +377,7 @@ fn construct_update_tip(
 /// cannot be found, or the transaction is unconfirmed, [`None`] is returned.
 ///
 /// [tx status](https://electrumx-spesmilo.readthedocs.io/en/latest/protocol-basics.html#status)
-fn determine_tx_anchor(
+pub(crate) fn determine_tx_anchor(
     cps: &BTreeMap<u32, CheckPoint>,
     raw_height: i32,
     txid: Txid,
@@ -0,0 +1,82 @@
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+#[cfg(feature = "async")]
+use bdk_chain::FeeEstimatorAsync;
+use bdk_chain::{bitcoin::FeeRate, fee_rate_from_sat_per_kvb, FeeEstimator};
+use electrum_client::{ElectrumApi, Error};
+
+/// Estimates fee rates with the `blockchain.estimatefee` method of an Electrum server.
+///
+/// This implements [`FeeEstimator`] for blocking clients (any [`ElectrumApi`]) and, with the
+/// `async` feature, `FeeEstimatorAsync` for `AsyncClient`.
+///
+/// An error is returned if the server does not have enough information for an estimate.
+#[derive(Debug, Clone, Copy)]
+pub struct ElectrumFeeEstimator<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C> ElectrumFeeEstimator<'c, C> {
+    /// Create an estimator which requests fee estimates with `client`.
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+}
+
+impl<'c, C: ElectrumApi> FeeEstimator for ElectrumFeeEstimator<'c, C> {
+    type Error = Error;
+
+    fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error> {
+        let btc_per_kvb = self.client.estimate_fee(target as usize)?;
+        fee_rate_from_btc_per_kvb(target, btc_per_kvb)
+    }
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<'c> FeeEstimatorAsync for ElectrumFeeEstimator<'c, crate::AsyncClient> {
+    type Error = Error;
+
+    async fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error> {
+        let btc_per_kvb = self.client.estimate_fee(target as usize).await?;
+        fee_rate_from_btc_per_kvb(target, btc_per_kvb)
+    }
+}
+
+/// Electrum estimates are in BTC/kvB, which are converted to whole sat/kvB (the precision of a BTC
+/// amount) and then rounded up to whole sat/kwu with [`fee_rate_from_sat_per_kvb`].
+fn fee_rate_from_btc_per_kvb(target: u16, btc_per_kvb: f64) -> Result<FeeRate, Error> {
+    if btc_per_kvb < 0.0 {
+        return Err(Error::Message(format!(
+            "no fee estimate available for a target of {} blocks",
+            target
+        )));
+    }
+    Ok(fee_rate_from_sat_per_kvb(
+        (btc_per_kvb * 100_000_000.0).round() as u64,
+    ))
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    #[tokio::test]
+    async fn estimates_are_converted_to_sat_per_kwu() {
+        let (server, client) = MockServer::start(1);
+        server.set_fee_estimate(2, 0.0002);
+        let estimator = ElectrumFeeEstimator::new(&client);
+
+        assert_eq!(
+            estimator.estimate_fee_rate(2).await.unwrap(),
+            FeeRate::from_sat_per_vb(20).unwrap()
+        );
+        // the server returns -1 without an estimate
+        assert!(matches!(
+            estimator.estimate_fee_rate(6).await,
+            Err(Error::Message(_))
+        ));
+    }
+}
@@ -17,6 +17,12 @@
 //!
 //! Refer to [`bdk_electrum_example`] for a complete example.
 //!
+//! With the `async` feature, `ElectrumAsyncExt::scan` does the same over an `AsyncClient`, which
+//! pipelines batched requests over a single connection. The update is then finalized with
+//! `RelevantTxids::into_tx_graph_async` (or `into_confirmation_time_tx_graph_async`). For live
+//! sync, `ElectrumSubscriber` subscribes to the statuses of script pubkeys and only emits updates
+//! when they (or the chain tip) change.
+//!
 //! [`ElectrumClient::scan`]: electrum_client::ElectrumClient::scan
 //! [`missing_full_txs`]: RelevantTxids::missing_full_txs
 //! [`batch_transaction_get`]: electrum_client::ElectrumApi::batch_transaction_get
@@ -28,3 +34,23 @@ mod electrum_ext;
 pub use bdk_chain;
 pub use electrum_client;
 pub use electrum_ext::*;
+mod broadcaster;
+pub use broadcaster::*;
+mod fee_estimator;
+pub use fee_estimator::*;
+
+#[cfg(feature = "async")]
+mod async_client;
+#[cfg(feature = "async")]
+pub use async_client::*;
+#[cfg(feature = "async")]
+mod async_ext;
+#[cfg(feature = "async")]
+pub use async_ext::*;
+#[cfg(feature = "async")]
+mod subscriber;
+#[cfg(feature = "async")]
+pub use subscriber::*;
+
+#[cfg(all(test, feature = "async"))]
+mod mock_server;
@@ -0,0 +1,266 @@
+//! An in-memory Electrum server for testing [`AsyncClient`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use bdk_chain::bitcoin::{
+    block::{Header, Version},
+    consensus::encode::{deserialize, serialize_hex},
+    constants::genesis_block,
+    hashes::{hex::FromHex, sha256, Hash},
+    BlockHash, CompactTarget, Network, ScriptBuf, Transaction, Txid,
+};
+use electrum_client::{ScriptHash, ToElectrumScriptHash};
+use serde_json::{json, Value};
+use tokio::{
+    io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, WriteHalf},
+    sync::Mutex as AsyncMutex,
+    task::JoinHandle,
+};
+
+use crate::AsyncClient;
+
+#[derive(Default)]
+struct State {
+    headers: Vec<Header>,
+    histories: HashMap<ScriptHash, Vec<(i32, Txid)>>,
+    txs: HashMap<Txid, Transaction>,
+    headers_subscribed: bool,
+    subscribed: HashSet<ScriptHash>,
+    fee_estimates: HashMap<u64, f64>,
+    broadcasted: Vec<Transaction>,
+    broadcast_error: Option<Value>,
+}
+
+/// Serves a chain of headers and the histories of script pubkeys to a single [`AsyncClient`].
+///
+/// Notifications are sent for the subscriptions of the client when the chain or the histories
+/// change. The connection is closed when the server is dropped.
+pub(crate) struct MockServer {
+    state: Arc<Mutex<State>>,
+    writer: Arc<AsyncMutex<WriteHalf<DuplexStream>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Start serving a chain of `height` blocks (on top of the regtest genesis block), the block
+    /// time of each block is its height.
+    pub(crate) fn start(height: u32) -> (Self, AsyncClient) {
+        let (client_stream, server_stream) = duplex(1 << 16);
+        let (reader, writer) = tokio::io::split(server_stream);
+        let state = Arc::new(Mutex::new(State {
+            headers: vec![genesis_block(Network::Regtest).header],
+            ..Default::default()
+        }));
+        state.lock().unwrap().extend_chain(height);
+        let writer = Arc::new(AsyncMutex::new(writer));
+
+        let (server_state, server_writer) = (state.clone(), writer.clone());
+        let task = tokio::spawn(async move {
+            let (state, writer) = (server_state, server_writer);
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let params = request["params"].as_array().unwrap();
+                let method = request["method"].as_str().unwrap();
+                let response = match state.lock().unwrap().handle(method, params) {
+                    Ok(result) => {
+                        json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+                    }
+                    Err(error) => json!({ "jsonrpc": "2.0", "id": request["id"], "error": error }),
+                };
+                write_line(&writer, response).await;
+            }
+        });
+        let server = Self {
+            state,
+            writer,
+            task,
+        };
+        (server, AsyncClient::from_stream(client_stream))
+    }
+
+    pub(crate) fn tip_hash(&self) -> BlockHash {
+        let state = self.state.lock().unwrap();
+        state.headers.last().unwrap().block_hash()
+    }
+
+    /// Set the fee rate estimate (in BTC/kvB) for a confirmation `target`.
+    pub(crate) fn set_fee_estimate(&self, target: u64, btc_per_kvb: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.fee_estimates.insert(target, btc_per_kvb);
+    }
+
+    /// The transactions broadcast by the client, in order.
+    pub(crate) fn broadcasted(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().broadcasted.clone()
+    }
+
+    /// Fail all further broadcasts with the JSON-RPC `error`.
+    pub(crate) fn set_broadcast_error(&self, error: Value) {
+        let mut state = self.state.lock().unwrap();
+        state.broadcast_error = Some(error);
+    }
+
+    /// Mine `count` blocks, notifying the client of the new tip.
+    pub(crate) async fn mine(&self, count: u32) {
+        let notification = {
+            let mut state = self.state.lock().unwrap();
+            state.extend_chain(count);
+            state.headers_subscribed.then(|| state.tip())
+        };
+        if let Some(tip) = notification {
+            self.notify("blockchain.headers.subscribe", json!([tip]))
+                .await;
+        }
+    }
+
+    /// Add `tx` at `height` (`0` for unconfirmed) to the histories of `spks`, notifying the
+    /// client of the new statuses.
+    pub(crate) async fn insert_tx(&self, height: i32, tx: Transaction, spks: &[&ScriptBuf]) {
+        let notifications = {
+            let mut state = self.state.lock().unwrap();
+            let txid = tx.txid();
+            state.txs.insert(txid, tx);
+            spks.iter()
+                .map(|spk| spk.to_electrum_scripthash())
+                .filter_map(|script_hash| {
+                    state
+                        .histories
+                        .entry(script_hash)
+                        .or_default()
+                        .push((height, txid));
+                    state
+                        .subscribed
+                        .contains(&script_hash)
+                        .then(|| json!([script_hash, state.status(&script_hash)]))
+                })
+                .collect::<Vec<_>>()
+        };
+        for params in notifications {
+            self.notify("blockchain.scripthash.subscribe", params).await;
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) {
+        let notification = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_line(&self.writer, notification).await;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl State {
+    fn extend_chain(&mut self, count: u32) {
+        for _ in 0..count {
+            let prev = self.headers.last().unwrap();
+            let header = Header {
+                version: Version::ONE,
+                prev_blockhash: prev.block_hash(),
+                merkle_root: Hash::all_zeros(),
+                time: self.headers.len() as u32,
+                bits: CompactTarget::from_consensus(0),
+                nonce: 0,
+            };
+            self.headers.push(header);
+        }
+    }
+
+    fn tip(&self) -> Value {
+        json!({
+            "height": self.headers.len() - 1,
+            "hex": serialize_hex(self.headers.last().unwrap()),
+        })
+    }
+
+    fn status(&self, script_hash: &ScriptHash) -> Option<String> {
+        let history = self.histories.get(script_hash)?;
+        let status = history
+            .iter()
+            .map(|(height, txid)| format!("{}:{}:", txid, height))
+            .collect::<String>();
+        Some(sha256::Hash::hash(status.as_bytes()).to_string())
+    }
+
+    fn handle(&mut self, method: &str, params: &[Value]) -> Result<Value, Value> {
+        let height = |i: usize| params[i].as_u64().unwrap() as usize;
+        let script_hash = || serde_json::from_value::<ScriptHash>(params[0].clone()).unwrap();
+        Ok(match method {
+            "blockchain.headers.subscribe" => {
+                self.headers_subscribed = true;
+                self.tip()
+            }
+            "blockchain.block.header" => json!(serialize_hex(&self.headers[height(0)])),
+            "blockchain.block.headers" => {
+                let headers = self.headers.iter().skip(height(0)).take(height(1));
+                json!({
+                    "count": headers.len(),
+                    "max": 2016,
+                    "hex": headers.map(serialize_hex).collect::<String>(),
+                })
+            }
+            "blockchain.scripthash.subscribe" => {
+                let script_hash = script_hash();
+                self.subscribed.insert(script_hash);
+                json!(self.status(&script_hash))
+            }
+            "blockchain.scripthash.get_history" => {
+                let history = self.histories.get(&script_hash()).into_iter().flatten();
+                json!(history
+                    .map(|(height, txid)| json!({ "height": height, "tx_hash": txid }))
+                    .collect::<Vec<_>>())
+            }
+            "blockchain.transaction.get" => {
+                let txid = params[0].as_str().unwrap().parse::<Txid>().unwrap();
+                match self.txs.get(&txid) {
+                    Some(tx) => json!(serialize_hex(tx)),
+                    None => return Err(json!({ "code": 2, "message": "not found" })),
+                }
+            }
+            "blockchain.transaction.broadcast" => {
+                let tx: Transaction =
+                    deserialize(&Vec::<u8>::from_hex(params[0].as_str().unwrap()).unwrap())
+                        .unwrap();
+                if let Some(error) = &self.broadcast_error {
+                    return Err(error.clone());
+                }
+                if self.broadcasted.contains(&tx) {
+                    return Err(json!({
+                        "code": 1,
+                        "message": rejected_by_network_rules("txn-already-in-mempool", &tx),
+                    }));
+                }
+                let txid = tx.txid();
+                self.broadcasted.push(tx);
+                json!(txid)
+            }
+            "blockchain.estimatefee" => {
+                let target = params[0].as_u64().unwrap();
+                json!(self.fee_estimates.get(&target).copied().unwrap_or(-1.0))
+            }
+            method => panic!("unexpected method {}", method),
+        })
+    }
+}
+
+async fn write_line(writer: &AsyncMutex<WriteHalf<DuplexStream>>, value: Value) {
+    let line = format!("{}\n", value);
+    // the client may be gone already
+    let _ = writer.lock().await.write_all(line.as_bytes()).await;
+}
+
+/// The message of the error ElectrumX and Fulcrum servers return when their node rejects a
+/// transaction for `reason`.
+pub(crate) fn rejected_by_network_rules(reason: &str, tx: &Transaction) -> String {
+    format!(
+        "the transaction was rejected by network rules.\n\n{}\n[{}]",
+        reason,
+        serialize_hex(tx)
+    )
+}
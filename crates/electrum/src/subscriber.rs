@@ -0,0 +1,295 @@
+use bdk_chain::{
+    bitcoin::ScriptBuf,
+    local_chain::{self, CheckPoint},
+};
+use electrum_client::{Error, ScriptHash, ScriptStatus, ToElectrumScriptHash};
+use futures::{channel::mpsc, StreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    async_client::connection_closed, async_ext::construct_update_tip,
+    electrum_ext::determine_tx_anchor, AsyncClient, ElectrumUpdate, Notification, RelevantTxids,
+};
+
+/// Emits [`ElectrumUpdate`]s driven by the subscriptions of an [`AsyncClient`].
+///
+/// Rather than requesting the histories of all script pubkeys on every sync (like
+/// [`ElectrumAsyncExt::scan`]), the subscriber subscribes to the statuses of the script pubkeys it
+/// is given and to the tip of the chain. The history of a script pubkey is only requested once the
+/// server notifies a change of its status.
+///
+/// ```rust,no_run
+/// # use bdk_chain::{bitcoin::{constants::genesis_block, Network}, local_chain::LocalChain};
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// # let (mut chain, _) =
+/// #     LocalChain::from_genesis_hash(genesis_block(Network::Regtest).block_hash());
+/// # let spks = Vec::<(&str, u32, bdk_chain::bitcoin::ScriptBuf)>::new();
+/// let client = bdk_electrum::AsyncClient::new("tcp://127.0.0.1:50001").await?;
+/// let mut subscriber = bdk_electrum::ElectrumSubscriber::new(&client, chain.tip()).await?;
+/// // the revealed script pubkeys and the lookahead of each keychain
+/// subscriber.subscribe_spks(spks).await?;
+/// loop {
+///     let (update, last_active_indices) = subscriber.next_update().await?;
+///     let _ = chain
+///         .apply_update(update.chain_update)
+///         .expect("the update must connect");
+///     // finalize `update.relevant_txids`, reveal up to `last_active_indices` and subscribe to
+///     // the script pubkeys which are now within the lookahead
+/// }
+/// # }
+/// ```
+///
+/// [`ElectrumAsyncExt::scan`]: crate::ElectrumAsyncExt::scan
+#[derive(Debug)]
+pub struct ElectrumSubscriber<'c, K> {
+    client: &'c AsyncClient,
+    notifications: mpsc::UnboundedReceiver<Notification>,
+
+    /// The tip of the last emitted update (or the tip the subscriber was created with).
+    tip: CheckPoint,
+
+    /// Subscribed script pubkeys, by script hash.
+    spks: HashMap<ScriptHash, SubscribedSpk<K>>,
+
+    /// Script hashes of the script pubkeys whose status changed since the last update.
+    changed_spks: HashSet<ScriptHash>,
+
+    /// Whether the tip of the chain changed since the last update.
+    tip_changed: bool,
+}
+
+#[derive(Debug)]
+struct SubscribedSpk<K> {
+    keychain: K,
+    index: u32,
+    spk: ScriptBuf,
+    status: Option<ScriptStatus>,
+}
+
+impl<'c, K: Ord + Clone> ElectrumSubscriber<'c, K> {
+    /// Create a subscriber which subscribes to the tip of the chain with `client`.
+    ///
+    /// `prev_tip` is the most recent blockchain tip present locally. The first update always
+    /// includes a chain update connecting to it.
+    ///
+    /// The subscriber takes over the [`AsyncClient::notifications`] of `client`.
+    pub async fn new(client: &'c AsyncClient, prev_tip: CheckPoint) -> Result<Self, Error> {
+        // listen first, so that no notification is missed after subscribing
+        let notifications = client.notifications();
+        client.block_headers_subscribe().await?;
+        Ok(Self {
+            client,
+            notifications,
+            tip: prev_tip,
+            spks: HashMap::new(),
+            changed_spks: HashSet::new(),
+            tip_changed: true,
+        })
+    }
+
+    /// Subscribe to the statuses of `spks`, given as `(keychain, index, script pubkey)`.
+    ///
+    /// Script pubkeys that are already subscribed to are skipped. The histories of the new script
+    /// pubkeys which have any are included in the next update.
+    pub async fn subscribe_spks(
+        &mut self,
+        spks: impl IntoIterator<Item = (K, u32, ScriptBuf)>,
+    ) -> Result<(), Error> {
+        let mut new_spks = HashMap::new();
+        for (keychain, index, spk) in spks {
+            let script_hash = spk.to_electrum_scripthash();
+            if !self.spks.contains_key(&script_hash) {
+                new_spks.insert(script_hash, (keychain, index, spk));
+            }
+        }
+        let new_spks = new_spks.into_iter().collect::<Vec<_>>();
+
+        let statuses = self
+            .client
+            .batch_script_subscribe(new_spks.iter().map(|(_, (_, _, spk))| spk.as_script()))
+            .await?;
+        for ((script_hash, (keychain, index, spk)), status) in new_spks.into_iter().zip(statuses) {
+            if status.is_some() {
+                self.changed_spks.insert(script_hash);
+            }
+            self.spks.insert(
+                script_hash,
+                SubscribedSpk {
+                    keychain,
+                    index,
+                    spk,
+                    status,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Wait until the tip of the chain or the status of a subscribed script pubkey changes, then
+    /// return an update with the new tip and the histories of the changed script pubkeys, together
+    /// with the last active index of each keychain among them.
+    ///
+    /// Notifications that arrive at once are coalesced into a single update. An update may only
+    /// consist of a chain update, if no status changed.
+    ///
+    /// Returns an error once the connection is closed.
+    pub async fn next_update(&mut self) -> Result<(ElectrumUpdate, BTreeMap<K, u32>), Error> {
+        loop {
+            // take all notifications that have arrived so far
+            while let Ok(notification) = self.notifications.try_recv() {
+                self.handle_notification(notification);
+            }
+            if !self.tip_changed && self.changed_spks.is_empty() {
+                let notification = self
+                    .notifications
+                    .next()
+                    .await
+                    .ok_or_else(connection_closed)?;
+                self.handle_notification(notification);
+                continue;
+            }
+
+            let tip = construct_update_tip(self.client, self.tip.clone()).await?;
+            let cps = tip
+                .iter()
+                .take(10)
+                .map(|cp| (cp.height(), cp))
+                .collect::<BTreeMap<u32, CheckPoint>>();
+
+            let changed_spks = self
+                .changed_spks
+                .iter()
+                .map(|script_hash| &self.spks[script_hash])
+                .collect::<Vec<_>>();
+            let histories = self
+                .client
+                .batch_script_get_history(changed_spks.iter().map(|s| s.spk.as_script()))
+                .await?;
+
+            let mut relevant_txids = RelevantTxids::default();
+            let mut keychain_update = BTreeMap::<K, u32>::new();
+            for (subscribed, history) in changed_spks.into_iter().zip(histories) {
+                if history.is_empty() {
+                    continue;
+                }
+                let last_active = keychain_update
+                    .entry(subscribed.keychain.clone())
+                    .or_insert(subscribed.index);
+                *last_active = subscribed.index.max(*last_active);
+                for tx in history {
+                    let tx_entry = relevant_txids.0.entry(tx.tx_hash).or_default();
+                    if let Some(anchor) = determine_tx_anchor(&cps, tx.height, tx.tx_hash) {
+                        tx_entry.insert(anchor);
+                    }
+                }
+            }
+
+            // check for reorgs during the update, the changed statuses are kept for the retry
+            let server_blockhash = self
+                .client
+                .block_header(tip.height() as usize)
+                .await?
+                .block_hash();
+            if tip.hash() != server_blockhash {
+                continue; // reorg
+            }
+
+            self.tip = tip.clone();
+            self.tip_changed = false;
+            self.changed_spks.clear();
+            let update = ElectrumUpdate {
+                chain_update: local_chain::Update {
+                    tip,
+                    introduce_older_blocks: true,
+                },
+                relevant_txids,
+            };
+            return Ok((update, keychain_update));
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) {
+        match notification {
+            Notification::Header(_) => self.tip_changed = true,
+            Notification::ScriptStatus {
+                script_hash,
+                status,
+            } => {
+                if let Some(subscribed) = self.spks.get_mut(&script_hash) {
+                    if subscribed.status != status {
+                        subscribed.status = status;
+                        self.changed_spks.insert(script_hash);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        async_ext::test::{spk, tx},
+        mock_server::MockServer,
+    };
+    use bdk_chain::bitcoin::{constants::genesis_block, hashes::Hash, Network, OutPoint, Txid};
+
+    fn relevant_txids(update: &ElectrumUpdate) -> HashSet<Txid> {
+        update.relevant_txids.0.keys().copied().collect()
+    }
+
+    #[tokio::test]
+    async fn updates_are_driven_by_notifications() {
+        let (server, client) = MockServer::start(10);
+        let confirmed_tx = tx(OutPoint::new(Txid::all_zeros(), 0), spk(2));
+        server.insert_tx(3, confirmed_tx.clone(), &[&spk(2)]).await;
+
+        let genesis_cp = CheckPoint::new(bdk_chain::BlockId {
+            height: 0,
+            hash: genesis_block(Network::Regtest).block_hash(),
+        });
+        let mut subscriber = ElectrumSubscriber::new(&client, genesis_cp).await.unwrap();
+        subscriber
+            .subscribe_spks((0..5).map(|i| ((), i as u32, spk(i))))
+            .await
+            .unwrap();
+
+        // the first update includes the histories of the script pubkeys that have any
+        let (update, keychain_update) = subscriber.next_update().await.unwrap();
+        assert_eq!(update.chain_update.tip.height(), 10);
+        assert_eq!(update.chain_update.tip.hash(), server.tip_hash());
+        assert_eq!(relevant_txids(&update), [confirmed_tx.txid()].into());
+        assert_eq!(keychain_update, [((), 2)].into());
+
+        // only the script pubkey whose status changed is requested
+        let mempool_tx = tx(OutPoint::new(Txid::all_zeros(), 1), spk(4));
+        server.insert_tx(0, mempool_tx.clone(), &[&spk(4)]).await;
+        let (update, keychain_update) = subscriber.next_update().await.unwrap();
+        assert_eq!(update.chain_update.tip.height(), 10);
+        assert_eq!(relevant_txids(&update), [mempool_tx.txid()].into());
+        assert_eq!(keychain_update, [((), 4)].into());
+
+        // a new block only updates the chain
+        server.mine(1).await;
+        let (update, keychain_update) = subscriber.next_update().await.unwrap();
+        assert_eq!(update.chain_update.tip.height(), 11);
+        assert_eq!(update.chain_update.tip.hash(), server.tip_hash());
+        assert!(relevant_txids(&update).is_empty());
+        assert!(keychain_update.is_empty());
+
+        // script pubkeys which are not subscribed to do not cause updates
+        server
+            .insert_tx(
+                0,
+                tx(OutPoint::new(Txid::all_zeros(), 2), spk(9)),
+                &[&spk(9)],
+            )
+            .await;
+        drop(server);
+        assert!(matches!(
+            subscriber.next_update().await,
+            Err(Error::IOError(_))
+        ));
+    }
+}
@@ -0,0 +1,83 @@
+use bdk_chain::Append;
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use serde::{Deserialize, Serialize};
+
+/// The length of the salt used for key derivation.
+pub const SALT_LEN: usize = 16;
+
+/// The Argon2id costs used to derive the encryption key from a passphrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfCosts {
+    /// Memory size in KiB.
+    pub m_cost: u32,
+    /// Number of iterations.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+}
+
+impl Default for KdfCosts {
+    fn default() -> Self {
+        Self {
+            m_cost: argon2::Params::DEFAULT_M_COST,
+            t_cost: argon2::Params::DEFAULT_T_COST,
+            p_cost: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// The parameters used to derive the encryption key from a passphrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Random salt of the key derivation.
+    pub salt: [u8; SALT_LEN],
+    /// Argon2id costs of the key derivation.
+    pub costs: KdfCosts,
+}
+
+impl KdfParams {
+    /// Create key derivation parameters with a fresh random salt.
+    pub fn new(costs: KdfCosts) -> Self {
+        let mut salt = [0_u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self { salt, costs }
+    }
+}
+
+/// The changeset that [`EncryptedStore`] writes to its inner persistence backend.
+///
+/// Every entry is a changeset encrypted under the key derived with `kdf`, and bound to its position
+/// among the entries. The `commitment` is the number of entries, encrypted under the same key. It
+/// is replaced on every write, so dropping trailing entries is detected, and it lets the
+/// passphrase be verified even when there are no entries.
+///
+/// When an `EncryptedChangeSet` with different key derivation parameters is appended (this happens
+/// when the passphrase is changed) it supersedes the entries encrypted under the previous key.
+///
+/// [`EncryptedStore`]: crate::EncryptedStore
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedChangeSet {
+    /// The parameters used to derive the key which encrypts `entries`.
+    pub kdf: Option<KdfParams>,
+    /// Encrypted changesets.
+    pub entries: Vec<Vec<u8>>,
+    /// The encrypted number of `entries`.
+    pub commitment: Option<Vec<u8>>,
+}
+
+impl Append for EncryptedChangeSet {
+    fn append(&mut self, other: Self) {
+        if other.kdf.is_some() && other.kdf != self.kdf {
+            *self = other;
+        } else {
+            self.entries.extend(other.entries);
+            if other.commitment.is_some() {
+                self.commitment = other.commitment;
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.kdf.is_none() && self.entries.is_empty() && self.commitment.is_none()
+    }
+}
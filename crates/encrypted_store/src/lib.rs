@@ -0,0 +1,88 @@
+#![doc = include_str!("../README.md")]
+mod changeset;
+mod store;
+
+pub use changeset::*;
+pub use store::*;
+
+/// Error that occurs while encrypting or decrypting changesets of an [`EncryptedStore`].
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The inner persistence backend failed.
+    Inner(E),
+    /// The changeset could not be (de)serialized.
+    Bincode(bincode::Error),
+    /// The key derivation parameters are invalid.
+    Kdf(argon2::Error),
+    /// An entry could not be decrypted, either the passphrase is wrong or the entry was tampered
+    /// with.
+    Decryption,
+    /// An entry was encrypted with an unsupported format version.
+    UnsupportedVersion {
+        /// The format version of the entry.
+        got: u8,
+        /// The format version supported by this crate.
+        expected: u8,
+    },
+    /// The number of stored entries differs from the number committed to by the last write, so
+    /// entries were dropped or added.
+    EntryCount {
+        /// The number of entries committed to.
+        committed: u64,
+        /// The number of stored entries.
+        stored: u64,
+    },
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Inner(e) => write!(f, "inner backend error: {}", e),
+            Self::Bincode(e) => write!(f, "bincode error: {}", e),
+            Self::Kdf(e) => write!(f, "invalid key derivation parameters: {}", e),
+            Self::Decryption => write!(
+                f,
+                "failed to decrypt entry: wrong passphrase or tampered data"
+            ),
+            Self::UnsupportedVersion { got, expected } => write!(
+                f,
+                "entry has unsupported format version: expected={} got={}",
+                expected, got
+            ),
+            Self::EntryCount { committed, stored } => write!(
+                f,
+                "stored entries do not match the commitment: committed={} stored={}",
+                committed, stored
+            ),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug + core::fmt::Display> std::error::Error for Error<E> {}
+
+/// Error returned by [`EncryptedStore::change_passphrase`].
+#[derive(Debug)]
+pub enum ChangePassphraseError<L, W> {
+    /// Loading the existing changesets failed.
+    Load(Error<L>),
+    /// Writing the re-encrypted changeset failed.
+    Write(Error<W>),
+}
+
+impl<L: core::fmt::Display, W: core::fmt::Display> core::fmt::Display
+    for ChangePassphraseError<L, W>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Load(e) => write!(f, "failed to load existing changesets: {}", e),
+            Self::Write(e) => write!(f, "failed to write re-encrypted changeset: {}", e),
+        }
+    }
+}
+
+impl<L, W> std::error::Error for ChangePassphraseError<L, W>
+where
+    L: core::fmt::Debug + core::fmt::Display,
+    W: core::fmt::Debug + core::fmt::Display,
+{
+}
@@ -0,0 +1,585 @@
+use core::marker::PhantomData;
+
+use bdk_chain::{Append, PersistBackend};
+use bincode::Options;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use zeroize::Zeroize;
+
+use crate::{ChangePassphraseError, EncryptedChangeSet, Error, KdfCosts, KdfParams};
+
+/// The format version which prefixes every encrypted entry.
+pub const ENTRY_VERSION: u8 = 1;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+/// Domain separation of the associated data of entries and commitments.
+const ENTRY_TAG: u8 = 0;
+const COMMITMENT_TAG: u8 = 1;
+
+/// Persists changesets (`C`) to an inner [`PersistBackend`] (`B`) after encrypting them with a
+/// passphrase-derived key.
+///
+/// Every changeset is serialized with `bincode` and encrypted with XChaCha20-Poly1305 under a key
+/// derived from the passphrase with Argon2id. The inner backend stores [`EncryptedChangeSet`]s.
+///
+/// Every entry is authenticated together with its index, and every write also stores the encrypted
+/// number of entries, so entries which are dropped, reordered, replayed or truncated result in an
+/// error when loading. Rolling the inner backend back to an earlier state as a whole (e.g.
+/// restoring an old copy of a file) cannot be detected.
+pub struct EncryptedStore<B, C> {
+    inner: B,
+    kdf: KdfParams,
+    cipher: XChaCha20Poly1305,
+    /// The number of entries stored under the current key.
+    entry_count: u64,
+    marker: PhantomData<C>,
+}
+
+impl<B, C> core::fmt::Debug for EncryptedStore<B, C>
+where
+    B: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("inner", &self.inner)
+            .field("kdf", &self.kdf)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B, C> EncryptedStore<B, C>
+where
+    B: PersistBackend<EncryptedChangeSet>,
+    C: Default + Append + serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Create a new [`EncryptedStore`] on top of the `inner` persistence backend.
+    ///
+    /// If `inner` already contains encrypted changesets, the key is derived with the stored
+    /// parameters and the `passphrase` is verified against the stored commitment. Otherwise, a new
+    /// key is derived with the default [`KdfCosts`] and a random salt.
+    pub fn new(inner: B, passphrase: &str) -> Result<Self, Error<B::LoadError>> {
+        Self::new_with_kdf_costs(inner, passphrase, KdfCosts::default())
+    }
+
+    /// Create a new [`EncryptedStore`] with custom key derivation costs.
+    ///
+    /// This is like [`EncryptedStore::new`], except that `costs` are used when `inner` does not
+    /// contain encrypted changesets yet. The costs of an existing store are always read from
+    /// `inner`.
+    pub fn new_with_kdf_costs(
+        mut inner: B,
+        passphrase: &str,
+        costs: KdfCosts,
+    ) -> Result<Self, Error<B::LoadError>> {
+        let stored = inner.load_from_persistence().map_err(Error::Inner)?;
+        let (kdf, stored) = match stored {
+            Some(stored) => match stored.kdf {
+                Some(kdf) => (kdf, Some(stored)),
+                None => (KdfParams::new(costs), None),
+            },
+            None => (KdfParams::new(costs), None),
+        };
+
+        let cipher = derive_cipher(passphrase, &kdf)?;
+        let entry_count = match &stored {
+            Some(stored) => verify_commitment(&cipher, &kdf, stored)?,
+            None => 0,
+        };
+
+        Ok(Self {
+            inner,
+            kdf,
+            cipher,
+            entry_count,
+            marker: PhantomData,
+        })
+    }
+
+    /// Re-encrypt the persisted changesets under a key derived from `new_passphrase`.
+    ///
+    /// The aggregate changeset is written to the inner backend as a single entry which supersedes
+    /// every entry encrypted under the previous key. Those entries remain in append-only backends
+    /// until they are compacted.
+    pub fn change_passphrase(
+        &mut self,
+        new_passphrase: &str,
+    ) -> Result<(), ChangePassphraseError<B::LoadError, B::WriteError>> {
+        let aggregate = self
+            .load_from_persistence()
+            .map_err(ChangePassphraseError::Load)?;
+
+        let kdf = KdfParams::new(self.kdf.costs);
+        let cipher = derive_cipher(new_passphrase, &kdf).map_err(ChangePassphraseError::Write)?;
+        let entries = match aggregate {
+            Some(changeset) => {
+                vec![encrypt(&cipher, &kdf, 0, &changeset).map_err(ChangePassphraseError::Write)?]
+            }
+            None => Vec::new(),
+        };
+        let entry_count = entries.len() as u64;
+
+        // the commitment is written even without entries so that the new passphrase is verified
+        self.inner
+            .write_changes(&EncryptedChangeSet {
+                kdf: Some(kdf),
+                entries,
+                commitment: Some(commit(&cipher, &kdf, entry_count)),
+            })
+            .map_err(|e| ChangePassphraseError::Write(Error::Inner(e)))?;
+        self.kdf = kdf;
+        self.cipher = cipher;
+        self.entry_count = entry_count;
+        Ok(())
+    }
+}
+
+impl<B, C> EncryptedStore<B, C> {
+    /// Get a reference to the inner persistence backend.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner persistence backend.
+    ///
+    /// Writing to the inner backend directly may make the store unreadable.
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    /// Consume the [`EncryptedStore`] and return the inner persistence backend.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B, C> PersistBackend<C> for EncryptedStore<B, C>
+where
+    B: PersistBackend<EncryptedChangeSet>,
+    C: Default + Append + serde::Serialize + serde::de::DeserializeOwned,
+{
+    type WriteError = Error<B::WriteError>;
+
+    type LoadError = Error<B::LoadError>;
+
+    fn write_changes(&mut self, changeset: &C) -> Result<(), Self::WriteError> {
+        if changeset.is_empty() {
+            return Ok(());
+        }
+        let entry = encrypt(&self.cipher, &self.kdf, self.entry_count, changeset)?;
+        let entry_count = self.entry_count + 1;
+        self.inner
+            .write_changes(&EncryptedChangeSet {
+                kdf: Some(self.kdf),
+                entries: vec![entry],
+                commitment: Some(commit(&self.cipher, &self.kdf, entry_count)),
+            })
+            .map_err(Error::Inner)?;
+        self.entry_count = entry_count;
+        Ok(())
+    }
+
+    fn load_from_persistence(&mut self) -> Result<Option<C>, Self::LoadError> {
+        let stored = match self.inner.load_from_persistence().map_err(Error::Inner)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        if stored.kdf.is_none() {
+            return Ok(None);
+        }
+        if stored.kdf != Some(self.kdf) {
+            // the entries were encrypted under a key we did not derive
+            return Err(Error::Decryption);
+        }
+        verify_commitment(&self.cipher, &self.kdf, &stored)?;
+        if stored.entries.is_empty() {
+            return Ok(None);
+        }
+
+        let mut aggregate = C::default();
+        for (index, entry) in stored.entries.iter().enumerate() {
+            aggregate.append(decrypt(&self.cipher, &self.kdf, index as u64, entry)?);
+        }
+        Ok(Some(aggregate))
+    }
+}
+
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_varint_encoding()
+}
+
+fn derive_cipher<E>(passphrase: &str, kdf: &KdfParams) -> Result<XChaCha20Poly1305, Error<E>> {
+    let params = argon2::Params::new(
+        kdf.costs.m_cost,
+        kdf.costs.t_cost,
+        kdf.costs.p_cost,
+        Some(KEY_LEN),
+    )
+    .map_err(Error::Kdf)?;
+    let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+
+    let mut key = [0_u8; KEY_LEN];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), &kdf.salt, &mut key)
+        .map_err(Error::Kdf)?;
+    let cipher = XChaCha20Poly1305::new(&key.into());
+    key.zeroize();
+    Ok(cipher)
+}
+
+/// The associated data authenticated with an entry (`tag` is [`ENTRY_TAG`]) at `index`, or with a
+/// commitment (`tag` is [`COMMITMENT_TAG`]).
+fn associated_data(kdf: &KdfParams, tag: u8, index: u64) -> Vec<u8> {
+    let mut aad = vec![ENTRY_VERSION];
+    aad.extend_from_slice(&kdf.salt);
+    aad.push(tag);
+    aad.extend_from_slice(&index.to_le_bytes());
+    aad
+}
+
+/// Encrypt `plaintext` into an entry of the form `[version][nonce][ciphertext]`.
+fn seal(cipher: &XChaCha20Poly1305, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("plaintext must not exceed the cipher's size limit");
+
+    let mut entry = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+    entry.push(ENTRY_VERSION);
+    entry.extend_from_slice(&nonce);
+    entry.extend_from_slice(&ciphertext);
+    entry
+}
+
+/// Verify and decrypt an entry created with [`seal`].
+fn open<E>(cipher: &XChaCha20Poly1305, aad: &[u8], entry: &[u8]) -> Result<Vec<u8>, Error<E>> {
+    let (&version, rest) = entry.split_first().ok_or(Error::Decryption)?;
+    if version != ENTRY_VERSION {
+        return Err(Error::UnsupportedVersion {
+            got: version,
+            expected: ENTRY_VERSION,
+        });
+    }
+    if rest.len() < NONCE_LEN {
+        return Err(Error::Decryption);
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::Decryption)
+}
+
+/// Encrypt `changeset` into the entry at `index`.
+fn encrypt<C, E>(
+    cipher: &XChaCha20Poly1305,
+    kdf: &KdfParams,
+    index: u64,
+    changeset: &C,
+) -> Result<Vec<u8>, Error<E>>
+where
+    C: serde::Serialize,
+{
+    let mut plaintext = bincode_options()
+        .serialize(changeset)
+        .map_err(Error::Bincode)?;
+    let entry = seal(cipher, &associated_data(kdf, ENTRY_TAG, index), &plaintext);
+    plaintext.zeroize();
+    Ok(entry)
+}
+
+/// Verify and decrypt the entry at `index`, created with [`encrypt`].
+fn decrypt<C, E>(
+    cipher: &XChaCha20Poly1305,
+    kdf: &KdfParams,
+    index: u64,
+    entry: &[u8],
+) -> Result<C, Error<E>>
+where
+    C: serde::de::DeserializeOwned,
+{
+    let mut plaintext = open(cipher, &associated_data(kdf, ENTRY_TAG, index), entry)?;
+    let changeset = bincode_options()
+        .deserialize(&plaintext)
+        .map_err(Error::Bincode);
+    plaintext.zeroize();
+    changeset
+}
+
+/// Encrypt the commitment to `entry_count` entries.
+fn commit(cipher: &XChaCha20Poly1305, kdf: &KdfParams, entry_count: u64) -> Vec<u8> {
+    seal(
+        cipher,
+        &associated_data(kdf, COMMITMENT_TAG, 0),
+        &entry_count.to_le_bytes(),
+    )
+}
+
+/// Verify the commitment of `stored` and that it commits to the stored entries, returning their
+/// number.
+///
+/// A missing commitment, or one which does not decrypt, means the passphrase is wrong or the data
+/// was tampered with.
+fn verify_commitment<E>(
+    cipher: &XChaCha20Poly1305,
+    kdf: &KdfParams,
+    stored: &EncryptedChangeSet,
+) -> Result<u64, Error<E>> {
+    let commitment = stored.commitment.as_ref().ok_or(Error::Decryption)?;
+    let plaintext = open(cipher, &associated_data(kdf, COMMITMENT_TAG, 0), commitment)?;
+    let mut count = [0_u8; 8];
+    if plaintext.len() != count.len() {
+        return Err(Error::Decryption);
+    }
+    count.copy_from_slice(&plaintext);
+    let committed = u64::from_le_bytes(count);
+    let stored = stored.entries.len() as u64;
+    if committed != stored {
+        return Err(Error::EntryCount { committed, stored });
+    }
+    Ok(committed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bdk_file_store::Store;
+    use std::path::{Path, PathBuf};
+    use tempfile::TempDir;
+
+    type TestChangeSet = Vec<String>;
+    type TestStore<'a> = EncryptedStore<Store<'a, EncryptedChangeSet>, TestChangeSet>;
+
+    const TEST_MAGIC_BYTES: &[u8] = b"bdk_encrypted_test";
+
+    /// Cheap key derivation so tests do not take long.
+    const TEST_COSTS: KdfCosts = KdfCosts {
+        m_cost: 8,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn temp_file() -> (TempDir, PathBuf) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        (temp_dir, file_path)
+    }
+
+    fn open(
+        file_path: &Path,
+        passphrase: &str,
+    ) -> Result<TestStore<'static>, Error<bdk_file_store::IterError>> {
+        let inner = Store::open_or_create_new(TEST_MAGIC_BYTES, file_path).expect("must open");
+        EncryptedStore::new_with_kdf_costs(inner, passphrase, TEST_COSTS)
+    }
+
+    /// Write the stored [`EncryptedChangeSet`] of `file_path`, modified by `tamper`, to a new file
+    /// and open it.
+    fn open_tampered(
+        file_path: &Path,
+        tamper: impl FnOnce(&mut EncryptedChangeSet),
+    ) -> Result<TestStore<'static>, Error<bdk_file_store::IterError>> {
+        let mut inner =
+            Store::<EncryptedChangeSet>::open(TEST_MAGIC_BYTES, file_path).expect("must open");
+        let mut stored = inner
+            .load_from_persistence()
+            .expect("must load")
+            .expect("must exist");
+        tamper(&mut stored);
+
+        let tampered_path = file_path.with_extension("tampered");
+        let _ = std::fs::remove_file(&tampered_path);
+        let mut tampered =
+            Store::create_new(TEST_MAGIC_BYTES, &tampered_path).expect("must create");
+        tampered.write_changes(&stored).expect("must write");
+        drop(tampered);
+        open(&tampered_path, "passphrase")
+    }
+
+    fn write_three_entries(file_path: &Path) {
+        let mut store = open(file_path, "passphrase").expect("must create");
+        for secret in ["one", "two", "three"] {
+            store
+                .write_changes(&vec![secret.to_string()])
+                .expect("must write");
+        }
+    }
+
+    #[test]
+    fn write_and_load_changesets() {
+        let (_temp_dir, file_path) = temp_file();
+        let changesets: Vec<TestChangeSet> = vec![
+            vec!["secret one".into()],
+            vec![],
+            vec!["secret two".into(), "secret three".into()],
+        ];
+
+        let mut store = open(&file_path, "passphrase").expect("must create");
+        assert_eq!(store.load_from_persistence().expect("must load"), None);
+        for changeset in &changesets {
+            store.write_changes(changeset).expect("must write");
+        }
+        drop(store);
+
+        let mut store = open(&file_path, "passphrase").expect("must open");
+        assert_eq!(
+            store.load_from_persistence().expect("must load"),
+            Some(changesets.concat())
+        );
+
+        // plaintext never reaches the inner backend
+        let data = std::fs::read(&file_path).unwrap();
+        assert!(!data.windows(6).any(|w| w == b"secret"));
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let (_temp_dir, file_path) = temp_file();
+        let mut store = open(&file_path, "passphrase").expect("must create");
+        store
+            .write_changes(&vec!["secret".to_string()])
+            .expect("must write");
+        drop(store);
+
+        assert!(matches!(open(&file_path, "wrong"), Err(Error::Decryption)));
+    }
+
+    #[test]
+    fn tampered_entry_is_rejected() {
+        let (_temp_dir, file_path) = temp_file();
+        let mut store = open(&file_path, "passphrase").expect("must create");
+        store
+            .write_changes(&vec!["secret".to_string()])
+            .expect("must write");
+        drop(store);
+
+        let mut store = open_tampered(&file_path, |stored| {
+            *stored.entries[0].last_mut().unwrap() ^= 1;
+        })
+        .expect("commitment is intact");
+        assert!(matches!(
+            store.load_from_persistence(),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn reordered_or_replayed_entries_are_rejected() {
+        let (_temp_dir, file_path) = temp_file();
+        write_three_entries(&file_path);
+
+        let mut store =
+            open_tampered(&file_path, |stored| stored.entries.swap(0, 1)).expect("count matches");
+        assert!(matches!(
+            store.load_from_persistence(),
+            Err(Error::Decryption)
+        ));
+
+        let mut store = open_tampered(&file_path, |stored| {
+            stored.entries[2] = stored.entries[0].clone()
+        })
+        .expect("count matches");
+        assert!(matches!(
+            store.load_from_persistence(),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn dropped_entries_are_rejected() {
+        let (_temp_dir, file_path) = temp_file();
+        write_three_entries(&file_path);
+
+        // truncated
+        assert!(matches!(
+            open_tampered(&file_path, |stored| {
+                stored.entries.pop();
+            }),
+            Err(Error::EntryCount {
+                committed: 3,
+                stored: 2
+            })
+        ));
+        // dropped in the middle
+        assert!(matches!(
+            open_tampered(&file_path, |stored| {
+                stored.entries.remove(1);
+            }),
+            Err(Error::EntryCount {
+                committed: 3,
+                stored: 2
+            })
+        ));
+        // commitment removed
+        assert!(matches!(
+            open_tampered(&file_path, |stored| stored.commitment = None),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected_without_entries() {
+        let (_temp_dir, file_path) = temp_file();
+        let mut store = open(&file_path, "old").expect("must create");
+        store
+            .change_passphrase("new")
+            .expect("must change passphrase");
+        drop(store);
+
+        assert!(matches!(open(&file_path, "wrong"), Err(Error::Decryption)));
+        let mut store = open(&file_path, "new").expect("must open");
+        assert_eq!(store.load_from_persistence().expect("must load"), None);
+    }
+
+    #[test]
+    fn change_passphrase() {
+        let (_temp_dir, file_path) = temp_file();
+        let changesets: Vec<TestChangeSet> = vec![vec!["one".into()], vec!["two".into()]];
+
+        let mut store = open(&file_path, "old").expect("must create");
+        for changeset in &changesets {
+            store.write_changes(changeset).expect("must write");
+        }
+        store
+            .change_passphrase("new")
+            .expect("must change passphrase");
+        store
+            .write_changes(&vec!["three".to_string()])
+            .expect("must write");
+        drop(store);
+
+        assert!(matches!(open(&file_path, "old"), Err(Error::Decryption)));
+
+        let mut store = open(&file_path, "new").expect("must open");
+        let expected = vec!["one".to_string(), "two".into(), "three".into()];
+        assert_eq!(
+            store.load_from_persistence().expect("must load"),
+            Some(expected.clone())
+        );
+
+        // compacting drops the entries encrypted under the old key
+        store.inner_mut().compact().expect("must compact");
+        drop(store);
+        let mut store = open(&file_path, "new").expect("must open");
+        assert_eq!(
+            store.load_from_persistence().expect("must load"),
+            Some(expected)
+        );
+    }
+}
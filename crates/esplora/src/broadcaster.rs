@@ -0,0 +1,97 @@
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+#[cfg(feature = "blocking")]
+use bdk_chain::Broadcaster;
+#[cfg(feature = "async")]
+use bdk_chain::BroadcasterAsync;
+use bdk_chain::{bitcoin::Transaction, BroadcastError};
+use esplora_client::Error;
+
+/// Broadcasts transactions with the `tx` endpoint of an Esplora server.
+///
+/// This implements [`Broadcaster`] for [`esplora_client::BlockingClient`] and
+/// [`BroadcasterAsync`] for [`esplora_client::AsyncClient`].
+///
+/// Unlike [`BlockingClient::broadcast`], the reason the server gives for rejecting a transaction
+/// is mapped to a [`BroadcastError`].
+///
+/// [`Broadcaster`]: bdk_chain::Broadcaster
+/// [`BroadcasterAsync`]: bdk_chain::BroadcasterAsync
+/// [`BlockingClient::broadcast`]: esplora_client::BlockingClient::broadcast
+#[derive(Debug, Clone, Copy)]
+pub struct EsploraBroadcaster<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C> EsploraBroadcaster<'c, C> {
+    /// Create a broadcaster which broadcasts with `client`.
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<'c> Broadcaster for EsploraBroadcaster<'c, esplora_client::BlockingClient> {
+    type Error = Error;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>> {
+        let resp = self
+            .client
+            .agent()
+            .post(&format!("{}/tx", self.client.url()))
+            .send_string(&tx_hex(tx));
+
+        let err = match resp {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        // the error holds the response if the server responded with an error status, otherwise
+        // it is a transport error, which is kept as an I/O error
+        let message = err.to_string();
+        match err.into_response() {
+            Some(resp) if resp.status() == 400 => {
+                let reason = resp
+                    .into_string()
+                    .map_err(|err| BroadcastError::Client(Error::Io(err)))?;
+                BroadcastError::from_reject_reason(reason).map_or(Ok(()), Err)
+            }
+            Some(resp) => Err(BroadcastError::Client(Error::HttpResponse(resp.status()))),
+            None => Err(BroadcastError::Client(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                message,
+            )))),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<'c> BroadcasterAsync for EsploraBroadcaster<'c, esplora_client::AsyncClient> {
+    type Error = Error;
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError<Self::Error>> {
+        let client_err = |err| BroadcastError::Client(Error::Reqwest(err));
+        let resp = self
+            .client
+            .client()
+            .post(format!("{}/tx", self.client.url()))
+            .body(tx_hex(tx))
+            .send()
+            .await
+            .map_err(client_err)?;
+
+        match resp.status().as_u16() {
+            200..=299 => Ok(()),
+            400 => {
+                let reason = resp.text().await.map_err(client_err)?;
+                BroadcastError::from_reject_reason(reason).map_or(Ok(()), Err)
+            }
+            code => Err(BroadcastError::Client(Error::HttpResponse(code))),
+        }
+    }
+}
+
+fn tx_hex(tx: &Transaction) -> String {
+    bdk_chain::bitcoin::consensus::encode::serialize_hex(tx)
+}
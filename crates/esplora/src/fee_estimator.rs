@@ -0,0 +1,86 @@
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+#[cfg(feature = "blocking")]
+use bdk_chain::FeeEstimator;
+#[cfg(feature = "async")]
+use bdk_chain::FeeEstimatorAsync;
+use bdk_chain::{bitcoin::FeeRate, fee_rate_from_sat_per_kvb};
+use esplora_client::Error;
+
+/// Estimates fee rates with the `fee-estimates` endpoint of an Esplora server.
+///
+/// This implements [`FeeEstimator`] for [`esplora_client::BlockingClient`] and
+/// [`FeeEstimatorAsync`] for [`esplora_client::AsyncClient`].
+///
+/// The estimate for a confirmation target is the one of the highest target the server has an
+/// estimate for which is not above it. If there is none, the minimum relay fee rate is returned.
+///
+/// [`FeeEstimator`]: bdk_chain::FeeEstimator
+/// [`FeeEstimatorAsync`]: bdk_chain::FeeEstimatorAsync
+#[derive(Debug, Clone, Copy)]
+pub struct EsploraFeeEstimator<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C> EsploraFeeEstimator<'c, C> {
+    /// Create an estimator which requests fee estimates with `client`.
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<'c> FeeEstimator for EsploraFeeEstimator<'c, esplora_client::BlockingClient> {
+    type Error = Error;
+
+    fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error> {
+        let estimates = self.client.get_fee_estimates()?;
+        fee_rate_from_estimates(target, estimates)
+    }
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<'c> FeeEstimatorAsync for EsploraFeeEstimator<'c, esplora_client::AsyncClient> {
+    type Error = Error;
+
+    async fn estimate_fee_rate(&self, target: u16) -> Result<FeeRate, Self::Error> {
+        let estimates = self.client.get_fee_estimates().await?;
+        fee_rate_from_estimates(target, estimates)
+    }
+}
+
+/// Esplora estimates are in sat/vB, which are converted to whole sat/kvB and then rounded up to
+/// whole sat/kwu with [`fee_rate_from_sat_per_kvb`].
+#[allow(clippy::result_large_err)]
+fn fee_rate_from_estimates(
+    target: u16,
+    estimates: std::collections::HashMap<String, f64>,
+) -> Result<FeeRate, Error> {
+    let sat_per_vb = esplora_client::convert_fee_rate(target as usize, estimates)?;
+    Ok(fee_rate_from_sat_per_kvb(
+        (sat_per_vb as f64 * 1_000.0).round() as u64,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn estimates_are_converted_to_sat_per_kwu() {
+        let estimates = [("1", 20.5), ("6", 10.0), ("144", 1.1), ("1008", 1.001)]
+            .into_iter()
+            .map(|(target, rate)| (target.to_string(), rate))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        let fee_rate = |target| fee_rate_from_estimates(target, estimates.clone()).unwrap();
+        assert_eq!(fee_rate(1), FeeRate::from_sat_per_kwu(5125));
+        // the highest target which is not above the requested one is used
+        assert_eq!(fee_rate(10), FeeRate::from_sat_per_vb(10).unwrap());
+        assert_eq!(fee_rate(144), FeeRate::from_sat_per_kwu(275));
+        // rounded up, like the estimates of the other sources
+        assert_eq!(fee_rate(1008), FeeRate::from_sat_per_kwu(251));
+    }
+}
@@ -14,6 +14,11 @@ mod async_ext;
 #[cfg(feature = "async")]
 pub use async_ext::*;
 
+mod broadcaster;
+pub use broadcaster::*;
+mod fee_estimator;
+pub use fee_estimator::*;
+
 const ASSUME_FINAL_DEPTH: u32 = 15;
 
 fn anchor_from_status(status: &TxStatus) -> Option<ConfirmationTimeHeightAnchor> {
@@ -1,12 +1,81 @@
 use bincode::Options;
 use std::{
     fs::File,
-    io::{self, Seek},
+    io::{self, Read, Seek, Write},
     marker::PhantomData,
 };
 
 use crate::bincode_options;
 
+/// The length of the header which prefixes every entry.
+///
+/// The header consists of the length of the encoded entry (`u64`, little-endian) followed by the
+/// CRC32 checksum of the encoded entry (`u32`, little-endian).
+pub(crate) const ENTRY_HEADER_LEN: usize = 8 + 4;
+
+/// Encode `entry` with its header into `writer`.
+pub(crate) fn write_entry<W, T>(mut writer: W, entry: &T) -> Result<(), io::Error>
+where
+    W: Write,
+    T: serde::Serialize,
+{
+    let payload = bincode_options().serialize(entry).map_err(|e| match *e {
+        bincode::ErrorKind::Io(inner) => inner,
+        unexpected_err => panic!("unexpected bincode error: {}", unexpected_err),
+    })?;
+    let mut buf = Vec::with_capacity(ENTRY_HEADER_LEN + payload.len());
+    buf.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    buf.extend_from_slice(&payload);
+    writer.write_all(&buf)
+}
+
+/// Count the entries of `f` from `start_pos` by walking their headers, without reading or checking
+/// their content. Counting stops at the first truncated entry.
+///
+/// This changes the seek position of `f`.
+pub(crate) fn count_entries(f: &mut File, start_pos: u64) -> Result<usize, io::Error> {
+    let eof = f.seek(io::SeekFrom::End(0))?;
+    let mut pos = f.seek(io::SeekFrom::Start(start_pos))?;
+    let mut count = 0;
+    while eof - pos >= ENTRY_HEADER_LEN as u64 {
+        let mut len = [0_u8; 8];
+        f.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        match (pos + ENTRY_HEADER_LEN as u64).checked_add(len) {
+            Some(next_pos) if next_pos <= eof => pos = f.seek(io::SeekFrom::Start(next_pos))?,
+            _ => break,
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Whether the entry of `f` at `pos` is complete and matches its checksum. A file ending at `pos`
+/// has no entry to check, so this is `true`.
+///
+/// This changes the seek position of `f`.
+pub(crate) fn entry_is_intact(f: &mut File, pos: u64) -> Result<bool, io::Error> {
+    let eof = f.seek(io::SeekFrom::End(0))?;
+    if pos == eof {
+        return Ok(true);
+    }
+    if eof - pos < ENTRY_HEADER_LEN as u64 {
+        return Ok(false);
+    }
+    f.seek(io::SeekFrom::Start(pos))?;
+    let mut header = [0_u8; ENTRY_HEADER_LEN];
+    f.read_exact(&mut header)?;
+    let len = u64::from_le_bytes(header[..8].try_into().expect("must be 8 bytes"));
+    let expected = u32::from_le_bytes(header[8..].try_into().expect("must be 4 bytes"));
+    if len > eof - pos - ENTRY_HEADER_LEN as u64 {
+        return Ok(false);
+    }
+    let mut payload = vec![0_u8; len as usize];
+    f.read_exact(&mut payload)?;
+    Ok(crc32fast::hash(&payload) == expected)
+}
+
 /// Iterator over entries in a file store.
 ///
 /// Reads and returns an entry each time [`next`] is called. If an error occurs while reading the
@@ -44,23 +113,49 @@ where
                 Some(pos) => f.seek(io::SeekFrom::Start(pos))?,
                 None => f.stream_position()?,
             };
+            let eof = f.seek(io::SeekFrom::End(0))?;
+            f.seek(io::SeekFrom::Start(pos))?;
+            if pos == eof {
+                return Ok(None);
+            }
 
-            match bincode_options().deserialize_from(&*f) {
-                Ok(changeset) => {
-                    f.stream_position()?;
-                    Ok(Some(changeset))
+            let result = (|| {
+                let remaining = eof - pos;
+                if remaining < ENTRY_HEADER_LEN as u64 {
+                    return Err(IterError::Truncated { offset: pos });
+                }
+                let mut header = [0_u8; ENTRY_HEADER_LEN];
+                f.read_exact(&mut header)?;
+                let len = u64::from_le_bytes(header[..8].try_into().expect("must be 8 bytes"));
+                let expected = u32::from_le_bytes(header[8..].try_into().expect("must be 4 bytes"));
+                if len > remaining - ENTRY_HEADER_LEN as u64 {
+                    return Err(IterError::Truncated { offset: pos });
                 }
+
+                let mut payload = vec![0_u8; len as usize];
+                f.read_exact(&mut payload)?;
+                let got = crc32fast::hash(&payload);
+                if got != expected {
+                    return Err(IterError::Checksum {
+                        offset: pos,
+                        expected,
+                        got,
+                    });
+                }
+
+                bincode_options()
+                    .deserialize(&payload)
+                    .map_err(|e| IterError::Bincode {
+                        offset: pos,
+                        error: *e,
+                    })
+            })();
+
+            match result {
+                Ok(entry) => Ok(Some(entry)),
                 Err(e) => {
-                    if let bincode::ErrorKind::Io(inner) = &*e {
-                        if inner.kind() == io::ErrorKind::UnexpectedEof {
-                            let eof = f.seek(io::SeekFrom::End(0))?;
-                            if pos == eof {
-                                return Ok(None);
-                            }
-                        }
-                    }
                     f.seek(io::SeekFrom::Start(pos))?;
-                    Err(IterError::Bincode(*e))
+                    Err(e)
                 }
             }
         };
@@ -84,15 +179,64 @@ impl From<io::Error> for IterError {
 pub enum IterError {
     /// Failure to read from the file.
     Io(io::Error),
-    /// Failure to decode data from the file.
-    Bincode(bincode::ErrorKind),
+    /// The entry extends beyond the end of the file, this is usually the result of a torn write.
+    Truncated {
+        /// The file offset of the entry.
+        offset: u64,
+    },
+    /// The checksum of the entry does not match its content.
+    Checksum {
+        /// The file offset of the entry.
+        offset: u64,
+        /// The checksum stored in the entry header.
+        expected: u32,
+        /// The checksum of the entry content.
+        got: u32,
+    },
+    /// Failure to decode the entry, even though its checksum is valid.
+    ///
+    /// This usually means the entry was written with an incompatible changeset type.
+    Bincode {
+        /// The file offset of the entry.
+        offset: u64,
+        /// The decoding error.
+        error: bincode::ErrorKind,
+    },
+}
+
+impl IterError {
+    /// The file offset of the entry that could not be read, if the entry is corrupted.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            IterError::Io(_) => None,
+            IterError::Truncated { offset }
+            | IterError::Checksum { offset, .. }
+            | IterError::Bincode { offset, .. } => Some(*offset),
+        }
+    }
 }
 
 impl core::fmt::Display for IterError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             IterError::Io(e) => write!(f, "io error trying to read entry {}", e),
-            IterError::Bincode(e) => write!(f, "bincode error while reading entry {}", e),
+            IterError::Truncated { offset } => {
+                write!(f, "entry at offset {} is truncated", offset)
+            }
+            IterError::Checksum {
+                offset,
+                expected,
+                got,
+            } => write!(
+                f,
+                "entry at offset {} has invalid checksum: expected={:#010x} got={:#010x}",
+                offset, expected, got
+            ),
+            IterError::Bincode { offset, error } => write!(
+                f,
+                "bincode error while reading entry at offset {}: {}",
+                offset, error
+            ),
         }
     }
 }
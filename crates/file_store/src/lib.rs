@@ -18,6 +18,8 @@ pub enum FileError<'a> {
     Io(io::Error),
     /// Magic bytes do not match what is expected.
     InvalidMagicBytes { got: Vec<u8>, expected: &'a [u8] },
+    /// The file format version is not supported.
+    UnsupportedVersion { got: u32, expected: u32 },
 }
 
 impl<'a> core::fmt::Display for FileError<'a> {
@@ -29,6 +31,11 @@ impl<'a> core::fmt::Display for FileError<'a> {
                 "file has invalid magic bytes: expected={:?} got={:?}",
                 expected, got,
             ),
+            Self::UnsupportedVersion { got, expected } => write!(
+                f,
+                "file has unsupported format version: expected={} got={}",
+                expected, got,
+            ),
         }
     }
 }
@@ -3,24 +3,77 @@ use std::{
     fs::{File, OpenOptions},
     io::{self, Read, Seek, Write},
     marker::PhantomData,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use bdk_chain::{Append, PersistBackend};
+
+use crate::{
+    bincode_options,
+    entry_iter::{count_entries, entry_is_intact, write_entry},
+    EntryIter, FileError, IterError,
+};
 use bincode::Options;
 
-use crate::{bincode_options, EntryIter, FileError, IterError};
+/// The version of the file format, written directly after the magic bytes.
+///
+/// Version `1` frames every entry with its length and CRC32 checksum.
+pub const STORE_VERSION: u32 = 1;
+
+const VERSION_LEN: usize = 4;
 
 /// Persists an append-only list of changesets (`C`) to a single file.
 ///
 /// The changesets are the results of altering a tracker implementation (`T`).
+///
+/// The file starts with the magic bytes, followed by the [`STORE_VERSION`] of the file format.
+/// Every changeset is then stored as an entry prefixed with its length and CRC32 checksum, so
+/// that torn writes and corrupted entries can be detected (see [`IterError`]) and discarded with
+/// [`recover_changesets`].
+///
+/// Since the file only ever grows, it can be periodically replaced by a file holding a single
+/// aggregated changeset with [`compact`]. This can be done automatically by setting a
+/// [`CompactionThreshold`] with [`set_compaction_threshold`].
+///
+/// [`compact`]: Store::compact
+/// [`set_compaction_threshold`]: Store::set_compaction_threshold
+/// [`recover_changesets`]: Store::recover_changesets
 #[derive(Debug)]
 pub struct Store<'a, C> {
     magic: &'a [u8],
     db_file: File,
+    file_path: PathBuf,
+    /// The number of entries known to be in the file.
+    entry_count: usize,
+    /// The length of the file when it was last compacted.
+    compacted_len: u64,
+    compaction_threshold: CompactionThreshold,
     marker: PhantomData<C>,
 }
 
+/// Thresholds which trigger an automatic [`Store::compact`] after a changeset is appended.
+///
+/// Compaction happens if *any* of the set thresholds is exceeded. The default has no thresholds
+/// set, so the store is never compacted automatically.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionThreshold {
+    /// Compact once the file holds more than this many entries.
+    pub max_entries: Option<usize>,
+    /// Compact once the file has grown by more than this many bytes since it was last compacted.
+    ///
+    /// A file opened with [`Store::open`] is considered never compacted, so its size is counted
+    /// from the end of its header. Counting from the last compaction keeps a compacted file which
+    /// is still larger than this from being compacted again after every append.
+    pub max_bytes: Option<u64>,
+}
+
+impl CompactionThreshold {
+    fn is_exceeded(&self, entry_count: usize, growth: u64) -> bool {
+        self.max_entries.map_or(false, |max| entry_count > max)
+            || self.max_bytes.map_or(false, |max| growth > max)
+    }
+}
+
 impl<'a, C> PersistBackend<C> for Store<'a, C>
 where
     C: Append + serde::Serialize + serde::de::DeserializeOwned,
@@ -44,8 +97,8 @@ where
 {
     /// Create a new [`Store`] file in write-only mode; error if the file exists.
     ///
-    /// `magic` is the prefixed bytes to write to the new file. This will be checked when opening
-    /// the `Store` in the future with [`open`].
+    /// `magic` is the prefixed bytes to write to the new file, followed by the [`STORE_VERSION`].
+    /// Both will be checked when opening the `Store` in the future with [`open`].
     ///
     /// [`open`]: Store::open
     pub fn create_new<P>(magic: &'a [u8], file_path: P) -> Result<Self, FileError>
@@ -64,11 +117,16 @@ where
             .create(true)
             .read(true)
             .write(true)
-            .open(file_path)?;
+            .open(&file_path)?;
         f.write_all(magic)?;
+        f.write_all(&STORE_VERSION.to_le_bytes())?;
         Ok(Self {
             magic,
             db_file: f,
+            file_path: file_path.as_ref().to_path_buf(),
+            entry_count: 0,
+            compacted_len: (magic.len() + VERSION_LEN) as u64,
+            compaction_threshold: CompactionThreshold::default(),
             marker: Default::default(),
         })
     }
@@ -80,14 +138,37 @@ where
     /// # Errors
     ///
     /// If the prefixed bytes of the opened file does not match the provided `magic`, the
-    /// [`FileError::InvalidMagicBytes`] error variant will be returned.
+    /// [`FileError::InvalidMagicBytes`] error variant will be returned. If the file was written
+    /// with another version of the file format, [`FileError::UnsupportedVersion`] is returned.
+    ///
+    /// Files written before the format was versioned, with unframed changesets directly after the
+    /// magic bytes, are migrated to the current [`STORE_VERSION`] when opened. Like [`compact`],
+    /// the migrated file is written next to the original and renamed over it. If the changeset
+    /// type has changed since, use [`open_migrating`] instead.
     ///
     /// [`create_new`]: Store::create_new
+    /// [`compact`]: Store::compact
+    /// [`open_migrating`]: Store::open_migrating
     pub fn open<P>(magic: &'a [u8], file_path: P) -> Result<Self, FileError>
     where
         P: AsRef<Path>,
     {
-        let mut f = OpenOptions::new().read(true).write(true).open(file_path)?;
+        Self::open_migrating::<C, P>(magic, file_path)
+    }
+
+    /// Open an existing [`Store`], reading the changesets of a file of the legacy (unversioned)
+    /// format as `L` and converting them into `C`.
+    ///
+    /// This is like [`open`], for changeset types which gained fields since files of the legacy
+    /// format were written: `L` is the changeset type as it was then.
+    ///
+    /// [`open`]: Store::open
+    pub fn open_migrating<L, P>(magic: &'a [u8], file_path: P) -> Result<Self, FileError>
+    where
+        L: Append + serde::de::DeserializeOwned + Into<C>,
+        P: AsRef<Path>,
+    {
+        let mut f = OpenOptions::new().read(true).write(true).open(&file_path)?;
 
         let mut magic_buf = vec![0_u8; magic.len()];
         f.read_exact(&mut magic_buf)?;
@@ -98,9 +179,46 @@ where
             });
         }
 
+        let header_len = (magic.len() + VERSION_LEN) as u64;
+        let mut version_buf = [0_u8; VERSION_LEN];
+        let version = match f.read_exact(&mut version_buf) {
+            Ok(()) => Some(u32::from_le_bytes(version_buf)),
+            // an empty file of the legacy format
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e.into()),
+        };
+        // a legacy file could start with bytes which look like the version, in which case its
+        // first entry is very unlikely to have a valid checksum
+        let is_current = version == Some(STORE_VERSION) && entry_is_intact(&mut f, header_len)?;
+        if !is_current {
+            match read_legacy_entries::<L, C>(&mut f, magic.len() as u64)? {
+                Some(changesets) => {
+                    f = rewrite_file(magic, file_path.as_ref(), &changesets)?;
+                }
+                // a damaged entry of the current format, which `recover_changesets` can fix
+                None if version == Some(STORE_VERSION) => {}
+                None => {
+                    return Err(match version {
+                        Some(version) => FileError::UnsupportedVersion {
+                            got: version,
+                            expected: STORE_VERSION,
+                        },
+                        None => io::Error::from(io::ErrorKind::UnexpectedEof).into(),
+                    })
+                }
+            }
+        }
+
+        let entry_count = count_entries(&mut f, header_len)?;
+        f.seek(io::SeekFrom::Start(header_len))?;
+
         Ok(Self {
             magic,
             db_file: f,
+            file_path: file_path.as_ref().to_path_buf(),
+            entry_count,
+            compacted_len: header_len,
+            compaction_threshold: CompactionThreshold::default(),
             marker: Default::default(),
         })
     }
@@ -132,7 +250,7 @@ where
     /// always iterate over all entries until `None` is returned if you want your next write to go
     /// at the end; otherwise, you will write over existing entries.
     pub fn iter_changesets(&mut self) -> EntryIter<C> {
-        EntryIter::new(self.magic.len() as u64, &mut self.db_file)
+        EntryIter::new(self.header_len(), &mut self.db_file)
     }
 
     /// Loads all the changesets that have been stored as one giant changeset.
@@ -149,52 +267,214 @@ where
     /// changeset will be written over the erroring entry (or the end of the file if none existed).
     pub fn aggregate_changesets(&mut self) -> Result<Option<C>, AggregateChangesetsError<C>> {
         let mut changeset = Option::<C>::None;
+        let mut entry_count = 0;
         for next_changeset in self.iter_changesets() {
             let next_changeset = match next_changeset {
                 Ok(next_changeset) => next_changeset,
                 Err(iter_error) => {
+                    self.entry_count = entry_count;
                     return Err(AggregateChangesetsError {
                         changeset,
                         iter_error,
-                    })
+                    });
                 }
             };
+            entry_count += 1;
             match &mut changeset {
                 Some(changeset) => changeset.append(next_changeset),
                 changeset => *changeset = Some(next_changeset),
             }
         }
+        self.entry_count = entry_count;
         Ok(changeset)
     }
 
+    /// Loads all the changesets that can be read as one aggregate changeset, and truncates the file
+    /// to the end of the last entry that was read successfully.
+    ///
+    /// Use this instead of [`aggregate_changesets`] to recover from a torn write or a corrupted
+    /// entry. The corrupted entry and every entry after it are discarded from the file, and the
+    /// [`IterError`] describing the corruption is returned alongside the aggregate changeset.
+    ///
+    /// # Errors
+    ///
+    /// The file is never truncated because of an [`IterError::Io`]; that error is returned
+    /// instead.
+    ///
+    /// [`aggregate_changesets`]: Self::aggregate_changesets
+    pub fn recover_changesets(&mut self) -> Result<(Option<C>, Option<IterError>), io::Error> {
+        match self.aggregate_changesets() {
+            Ok(changeset) => Ok((changeset, None)),
+            Err(AggregateChangesetsError {
+                iter_error: IterError::Io(e),
+                ..
+            }) => Err(e),
+            Err(AggregateChangesetsError {
+                changeset,
+                iter_error,
+            }) => {
+                let offset = iter_error.offset().expect("only io errors have no offset");
+                self.db_file.set_len(offset)?;
+                self.db_file.seek(io::SeekFrom::Start(offset))?;
+                Ok((changeset, Some(iter_error)))
+            }
+        }
+    }
+
+    /// Get the thresholds which trigger automatic compaction.
+    pub fn compaction_threshold(&self) -> CompactionThreshold {
+        self.compaction_threshold
+    }
+
+    /// Set the thresholds which trigger an automatic [`compact`] after [`append_changeset`].
+    ///
+    /// [`compact`]: Self::compact
+    /// [`append_changeset`]: Self::append_changeset
+    pub fn set_compaction_threshold(&mut self, threshold: CompactionThreshold) {
+        self.compaction_threshold = threshold;
+    }
+
+    /// Replace the file with one that holds a single changeset: the aggregate of all changesets
+    /// currently stored.
+    ///
+    /// The compacted file is first written next to the original (with a `.compact` suffix), synced
+    /// to disk and then renamed over the original. Since the rename is atomic, the original file
+    /// is left untouched if this fails at any point. On Unix, the directory is synced after the
+    /// rename so that the compacted file replaces the original durably.
+    ///
+    /// # Errors
+    ///
+    /// If an entry cannot be read, the file is not compacted and the [`IterError`] is returned.
+    /// Like with [`aggregate_changesets`], the write position is then left at the erroring entry.
+    ///
+    /// [`aggregate_changesets`]: Self::aggregate_changesets
+    pub fn compact(&mut self) -> Result<(), IterError> {
+        let changeset = self.aggregate_changesets().map_err(|e| e.iter_error)?;
+        let changesets = changeset
+            .filter(|c| !c.is_empty())
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut file = rewrite_file(self.magic, &self.file_path, &changesets)?;
+        self.entry_count = changesets.len();
+        self.compacted_len = file.seek(io::SeekFrom::End(0))?;
+        self.db_file = file;
+        Ok(())
+    }
+
+    /// The length of the magic bytes and format version which prefix the file.
+    fn header_len(&self) -> u64 {
+        (self.magic.len() + VERSION_LEN) as u64
+    }
+
     /// Append a new changeset to the file and truncate the file to the end of the appended
     /// changeset.
     ///
     /// The truncation is to avoid the possibility of having a valid but inconsistent changeset
     /// directly after the appended changeset.
+    ///
+    /// If the [`CompactionThreshold`] is exceeded after appending, the file is [`compact`]ed. Note
+    /// that the changeset has already been appended if compaction fails.
+    ///
+    /// [`compact`]: Self::compact
     pub fn append_changeset(&mut self, changeset: &C) -> Result<(), io::Error> {
         // no need to write anything if changeset is empty
         if changeset.is_empty() {
             return Ok(());
         }
 
-        bincode_options()
-            .serialize_into(&mut self.db_file, changeset)
-            .map_err(|e| match *e {
-                bincode::ErrorKind::Io(inner) => inner,
-                unexpected_err => panic!("unexpected bincode error: {}", unexpected_err),
-            })?;
+        write_entry(&mut self.db_file, changeset)?;
 
         // truncate file after this changeset addition
         // if this is not done, data after this changeset may represent valid changesets, however
         // applying those changesets on top of this one may result in an inconsistent state
         let pos = self.db_file.stream_position()?;
         self.db_file.set_len(pos)?;
+        self.entry_count += 1;
+
+        let growth = pos.saturating_sub(self.compacted_len);
+        if self
+            .compaction_threshold
+            .is_exceeded(self.entry_count, growth)
+        {
+            self.compact().map_err(|e| match e {
+                IterError::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e),
+            })?;
+        }
 
         Ok(())
     }
 }
 
+/// Reads the changesets of a file of the legacy format from `start_pos`: bincode encoded
+/// changesets (`L`), one after the other.
+///
+/// Returns `None` if the file isn't of the legacy format. Since empty changesets were never
+/// written, an empty changeset means the file isn't either.
+fn read_legacy_entries<L, C>(f: &mut File, start_pos: u64) -> Result<Option<Vec<C>>, io::Error>
+where
+    L: Append + serde::de::DeserializeOwned + Into<C>,
+{
+    let mut data = Vec::new();
+    f.seek(io::SeekFrom::Start(start_pos))?;
+    f.read_to_end(&mut data)?;
+
+    let mut reader = data.as_slice();
+    let mut changesets = Vec::new();
+    while !reader.is_empty() {
+        match bincode_options().deserialize_from::<_, L>(&mut reader) {
+            Ok(changeset) if !changeset.is_empty() => changesets.push(changeset.into()),
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(changesets))
+}
+
+/// Replace the file at `file_path` with a file of the current format holding `changesets`, and
+/// return the new file.
+///
+/// The new file is written next to the original (with a `.compact` suffix), synced to disk and
+/// renamed over the original, so the original is left untouched if this fails at any point.
+fn rewrite_file<C: serde::Serialize>(
+    magic: &[u8],
+    file_path: &Path,
+    changesets: &[C],
+) -> Result<File, io::Error> {
+    let mut tmp_path = file_path.to_path_buf().into_os_string();
+    tmp_path.push(".compact");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut tmp_file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(&tmp_path)?;
+    tmp_file.write_all(magic)?;
+    tmp_file.write_all(&STORE_VERSION.to_le_bytes())?;
+    for changeset in changesets {
+        write_entry(&mut tmp_file, changeset)?;
+    }
+    tmp_file.sync_all()?;
+    std::fs::rename(&tmp_path, file_path)?;
+    #[cfg(unix)]
+    sync_parent_dir(file_path)?;
+
+    // the file handle still points to the file we renamed over
+    Ok(tmp_file)
+}
+
+/// Sync the directory holding `file_path`, which makes a rename of `file_path` durable.
+#[cfg(unix)]
+fn sync_parent_dir(file_path: &Path) -> Result<(), io::Error> {
+    let dir = match file_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
 /// Error type for [`Store::aggregate_changesets`].
 #[derive(Debug)]
 pub struct AggregateChangesetsError<C> {
@@ -217,7 +497,6 @@ impl<C: std::fmt::Debug> std::error::Error for AggregateChangesetsError<C> {}
 mod test {
     use super::*;
 
-    use bincode::DefaultOptions;
     use std::{
         io::{Read, Write},
         vec::Vec,
@@ -227,6 +506,7 @@ mod test {
     const TEST_MAGIC_BYTES_LEN: usize = 12;
     const TEST_MAGIC_BYTES: [u8; TEST_MAGIC_BYTES_LEN] =
         [98, 100, 107, 102, 115, 49, 49, 49, 49, 49, 49, 49];
+    const TEST_HEADER_LEN: usize = TEST_MAGIC_BYTES_LEN + VERSION_LEN;
 
     type TestChangeSet = Vec<String>;
 
@@ -298,11 +578,126 @@ mod test {
         };
     }
 
+    #[test]
+    fn new_fails_if_version_is_unsupported() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&TEST_MAGIC_BYTES).expect("should write");
+        file.write_all(&0_u32.to_le_bytes()).expect("should write");
+
+        match Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file.path()) {
+            Err(FileError::UnsupportedVersion { got, expected }) => {
+                assert_eq!(got, 0);
+                assert_eq!(expected, STORE_VERSION);
+            }
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        };
+    }
+
+    #[test]
+    fn open_migrates_legacy_file() {
+        let changesets: Vec<TestChangeSet> =
+            vec![vec!["one".into()], vec!["two".into(), "three".into()]];
+        let mut legacy = TEST_MAGIC_BYTES.to_vec();
+        for changeset in &changesets {
+            bincode_options()
+                .serialize_into(&mut legacy, changeset)
+                .expect("should encode");
+        }
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&legacy).expect("should write");
+
+        let mut store =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file.path()).expect("should open");
+        assert_eq!(
+            store.aggregate_changesets().expect("must read"),
+            Some(changesets.concat())
+        );
+        assert_eq!(read_file(file.path()), encoded_file(&changesets));
+
+        // a legacy file without changesets is only the magic bytes
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&TEST_MAGIC_BYTES).expect("should write");
+        let mut store =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file.path()).expect("should open");
+        assert_eq!(store.aggregate_changesets().expect("must read"), None);
+        assert_eq!(read_file(file.path()), encoded_file(&[]));
+    }
+
+    #[test]
+    fn open_migrating_converts_legacy_changesets() {
+        /// `TestChangeSet` with a field added after the legacy format.
+        #[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct ExtendedChangeSet {
+            names: TestChangeSet,
+            count: u32,
+        }
+
+        impl Append for ExtendedChangeSet {
+            fn append(&mut self, other: Self) {
+                self.names.extend(other.names);
+                self.count += other.count;
+            }
+
+            fn is_empty(&self) -> bool {
+                self.names.is_empty() && self.count == 0
+            }
+        }
+
+        impl From<TestChangeSet> for ExtendedChangeSet {
+            fn from(names: TestChangeSet) -> Self {
+                Self { names, count: 0 }
+            }
+        }
+
+        let changesets: Vec<TestChangeSet> =
+            vec![vec!["one".into()], vec!["two".into(), "three".into()]];
+        let mut legacy = TEST_MAGIC_BYTES.to_vec();
+        for changeset in &changesets {
+            bincode_options()
+                .serialize_into(&mut legacy, changeset)
+                .expect("should encode");
+        }
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&legacy).expect("should write");
+
+        // the legacy changesets can't be read as the extended type
+        assert!(Store::<ExtendedChangeSet>::open(&TEST_MAGIC_BYTES, file.path()).is_err());
+
+        let mut store = Store::<ExtendedChangeSet>::open_migrating::<TestChangeSet, _>(
+            &TEST_MAGIC_BYTES,
+            file.path(),
+        )
+        .expect("should open");
+        assert_eq!(
+            store.aggregate_changesets().expect("must read"),
+            Some(changesets.concat().into())
+        );
+        store
+            .append_changeset(&ExtendedChangeSet {
+                names: vec!["four".into()],
+                count: 1,
+            })
+            .expect("should append");
+        drop(store);
+
+        // once migrated, the file is of the current format
+        let mut store =
+            Store::<ExtendedChangeSet>::open(&TEST_MAGIC_BYTES, file.path()).expect("should open");
+        assert_eq!(
+            store.aggregate_changesets().expect("must read"),
+            Some(ExtendedChangeSet {
+                names: vec!["one".into(), "two".into(), "three".into(), "four".into()],
+                count: 1,
+            })
+        );
+    }
+
     #[test]
     fn append_changeset_truncates_invalid_bytes() {
-        // initial data to write to file (magic bytes + invalid data)
+        // initial data to write to file (magic bytes + version + invalid data)
         let mut data = [255_u8; 2000];
         data[..TEST_MAGIC_BYTES_LEN].copy_from_slice(&TEST_MAGIC_BYTES);
+        data[TEST_MAGIC_BYTES_LEN..TEST_HEADER_LEN].copy_from_slice(&STORE_VERSION.to_le_bytes());
 
         let changeset = vec!["one".into(), "two".into(), "three!".into()];
 
@@ -312,7 +707,9 @@ mod test {
         let mut store =
             Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file.path()).expect("should open");
         match store.iter_changesets().next() {
-            Some(Err(IterError::Bincode(_))) => {}
+            Some(Err(IterError::Truncated { offset })) => {
+                assert_eq!(offset, TEST_HEADER_LEN as u64)
+            }
             unexpected_res => panic!("unexpected result: {:?}", unexpected_res),
         }
 
@@ -320,24 +717,244 @@ mod test {
 
         drop(store);
 
-        let got_bytes = {
-            let mut buf = Vec::new();
-            file.reopen()
-                .unwrap()
-                .read_to_end(&mut buf)
-                .expect("should read");
-            buf
-        };
+        assert_eq!(read_file(file.path()), encoded_file(&[changeset]));
+    }
 
-        let expected_bytes = {
-            let mut buf = TEST_MAGIC_BYTES.to_vec();
-            DefaultOptions::new()
-                .with_varint_encoding()
-                .serialize_into(&mut buf, &changeset)
-                .expect("should encode");
-            buf
-        };
+    #[test]
+    fn iter_detects_corrupted_entry() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets: Vec<TestChangeSet> = vec![vec!["one".into()], vec!["two".into()]];
+
+        let mut store =
+            Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).expect("must create");
+        for changeset in &changesets {
+            store.append_changeset(changeset).expect("must append");
+        }
+        drop(store);
+
+        // flip a bit in the content of the second entry
+        let second_offset = encoded_file(&changesets[..1]).len();
+        let mut data = read_file(&file_path);
+        *data.last_mut().unwrap() ^= 1;
+        std::fs::write(&file_path, &data).unwrap();
+
+        let mut store =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).expect("must open");
+        let mut iter = store.iter_changesets();
+        assert_eq!(iter.next().unwrap().expect("must read"), changesets[0]);
+        match iter.next() {
+            Some(Err(IterError::Checksum { offset, .. })) => {
+                assert_eq!(offset, second_offset as u64)
+            }
+            unexpected_res => panic!("unexpected result: {:?}", unexpected_res),
+        }
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn recover_truncates_to_last_good_entry() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets: Vec<TestChangeSet> = vec![vec!["one".into()], vec!["two".into()]];
+
+        let mut store =
+            Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).expect("must create");
+        for changeset in &changesets {
+            store.append_changeset(changeset).expect("must append");
+        }
+        drop(store);
+
+        // simulate a torn write of a third entry
+        let good_len = encoded_file(&changesets).len();
+        let torn_entry = encoded_file(&[vec!["three".into()]])[TEST_HEADER_LEN..].to_vec();
+        let mut data = read_file(&file_path);
+        data.extend_from_slice(&torn_entry[..torn_entry.len() - 2]);
+        std::fs::write(&file_path, &data).unwrap();
+
+        let mut store =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).expect("must open");
+        let (changeset, error) = store.recover_changesets().expect("must recover");
+        assert_eq!(changeset, Some(changesets.concat()));
+        match error {
+            Some(IterError::Truncated { offset }) => assert_eq!(offset, good_len as u64),
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+        assert_eq!(read_file(&file_path).len(), good_len);
+
+        // nothing more to recover
+        let (changeset, error) = store.recover_changesets().expect("must recover");
+        assert_eq!(changeset, Some(changesets.concat()));
+        assert!(error.is_none());
+    }
+
+    fn read_file(path: &Path) -> Vec<u8> {
+        let mut buf = Vec::new();
+        File::open(path)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .expect("should read");
+        buf
+    }
+
+    fn encoded_file(changesets: &[TestChangeSet]) -> Vec<u8> {
+        let mut buf = TEST_MAGIC_BYTES.to_vec();
+        buf.extend_from_slice(&STORE_VERSION.to_le_bytes());
+        for changeset in changesets {
+            write_entry(&mut buf, changeset).expect("should encode");
+        }
+        buf
+    }
+
+    #[test]
+    fn compact_replaces_entries_with_aggregate() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets: Vec<TestChangeSet> = vec![
+            vec!["one".into()],
+            vec!["two".into(), "three".into()],
+            vec!["four".into()],
+        ];
+
+        let mut store =
+            Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).expect("must create");
+        for changeset in &changesets {
+            store.append_changeset(changeset).expect("must append");
+        }
+        store.compact().expect("must compact");
+
+        let aggregate = changesets.concat();
+        assert_eq!(
+            read_file(&file_path),
+            encoded_file(std::slice::from_ref(&aggregate))
+        );
+        assert!(!temp_dir.path().join("db_file.compact").exists());
+
+        // new changesets are appended after the compacted entry
+        store
+            .append_changeset(&vec!["five".into()])
+            .expect("must append");
+        drop(store);
+
+        let mut store =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).expect("must open");
+        let mut expected = aggregate;
+        expected.push("five".into());
+        assert_eq!(
+            store.aggregate_changesets().expect("must read"),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn compact_keeps_file_on_invalid_entry() {
+        let mut data = [255_u8; 40];
+        data[..TEST_MAGIC_BYTES_LEN].copy_from_slice(&TEST_MAGIC_BYTES);
+        data[TEST_MAGIC_BYTES_LEN..TEST_HEADER_LEN].copy_from_slice(&STORE_VERSION.to_le_bytes());
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&data).expect("should write");
+
+        let mut store =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file.path()).expect("should open");
+        match store.compact() {
+            Err(IterError::Truncated { .. }) => {}
+            unexpected_res => panic!("unexpected result: {:?}", unexpected_res),
+        }
+        assert_eq!(read_file(file.path()), data);
+    }
 
-        assert_eq!(got_bytes, expected_bytes);
+    #[test]
+    fn compacts_automatically_after_max_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let mut store =
+            Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).expect("must create");
+        store.set_compaction_threshold(CompactionThreshold {
+            max_entries: Some(2),
+            max_bytes: None,
+        });
+
+        store.append_changeset(&vec!["a".into()]).unwrap();
+        store.append_changeset(&vec!["b".into()]).unwrap();
+        assert_eq!(store.iter_changesets().count(), 2);
+
+        store.append_changeset(&vec!["c".into()]).unwrap();
+        assert_eq!(
+            read_file(&file_path),
+            encoded_file(&[vec!["a".into(), "b".into(), "c".into()]])
+        );
+    }
+
+    #[test]
+    fn compacts_automatically_after_max_bytes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let mut store =
+            Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).expect("must create");
+        let changeset: TestChangeSet = vec!["0123456789".into()];
+        store.set_compaction_threshold(CompactionThreshold {
+            max_entries: None,
+            max_bytes: Some(encoded_file(&[changeset.clone(), changeset.clone()]).len() as u64),
+        });
+
+        store.append_changeset(&changeset).unwrap();
+        store.append_changeset(&changeset).unwrap();
+        assert_eq!(store.iter_changesets().count(), 2);
+
+        // exceeding the size limit compacts all three entries into one
+        store.append_changeset(&changeset).unwrap();
+        assert_eq!(store.iter_changesets().count(), 1);
+        assert_eq!(
+            store.aggregate_changesets().unwrap(),
+            Some([changeset.clone(), changeset.clone(), changeset].concat())
+        );
+    }
+
+    #[test]
+    fn compacts_automatically_after_growing_max_bytes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let mut store =
+            Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).expect("must create");
+        store.set_compaction_threshold(CompactionThreshold {
+            max_entries: None,
+            max_bytes: Some(50),
+        });
+
+        // the compacted file is still larger than the limit
+        store.append_changeset(&vec!["x".repeat(100)]).unwrap();
+        assert_eq!(store.iter_changesets().count(), 1);
+
+        // but it is only compacted again once it grows by more than the limit
+        store.append_changeset(&vec!["a".into()]).unwrap();
+        store.append_changeset(&vec!["b".into()]).unwrap();
+        assert_eq!(store.iter_changesets().count(), 3);
+        store.append_changeset(&vec!["c".repeat(50)]).unwrap();
+        assert_eq!(store.iter_changesets().count(), 1);
+    }
+
+    #[test]
+    fn open_counts_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let mut store =
+            Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).expect("must create");
+        store.append_changeset(&vec!["a".into()]).unwrap();
+        store.append_changeset(&vec!["b".into()]).unwrap();
+        drop(store);
+
+        let mut store =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).expect("must open");
+        store.set_compaction_threshold(CompactionThreshold {
+            max_entries: Some(2),
+            max_bytes: None,
+        });
+        // the write position is left at the first entry
+        assert_eq!(store.iter_changesets().count(), 2);
+        store.append_changeset(&vec!["c".into()]).unwrap();
+        assert_eq!(
+            read_file(&file_path),
+            encoded_file(&[vec!["a".into(), "b".into(), "c".into()]])
+        );
     }
 }
@@ -0,0 +1,90 @@
+#![doc = include_str!("../README.md")]
+mod schema;
+mod store;
+
+use bdk::{bitcoin, miniscript};
+pub use rusqlite;
+pub use store::*;
+
+/// Error that occurs while reading or writing to the SQLite database.
+#[derive(Debug)]
+pub enum Error {
+    /// SQLite error.
+    Sqlite(rusqlite::Error),
+    /// The database was created by a newer version of this crate.
+    UnsupportedSchemaVersion {
+        /// The schema version of the database.
+        got: usize,
+        /// The latest schema version supported by this crate.
+        max: usize,
+    },
+    /// Stored network name could not be parsed.
+    Network(bitcoin::network::constants::ParseNetworkError),
+    /// Stored block hash or txid could not be parsed.
+    Hash(bitcoin::hashes::hex::Error),
+    /// Stored transaction could not be decoded.
+    Consensus(bitcoin::consensus::encode::Error),
+    /// Stored keychain could not be (de)serialized.
+    Keychain(serde_json::Error),
+    /// Stored descriptor could not be parsed.
+    Descriptor(miniscript::Error),
+    /// Stored label reference could not be parsed.
+    Label(bdk::wallet::labels::InvalidLabelRef),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Sqlite(e) => write!(f, "sqlite error: {}", e),
+            Self::UnsupportedSchemaVersion { got, max } => write!(
+                f,
+                "unsupported database schema version: got={} max={}",
+                got, max
+            ),
+            Self::Network(e) => write!(f, "invalid stored network: {}", e),
+            Self::Hash(e) => write!(f, "invalid stored hash: {}", e),
+            Self::Consensus(e) => write!(f, "invalid stored transaction: {}", e),
+            Self::Keychain(e) => write!(f, "invalid stored keychain: {}", e),
+            Self::Descriptor(e) => write!(f, "invalid stored descriptor: {}", e),
+            Self::Label(e) => write!(f, "invalid stored label: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+    fn from(value: rusqlite::Error) -> Self {
+        Self::Sqlite(value)
+    }
+}
+
+impl From<bitcoin::network::constants::ParseNetworkError> for Error {
+    fn from(value: bitcoin::network::constants::ParseNetworkError) -> Self {
+        Self::Network(value)
+    }
+}
+
+impl From<bitcoin::hashes::hex::Error> for Error {
+    fn from(value: bitcoin::hashes::hex::Error) -> Self {
+        Self::Hash(value)
+    }
+}
+
+impl From<bitcoin::consensus::encode::Error> for Error {
+    fn from(value: bitcoin::consensus::encode::Error) -> Self {
+        Self::Consensus(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Keychain(value)
+    }
+}
+
+impl From<miniscript::Error> for Error {
+    fn from(value: miniscript::Error) -> Self {
+        Self::Descriptor(value)
+    }
+}
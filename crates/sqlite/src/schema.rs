@@ -0,0 +1,119 @@
+use rusqlite::{params, Connection, Transaction};
+
+use crate::Error;
+
+/// Database schema migrations.
+///
+/// Each entry is applied in order, exactly once. The number of applied migrations is stored as the
+/// schema version in the `version` table. New migrations must only ever be appended to this list.
+const MIGRATIONS: &[&str] = &[
+    // schema version control
+    "CREATE TABLE version (version INTEGER NOT NULL);
+     INSERT INTO version VALUES (0);",
+    // wallet network, a single row
+    "CREATE TABLE network (name TEXT NOT NULL);",
+    // keychain descriptor indexes
+    "CREATE TABLE keychain (
+         keychain TEXT PRIMARY KEY NOT NULL,
+         last_revealed INTEGER NOT NULL
+     ) STRICT;",
+    // local chain checkpoints
+    "CREATE TABLE block (
+         height INTEGER PRIMARY KEY NOT NULL,
+         hash TEXT NOT NULL
+     ) STRICT;",
+    // full transactions and the last time they were seen unconfirmed, either may be missing
+    "CREATE TABLE tx (
+         txid TEXT PRIMARY KEY NOT NULL,
+         whole_tx BLOB,
+         last_seen INTEGER
+     ) STRICT;",
+    // floating txouts
+    "CREATE TABLE txout (
+         txid TEXT NOT NULL,
+         vout INTEGER NOT NULL,
+         value INTEGER NOT NULL,
+         script BLOB NOT NULL,
+         PRIMARY KEY (txid, vout)
+     ) STRICT;",
+    // transaction anchors
+    "CREATE TABLE anchor_tx (
+         anchor_height INTEGER NOT NULL,
+         anchor_hash TEXT NOT NULL,
+         confirmation_height INTEGER NOT NULL,
+         confirmation_time INTEGER NOT NULL,
+         txid TEXT NOT NULL,
+         PRIMARY KEY (anchor_height, anchor_hash, confirmation_height, confirmation_time, txid)
+     ) STRICT;",
+    "CREATE INDEX anchor_tx_txid ON anchor_tx (txid);",
+    // public descriptors of custom keychains
+    "CREATE TABLE descriptor (
+         keychain TEXT PRIMARY KEY NOT NULL,
+         descriptor TEXT NOT NULL
+     ) STRICT;",
+    // BIP329 labels, keyed by their type and ref
+    "CREATE TABLE label (
+         type TEXT NOT NULL,
+         ref TEXT NOT NULL,
+         label TEXT NOT NULL,
+         origin TEXT,
+         PRIMARY KEY (type, ref)
+     ) STRICT;",
+    // outpoints frozen by the user
+    "CREATE TABLE frozen_utxo (
+         txid TEXT NOT NULL,
+         vout INTEGER NOT NULL,
+         PRIMARY KEY (txid, vout)
+     ) STRICT;",
+    // minimum confirmations of the outputs of a keychain to be spent
+    "CREATE TABLE min_confirmations (
+         keychain TEXT PRIMARY KEY NOT NULL,
+         confirmations INTEGER NOT NULL
+     ) STRICT;",
+    // payments queued for the next batch, and the batches built, keyed by the order they were
+    // built in
+    "CREATE TABLE payment (
+         id TEXT PRIMARY KEY NOT NULL,
+         script BLOB NOT NULL,
+         amount INTEGER NOT NULL
+     ) STRICT;
+     CREATE TABLE payment_batch (
+         idx INTEGER PRIMARY KEY NOT NULL,
+         batch TEXT NOT NULL
+     ) STRICT;",
+];
+
+/// Apply all migrations that are newer than the database's current schema version.
+pub(crate) fn migrate(conn: &mut Connection) -> Result<(), Error> {
+    let db_tx = conn.transaction()?;
+    let current = schema_version(&db_tx)?;
+    let target = MIGRATIONS.len();
+    if current > target {
+        return Err(Error::UnsupportedSchemaVersion {
+            got: current,
+            max: target,
+        });
+    }
+    for migration in &MIGRATIONS[current..] {
+        db_tx.execute_batch(migration)?;
+    }
+    if current < target {
+        db_tx.execute("UPDATE version SET version = ?1", params![target as i64])?;
+    }
+    db_tx.commit()?;
+    Ok(())
+}
+
+/// Read the schema version, `0` if the database was never initialized.
+fn schema_version(db_tx: &Transaction) -> Result<usize, Error> {
+    let has_version_table: bool = db_tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'version')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_version_table {
+        return Ok(0);
+    }
+    let version: i64 = db_tx.query_row("SELECT version FROM version", [], |row| row.get(0))?;
+    Ok(version as usize)
+}
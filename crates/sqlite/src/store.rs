@@ -0,0 +1,759 @@
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+use bdk::bitcoin::{
+    consensus::{deserialize, serialize},
+    BlockHash, Network, OutPoint, ScriptBuf, Transaction, TxOut, Txid,
+};
+use bdk::chain::{
+    indexed_tx_graph, keychain, local_chain, tx_graph, Append, BlockId,
+    ConfirmationTimeHeightAnchor, PersistBackend,
+};
+use bdk::descriptor::ExtendedDescriptor;
+use bdk::wallet::labels::{self, InvalidLabelRef, Label, LabelRef};
+use bdk::wallet::payment_queue;
+use bdk::wallet::ChangeSet;
+use bdk::KeychainKind;
+use rusqlite::{named_params, params, Connection, OptionalExtension, Transaction as DbTransaction};
+
+use crate::{schema, Error};
+
+/// Persists a wallet [`ChangeSet`] into normalized tables of a SQLite database.
+///
+/// Each table holds one part of the aggregate changeset: the network, the last revealed index of
+/// each keychain, the local chain's blocks, and the transactions, txouts, anchors and last-seen
+/// timestamps of the transaction graph. Writing a changeset merges it into the existing rows, so
+/// loading only needs to read the tables back rather than replay every changeset ever written.
+#[derive(Debug)]
+pub struct Store {
+    conn: Connection,
+}
+
+impl PersistBackend<ChangeSet> for Store {
+    type WriteError = Error;
+
+    type LoadError = Error;
+
+    fn write_changes(&mut self, changeset: &ChangeSet) -> Result<(), Self::WriteError> {
+        self.write(changeset)
+    }
+
+    fn load_from_persistence(&mut self) -> Result<Option<ChangeSet>, Self::LoadError> {
+        self.read()
+    }
+}
+
+impl Store {
+    /// Create a [`Store`] from an existing SQLite [`Connection`].
+    ///
+    /// The wallet tables are created, or migrated to the latest schema version, if needed.
+    pub fn new(mut conn: Connection) -> Result<Self, Error> {
+        schema::migrate(&mut conn)?;
+        Ok(Self { conn })
+    }
+
+    /// Open the SQLite database at `path`, creating it if it does not exist.
+    pub fn open<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Open a new in-memory SQLite database, mostly useful for testing.
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    /// Get a reference to the underlying [`Connection`].
+    ///
+    /// This can be used to query the wallet tables directly. Writing to the wallet tables through
+    /// this connection may leave the store in an inconsistent state.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Write `changeset` to the database in a single database transaction.
+    pub fn write(&mut self, changeset: &ChangeSet) -> Result<(), Error> {
+        // `ChangeSet::is_empty` does not account for the network
+        if changeset.is_empty() && changeset.network.is_none() {
+            return Ok(());
+        }
+
+        let db_tx = self.conn.transaction()?;
+        if let Some(network) = changeset.network {
+            insert_network(&db_tx, network)?;
+        }
+        insert_descriptors(&db_tx, &changeset.descriptors)?;
+        insert_keychains(&db_tx, &changeset.indexed_tx_graph.indexer)?;
+        insert_blocks(&db_tx, &changeset.chain)?;
+        insert_graph(&db_tx, &changeset.indexed_tx_graph.graph)?;
+        insert_labels(&db_tx, &changeset.labels)?;
+        insert_frozen(&db_tx, &changeset.frozen)?;
+        insert_min_confirmations(&db_tx, &changeset.min_confirmations)?;
+        insert_payment_queue(&db_tx, &changeset.payment_queue)?;
+        db_tx.commit()?;
+        Ok(())
+    }
+
+    /// Read the aggregate [`ChangeSet`] from the database.
+    ///
+    /// Returns `None` if nothing has been written to the database yet.
+    pub fn read(&mut self) -> Result<Option<ChangeSet>, Error> {
+        let db_tx = self.conn.transaction()?;
+        let changeset = ChangeSet {
+            chain: select_blocks(&db_tx)?,
+            indexed_tx_graph: indexed_tx_graph::ChangeSet {
+                graph: select_graph(&db_tx)?,
+                indexer: select_keychains(&db_tx)?,
+            },
+            network: select_network(&db_tx)?,
+            descriptors: select_descriptors(&db_tx)?,
+            labels: select_labels(&db_tx)?,
+            frozen: select_frozen(&db_tx)?,
+            min_confirmations: select_min_confirmations(&db_tx)?,
+            payment_queue: select_payment_queue(&db_tx)?,
+        };
+        db_tx.commit()?;
+
+        if changeset.is_empty() && changeset.network.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(changeset))
+    }
+}
+
+fn insert_network(db_tx: &DbTransaction, network: Network) -> Result<(), Error> {
+    // the network of a wallet never changes, so only the first one is kept
+    db_tx.execute(
+        "INSERT INTO network (name) SELECT ?1 WHERE NOT EXISTS (SELECT 1 FROM network)",
+        params![network.to_string()],
+    )?;
+    Ok(())
+}
+
+fn select_network(db_tx: &DbTransaction) -> Result<Option<Network>, Error> {
+    let name: Option<String> = db_tx
+        .query_row("SELECT name FROM network", [], |row| row.get(0))
+        .optional()?;
+    Ok(name.map(|name| Network::from_str(&name)).transpose()?)
+}
+
+fn insert_descriptors(
+    db_tx: &DbTransaction,
+    descriptors: &BTreeMap<KeychainKind, ExtendedDescriptor>,
+) -> Result<(), Error> {
+    // the descriptor of a keychain never changes, so only the first one is kept
+    let mut stmt = db_tx.prepare_cached(
+        "INSERT OR IGNORE INTO descriptor (keychain, descriptor) VALUES (:keychain, :descriptor)",
+    )?;
+    for (keychain, descriptor) in descriptors {
+        stmt.execute(named_params! {
+            ":keychain": serde_json::to_string(keychain)?,
+            ":descriptor": descriptor.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+fn select_descriptors(
+    db_tx: &DbTransaction,
+) -> Result<BTreeMap<KeychainKind, ExtendedDescriptor>, Error> {
+    let mut stmt = db_tx.prepare_cached("SELECT keychain, descriptor FROM descriptor")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut descriptors = BTreeMap::new();
+    for row in rows {
+        let (keychain, descriptor) = row?;
+        descriptors.insert(
+            serde_json::from_str(&keychain)?,
+            ExtendedDescriptor::from_str(&descriptor)?,
+        );
+    }
+    Ok(descriptors)
+}
+
+fn insert_labels(db_tx: &DbTransaction, changeset: &labels::ChangeSet) -> Result<(), Error> {
+    let mut insert_stmt = db_tx.prepare_cached(
+        "INSERT OR REPLACE INTO label (type, ref, label, origin)
+         VALUES (:type, :ref, :label, :origin)",
+    )?;
+    let mut delete_stmt = db_tx.prepare_cached("DELETE FROM label WHERE type = ?1 AND ref = ?2")?;
+    for (reference, label) in &changeset.0 {
+        match label {
+            Some(label) => {
+                insert_stmt.execute(named_params! {
+                    ":type": reference.type_name(),
+                    ":ref": reference.to_string(),
+                    ":label": label.text,
+                    ":origin": label.origin,
+                })?;
+            }
+            None => {
+                delete_stmt.execute(params![reference.type_name(), reference.to_string()])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn select_labels(db_tx: &DbTransaction) -> Result<labels::ChangeSet, Error> {
+    let mut stmt = db_tx.prepare_cached("SELECT type, ref, label, origin FROM label")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            Label {
+                text: row.get(2)?,
+                origin: row.get(3)?,
+            },
+        ))
+    })?;
+    let mut changeset = labels::ChangeSet::default();
+    for row in rows {
+        let (type_name, reference, label) = row?;
+        let reference = LabelRef::from_bip329(&type_name, &reference)
+            .ok()
+            .flatten()
+            .ok_or(Error::Label(InvalidLabelRef {
+                type_name,
+                reference,
+            }))?;
+        changeset.0.insert(reference, Some(label));
+    }
+    Ok(changeset)
+}
+
+fn insert_frozen(db_tx: &DbTransaction, frozen: &BTreeMap<OutPoint, bool>) -> Result<(), Error> {
+    let mut insert_stmt =
+        db_tx.prepare_cached("INSERT OR IGNORE INTO frozen_utxo (txid, vout) VALUES (?1, ?2)")?;
+    let mut delete_stmt =
+        db_tx.prepare_cached("DELETE FROM frozen_utxo WHERE txid = ?1 AND vout = ?2")?;
+    for (outpoint, &frozen) in frozen {
+        let stmt = if frozen {
+            &mut insert_stmt
+        } else {
+            &mut delete_stmt
+        };
+        stmt.execute(params![outpoint.txid.to_string(), outpoint.vout])?;
+    }
+    Ok(())
+}
+
+fn select_frozen(db_tx: &DbTransaction) -> Result<BTreeMap<OutPoint, bool>, Error> {
+    let mut stmt = db_tx.prepare_cached("SELECT txid, vout FROM frozen_utxo")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+    })?;
+    let mut frozen = BTreeMap::new();
+    for row in rows {
+        let (txid, vout) = row?;
+        frozen.insert(OutPoint::new(Txid::from_str(&txid)?, vout), true);
+    }
+    Ok(frozen)
+}
+
+fn insert_min_confirmations(
+    db_tx: &DbTransaction,
+    min_confirmations: &BTreeMap<KeychainKind, u32>,
+) -> Result<(), Error> {
+    let mut stmt = db_tx.prepare_cached(
+        "INSERT OR REPLACE INTO min_confirmations (keychain, confirmations)
+         VALUES (:keychain, :confirmations)",
+    )?;
+    for (keychain, &confirmations) in min_confirmations {
+        stmt.execute(named_params! {
+            ":keychain": serde_json::to_string(keychain)?,
+            ":confirmations": confirmations,
+        })?;
+    }
+    Ok(())
+}
+
+fn select_min_confirmations(db_tx: &DbTransaction) -> Result<BTreeMap<KeychainKind, u32>, Error> {
+    let mut stmt = db_tx.prepare_cached("SELECT keychain, confirmations FROM min_confirmations")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+    })?;
+    let mut min_confirmations = BTreeMap::new();
+    for row in rows {
+        let (keychain, confirmations) = row?;
+        min_confirmations.insert(serde_json::from_str(&keychain)?, confirmations);
+    }
+    Ok(min_confirmations)
+}
+
+fn insert_payment_queue(
+    db_tx: &DbTransaction,
+    changeset: &payment_queue::ChangeSet,
+) -> Result<(), Error> {
+    let mut payment_stmt = db_tx.prepare_cached(
+        "INSERT OR REPLACE INTO payment (id, script, amount) VALUES (:id, :script, :amount)",
+    )?;
+    for (id, (script_pubkey, amount)) in &changeset.payments {
+        payment_stmt.execute(named_params! {
+            ":id": serde_json::to_string(id)?,
+            ":script": script_pubkey.as_bytes(),
+            ":amount": amount,
+        })?;
+    }
+    let mut insert_batch_stmt = db_tx
+        .prepare_cached("INSERT OR REPLACE INTO payment_batch (idx, batch) VALUES (?1, ?2)")?;
+    let mut delete_batch_stmt = db_tx.prepare_cached("DELETE FROM payment_batch WHERE idx = ?1")?;
+    for (&index, batch) in &changeset.batches {
+        match batch {
+            Some(batch) => {
+                insert_batch_stmt.execute(params![index, serde_json::to_string(batch)?])?
+            }
+            None => delete_batch_stmt.execute(params![index])?,
+        };
+    }
+    Ok(())
+}
+
+fn select_payment_queue(db_tx: &DbTransaction) -> Result<payment_queue::ChangeSet, Error> {
+    let mut changeset = payment_queue::ChangeSet::default();
+    let mut payment_stmt = db_tx.prepare_cached("SELECT id, script, amount FROM payment")?;
+    let rows = payment_stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, Vec<u8>>(1)?,
+            row.get::<_, u64>(2)?,
+        ))
+    })?;
+    for row in rows {
+        let (id, script, amount) = row?;
+        changeset.payments.insert(
+            serde_json::from_str(&id)?,
+            (ScriptBuf::from(script), amount),
+        );
+    }
+    let mut batch_stmt = db_tx.prepare_cached("SELECT idx, batch FROM payment_batch")?;
+    let rows = batch_stmt.query_map([], |row| {
+        Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (index, batch) = row?;
+        changeset
+            .batches
+            .insert(index, Some(serde_json::from_str(&batch)?));
+    }
+    Ok(changeset)
+}
+
+fn insert_keychains(
+    db_tx: &DbTransaction,
+    changeset: &keychain::ChangeSet<KeychainKind>,
+) -> Result<(), Error> {
+    let mut stmt = db_tx.prepare_cached(
+        "INSERT INTO keychain (keychain, last_revealed) VALUES (:keychain, :last_revealed)
+         ON CONFLICT (keychain) DO UPDATE
+         SET last_revealed = MAX(last_revealed, excluded.last_revealed)",
+    )?;
+    for (keychain, &last_revealed) in changeset.as_inner() {
+        stmt.execute(named_params! {
+            ":keychain": serde_json::to_string(keychain)?,
+            ":last_revealed": last_revealed,
+        })?;
+    }
+    Ok(())
+}
+
+fn select_keychains(db_tx: &DbTransaction) -> Result<keychain::ChangeSet<KeychainKind>, Error> {
+    let mut stmt = db_tx.prepare_cached("SELECT keychain, last_revealed FROM keychain")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+    })?;
+    let mut changeset = keychain::ChangeSet::default();
+    for row in rows {
+        let (keychain, last_revealed) = row?;
+        changeset
+            .0
+            .insert(serde_json::from_str(&keychain)?, last_revealed);
+    }
+    Ok(changeset)
+}
+
+fn insert_blocks(db_tx: &DbTransaction, changeset: &local_chain::ChangeSet) -> Result<(), Error> {
+    let mut insert_stmt =
+        db_tx.prepare_cached("INSERT OR REPLACE INTO block (height, hash) VALUES (?1, ?2)")?;
+    let mut delete_stmt = db_tx.prepare_cached("DELETE FROM block WHERE height = ?1")?;
+    for (&height, hash) in changeset {
+        match hash {
+            Some(hash) => insert_stmt.execute(params![height, hash.to_string()])?,
+            None => delete_stmt.execute(params![height])?,
+        };
+    }
+    Ok(())
+}
+
+fn select_blocks(db_tx: &DbTransaction) -> Result<local_chain::ChangeSet, Error> {
+    let mut stmt = db_tx.prepare_cached("SELECT height, hash FROM block")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut changeset = local_chain::ChangeSet::default();
+    for row in rows {
+        let (height, hash) = row?;
+        changeset.insert(height, Some(BlockHash::from_str(&hash)?));
+    }
+    Ok(changeset)
+}
+
+fn insert_graph(
+    db_tx: &DbTransaction,
+    changeset: &tx_graph::ChangeSet<ConfirmationTimeHeightAnchor>,
+) -> Result<(), Error> {
+    let mut tx_stmt = db_tx.prepare_cached(
+        "INSERT INTO tx (txid, whole_tx) VALUES (:txid, :whole_tx)
+         ON CONFLICT (txid) DO UPDATE SET whole_tx = excluded.whole_tx",
+    )?;
+    for tx in &changeset.txs {
+        tx_stmt.execute(named_params! {
+            ":txid": tx.txid().to_string(),
+            ":whole_tx": serialize(tx),
+        })?;
+    }
+
+    // last-seen timestamps should only ever increase
+    let mut last_seen_stmt = db_tx.prepare_cached(
+        "INSERT INTO tx (txid, last_seen) VALUES (:txid, :last_seen)
+         ON CONFLICT (txid) DO UPDATE
+         SET last_seen = MAX(COALESCE(last_seen, excluded.last_seen), excluded.last_seen)",
+    )?;
+    for (txid, &last_seen) in &changeset.last_seen {
+        last_seen_stmt.execute(named_params! {
+            ":txid": txid.to_string(),
+            ":last_seen": last_seen as i64,
+        })?;
+    }
+
+    let mut txout_stmt = db_tx.prepare_cached(
+        "INSERT OR REPLACE INTO txout (txid, vout, value, script)
+         VALUES (:txid, :vout, :value, :script)",
+    )?;
+    for (outpoint, txout) in &changeset.txouts {
+        txout_stmt.execute(named_params! {
+            ":txid": outpoint.txid.to_string(),
+            ":vout": outpoint.vout,
+            ":value": txout.value as i64,
+            ":script": txout.script_pubkey.as_bytes(),
+        })?;
+    }
+
+    let mut anchor_stmt = db_tx.prepare_cached(
+        "INSERT OR IGNORE INTO anchor_tx
+         (anchor_height, anchor_hash, confirmation_height, confirmation_time, txid)
+         VALUES (:anchor_height, :anchor_hash, :confirmation_height, :confirmation_time, :txid)",
+    )?;
+    for (anchor, txid) in &changeset.anchors {
+        anchor_stmt.execute(named_params! {
+            ":anchor_height": anchor.anchor_block.height,
+            ":anchor_hash": anchor.anchor_block.hash.to_string(),
+            ":confirmation_height": anchor.confirmation_height,
+            ":confirmation_time": anchor.confirmation_time as i64,
+            ":txid": txid.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+fn select_graph(
+    db_tx: &DbTransaction,
+) -> Result<tx_graph::ChangeSet<ConfirmationTimeHeightAnchor>, Error> {
+    let mut changeset = tx_graph::ChangeSet::default();
+
+    let mut tx_stmt = db_tx.prepare_cached("SELECT txid, whole_tx, last_seen FROM tx")?;
+    let rows = tx_stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<Vec<u8>>>(1)?,
+            row.get::<_, Option<i64>>(2)?,
+        ))
+    })?;
+    for row in rows {
+        let (txid, whole_tx, last_seen) = row?;
+        if let Some(whole_tx) = whole_tx {
+            changeset.txs.insert(deserialize::<Transaction>(&whole_tx)?);
+        }
+        if let Some(last_seen) = last_seen {
+            changeset
+                .last_seen
+                .insert(Txid::from_str(&txid)?, last_seen as u64);
+        }
+    }
+
+    let mut txout_stmt = db_tx.prepare_cached("SELECT txid, vout, value, script FROM txout")?;
+    let rows = txout_stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, u32>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, Vec<u8>>(3)?,
+        ))
+    })?;
+    for row in rows {
+        let (txid, vout, value, script) = row?;
+        changeset.txouts.insert(
+            OutPoint::new(Txid::from_str(&txid)?, vout),
+            TxOut {
+                value: value as u64,
+                script_pubkey: ScriptBuf::from_bytes(script),
+            },
+        );
+    }
+
+    let mut anchor_stmt = db_tx.prepare_cached(
+        "SELECT anchor_height, anchor_hash, confirmation_height, confirmation_time, txid
+         FROM anchor_tx",
+    )?;
+    let rows = anchor_stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, u32>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, u32>(2)?,
+            row.get::<_, i64>(3)?,
+            row.get::<_, String>(4)?,
+        ))
+    })?;
+    for row in rows {
+        let (anchor_height, anchor_hash, confirmation_height, confirmation_time, txid) = row?;
+        let anchor = ConfirmationTimeHeightAnchor {
+            anchor_block: BlockId {
+                height: anchor_height,
+                hash: BlockHash::from_str(&anchor_hash)?,
+            },
+            confirmation_height,
+            confirmation_time: confirmation_time as u64,
+        };
+        changeset.anchors.insert((anchor, Txid::from_str(&txid)?));
+    }
+
+    Ok(changeset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bdk::bitcoin::{absolute, hashes::Hash, TxIn};
+    use bdk::chain::ConfirmationTime;
+    use bdk::wallet::{AddressIndex, Wallet};
+
+    const DESCRIPTOR: &str = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+
+    fn test_tx(value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn empty_store_loads_none() {
+        let mut store = Store::open_in_memory().expect("must open");
+        assert_eq!(store.load_from_persistence().expect("must load"), None);
+    }
+
+    #[test]
+    fn write_and_read_aggregates_changesets() {
+        let mut store = Store::open_in_memory().expect("must open");
+        let tx = test_tx(10_000);
+        let txid = tx.txid();
+        let anchor = ConfirmationTimeHeightAnchor {
+            anchor_block: BlockId {
+                height: 2,
+                hash: BlockHash::all_zeros(),
+            },
+            confirmation_height: 1,
+            confirmation_time: 100,
+        };
+        let floating_op = OutPoint::new(Txid::all_zeros(), 3);
+        let floating_txout = TxOut {
+            value: 42,
+            script_pubkey: ScriptBuf::new(),
+        };
+        let payment_id =
+            |id: u64| -> payment_queue::PaymentId { serde_json::from_value(id.into()).unwrap() };
+        let batch = |vout: u32, payments: &[u64]| payment_queue::Batch {
+            inputs: vec![OutPoint::new(txid, vout)],
+            payments: payments.iter().copied().map(payment_id).collect(),
+        };
+
+        let mut changesets = vec![
+            ChangeSet {
+                chain: [
+                    (0, Some(BlockHash::all_zeros())),
+                    (2, Some(BlockHash::all_zeros())),
+                ]
+                .into(),
+                indexed_tx_graph: keychain::ChangeSet([(KeychainKind::External, 3)].into()).into(),
+                network: Some(Network::Testnet),
+                descriptors: [(
+                    KeychainKind::custom(7),
+                    ExtendedDescriptor::from_str(DESCRIPTOR).unwrap(),
+                )]
+                .into(),
+                labels: labels::ChangeSet(
+                    [
+                        (LabelRef::Tx(txid), Some(Label::from("rent"))),
+                        (
+                            LabelRef::Output(floating_op),
+                            Some(Label {
+                                text: "change".into(),
+                                origin: Some("wpkh([d34db33f/84'/1'/0'])".into()),
+                            }),
+                        ),
+                    ]
+                    .into(),
+                ),
+                frozen: [(floating_op, true), (OutPoint::new(txid, 0), true)].into(),
+                min_confirmations: [(KeychainKind::External, 6)].into(),
+                payment_queue: payment_queue::ChangeSet {
+                    payments: [
+                        (payment_id(0), (ScriptBuf::from(vec![0x51]), 1_000)),
+                        (payment_id(1), (ScriptBuf::from(vec![0x52]), 2_000)),
+                    ]
+                    .into(),
+                    batches: [(0, Some(batch(0, &[0]))), (1, Some(batch(1, &[0, 1])))].into(),
+                },
+            },
+            ChangeSet::from(indexed_tx_graph::ChangeSet::from(tx_graph::ChangeSet {
+                txs: [tx.clone()].into(),
+                txouts: [(floating_op, floating_txout.clone())].into(),
+                anchors: [(anchor, txid)].into(),
+                last_seen: [(txid, 20)].into(),
+            })),
+            ChangeSet {
+                // a block is removed and the last seen and revealed index decrease, which are
+                // both ignored
+                chain: [(2, None)].into(),
+                indexed_tx_graph: indexed_tx_graph::ChangeSet {
+                    graph: tx_graph::ChangeSet {
+                        last_seen: [(txid, 10)].into(),
+                        ..Default::default()
+                    },
+                    indexer: keychain::ChangeSet(
+                        [(KeychainKind::External, 1), (KeychainKind::Internal, 5)].into(),
+                    ),
+                },
+                network: None,
+                descriptors: Default::default(),
+                // a label is removed and another one replaced
+                labels: labels::ChangeSet(
+                    [
+                        (LabelRef::Tx(txid), Some(Label::from("groceries"))),
+                        (LabelRef::Output(floating_op), None),
+                    ]
+                    .into(),
+                ),
+                // an outpoint is unfrozen
+                frozen: [(floating_op, false)].into(),
+                min_confirmations: [(KeychainKind::External, 1), (KeychainKind::Internal, 2)]
+                    .into(),
+                // a batch is cancelled
+                payment_queue: payment_queue::ChangeSet {
+                    batches: [(1, None)].into(),
+                    ..Default::default()
+                },
+            },
+        ];
+
+        for changeset in &changesets {
+            store.write_changes(changeset).expect("must write");
+        }
+
+        let mut expected = changesets.remove(0);
+        for changeset in changesets {
+            expected.append(changeset);
+        }
+        // the removed block, label, unfrozen outpoint and cancelled batch are not stored at all
+        expected.chain.remove(&2);
+        expected.labels.0.remove(&LabelRef::Output(floating_op));
+        expected.frozen.remove(&floating_op);
+        expected.payment_queue.batches.remove(&1);
+
+        let loaded = store
+            .load_from_persistence()
+            .expect("must load")
+            .expect("must not be empty");
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn reopening_does_not_rerun_migrations() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("wallet.sqlite");
+
+        {
+            let mut store = Store::open(&file_path).expect("must create");
+            store
+                .write_changes(&ChangeSet {
+                    network: Some(Network::Regtest),
+                    ..Default::default()
+                })
+                .expect("must write");
+        }
+
+        let mut store = Store::open(&file_path).expect("must reopen");
+        let loaded = store.load_from_persistence().expect("must load");
+        assert_eq!(loaded.and_then(|c| c.network), Some(Network::Regtest));
+    }
+
+    #[test]
+    fn wallet_is_recovered() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("wallet.sqlite");
+
+        let (keychains, balance, address) = {
+            let db = Store::open(&file_path).expect("must create db");
+            let mut wallet =
+                Wallet::new(DESCRIPTOR, None, db, Network::Testnet).expect("must init wallet");
+            let address = wallet.try_get_address(AddressIndex::New).unwrap();
+            let tx = Transaction {
+                output: vec![TxOut {
+                    value: 50_000,
+                    script_pubkey: address.script_pubkey(),
+                }],
+                ..test_tx(0)
+            };
+            wallet
+                .insert_checkpoint(BlockId {
+                    height: 1_000,
+                    hash: BlockHash::all_zeros(),
+                })
+                .unwrap();
+            wallet
+                .insert_tx(
+                    tx,
+                    ConfirmationTime::Confirmed {
+                        height: 1_000,
+                        time: 100,
+                    },
+                )
+                .unwrap();
+            wallet.commit().expect("must commit");
+            (wallet.keychains().clone(), wallet.get_balance(), address)
+        };
+
+        let db = Store::open(&file_path).expect("must open db");
+        let wallet = Wallet::load(DESCRIPTOR, None, db).expect("must recover wallet");
+        assert_eq!(wallet.network(), Network::Testnet);
+        assert_eq!(wallet.keychains(), &keychains);
+        assert_eq!(wallet.get_balance(), balance);
+        assert_eq!(wallet.latest_checkpoint().height(), 1_000);
+        assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+        assert_eq!(
+            wallet
+                .list_unspent()
+                .next()
+                .map(|utxo| utxo.txout.script_pubkey),
+            Some(address.script_pubkey())
+        );
+    }
+}
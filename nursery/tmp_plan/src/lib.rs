@@ -1,3 +1,4 @@
+#![no_std]
 #![allow(unused)]
 #![allow(missing_docs)]
 #![allow(clippy::all)] // FIXME
@@ -14,6 +15,14 @@
 //!
 //! Once you've obstained signatures, hash pre-images etc required by the plan, it can create a
 //! witness/script_sig for the input.
+
+#[cfg(feature = "std")]
+extern crate std;
+
+#[macro_use]
+extern crate alloc;
+
+use alloc::vec::Vec;
 use bdk_chain::{bitcoin, collections::*, miniscript};
 use bitcoin::{
     absolute,
@@ -27,8 +36,9 @@ use bitcoin::{
     ScriptBuf, TxIn, Witness,
 };
 use miniscript::{
-    descriptor::{InnerXKey, Tr},
-    hash256, DefiniteDescriptorKey, Descriptor, DescriptorPublicKey, ScriptContext, ToPublicKey,
+    descriptor::{InnerXKey, Tr, WshInner},
+    hash256, DefiniteDescriptorKey, Descriptor, DescriptorPublicKey, MiniscriptKey, ScriptContext,
+    SigType, ToPublicKey,
 };
 
 pub(crate) fn varint_len(v: usize) -> usize {
@@ -56,6 +66,8 @@ enum Target {
     Legacy,
     Segwitv0 {
         script_code: ScriptBuf,
+        /// The script pushed at the end of the witness, for `wsh` descriptors
+        witness_script: Option<ScriptBuf>,
     },
     Segwitv1 {
         tr: Tr<DefiniteDescriptorKey>,
@@ -118,17 +130,23 @@ where
         };
         let witness_elem_sizes: Option<Vec<usize>> = match &self.target {
             Target::Legacy => None,
-            Target::Segwitv0 { .. } => Some(
-                self.template
+            Target::Segwitv0 { witness_script, .. } => {
+                let mut witness_elems = self
+                    .template
                     .iter()
-                    .map(|step| step.expected_size())
-                    .collect(),
-            ),
+                    .map(|step| step.expected_size(SigType::Ecdsa))
+                    .collect::<Vec<_>>();
+                if let Some(witness_script) = witness_script {
+                    witness_elems.push(witness_script.len());
+                }
+
+                Some(witness_elems)
+            }
             Target::Segwitv1 { tr, tr_plan } => {
                 let mut witness_elems = self
                     .template
                     .iter()
-                    .map(|step| step.expected_size())
+                    .map(|step| step.expected_size(SigType::Schnorr))
                     .collect::<Vec<_>>();
 
                 if let TrSpend::LeafSpend {
@@ -176,6 +194,7 @@ where
             .filter(|step| match step {
                 TemplateItem::Sign(key) => {
                     !auth_data.schnorr_sigs.contains_key(&key.descriptor_key)
+                        && !auth_data.ecdsa_sigs.contains_key(&key.descriptor_key)
                 }
                 TemplateItem::Hash160(image) => !auth_data.hash160_preimages.contains_key(image),
                 TemplateItem::Hash256(image) => !auth_data.hash256_preimages.contains_key(image),
@@ -194,7 +213,16 @@ where
                 .flat_map(|step| step.to_witness_stack(&auth_data))
                 .collect::<Vec<_>>();
             match &self.target {
-                Target::Segwitv0 { .. } => todo!(),
+                Target::Segwitv0 { witness_script, .. } => {
+                    if let Some(witness_script) = witness_script {
+                        witness.push(witness_script.clone().into_bytes());
+                    }
+
+                    PlanState::Complete {
+                        final_script_sig: None,
+                        final_script_witness: Some(Witness::from(witness)),
+                    }
+                }
                 Target::Legacy => todo!(),
                 Target::Segwitv1 {
                     tr_plan: TrSpend::KeySpend,
@@ -232,7 +260,7 @@ where
                     todo!()
                 }
                 Target::Segwitv0 { .. } => {
-                    todo!()
+                    requirements.signatures = RequiredSignatures::Segwitv0 { keys: vec![] };
                 }
                 Target::Segwitv1 { tr, tr_plan } => {
                     let spend_info = tr.spend_info();
@@ -262,7 +290,7 @@ where
 
             let required_signatures = match requirements.signatures {
                 RequiredSignatures::Legacy { .. } => todo!(),
-                RequiredSignatures::Segwitv0 { .. } => todo!(),
+                RequiredSignatures::Segwitv0 { ref mut keys } => keys,
                 RequiredSignatures::TapKey { .. } => return PlanState::Incomplete(requirements),
                 RequiredSignatures::TapScript {
                     plan_keys: ref mut keys,
@@ -432,11 +460,28 @@ where
     Ak: CanDerive + Clone,
 {
     match desc {
-        Descriptor::Bare(_) => todo!(),
-        Descriptor::Pkh(_) => todo!(),
-        Descriptor::Wpkh(_) => todo!(),
-        Descriptor::Sh(_) => todo!(),
-        Descriptor::Wsh(_) => todo!(),
         Descriptor::Tr(tr) => crate::plan_impls::plan_satisfaction_tr(tr, assets),
+        Descriptor::Wpkh(wpkh) => crate::plan_impls::plan_satisfaction_wpkh(wpkh, assets),
+        Descriptor::Wsh(wsh) => crate::plan_impls::plan_satisfaction_wsh(wsh, assets),
+        // TODO: legacy descriptors can't be planned yet
+        Descriptor::Bare(_) | Descriptor::Pkh(_) | Descriptor::Sh(_) => None,
+    }
+}
+
+/// Whether [`plan_satisfaction`] supports `desc`.
+///
+/// For the descriptors it doesn't support [`plan_satisfaction`] always returns `None`, for the
+/// other ones `None` means that the assets can't satisfy the descriptor.
+pub fn can_plan<Pk: MiniscriptKey>(desc: &Descriptor<Pk>) -> bool {
+    match desc {
+        Descriptor::Wpkh(_) => true,
+        Descriptor::Wsh(wsh) => match wsh.as_inner() {
+            WshInner::Ms(ms) => ms.iter().all(|ms| plan_impls::can_plan_term(&ms.node)),
+            WshInner::SortedMulti(_) => false,
+        },
+        Descriptor::Tr(tr) => tr
+            .iter_scripts()
+            .all(|(_, ms)| ms.iter().all(|ms| plan_impls::can_plan_term(&ms.node))),
+        Descriptor::Bare(_) | Descriptor::Pkh(_) | Descriptor::Sh(_) => false,
     }
 }
@@ -50,11 +50,71 @@ impl<Ak> TermPlan<Ak> {
         })
     }
 
-    pub(crate) fn expected_size(&self) -> usize {
-        self.template.iter().map(|step| step.expected_size()).sum()
+    pub(crate) fn expected_size<Ctx: ScriptContext>(&self) -> usize {
+        self.template
+            .iter()
+            .map(|step| step.expected_size(Ctx::sig_type()))
+            .sum()
     }
 }
 
+pub(crate) fn plan_satisfaction_wpkh<Ak>(
+    wpkh: &miniscript::descriptor::Wpkh<DefiniteDescriptorKey>,
+    assets: &Assets<Ak>,
+) -> Option<Plan<Ak>>
+where
+    Ak: CanDerive + Clone,
+{
+    let key = wpkh.as_inner();
+    let (asset_key, derivation_hint) = assets
+        .keys
+        .iter()
+        .find_map(|asset_key| Some((asset_key, asset_key.can_derive(key)?)))?;
+
+    Some(Plan {
+        template: vec![
+            TemplateItem::Sign(PlanKey {
+                asset_key: asset_key.clone(),
+                descriptor_key: key.clone(),
+                derivation_hint,
+            }),
+            TemplateItem::Pk { key: key.clone() },
+        ],
+        target: Target::Segwitv0 {
+            script_code: wpkh.ecdsa_sighash_script_code(),
+            witness_script: None,
+        },
+        set_locktime: None,
+        set_sequence: None,
+    })
+}
+
+pub(crate) fn plan_satisfaction_wsh<Ak>(
+    wsh: &miniscript::descriptor::Wsh<DefiniteDescriptorKey>,
+    assets: &Assets<Ak>,
+) -> Option<Plan<Ak>>
+where
+    Ak: CanDerive + Clone,
+{
+    let ms = match wsh.as_inner() {
+        WshInner::Ms(ms) => ms,
+        // TODO: sortedmulti can't be planned yet
+        WshInner::SortedMulti(_) => return None,
+    };
+    let plan = plan_steps(&ms.node, assets)?;
+    let witness_script = ms.encode();
+
+    Some(Plan {
+        target: Target::Segwitv0 {
+            script_code: witness_script.clone(),
+            witness_script: Some(witness_script),
+        },
+        set_locktime: plan.min_locktime,
+        set_sequence: plan.min_sequence,
+        template: plan.template,
+    })
+}
+
 // impl crate::descriptor::Pkh<DefiniteDescriptorKey> {
 //     pub(crate) fn plan_satisfaction<Ak>(&self, assets: &Assets<Ak>) -> Option<Plan<Ak>>
 //     where
@@ -134,7 +194,7 @@ where
         .filter_map(|(_, ms)| Some((ms, (plan_steps(&ms.node, assets)?))))
         .collect::<Vec<_>>();
 
-    plans.sort_by_cached_key(|(_, plan)| plan.expected_size());
+    plans.sort_by_cached_key(|(_, plan)| plan.expected_size::<miniscript::Tap>());
 
     let (script, best_plan) = plans.into_iter().next()?;
 
@@ -217,13 +277,7 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
         Terminal::After(locktime) => {
             let max_locktime = assets.max_locktime?;
             let locktime = absolute::LockTime::from(*locktime);
-            let (height, time) = match max_locktime {
-                absolute::LockTime::Blocks(height) => {
-                    (height, absolute::Time::from_consensus(0).unwrap())
-                }
-                absolute::LockTime::Seconds(seconds) => (absolute::Height::ZERO, seconds),
-            };
-            if max_locktime.is_satisfied_by(height, time) {
+            if locktime.is_implied_by(max_locktime) {
                 Some(TermPlan {
                     min_locktime: Some(locktime),
                     ..Default::default()
@@ -294,10 +348,11 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
             let rhs = plan_steps(&r.node, assets)?;
             lhs.combine(rhs)
         }
-        Terminal::AndOr(_, _, _) => todo!(),
-        Terminal::OrB(_, _) => todo!(),
-        Terminal::OrD(_, _) => todo!(),
-        Terminal::OrC(_, _) => todo!(),
+        // TODO: these fragments can't be planned yet
+        Terminal::AndOr(_, _, _)
+        | Terminal::OrB(_, _)
+        | Terminal::OrD(_, _)
+        | Terminal::OrC(_, _) => None,
         Terminal::OrI(lhs, rhs) => {
             let lplan = plan_steps(&lhs.node, assets).map(|mut plan| {
                 plan.template.push(TemplateItem::One);
@@ -309,7 +364,7 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
             });
             match (lplan, rplan) {
                 (Some(lplan), Some(rplan)) => {
-                    if lplan.expected_size() <= rplan.expected_size() {
+                    if lplan.expected_size::<Ctx>() <= rplan.expected_size::<Ctx>() {
                         Some(lplan)
                     } else {
                         Some(rplan)
@@ -318,8 +373,73 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
                 (lplan, rplan) => lplan.or(rplan),
             }
         }
-        Terminal::Thresh(_, _) => todo!(),
-        Terminal::Multi(_, _) => todo!(),
-        Terminal::MultiA(_, _) => todo!(),
+        Terminal::Multi(k, keys) => {
+            // The extra element consumed by CHECKMULTISIG, then the signatures in the order of
+            // the keys
+            let mut template = vec![TemplateItem::Zero];
+            for key in keys {
+                if template.len() > *k {
+                    break;
+                }
+                if let Some(plan_key) = plan_key(key, assets) {
+                    template.push(TemplateItem::Sign(plan_key));
+                }
+            }
+            if template.len() > *k {
+                Some(TermPlan::new(template))
+            } else {
+                None
+            }
+        }
+        Terminal::MultiA(k, keys) => {
+            // A signature or an empty element for every key, the last key goes first
+            let mut signatures = 0;
+            let template = keys
+                .iter()
+                .rev()
+                .map(|key| match plan_key(key, assets) {
+                    Some(plan_key) if signatures < *k => {
+                        signatures += 1;
+                        TemplateItem::Sign(plan_key)
+                    }
+                    _ => TemplateItem::Zero,
+                })
+                .collect::<Vec<_>>();
+            if signatures == *k {
+                Some(TermPlan::new(template))
+            } else {
+                None
+            }
+        }
+        // TODO: thresh can't be planned yet
+        Terminal::Thresh(_, _) => None,
     }
 }
+
+fn plan_key<Ak: Clone + CanDerive>(
+    key: &DefiniteDescriptorKey,
+    assets: &Assets<Ak>,
+) -> Option<PlanKey<Ak>> {
+    assets.keys.iter().find_map(|asset_key| {
+        Some(PlanKey {
+            asset_key: asset_key.clone(),
+            derivation_hint: asset_key.can_derive(key)?,
+            descriptor_key: key.clone(),
+        })
+    })
+}
+
+/// Whether [`plan_steps`] can plan `term`, its sub-fragments aside
+pub(crate) fn can_plan_term<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    term: &Terminal<Pk, Ctx>,
+) -> bool {
+    !matches!(
+        term,
+        Terminal::RawPkH(_)
+            | Terminal::AndOr(_, _, _)
+            | Terminal::OrB(_, _)
+            | Terminal::OrD(_, _)
+            | Terminal::OrC(_, _)
+            | Terminal::Thresh(_, _)
+    )
+}
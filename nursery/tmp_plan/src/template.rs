@@ -5,7 +5,7 @@ use bitcoin::{
 };
 
 use super::*;
-use crate::{hash256, varint_len, DefiniteDescriptorKey};
+use crate::{hash256, varint_len, DefiniteDescriptorKey, SigType};
 
 #[derive(Clone, Debug)]
 pub(crate) enum TemplateItem<Ak> {
@@ -33,16 +33,18 @@ pub struct PlanKey<Ak> {
 }
 
 impl<Ak> TemplateItem<Ak> {
-    pub fn expected_size(&self) -> usize {
-        match self {
-            TemplateItem::Sign { .. } => 64, /* size of sig TODO: take into consideration sighash flag */
-            TemplateItem::Pk { .. } => 32,
-            TemplateItem::One => varint_len(1),
-            TemplateItem::Zero => 0, /* zero means an empty witness element */
+    pub fn expected_size(&self, sig_type: SigType) -> usize {
+        match (self, sig_type) {
+            (TemplateItem::Sign { .. }, SigType::Schnorr) => 64, /* size of sig TODO: take into consideration sighash flag */
+            (TemplateItem::Sign { .. }, SigType::Ecdsa) => 73, /* max size of sig with the sighash flag */
+            (TemplateItem::Pk { .. }, SigType::Schnorr) => 32,
+            (TemplateItem::Pk { .. }, SigType::Ecdsa) => 33,
+            (TemplateItem::One, _) => varint_len(1),
+            (TemplateItem::Zero, _) => 0, /* zero means an empty witness element */
             // I'm not sure if it should be 32 here (it's a 20 byte hash) but that's what other
             // parts of the code were doing.
-            TemplateItem::Hash160(_) | TemplateItem::Ripemd160(_) => 32,
-            TemplateItem::Sha256(_) | TemplateItem::Hash256(_) => 32,
+            (TemplateItem::Hash160(_), _) | (TemplateItem::Ripemd160(_), _) => 32,
+            (TemplateItem::Sha256(_), _) | (TemplateItem::Hash256(_), _) => 32,
         }
     }
 
@@ -50,11 +52,14 @@ impl<Ak> TemplateItem<Ak> {
     pub(super) fn to_witness_stack(&self, auth_data: &SatisfactionMaterial) -> Vec<Vec<u8>> {
         match self {
             TemplateItem::Sign(plan_key) => {
-                vec![auth_data
-                    .schnorr_sigs
-                    .get(&plan_key.descriptor_key)
-                    .unwrap()
-                    .to_vec()]
+                match auth_data.schnorr_sigs.get(&plan_key.descriptor_key) {
+                    Some(sig) => vec![sig.to_vec()],
+                    None => vec![auth_data
+                        .ecdsa_sigs
+                        .get(&plan_key.descriptor_key)
+                        .unwrap()
+                        .to_vec()],
+                }
             }
             TemplateItem::One => vec![vec![1]],
             TemplateItem::Zero => vec![vec![]],